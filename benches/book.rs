@@ -0,0 +1,124 @@
+//! Benchmarks for book operations on synthetic vaults, run with `cargo bench`.
+//! Sizes cover a typical vault and a very large one, as the history grows with every change.
+
+use criterion::{BatchSize, Benchmark, Criterion, criterion_group, criterion_main};
+use vpass::{Book, DeviceId, Item, Password};
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+
+const PASSWORD: &str = "BenchmarkPassword";
+
+fn item_name(i: usize) -> String {
+    format!("site{}.example.com/user{}", i, i % 7)
+}
+
+/// Book with `n` items, each with a password, a tag and a note
+fn synthetic(n: usize) -> Book {
+    let mut book = Book::new();
+    for i in 0..n {
+        let mut item = Item::new(&item_name(i));
+        item.password = Some(Password::new(&format!("password-{}", i)));
+        item.tags.insert(format!("tag{}", i % 10));
+        item.add_note(None, "Synthetic benchmark item");
+        book.add(item).unwrap();
+    }
+    book
+}
+
+fn read_item(c: &mut Criterion) {
+    for &n in &SIZES {
+        let book = synthetic(n);
+        let name = item_name(n / 2);
+        c.bench(
+            "get_item_by_name",
+            Benchmark::new(n.to_string(), move |b| {
+                b.iter(|| book.get_item_by_name(&name).unwrap())
+            }),
+        );
+    }
+}
+
+fn items(c: &mut Criterion) {
+    for &n in &SIZES {
+        let book = synthetic(n);
+        c.bench(
+            "items",
+            Benchmark::new(n.to_string(), move |b| b.iter(|| book.items())),
+        );
+    }
+}
+
+/// Merge two copies that have diverged by a few items each
+fn merge_versions(c: &mut Criterion) {
+    for &n in &SIZES {
+        let base = synthetic(n);
+        let mut local = base.clone();
+        let mut remote = base.clone();
+        for i in 0..5 {
+            local.add(Item::new(&format!("local{}", i))).unwrap();
+            remote.add(Item::new(&format!("remote{}", i))).unwrap();
+            remote.remove(&item_name(i)).unwrap();
+        }
+        c.bench(
+            "merge_versions",
+            Benchmark::new(n.to_string(), move |b| {
+                b.iter_batched(
+                    || local.clone(),
+                    |l| l.merge_versions(&remote).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            }),
+        );
+    }
+}
+
+/// Prune a tenth of the items, removed and synchronized
+fn prune(c: &mut Criterion) {
+    for &n in &SIZES {
+        let mut book = synthetic(n);
+        for i in (0..n).step_by(10) {
+            book.remove(&item_name(i)).unwrap();
+        }
+        book.mark_synced(DeviceId::generate());
+        c.bench(
+            "prune",
+            Benchmark::new(n.to_string(), move |b| {
+                b.iter_batched(|| book.clone(), |mut book| book.prune(), BatchSize::LargeInput)
+            }),
+        );
+    }
+}
+
+/// Encryption and decryption, including key derivation with the default parameters
+fn encrypt_decrypt(c: &mut Criterion) {
+    rust_sodium::init().expect("Sodium init failed");
+
+    for &n in &SIZES {
+        let book = synthetic(n);
+        c.bench(
+            "encrypt",
+            Benchmark::new(n.to_string(), move |b| {
+                b.iter_batched(
+                    || book.clone(),
+                    |book| vpass::encrypt(PASSWORD, book).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            })
+            .sample_size(10),
+        );
+    }
+
+    for &n in &SIZES {
+        let data = vpass::encrypt(PASSWORD, synthetic(n)).unwrap();
+        c.bench(
+            "decrypt",
+            Benchmark::new(n.to_string(), move |b| {
+                b.iter(|| vpass::decrypt(&data, PASSWORD).unwrap())
+            })
+            .sample_size(10),
+        );
+    }
+}
+
+criterion_group!(benches, read_item, items, merge_versions, prune, encrypt_decrypt);
+criterion_main!(benches);
@@ -0,0 +1,11 @@
+//! Decrypted books are only authenticated, a member of a shared vault can write anything
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(book) = serde_json::from_slice::<vpass::Book>(data) {
+        let _ = book.items();
+        let _ = book.items_metadata();
+        let _ = book.item_names();
+    }
+});
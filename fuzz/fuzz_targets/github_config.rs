@@ -0,0 +1,8 @@
+//! GitHub configuration has its own compact encoding inside transfer strings
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use vpass::sync::providers::Provider;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Provider::GitHub.configuration_decompress(data);
+});
@@ -0,0 +1,12 @@
+//! Transfer strings are pasted by the user, and may come from anywhere
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use vpass::sync::config::SyncConfig;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _ = vpass::sync::transfer_string::decode(s);
+    }
+    // Random input rarely passes the checksum, so the contents are fuzzed separately
+    let _ = SyncConfig::decompress(data);
+});
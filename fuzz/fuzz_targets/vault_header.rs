@@ -0,0 +1,7 @@
+//! Vault files come from synchronization providers and backups
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = vpass::EncryptedVault::from_bytes(data);
+});
@@ -1,23 +1,207 @@
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fmt;
+use std::str::FromStr;
 
 use chrono::prelude::*;
+use rust_sodium::crypto::hash::sha256;
+use unicode_normalization::UnicodeNormalization;
 use uuid::Uuid;
 
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+use super::card::Card;
+use super::derive::DerivationRule;
+use super::vault::{EncryptedVault, RecoveryKey, SharedKey, Vault};
+use super::wifi::Wifi;
 use crate::cli::error::{Error, VResult};
 
+/// Serialize a set in sorted order, so that equal books serialize identically
+fn serialize_sorted<S: Serializer>(set: &HashSet<String>, serializer: S) -> Result<S::Ok, S::Error> {
+    let mut items: Vec<&String> = set.iter().collect();
+    items.sort();
+    serializer.collect_seq(items)
+}
+
+/// Notes were stored as plain strings before they had ids
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredNote {
+    Note(Note),
+    Plain(String),
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+/// Plain string notes get ids by position, so that the ids match the old indices plus one
+fn deserialize_notes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Note>, D::Error> {
+    let stored: Vec<StoredNote> = Vec::deserialize(deserializer)?;
+    Ok(stored
+        .into_iter()
+        .enumerate()
+        .map(|(i, note)| match note {
+            StoredNote::Note(note) => note,
+            StoredNote::Plain(text) => Note {
+                id: i as u32 + 1,
+                title: None,
+                text,
+            },
+        })
+        .collect())
+}
+
+/// Metadata key of the vault description
+const METADATA_DESCRIPTION: &str = "description";
+
+/// Prefix of metadata keys of vault annotations
+const METADATA_ANNOTATION: &str = "annotation/";
+
+/// Value in the metadata map of a book
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+struct MetadataEntry {
+    /// `None` if removed, so that the removal is kept when merging
+    value: Option<serde_json::Value>,
+    /// Newer entry is kept when merging
+    changed: DateTime<Utc>,
+}
+/// JSON values can't contain NaN, so equality is reflexive
+impl Eq for MetadataEntry {}
+
 /// The contents of this are an implementation detail
-#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct ItemId(Uuid);
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+/// Identifies the device that made a change, generated on `init`
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DeviceId(Uuid);
+impl DeviceId {
+    pub fn generate() -> Self {
+        DeviceId(Uuid::new_v4())
+    }
+}
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Identifies a book and all of its copies, so that unrelated books are never merged
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct OriginId(Uuid);
+impl OriginId {
+    fn new() -> Self {
+        OriginId(Uuid::new_v4())
+    }
+
+    /// Books created before origin ids existed are identified by their creation time
+    fn from_creation_time(created: DateTime<Utc>) -> Self {
+        let mut bytes = created.timestamp().to_be_bytes().to_vec();
+        bytes.extend(&created.timestamp_subsec_nanos().to_be_bytes());
+        bytes.resize(16, 0);
+        OriginId(Uuid::from_slice(&bytes).unwrap())
+    }
+}
+
+/// Events a device had when it last synchronized,
+/// as the highest sequence number of the events created by each device
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+#[serde(from = "AcknowledgedFormat")]
+struct Acknowledged(BTreeMap<DeviceId, u64>);
+impl Acknowledged {
+    fn covers(&self, device: DeviceId, seq: u64) -> bool {
+        self.0.get(&device).is_some_and(|s| *s >= seq)
+    }
+
+    /// Keep the higher sequence number of each device
+    fn merge(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (device, seq) in &other.0 {
+            let current = self.0.entry(*device).or_insert(0);
+            if *current < *seq {
+                *current = *seq;
+                changed = true;
+            }
+        }
+        changed
+    }
+}
+
+/// Markers written by older versions were timestamps, which don't tell which events a device had
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AcknowledgedFormat {
+    Sequences(BTreeMap<DeviceId, u64>),
+    Timestamp(DateTime<Utc>),
+}
+impl From<AcknowledgedFormat> for Acknowledged {
+    fn from(format: AcknowledgedFormat) -> Self {
+        match format {
+            AcknowledgedFormat::Sequences(sequences) => Acknowledged(sequences),
+            AcknowledgedFormat::Timestamp(_) => Acknowledged::default(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Book {
     events: Vec<EventFrame>,
     created: DateTime<Utc>,
+    /// Missing from books created by older versions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<OriginId>,
+    /// Events each device has synchronized,
+    /// used to decide when history of removed items can be pruned
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sync_markers: BTreeMap<DeviceId, Acknowledged>,
+    /// Removed items whose history has been pruned, so that their events are never merged back
+    /// from copies that haven't synchronized since
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pruned: BTreeSet<ItemId>,
+    /// Passwords are removed from versions of these items older than the time,
+    /// e.g. from before the password was protected with a passphrase
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    redacted: BTreeMap<ItemId, DateTime<Utc>>,
+    /// Key of a shared vault, `None` if the vault is encrypted with the password directly
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    shared: Option<SharedKey>,
+    /// Recovery key the vault key is sealed for, see `vpass vault shamir-split`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    recovery: Option<RecoveryKey>,
+    /// Internal structured configuration, like synchronization settings, kept outside of items
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    metadata: BTreeMap<String, MetadataEntry>,
+    /// Events have been added since the book was loaded
+    #[serde(skip)]
+    dirty: bool,
+    /// Item names are matched ignoring case and normalization form
+    #[serde(skip)]
+    case_insensitive: bool,
+    /// New events are stamped with this
+    #[serde(skip)]
+    device: Option<DeviceId>,
+    /// Accesses are not recorded
+    #[serde(skip)]
+    ignore_access: bool,
+    /// Hash of the vault file the book was read from, to detect changes written by others since
+    #[serde(skip)]
+    source: Option<[u8; 32]>,
+}
+impl PartialEq for Book {
+    fn eq(&self, other: &Self) -> bool {
+        self.events == other.events
+            && self.created == other.created
+            && self.origin() == other.origin()
+            && self.sync_markers == other.sync_markers
+            && self.pruned == other.pruned
+            && self.redacted == other.redacted
+            && self.shared == other.shared
+            && self.recovery == other.recovery
+            && self.metadata == other.metadata
+    }
 }
+impl Eq for Book {}
 impl Default for Book {
     fn default() -> Self {
         Self::new()
@@ -28,9 +212,90 @@ impl Book {
         Book {
             events: Vec::new(),
             created: Utc::now(),
+            origin: Some(OriginId::new()),
+            sync_markers: BTreeMap::new(),
+            pruned: BTreeSet::new(),
+            redacted: BTreeMap::new(),
+            shared: None,
+            recovery: None,
+            metadata: BTreeMap::new(),
+            dirty: true,
+            case_insensitive: false,
+            device: None,
+            ignore_access: false,
+            source: None,
+        }
+    }
+
+    /// Match item names ignoring case and Unicode normalization form.
+    /// Names differing only in case are then treated as duplicates.
+    pub fn set_case_insensitive(&mut self, value: bool) {
+        self.case_insensitive = value;
+    }
+
+    /// Record this device as the author of new events
+    pub fn set_device(&mut self, device: DeviceId) {
+        self.device = Some(device);
+    }
+
+    /// Don't record accesses, so that only reading items doesn't change the book
+    pub fn set_ignore_access(&mut self, value: bool) {
+        self.ignore_access = value;
+    }
+
+    /// Device recording new events, if set
+    pub fn device(&self) -> Option<DeviceId> {
+        self.device
+    }
+
+    pub fn set_source(&mut self, hash: [u8; 32]) {
+        self.source = Some(hash);
+    }
+
+    /// Hash of the vault file the book was read from, `None` for new books
+    pub fn source(&self) -> Option<[u8; 32]> {
+        self.source
+    }
+
+    /// Check if two item names refer to the same item
+    pub fn names_match(&self, a: &str, b: &str) -> bool {
+        if self.case_insensitive {
+            let normalize = |s: &str| s.nfkc().collect::<String>().to_lowercase();
+            normalize(a) == normalize(b)
+        } else {
+            a == b
         }
     }
 
+    /// Check if the book has been changed since it was loaded,
+    /// i.e. whether it needs to be written back
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    fn push_event(&mut self, time: DateTime<Utc>, event: Event) {
+        let seq = self.device.map(|device| self.latest_seq(device) + 1);
+        self.events.push(EventFrame {
+            time,
+            event,
+            device: self.device,
+            seq,
+        });
+        self.dirty = true;
+    }
+
+    /// Sequence number of the latest event created by `device`, including pruned events
+    fn latest_seq(&self, device: DeviceId) -> u64 {
+        let acknowledged = self.sync_markers.get(&device).and_then(|a| a.0.get(&device)).copied();
+        self.events
+            .iter()
+            .filter(|ef| ef.device == Some(device))
+            .filter_map(|ef| ef.seq)
+            .chain(acknowledged)
+            .max()
+            .unwrap_or(0)
+    }
+
     fn next_id(&mut self) -> ItemId {
         ItemId(Uuid::new_v4())
     }
@@ -39,65 +304,205 @@ impl Book {
         self.created
     }
 
+    /// Shared by all copies of the book
+    pub fn origin(&self) -> OriginId {
+        self.origin
+            .unwrap_or_else(|| OriginId::from_creation_time(self.created))
+    }
+
+    /// Key of a shared vault
+    pub fn shared(&self) -> Option<&SharedKey> {
+        self.shared.as_ref()
+    }
+
+    pub fn set_shared(&mut self, shared: SharedKey) {
+        self.shared = Some(shared);
+        self.dirty = true;
+    }
+
+    /// Recovery key of the vault
+    pub fn recovery(&self) -> Option<&RecoveryKey> {
+        self.recovery.as_ref()
+    }
+
+    pub fn set_recovery(&mut self, recovery: RecoveryKey) {
+        self.recovery = Some(recovery);
+        self.dirty = true;
+    }
+
+    /// Internal configuration value
+    pub fn metadata(&self, key: &str) -> Option<&serde_json::Value> {
+        self.metadata.get(key).and_then(|e| e.value.as_ref())
+    }
+
+    pub fn set_metadata(&mut self, key: &str, value: serde_json::Value) {
+        self.metadata.insert(key.to_owned(), MetadataEntry {
+            value: Some(value),
+            changed: Utc::now(),
+        });
+        self.dirty = true;
+    }
+
+    /// Returns false if the key was not set
+    pub fn remove_metadata(&mut self, key: &str) -> bool {
+        if self.metadata(key).is_none() {
+            return false;
+        }
+        self.metadata.insert(key.to_owned(), MetadataEntry {
+            value: None,
+            changed: Utc::now(),
+        });
+        self.dirty = true;
+        true
+    }
+
+    /// Free-form description of the vault
+    pub fn description(&self) -> Option<&str> {
+        self.metadata(METADATA_DESCRIPTION).and_then(|v| v.as_str())
+    }
+
+    /// Set or remove the description
+    pub fn set_description(&mut self, description: Option<&str>) {
+        match description {
+            Some(d) => self.set_metadata(METADATA_DESCRIPTION, serde_json::Value::String(d.to_owned())),
+            None => {
+                self.remove_metadata(METADATA_DESCRIPTION);
+            },
+        }
+    }
+
+    /// Short display hint of the vault in listings, set as the `label` annotation
+    pub fn label(&self) -> Option<&str> {
+        self.metadata(&format!("{}label", METADATA_ANNOTATION)).and_then(|v| v.as_str())
+    }
+
+    /// Custom key-value annotations of the vault
+    pub fn annotations(&self) -> BTreeMap<String, String> {
+        self.metadata
+            .iter()
+            .filter(|(key, _)| key.starts_with(METADATA_ANNOTATION))
+            .filter_map(|(key, entry)| {
+                let value = entry.value.as_ref()?.as_str()?;
+                Some((key[METADATA_ANNOTATION.len()..].to_owned(), value.to_owned()))
+            })
+            .collect()
+    }
+
+    /// Set or remove an annotation.
+    /// Each annotation is merged separately, so that different keys can be changed on different devices.
+    pub fn annotate(&mut self, key: &str, value: Option<&str>) {
+        let key = format!("{}{}", METADATA_ANNOTATION, key);
+        match value {
+            Some(v) => self.set_metadata(&key, serde_json::Value::String(v.to_owned())),
+            None => {
+                self.remove_metadata(&key);
+            },
+        }
+    }
+
+    /// Password the book is encrypted with: the content password of a shared vault,
+    /// or the given password otherwise
+    pub fn content_password<'a>(&'a self, password: &'a str) -> &'a str {
+        match self.shared {
+            Some(ref shared) => shared.content_password(),
+            None => password,
+        }
+    }
+
     /// Create and update with data
     pub fn add(&mut self, item: Item) -> VResult<ItemId> {
         self.verify_not_exists(&item.name)?;
         let item_id = self.next_id();
         let time = Utc::now();
-        self.events.push(EventFrame {
-            time,
-            event: Event::Create(item_id),
-        });
-        self.events.push(EventFrame {
-            time,
-            event: Event::Update(item_id, item),
-        });
+        self.push_event(time, Event::Create(item_id));
+        self.push_event(time, Event::Update(item_id, item));
         Ok(item_id)
     }
 
     /// Updates item using new value
-    pub fn update(&mut self, item_id: ItemId, item: Item) {
+    pub fn update(&mut self, item_id: ItemId, item: Item) -> VResult<()> {
         if !self.item_ids().contains(&item_id) {
-            panic!("Cannot update nonexistent ItemId");
+            return Err(Error::NoSuchItem(item.name));
         }
-        self.events.push(EventFrame {
-            time: Utc::now(),
-            event: Event::Update(item_id, item),
-        });
+        self.push_event(Utc::now(), Event::Update(item_id, item));
+        Ok(())
     }
 
-    /// Updates item by mapping the old value
-    #[must_use]
-    fn modify<F, R>(&mut self, item_id: ItemId, f: F) -> Option<R>
+    /// Updates item by mapping the old value.
+    /// Nothing is recorded if the value doesn't change.
+    fn modify<F, R>(&mut self, item_id: ItemId, f: F) -> VResult<R>
     where F: FnOnce(&mut Item) -> R {
-        let mut item = self.read_item(item_id)?;
+        let old = self.read_item(item_id).ok_or(Error::VaultCorrupted)?;
+        let mut item = old.clone();
         let r = f(&mut item);
-        self.update(item_id, item);
-        Some(r)
+        if item != old {
+            self.update(item_id, item)?;
+        }
+        Ok(r)
     }
 
     /// Updates item by mapping the old value
-    #[must_use]
     pub fn modify_by_name<F, R>(&mut self, name: &str, f: F) -> VResult<R>
     where F: FnOnce(&mut Item) -> R {
-        Ok(self
-            .modify(
-                self.find_id_by_name(name)
-                    .ok_or_else(|| Error::NoSuchItem(name.to_owned()))?,
-                f,
-            )
-            .unwrap())
+        self.modify(self.get_id_by_name(name)?, f)
+    }
+
+    /// Record that the secret of an item was revealed
+    pub fn record_access(&mut self, name: &str) -> VResult<()> {
+        let id = self.get_id_by_name(name)?;
+        if !self.ignore_access {
+            self.push_event(Utc::now(), Event::Access(id));
+        }
+        Ok(())
     }
 
     pub fn remove(&mut self, name: &str) -> VResult<()> {
         let id = self.get_id_by_name(name)?;
-        self.events.push(EventFrame {
-            time: Utc::now(),
-            event: Event::Remove(id),
-        });
+        self.push_event(Utc::now(), Event::Remove(id));
         Ok(())
     }
 
+    /// Replace derived passwords with stored ones, e.g. before the master password changes.
+    /// Returns the number of items changed.
+    pub fn store_derived_passwords(&mut self, master_password: &str) -> VResult<usize> {
+        let names: Vec<String> = self
+            .items()
+            .into_iter()
+            .filter(|item| item.derived.is_some())
+            .map(|item| item.name)
+            .collect();
+        for name in &names {
+            self.modify_by_name(name, |item| {
+                item.password = item.password_for(master_password);
+                item.derived = None;
+            })?;
+        }
+        Ok(names.len())
+    }
+
+    /// Merge notes and tags of an item into another one, and remove it.
+    /// The password, stored or derived, is moved only if the target doesn't have one.
+    pub fn merge_into(&mut self, from: &str, to: &str) -> VResult<()> {
+        let source = self.get_item_by_name(from)?;
+        if self.get_id_by_name(from)? == self.get_id_by_name(to)? {
+            return Ok(());
+        }
+        self.modify_by_name(to, |item| {
+            item.tags.extend(source.tags);
+            for note in source.notes {
+                if !item.notes.iter().any(|n| n.title == note.title && n.text == note.text) {
+                    item.add_note(note.title, &note.text);
+                }
+            }
+            if item.password.is_none() && item.derived.is_none() && item.protected.is_none() {
+                item.password = source.password;
+                item.derived = source.derived;
+                item.protected = source.protected;
+            }
+        })?;
+        self.remove(from)
+    }
+
     /// All ItemId:s, including removed ones
     fn all_ids(&self) -> HashSet<ItemId> {
         self.events.iter().filter_map(EventFrame::creates_id).collect()
@@ -119,8 +524,9 @@ impl Book {
                 Event::Update(e_id, event) if e_id == id => {
                     return Some(event);
                 },
+                // Never written by vpass, but a corrupted or crafted book may contain this
                 Event::Create(e_id) if e_id == id => {
-                    unreachable!("Item created but not initialized");
+                    return None;
                 },
                 _ => {},
             }
@@ -132,6 +538,9 @@ impl Book {
     fn read_item_metadata(&self, id: ItemId) -> Option<ItemMetadata> {
         let mut created: Option<DateTime<Utc>> = None;
         let mut changed: Option<DateTime<Utc>> = None;
+        let mut changed_by: Option<DeviceId> = None;
+        let mut last_used: Option<DateTime<Utc>> = None;
+        let mut use_count: usize = 0;
 
         for ef in self.events.iter() {
             match ef.clone().event {
@@ -140,6 +549,11 @@ impl Book {
                 },
                 Event::Update(e_id, _) if e_id == id => {
                     changed = Some(ef.time);
+                    changed_by = ef.device;
+                },
+                Event::Access(e_id) if e_id == id => {
+                    last_used = Some(ef.time);
+                    use_count += 1;
                 },
                 _ => {},
             }
@@ -148,20 +562,21 @@ impl Book {
         Some(ItemMetadata {
             created: created?,
             changed: changed?,
+            changed_by,
+            last_used,
+            use_count,
         })
     }
 
+    /// Items with an inconsistent history are skipped
     pub fn items(&self) -> Vec<Item> {
-        self.item_ids()
-            .into_iter()
-            .map(|id| self.read_item(id).unwrap())
-            .collect()
+        self.id_items().into_iter().map(|(_, item)| item).collect()
     }
 
     fn id_items(&self) -> Vec<(ItemId, Item)> {
         self.item_ids()
             .into_iter()
-            .map(|id| (id, self.read_item(id).unwrap()))
+            .filter_map(|id| Some((id, self.read_item(id)?)))
             .collect()
     }
 
@@ -169,14 +584,15 @@ impl Book {
     pub fn items_metadata(&self) -> Vec<(Item, ItemMetadata)> {
         self.item_ids()
             .into_iter()
-            .map(|id| (self.read_item(id).unwrap(), self.read_item_metadata(id).unwrap()))
+            .filter_map(|id| Some((self.read_item(id)?, self.read_item_metadata(id)?)))
             .collect()
     }
 
     /// Items and associated metadata
     pub fn get_item_and_metadata(&self, name: &str) -> VResult<(Item, ItemMetadata)> {
         let id = self.get_id_by_name(name)?;
-        Ok((self.read_item(id).unwrap(), self.read_item_metadata(id).unwrap()))
+        let metadata = self.read_item_metadata(id).ok_or(Error::VaultCorrupted)?;
+        Ok((self.read_item(id).ok_or(Error::VaultCorrupted)?, metadata))
     }
 
     fn find_id<F>(&self, f: F) -> Option<ItemId>
@@ -188,7 +604,7 @@ impl Book {
     }
 
     fn find_id_by_name(&self, name: &str) -> Option<ItemId> {
-        self.find_id(|item| item.name == name)
+        self.find_id(|item| self.names_match(&item.name, name))
     }
 
     pub fn has_item(&self, name: &str) -> bool {
@@ -201,7 +617,7 @@ impl Book {
     }
 
     pub fn get_item_by_name(&self, name: &str) -> VResult<Item> {
-        Ok(self.read_item(self.get_id_by_name(name)?).unwrap())
+        self.read_item(self.get_id_by_name(name)?).ok_or(Error::VaultCorrupted)
     }
 
     pub fn verify_exists(&self, name: &str) -> VResult<()> {
@@ -228,6 +644,28 @@ impl Book {
         self.items().iter().map(|item| item.name.clone()).collect()
     }
 
+    /// Changes needed to turn this book into `other`.
+    /// Items are matched by id, so renames are detected,
+    /// but all items differ if the books don't have a common origin.
+    pub fn diff(&self, other: &Self) -> Vec<ItemChange> {
+        let mut old: HashMap<ItemId, Item> = self.id_items().into_iter().collect();
+        let mut result = Vec::new();
+        for (id, new_item) in other.id_items() {
+            match old.remove(&id) {
+                Some(old_item) => {
+                    let diff = ItemDiff::between(&old_item, &new_item);
+                    if !diff.changes.is_empty() {
+                        result.push(ItemChange::Modified(diff));
+                    }
+                },
+                None => result.push(ItemChange::Added(new_item)),
+            }
+        }
+        result.extend(old.into_values().map(ItemChange::Removed));
+        result.sort_by(|a, b| a.name().cmp(b.name()));
+        result
+    }
+
     /// Index of the the last common event
     /// Must be only used with two books with common history
     /// Returns None in case the event lists are equal
@@ -245,44 +683,357 @@ impl Book {
         }
     }
 
-    /// Check if two books have same origin, i.e. creation time, and can be merged together
+    /// Check if two books are copies of the same book, and can be merged together
     #[must_use]
     pub fn has_same_origin(&self, other: &Self) -> bool {
-        self.created != other.created
+        self.origin() == other.origin()
+    }
+
+    /// Copy items of this book on top of an unrelated book, which then becomes the origin.
+    /// Items with the same name are replaced, and item history is not preserved.
+    /// Used to intentionally link separately created vaults together.
+    pub fn adopt_into(&self, other: &Self) -> Self {
+        let mut result = other.clone();
+        result.case_insensitive = self.case_insensitive;
+        result.device = self.device;
+        for item in self.items() {
+            match result.find_id_by_name(&item.name) {
+                Some(id) => result.modify(id, |it| *it = item).unwrap(),
+                None => {
+                    result.add(item).unwrap();
+                },
+            }
+        }
+        result
     }
 
     /// Merge two versions of one password book together
-    #[must_use]
     pub fn merge_versions(mut self, other: &Self) -> Result<Self, VersionMergeError> {
-        if self.has_same_origin(other) {
-            Err(VersionMergeError::DifferentOrigins)
-        } else if let Some(di) = self.differ_index(&other) {
-            println!("{:?}", di);
+        if !self.has_same_origin(other) {
+            return Err(VersionMergeError::DifferentOrigins);
+        }
+        // Redact both sides the same way first, so that redacted events are equal
+        let redacted_other;
+        let other = if self.redacted == other.redacted {
+            other
+        } else {
+            self.merge_redacted(&other.redacted);
+            let mut copy = other.clone();
+            copy.merge_redacted(&self.redacted);
+            redacted_other = copy;
+            &redacted_other
+        };
+        if let Some(di) = self.differ_index(other) {
             // Remove new events from self, making it the common prefix
             let mut tail = self.events.split_off(di);
-            // Sort only new events, and append them
-            tail.extend(other.events.iter().skip(di).cloned().collect::<Vec<_>>());
-            tail.sort();
-            for t in &tail {
-                println!("{:?}", t);
+            // Sort only new events, and append them.
+            // Pruned histories don't share a prefix, so events may occur on both sides.
+            let seen: HashSet<EventFrame> = tail.iter().cloned().collect();
+            for ef in other.events.iter().skip(di) {
+                if !seen.contains(ef) && !self.pruned.contains(&ef.event.item_id()) {
+                    tail.push(ef.clone());
+                }
             }
+            tail.sort();
             self.events.extend(tail);
             self.clean();
+            self.rename_conflicts();
+            self.merge_pruned(&other.pruned);
+            self.merge_sync_markers(&other.sync_markers);
+            self.merge_shared(&other.shared);
+            self.merge_recovery(&other.recovery);
+            self.merge_metadata(&other.metadata);
+            self.dirty = true;
             Ok(self)
         } else {
-            // The books are equal
+            // The events are equal
+            self.merge_pruned(&other.pruned);
+            self.merge_sync_markers(&other.sync_markers);
+            self.merge_shared(&other.shared);
+            self.merge_recovery(&other.recovery);
+            self.merge_metadata(&other.metadata);
             Ok(self)
         }
     }
 
-    /// Remove unnecessary events, such as multiple removes
+    /// Merge members of the shared keys, see `SharedKey::merge`
+    fn merge_shared(&mut self, other: &Option<SharedKey>) {
+        if let Some(other) = other {
+            let merged = match self.shared {
+                Some(ref shared) => shared.merge(other),
+                None => other.clone(),
+            };
+            if self.shared.as_ref() != Some(&merged) {
+                self.shared = Some(merged);
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Keep the most recently created recovery key
+    fn merge_recovery(&mut self, other: &Option<RecoveryKey>) {
+        if let Some(other) = other {
+            if self.recovery.as_ref().is_none_or(|r| r.created() < other.created()) {
+                self.recovery = Some(other.clone());
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Keep the most recently changed entry of each key
+    fn merge_metadata(&mut self, metadata: &BTreeMap<String, MetadataEntry>) {
+        for (key, entry) in metadata {
+            if self.metadata.get(key).is_none_or(|e| e.changed < entry.changed) {
+                self.metadata.insert(key.clone(), entry.clone());
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Redact versions of items redacted on the other side
+    fn merge_redacted(&mut self, redacted: &BTreeMap<ItemId, DateTime<Utc>>) {
+        let mut changed = false;
+        for (id, time) in redacted {
+            if self.redacted.get(id).is_none_or(|t| t < time) {
+                self.redacted.insert(*id, *time);
+                changed = true;
+            }
+        }
+        if changed {
+            let redacted = &self.redacted;
+            for ef in &mut self.events {
+                let time = ef.time;
+                if let Event::Update(id, ref mut item) = ef.event {
+                    if redacted.get(&id).is_some_and(|t| time < *t) {
+                        item.password = None;
+                    }
+                }
+            }
+            self.dirty = true;
+        }
+    }
+
+    /// Remove the plaintext password from earlier versions of an item, e.g. after protecting it.
+    /// The latest version is kept as is, and copies merged later are redacted as well.
+    pub fn redact_history(&mut self, name: &str) -> VResult<()> {
+        let id = self.get_id_by_name(name)?;
+        let latest = self
+            .events
+            .iter()
+            .rev()
+            .find(|ef| match ef.event {
+                Event::Update(i, _) => i == id,
+                _ => false,
+            })
+            .ok_or(Error::VaultCorrupted)?
+            .time;
+        self.merge_redacted(&std::iter::once((id, latest)).collect());
+        Ok(())
+    }
+
+    /// Remove an item with its whole history, so that no earlier version of it remains in the book.
+    /// The item is recorded as pruned, so that its events are dropped when other copies are merged.
+    pub fn purge(&mut self, name: &str) -> VResult<()> {
+        let id = self.get_id_by_name(name)?;
+        self.merge_pruned(&std::iter::once(id).collect());
+        Ok(())
+    }
+
+    /// Drop events of items pruned on the other side
+    fn merge_pruned(&mut self, pruned: &BTreeSet<ItemId>) {
+        let new: Vec<ItemId> = pruned.difference(&self.pruned).copied().collect();
+        if !new.is_empty() {
+            self.pruned.extend(new);
+            let pruned = &self.pruned;
+            self.events.retain(|ef| !pruned.contains(&ef.event.item_id()));
+            self.dirty = true;
+        }
+    }
+
+    /// Keep the latest marker of each device
+    fn merge_sync_markers(&mut self, markers: &BTreeMap<DeviceId, Acknowledged>) {
+        for (device, acknowledged) in markers {
+            if !self.sync_markers.contains_key(device) {
+                self.sync_markers.insert(*device, Acknowledged::default());
+                self.dirty = true;
+            }
+            if self.sync_markers.get_mut(device).unwrap().merge(acknowledged) {
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Record that `device` has synchronized all events of the book
+    pub fn mark_synced(&mut self, device: DeviceId) {
+        let mut acknowledged = Acknowledged::default();
+        for ef in &self.events {
+            if let (Some(author), Some(seq)) = (ef.device, ef.seq) {
+                let latest = acknowledged.0.entry(author).or_insert(0);
+                *latest = (*latest).max(seq);
+            }
+        }
+        self.merge_sync_markers(&std::iter::once((device, acknowledged)).collect());
+    }
+
+    /// Remove all events of removed items, once every device that has synchronized the book
+    /// has synchronized the removal as well. Devices that have never synchronized are not waited for;
+    /// the ids of pruned items are kept so that their events are dropped when those devices merge.
+    /// Removals without a device are never pruned, as they can't be acknowledged.
+    /// Returns the number of pruned items.
+    pub fn prune(&mut self) -> usize {
+        if self.sync_markers.is_empty() {
+            return 0;
+        }
+        let markers = &self.sync_markers;
+        let pruned: HashSet<ItemId> = self
+            .events
+            .iter()
+            .filter(|ef| match (ef.device, ef.seq) {
+                (Some(device), Some(seq)) => markers.values().all(|a| a.covers(device, seq)),
+                _ => false,
+            })
+            .filter_map(EventFrame::removes_id)
+            .collect();
+        if !pruned.is_empty() {
+            self.events.retain(|ef| !pruned.contains(&ef.event.item_id()));
+            self.pruned.extend(pruned.iter().copied());
+            self.dirty = true;
+        }
+        pruned.len()
+    }
+
+    /// Time of the oldest access added on top of `base`, if accesses are the only new events.
+    /// Synchronization markers are not compared, as they change whenever the book is synchronized.
+    pub fn only_new_accesses(&self, base: &Book) -> Option<DateTime<Utc>> {
+        let known: HashSet<&EventFrame> = base.events.iter().collect();
+        let mut oldest: Option<DateTime<Utc>> = None;
+        let mut without = self.clone();
+        without.events.retain(|ef| {
+            let new_access = match ef.event {
+                Event::Access(_) => !known.contains(ef),
+                _ => false,
+            };
+            if new_access {
+                oldest = Some(oldest.map_or(ef.time, |t| t.min(ef.time)));
+            }
+            !new_access
+        });
+        without.sync_markers = base.sync_markers.clone();
+        if without == *base {
+            oldest
+        } else {
+            None
+        }
+    }
+
+    /// Full history of the book, oldest first
+    pub fn events(&self) -> impl Iterator<Item = &EventFrame> {
+        self.events.iter()
+    }
+
+    /// Number of events in the history
+    pub fn event_count(&self) -> usize {
+        self.events.len()
+    }
+
+    /// Events added on top of `base`.
+    /// Returns `None` if `base` history is not a strict prefix of this one,
+    /// or if the shared key, recovery key or metadata has changed, as deltas don't carry them.
+    pub fn delta_since(&self, base: &Self) -> Option<BookDelta> {
+        if self.has_same_origin(base)
+            && self.created == base.created
+            && self.shared == base.shared
+            && self.recovery == base.recovery
+            && self.metadata == base.metadata
+            && self.pruned == base.pruned
+            && self.redacted == base.redacted
+            && self.events.len() > base.events.len()
+            && self.events.starts_with(&base.events)
+        {
+            Some(BookDelta {
+                created: self.created,
+                origin: self.origin,
+                sync_markers: self.sync_markers.clone(),
+                base: base.events.len(),
+                base_hash: Some(base.events_hash()),
+                events: self.events[base.events.len()..].to_vec(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Append events from a delta created with `delta_since`
+    pub fn apply_delta(&mut self, delta: BookDelta) -> Result<(), VersionMergeError> {
+        if self.created != delta.created || self.origin != delta.origin {
+            Err(VersionMergeError::DifferentOrigins)
+        } else if self.events.len() != delta.base
+            || delta.base_hash.is_some_and(|hash| hash != self.events_hash())
+        {
+            Err(VersionMergeError::DeltaBaseMismatch)
+        } else {
+            self.events.extend(delta.events);
+            self.merge_sync_markers(&delta.sync_markers);
+            self.dirty = true;
+            Ok(())
+        }
+    }
+
+    /// Identifies the history, so that a delta is only applied on the one it was created on
+    fn events_hash(&self) -> [u8; sha256::DIGESTBYTES] {
+        sha256::hash(&serde_json::to_vec(&self.events).unwrap()).0
+    }
+
+    /// Rename items that were created separately with the same name, e.g. on two devices:
+    /// the earliest created one keeps the name, and the others are renamed to conflict copies,
+    /// e.g. `name.conflict` and `name.conflict2`, which are valid item names.
+    /// The renames are stamped with the time of the latest event and no device,
+    /// so that both sides of a merge record equal events. Names are compared exactly,
+    /// as the case-insensitivity setting is per device and may differ between the sides.
+    fn rename_conflicts(&mut self) {
+        let time = match self.events.last() {
+            Some(ef) => ef.time,
+            None => return,
+        };
+        let created: HashMap<ItemId, DateTime<Utc>> =
+            self.events.iter().filter_map(|ef| Some((ef.creates_id()?, ef.time))).collect();
+        let mut items = self.id_items();
+        items.sort_by_key(|(id, _)| (created.get(id).copied(), *id));
+        let mut taken: Vec<String> = items.iter().map(|(_, item)| item.name.clone()).collect();
+        let mut kept: Vec<String> = Vec::new();
+        for (id, mut item) in items {
+            if kept.contains(&item.name) {
+                let name = (1..)
+                    .map(|n| match n {
+                        1 => format!("{}.conflict", item.name),
+                        n => format!("{}.conflict{}", item.name, n),
+                    })
+                    .find(|candidate| !taken.contains(candidate))
+                    .unwrap();
+                item.name = name.clone();
+                taken.push(name);
+                self.events.push(EventFrame {
+                    time,
+                    event: Event::Update(id, item.clone()),
+                    device: None,
+                    seq: None,
+                });
+            }
+            kept.push(item.name);
+        }
+    }
+
+    /// Remove unnecessary events, such as multiple removes.
+    /// History of removed items is only removed by `prune`.
     fn clean(&mut self) {
         // Multiple removes
         let mut removed: HashSet<ItemId> = HashSet::new();
         for ef in self.events.clone() {
             if let Some(id) = ef.removes_id() {
                 if removed.contains(&id) {
-                    self.events.remove_item(&ef);
+                    if let Some(i) = self.events.iter().position(|e| *e == ef) {
+                        self.events.remove(i);
+                    }
                 } else {
                     removed.insert(id);
                 }
@@ -295,15 +1046,59 @@ impl Book {
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub enum VersionMergeError {
     DifferentOrigins,
+    /// Delta was created on top of a different history
+    DeltaBaseMismatch,
+}
+
+/// Events appended to a book after a known point in history
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct BookDelta {
+    /// Creation time of the book, to detect unrelated books
+    created: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    origin: Option<OriginId>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    sync_markers: BTreeMap<DeviceId, Acknowledged>,
+    /// Number of events the delta applies on
+    base: usize,
+    /// Hash of the events the delta applies on, as a pruned or rewritten history
+    /// may have the same number of events. Missing from deltas created by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    base_hash: Option<[u8; sha256::DIGESTBYTES]>,
+    events: Vec<EventFrame>,
 }
 
-/// An event and it's context in the book
+/// An event and it's context in the book.
+/// The serialized form is stable, and in JSON it looks like
+/// `{"time": "2019-01-01T00:00:00Z", "event": {"Remove": "<uuid>"}, "device": "<uuid>", "seq": 1}`.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct EventFrame {
     time: DateTime<Utc>,
     event: Event,
+    /// Missing from events created by older versions or without a config
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    device: Option<DeviceId>,
+    /// Number of the event among the events created by `device`, starting from 1.
+    /// Unlike the time, it's not affected by the clock of the device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+}
+/// Consistent with `Eq`, as the serialized form of equal events is equal
+impl std::hash::Hash for EventFrame {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        serde_json::to_vec(self).unwrap().hash(state);
+    }
 }
 impl EventFrame {
+    pub fn time(&self) -> DateTime<Utc> {
+        self.time
+    }
+    pub fn device(&self) -> Option<DeviceId> {
+        self.device
+    }
+    pub fn event(&self) -> &Event {
+        &self.event
+    }
     fn creates_id(&self) -> Option<ItemId> {
         self.event.creates_id()
     }
@@ -313,7 +1108,7 @@ impl EventFrame {
 }
 impl PartialOrd for EventFrame {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(&other))
+        Some(self.cmp(other))
     }
 }
 impl Ord for EventFrame {
@@ -322,14 +1117,27 @@ impl Ord for EventFrame {
     }
 }
 
-/// Actual event that occurred
+/// Actual event that occurred.
+/// New variants are only ever added to the end, so that old vaults keep decoding.
+/// Note that updates contain the full item, including the plaintext password.
+/// Updates are most of the events, so boxing the item wouldn't save memory.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-enum Event {
+pub enum Event {
     Create(ItemId),
+    /// Full new value of the item
     Update(ItemId, Item),
     Remove(ItemId),
+    /// Secret was revealed, e.g. shown or copied
+    Access(ItemId),
 }
 impl Event {
+    /// Item the event applies to
+    pub fn item_id(&self) -> ItemId {
+        match self {
+            Event::Create(id) | Event::Update(id, _) | Event::Remove(id) | Event::Access(id) => *id,
+        }
+    }
     fn creates_id(&self) -> Option<ItemId> {
         match self {
             Event::Create(id) => Some(*id),
@@ -348,29 +1156,371 @@ impl Event {
 pub struct ItemMetadata {
     pub created: DateTime<Utc>,
     pub changed: DateTime<Utc>,
+    /// Device that made the last change, if known
+    pub changed_by: Option<DeviceId>,
+    /// Last time the secret was revealed
+    pub last_used: Option<DateTime<Utc>>,
+    /// Number of times the secret was revealed
+    pub use_count: usize,
+}
+impl ItemMetadata {
+    /// Last use, or creation if never used
+    pub fn last_activity(&self) -> DateTime<Utc> {
+        self.last_used.unwrap_or(self.created)
+    }
 }
 
+/// Difference of a single item between two books
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct Item {
-    /// Although all fields have ids, names must still be unique
-    /// Name schema, by-convention:
-    ///     * Folders can be emulated with "path/to/filename"
-    ///     * Folder "vpass/" contains internal vpass items
-    pub name: String,
-    /// Password itself, if set
-    pub password: Option<Password>,
-    /// One word tags
-    pub tags: HashSet<String>,
-    /// Free-form text notes
-    pub notes: Vec<String>,
+pub enum ItemChange {
+    Added(Item),
+    Removed(Item),
+    Modified(ItemDiff),
 }
-impl Item {
-    pub fn new(name: &str) -> Item {
-        Item {
-            name: name.to_owned(),
+impl ItemChange {
+    /// Current name of the item, or the last name of a removed item
+    pub fn name(&self) -> &str {
+        match self {
+            ItemChange::Added(item) | ItemChange::Removed(item) => &item.name,
+            ItemChange::Modified(diff) => &diff.name,
+        }
+    }
+}
+
+/// Changed fields of an item
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ItemDiff {
+    /// New name of the item
+    pub name: String,
+    pub changes: Vec<FieldChange>,
+}
+impl ItemDiff {
+    pub fn between(old: &Item, new: &Item) -> Self {
+        let mut changes = Vec::new();
+        if old.name != new.name {
+            changes.push(FieldChange::Name(old.name.clone()));
+        }
+        if old.password != new.password || old.derived != new.derived || old.protected != new.protected {
+            changes.push(FieldChange::Password);
+        }
+        let mut added: Vec<_> = new.tags.difference(&old.tags).cloned().collect();
+        let mut removed: Vec<_> = old.tags.difference(&new.tags).cloned().collect();
+        added.sort();
+        removed.sort();
+        changes.extend(added.into_iter().map(FieldChange::TagAdded));
+        changes.extend(removed.into_iter().map(FieldChange::TagRemoved));
+        if old.notes != new.notes {
+            changes.push(FieldChange::Notes);
+        }
+        if old.archived != new.archived {
+            changes.push(FieldChange::Archived(new.archived));
+        }
+        if old.ssh_key != new.ssh_key {
+            changes.push(FieldChange::SshKey);
+        }
+        if old.kind != new.kind {
+            changes.push(FieldChange::Kind(new.kind));
+        }
+        if old.data != new.data {
+            changes.push(FieldChange::Data);
+        }
+        if old.card != new.card {
+            changes.push(FieldChange::Card);
+        }
+        if old.wifi != new.wifi {
+            changes.push(FieldChange::Wifi);
+        }
+        if old.label != new.label {
+            changes.push(FieldChange::Label);
+        }
+        ItemDiff {
+            name: new.name.clone(),
+            changes,
+        }
+    }
+}
+
+/// A single changed field.
+/// Secret values are not included, so these can be displayed freely.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum FieldChange {
+    /// Renamed, contains the old name
+    Name(String),
+    Password,
+    TagAdded(String),
+    TagRemoved(String),
+    Notes,
+    /// Archived or unarchived, contains the new state
+    Archived(bool),
+    SshKey,
+    /// Kind changed, contains the new kind
+    Kind(ItemKind),
+    Data,
+    Card,
+    Wifi,
+    Label,
+}
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FieldChange::Name(old) => write!(f, "renamed from {}", old),
+            FieldChange::Password => write!(f, "password"),
+            FieldChange::TagAdded(tag) => write!(f, "+tag {}", tag),
+            FieldChange::TagRemoved(tag) => write!(f, "-tag {}", tag),
+            FieldChange::Notes => write!(f, "notes"),
+            FieldChange::Archived(true) => write!(f, "archived"),
+            FieldChange::Archived(false) => write!(f, "unarchived"),
+            FieldChange::SshKey => write!(f, "ssh key"),
+            FieldChange::Kind(kind) => write!(f, "kind {}", kind),
+            FieldChange::Data => write!(f, "data"),
+            FieldChange::Card => write!(f, "card"),
+            FieldChange::Wifi => write!(f, "wifi"),
+            FieldChange::Label => write!(f, "label"),
+        }
+    }
+}
+
+/// Text note of an item, addressed by an id that stays the same when other notes are removed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Note {
+    /// Unique within the item, starting from 1
+    pub id: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub text: String,
+}
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.title {
+            Some(ref title) => write!(f, "[{}] {}: {}", self.id, title, self.text),
+            None => write!(f, "[{}] {}", self.id, self.text),
+        }
+    }
+}
+
+/// What an item stores, deciding which fields are asked for and shown, and what `copy` copies
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+#[derive(Default)]
+pub enum ItemKind {
+    /// Password for a site or a service
+    #[default]
+    Login,
+    /// Markdown document in `data`, like recovery instructions, or just notes
+    Note,
+    /// Private key in `ssh_key`
+    SshKey,
+    /// Exported private key in `data`
+    GpgKey,
+    /// Token used instead of a password, stored as the password
+    ApiToken,
+    /// Certificate and its private key in `data`
+    Certificate,
+    /// Any other file in `data`
+    Blob,
+    /// Payment card in `card`
+    Card,
+    /// Wi-Fi network in `wifi`, with the passphrase as the password
+    Wifi,
+}
+impl ItemKind {
+    pub const ALL: &'static [ItemKind] = &[
+        ItemKind::Login,
+        ItemKind::Note,
+        ItemKind::SshKey,
+        ItemKind::GpgKey,
+        ItemKind::ApiToken,
+        ItemKind::Certificate,
+        ItemKind::Blob,
+        ItemKind::Card,
+        ItemKind::Wifi,
+    ];
+
+    /// The secret of this kind is the password
+    pub fn has_password(self) -> bool {
+        matches!(self, ItemKind::Login | ItemKind::ApiToken | ItemKind::Wifi)
+    }
+
+    /// The secret of this kind is a file, stored in `data`
+    pub fn has_data(self) -> bool {
+        matches!(self, ItemKind::GpgKey | ItemKind::Certificate | ItemKind::Blob)
+    }
+
+    fn is_login(&self) -> bool {
+        *self == ItemKind::Login
+    }
+}
+impl fmt::Display for ItemKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ItemKind::Login => "login",
+            ItemKind::Note => "note",
+            ItemKind::SshKey => "ssh-key",
+            ItemKind::GpgKey => "gpg-key",
+            ItemKind::ApiToken => "api-token",
+            ItemKind::Certificate => "certificate",
+            ItemKind::Blob => "blob",
+            ItemKind::Card => "card",
+            ItemKind::Wifi => "wifi",
+        };
+        write!(f, "{}", name)
+    }
+}
+impl FromStr for ItemKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ItemKind::ALL.iter().cloned().find(|k| k.to_string() == s).ok_or_else(|| {
+            let names: Vec<String> = ItemKind::ALL.iter().map(ToString::to_string).collect();
+            format!("Unknown kind {}, expected one of {}", s, names.join(", "))
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Item {
+    /// Although all fields have ids, names must still be unique
+    /// Name schema, by-convention:
+    ///     * Folders can be emulated with "path/to/filename"
+    pub name: String,
+    /// Password itself, if set
+    pub password: Option<Password>,
+    /// One word tags
+    #[serde(serialize_with = "serialize_sorted")]
+    pub tags: HashSet<String>,
+    /// Free-form text notes
+    #[serde(deserialize_with = "deserialize_notes")]
+    pub notes: Vec<Note>,
+    /// Id given to the next note, so that ids of removed notes are never reused.
+    /// Zero in items created by older versions.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub next_note_id: u32,
+    /// Rule for a derived password, used instead of a stored one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub derived: Option<DerivationRule>,
+    /// Password encrypted with a separate passphrase, used instead of a stored one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protected: Option<EncryptedVault>,
+    /// Hidden from listings unless asked for, but otherwise kept as is
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool,
+    /// Password was rotated, but not yet changed on the site
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rotation_pending: bool,
+    /// Private SSH key in the OpenSSH format
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssh_key: Option<Password>,
+    #[serde(default, skip_serializing_if = "ItemKind::is_login")]
+    pub kind: ItemKind,
+    /// Contents of a file, like an exported GPG key or a certificate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Blob>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub card: Option<Card>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wifi: Option<Wifi>,
+    /// Short display hint shown before the name in listings, like an emoji
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+}
+impl Item {
+    pub fn new(name: &str) -> Item {
+        Item {
+            name: name.to_owned(),
             password: None,
             tags: HashSet::new(),
             notes: Vec::new(),
+            next_note_id: 0,
+            derived: None,
+            protected: None,
+            archived: false,
+            rotation_pending: false,
+            ssh_key: None,
+            kind: ItemKind::Login,
+            data: None,
+            card: None,
+            wifi: None,
+            label: None,
+        }
+    }
+
+    /// Add a note with the next free id, and return the id
+    pub fn add_note(&mut self, title: Option<String>, text: &str) -> u32 {
+        let id = self.notes.iter().map(|n| n.id + 1).fold(self.next_note_id.max(1), u32::max);
+        self.next_note_id = id + 1;
+        self.notes.push(Note {
+            id,
+            title,
+            text: text.to_owned(),
+        });
+        id
+    }
+
+    pub fn note(&self, id: u32) -> VResult<&Note> {
+        self.notes.iter().find(|n| n.id == id).ok_or(Error::NoSuchNote(id))
+    }
+
+    /// Fields written as `key: value` lines in notes, like the username and url of imported entries.
+    /// Keys consist of letters, digits, spaces, `_` and `-`, and the first value of each key is used.
+    pub fn note_fields(&self) -> Vec<(String, String)> {
+        let mut result: Vec<(String, String)> = Vec::new();
+        for line in self.notes.iter().flat_map(|n| n.text.lines()) {
+            let mut parts = line.splitn(2, ": ");
+            if let (Some(key), Some(value)) = (parts.next(), parts.next()) {
+                let valid = !key.trim().is_empty()
+                    && key.chars().all(|c| c.is_alphanumeric() || c == ' ' || c == '_' || c == '-');
+                if valid && !result.iter().any(|(k, _)| k == key) {
+                    result.push((key.to_owned(), value.to_owned()));
+                }
+            }
+        }
+        result
+    }
+
+    pub fn note_mut(&mut self, id: u32) -> VResult<&mut Note> {
+        self.notes.iter_mut().find(|n| n.id == id).ok_or(Error::NoSuchNote(id))
+    }
+
+    pub fn remove_note(&mut self, id: u32) -> VResult<Note> {
+        let index = self.notes.iter().position(|n| n.id == id).ok_or(Error::NoSuchNote(id))?;
+        Ok(self.notes.remove(index))
+    }
+
+    /// Encrypt the stored password with a separate passphrase,
+    /// so that revealing it requires the passphrase in addition to the vault password
+    pub fn protect(&mut self, passphrase: &str) {
+        if let Some(password) = self.password.take() {
+            self.protected = Some(Vault::new(password).encrypt(passphrase));
+        }
+    }
+
+    /// Decrypt a protected password, without changing the item
+    pub fn unlock(&self, passphrase: &str) -> VResult<Option<Password>> {
+        match self.protected {
+            Some(ref encrypted) => Ok(Some(
+                encrypted
+                    .clone()
+                    .decrypt::<Password>(passphrase)
+                    .ok_or(Error::WrongPassphrase)?
+                    .content,
+            )),
+            None => Ok(self.password.clone()),
+        }
+    }
+
+    /// Store the protected password normally again
+    pub fn unprotect(&mut self, passphrase: &str) -> VResult<()> {
+        self.password = self.unlock(passphrase)?;
+        self.protected = None;
+        Ok(())
+    }
+
+    /// Stored password, or the derived one computed from the master password.
+    /// Protected passwords must be unlocked separately.
+    pub fn password_for(&self, master_password: &str) -> Option<Password> {
+        match self.derived {
+            Some(ref rule) => Some(rule.derive(master_password)),
+            None => self.password.clone(),
         }
     }
 }
@@ -396,10 +1546,51 @@ impl fmt::Debug for Password {
     }
 }
 
+/// Contents of a secret file, stored as base64 text.
+/// A separate struct is used to hide the contents from debug output.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Blob(Vec<u8>);
+impl Blob {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Contents as text, if they are valid UTF-8
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.0).ok()
+    }
+}
+impl fmt::Debug for Blob {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Blob({} bytes)", self.0.len())
+    }
+}
+impl Serialize for Blob {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(&self.0))
+    }
+}
+impl<'de> Deserialize<'de> for Blob {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        base64::decode(&s).map(Blob).map_err(serde::de::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Book, Item, ItemId, Password, VersionMergeError};
+    use super::super::derive::DerivationRule;
+    use super::{
+        Blob, Book, DeviceId, Event, FieldChange, Item, ItemChange, ItemDiff, ItemId, ItemKind, Password,
+        VersionMergeError,
+    };
+    use crate::cli::error::Error;
     use maplit::hashset;
+    use matches::matches;
     use std::collections::HashSet;
 
     #[test]
@@ -429,6 +1620,47 @@ mod tests {
         assert!(items[2].0.password.is_none());
     }
 
+    #[test]
+    fn book_uninitialized_item() {
+        let mut book = Book::new();
+        book.add(Item::new("Test 1")).unwrap();
+        let id = book.next_id();
+        book.push_event(chrono::Utc::now(), Event::Create(id));
+        assert_eq!(book.items().len(), 1);
+        assert_eq!(book.items_metadata().len(), 1);
+    }
+
+    #[test]
+    fn item_note_fields() {
+        let mut item = Item::new("db");
+        item.add_note(None, "username: admin\nurl: https://db.example.com\nnot a field\nbad.key: x");
+        item.add_note(None, "username: other");
+        assert_eq!(
+            item.note_fields(),
+            vec![
+                ("username".to_owned(), "admin".to_owned()),
+                ("url".to_owned(), "https://db.example.com".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn item_kinds() {
+        // Logins are stored as before
+        let json = serde_json::to_string(&Item::new("site")).unwrap();
+        assert!(!json.contains("kind"));
+
+        let mut item = Item::new("cert");
+        item.kind = "certificate".parse().unwrap();
+        item.data = Some(Blob::new(vec![0, 1, 2]));
+        let json = serde_json::to_string(&item).unwrap();
+        assert!(json.contains(r#""kind":"certificate""#));
+        assert!(json.contains(r#""data":"AAEC""#));
+        assert_eq!(serde_json::from_str::<Item>(&json).unwrap(), item);
+        assert_eq!(format!("{:?}", item.data.unwrap()), "Blob(3 bytes)");
+        assert!("secret".parse::<ItemKind>().is_err());
+    }
+
     #[test]
     fn book_differ_index() {
         let mut book1 = Book::new();
@@ -493,6 +1725,465 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn book_only_new_accesses() {
+        let mut base = Book::new();
+        base.add(Item::new("Site")).unwrap();
+        let mut book = base.clone();
+        assert_eq!(book.only_new_accesses(&base), None);
+
+        book.record_access("Site").unwrap();
+        let accessed = book.events.last().unwrap().time;
+        assert_eq!(book.only_new_accesses(&base), Some(accessed));
+        book.mark_synced(DeviceId::generate());
+        assert_eq!(book.only_new_accesses(&base), Some(accessed));
+
+        book.add(Item::new("Other")).unwrap();
+        assert_eq!(book.only_new_accesses(&base), None);
+    }
+
+    #[test]
+    fn book_merge_same_name() -> Result<(), VersionMergeError> {
+        let mut book1 = Book::new();
+        let mut book2 = book1.clone();
+        book1.add(Item::new("Site")).unwrap();
+        book2.add(Item::new("Site.conflict")).unwrap();
+        book2.add(Item::new("Site")).unwrap();
+
+        let merged_12 = book1.clone().merge_versions(&book2)?;
+        let merged_21 = book2.clone().merge_versions(&book1)?;
+        assert_eq!(merged_12.events, merged_21.events);
+        assert_eq!(merged_12.clone().merge_versions(&book2)?, merged_12);
+
+        // The item created first keeps its name
+        let mut names = merged_12.item_names();
+        names.sort();
+        assert_eq!(names, vec!["Site", "Site.conflict", "Site.conflict2"]);
+        assert_eq!(merged_12.get_id_by_name("Site").unwrap(), book1.get_id_by_name("Site").unwrap());
+        Ok(())
+    }
+
+    #[test]
+    fn book_merge_same_name_case_insensitive() -> Result<(), VersionMergeError> {
+        let mut book1 = Book::new();
+        let mut book2 = book1.clone();
+        book1.set_case_insensitive(true);
+        book1.add(Item::new("Site")).unwrap();
+        book2.add(Item::new("site")).unwrap();
+
+        // Both devices record the same events regardless of their setting
+        let merged_12 = book1.clone().merge_versions(&book2)?;
+        let merged_21 = book2.clone().merge_versions(&book1)?;
+        assert_eq!(merged_12.events, merged_21.events);
+        Ok(())
+    }
+
+    #[test]
+    fn book_origin() {
+        let mut book1 = Book::new();
+        book1.add(Item::new("Test 1")).unwrap();
+        let book2 = book1.clone();
+        assert!(book1.has_same_origin(&book2));
+
+        let mut book3 = Book::new();
+        book3.add(Item::new("Test 1")).unwrap();
+        book3.add(Item::new("Test 2")).unwrap();
+        assert!(!book1.has_same_origin(&book3));
+        assert_eq!(book1.clone().merge_versions(&book3), Err(VersionMergeError::DifferentOrigins));
+
+        let adopted = book1.adopt_into(&book3);
+        assert!(adopted.has_same_origin(&book3));
+        assert_eq!(adopted.item_count(), 2);
+        assert!(book3.merge_versions(&adopted).is_ok());
+    }
+
+    #[test]
+    fn book_origin_legacy() {
+        let book = Book::new();
+        let mut j = serde_json::to_value(&book).unwrap();
+        j.as_object_mut().unwrap().remove("origin");
+        let legacy1: Book = serde_json::from_value(j.clone()).unwrap();
+        let legacy2: Book = serde_json::from_value(j).unwrap();
+        assert!(legacy1.has_same_origin(&legacy2));
+        assert!(!legacy1.has_same_origin(&book));
+    }
+
+    #[test]
+    fn book_derived_password() {
+        let mut book = Book::new();
+        let mut item = Item::new("site");
+        item.derived = Some(DerivationRule::new("site", 20).unwrap());
+        book.add(item).unwrap();
+        book.add(Item::new("other")).unwrap();
+
+        let derived = book.get_item_by_name("site").unwrap().password_for("master").unwrap();
+        assert_eq!(book.store_derived_passwords("master").unwrap(), 1);
+        let stored = book.get_item_by_name("site").unwrap();
+        assert!(stored.derived.is_none());
+        assert_eq!(stored.password, Some(derived.clone()));
+        assert_eq!(stored.password_for("changed"), Some(derived));
+        assert_eq!(book.store_derived_passwords("master").unwrap(), 0);
+    }
+
+    #[test]
+    fn book_protected_password() {
+        let mut item = Item::new("bank");
+        item.password = Some(Password::new("secret"));
+        item.protect("passphrase");
+        assert!(item.password.is_none());
+        assert!(item.password_for("master").is_none());
+        assert!(matches!(item.unlock("wrong"), Err(Error::WrongPassphrase)));
+        assert_eq!(item.unlock("passphrase").unwrap(), Some(Password::new("secret")));
+
+        let mut book = Book::new();
+        book.add(item.clone()).unwrap();
+        assert_eq!(book.get_item_by_name("bank").unwrap(), item);
+
+        item.unprotect("passphrase").unwrap();
+        assert!(item.protected.is_none());
+        assert_eq!(item.password, Some(Password::new("secret")));
+    }
+
+    #[test]
+    fn book_prune() -> Result<(), VersionMergeError> {
+        let (device1, device2) = (DeviceId::generate(), DeviceId::generate());
+        let mut book1 = Book::new();
+        book1.set_device(device1);
+        book1.add(Item::new("Test 1")).unwrap();
+        book1.add(Item::new("Test 2")).unwrap();
+        book1.mark_synced(device1);
+        let mut book2 = book1.clone();
+        book2.set_device(device2);
+        book2.mark_synced(device2);
+
+        book1.remove("Test 1").unwrap();
+        book1.mark_synced(device1);
+        // Device 2 hasn't seen the removal yet
+        let mut merged = book1.clone().merge_versions(&book2)?;
+        assert_eq!(merged.prune(), 0);
+        assert_eq!(merged.event_count(), 5);
+
+        book2 = book2.merge_versions(&merged)?;
+        book2.mark_synced(device2);
+        assert_eq!(book2.prune(), 1);
+        assert_eq!(book2.event_count(), 2);
+        assert_eq!(book2.item_names(), vec!["Test 2".to_owned()]);
+
+        // Merging with unpruned history doesn't restore the events
+        let mut merged = book1.merge_versions(&book2)?;
+        assert_eq!(merged.item_names(), vec!["Test 2".to_owned()]);
+        assert_eq!(merged.event_count(), 2);
+        assert_eq!(merged.prune(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn book_prune_skewed_clock() -> Result<(), VersionMergeError> {
+        let (device1, device2) = (DeviceId::generate(), DeviceId::generate());
+        let mut book1 = Book::new();
+        book1.set_device(device1);
+        book1.add(Item::new("Test 1")).unwrap();
+        book1.mark_synced(device1);
+        let mut book2 = book1.clone();
+        book2.set_device(device2);
+        book2.mark_synced(device2);
+
+        // The clock of device 2 is behind, so the removal is older than both markers
+        book2.remove("Test 1").unwrap();
+        book2.events.last_mut().unwrap().time = book2.created - chrono::Duration::hours(1);
+        let mut merged = book1.clone().merge_versions(&book2)?;
+        assert_eq!(merged.prune(), 0);
+        merged.mark_synced(device2);
+
+        // Only device 2 has acknowledged the removal
+        assert_eq!(merged.prune(), 0);
+        book1 = book1.merge_versions(&merged)?;
+        book1.mark_synced(device1);
+        assert_eq!(book1.prune(), 1);
+        assert!(book1.item_names().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn book_prune_unsynced_device() -> Result<(), VersionMergeError> {
+        let device1 = DeviceId::generate();
+        let mut book1 = Book::new();
+        book1.set_device(device1);
+        book1.add(Item::new("Test 1")).unwrap();
+        // Copied, but never synchronized
+        let mut book2 = book1.clone();
+        book2.set_device(DeviceId::generate());
+        book2.modify_by_name("Test 1", |it| it.tags.insert("tag".to_owned())).unwrap();
+
+        book1.remove("Test 1").unwrap();
+        book1.mark_synced(device1);
+        assert_eq!(book1.prune(), 1);
+        let merged = book2.merge_versions(&book1)?;
+        assert!(merged.item_names().is_empty());
+        assert_eq!(merged.event_count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn book_delta() -> Result<(), VersionMergeError> {
+        let mut book1 = Book::new();
+        book1.add(Item::new("Test 1")).unwrap();
+
+        let mut book2 = book1.clone();
+        assert!(book2.delta_since(&book1).is_none());
+        book2.add(Item::new("Test 2")).unwrap();
+        book2.remove("Test 1").unwrap();
+
+        let delta = book2.delta_since(&book1).unwrap();
+        assert!(book1.delta_since(&book2).is_none());
+
+        let mut book3 = book1.clone();
+        book3.apply_delta(delta.clone())?;
+        assert_eq!(book3, book2);
+        assert_eq!(book3.apply_delta(delta.clone()), Err(VersionMergeError::DeltaBaseMismatch));
+
+        // Same number of events, but a different history
+        let mut book4 = Book::new();
+        book4.origin = book1.origin;
+        book4.created = book1.created;
+        book4.add(Item::new("Other")).unwrap();
+        assert_eq!(book4.apply_delta(delta), Err(VersionMergeError::DeltaBaseMismatch));
+        Ok(())
+    }
+
+    #[test]
+    fn book_merge_into() {
+        let mut book = Book::new();
+        let mut item1 = Item::new("Test 1");
+        item1.password = Some(Password::new("Pass1"));
+        item1.tags = hashset!["a".to_owned(), "b".to_owned()];
+        item1.add_note(None, "Shared");
+        item1.add_note(None, "Note 1");
+        book.add(item1).unwrap();
+        let mut item2 = Item::new("Test 2");
+        item2.tags = hashset!["b".to_owned(), "c".to_owned()];
+        item2.add_note(None, "Shared");
+        book.add(item2).unwrap();
+
+        book.merge_into("Test 1", "Test 2").unwrap();
+        assert!(!book.has_item("Test 1"));
+        let merged = book.get_item_by_name("Test 2").unwrap();
+        assert_eq!(merged.password.map(|p| p.plaintext()), Some("Pass1".to_owned()));
+        assert_eq!(merged.tags, hashset!["a".to_owned(), "b".to_owned(), "c".to_owned()]);
+        let notes: Vec<(u32, &str)> = merged.notes.iter().map(|n| (n.id, n.text.as_str())).collect();
+        assert_eq!(notes, vec![(1, "Shared"), (2, "Note 1")]);
+    }
+
+    #[test]
+    fn book_metadata_merge() -> Result<(), VersionMergeError> {
+        let mut book1 = Book::new();
+        book1.set_metadata("a", serde_json::json!(1));
+        book1.set_metadata("b", serde_json::json!(2));
+        let mut book2 = book1.clone();
+        assert!(book2.remove_metadata("a"));
+        assert!(!book2.remove_metadata("a"));
+        book1.set_metadata("b", serde_json::json!(3));
+
+        let merged = book1.clone().merge_versions(&book2)?;
+        assert_eq!(merged.metadata("a"), None);
+        assert_eq!(merged.metadata("b"), Some(&serde_json::json!(3)));
+        assert_eq!(merged, book2.merge_versions(&book1)?);
+        Ok(())
+    }
+
+    #[test]
+    fn book_annotations() {
+        let mut book = Book::new();
+        assert_eq!(book.description(), None);
+        book.set_description(Some("Work credentials"));
+        book.annotate("owner", Some("me"));
+        book.annotate("team", Some("ops"));
+        book.annotate("team", None);
+        assert_eq!(book.description(), Some("Work credentials"));
+        let annotations: Vec<_> = book.annotations().into_iter().collect();
+        assert_eq!(annotations, vec![("owner".to_owned(), "me".to_owned())]);
+    }
+
+    #[test]
+    fn item_note_ids() {
+        let mut item = Item::new("Test");
+        assert_eq!(item.add_note(None, "First"), 1);
+        assert_eq!(item.add_note(Some("Title".to_owned()), "Second"), 2);
+        item.remove_note(1).unwrap();
+        assert_eq!(item.note(2).unwrap().text, "Second");
+        assert_eq!(item.add_note(None, "Third"), 3);
+        assert!(matches!(item.remove_note(1), Err(Error::NoSuchNote(1))));
+
+        // Removing the latest note doesn't free its id
+        item.remove_note(3).unwrap();
+        assert_eq!(item.add_note(None, "Fourth"), 4);
+
+        // Notes stored as plain strings get ids by position
+        let old = r#"{"name":"Test","password":null,"tags":[],"notes":["a","b"]}"#;
+        let item: Item = serde_json::from_str(old).unwrap();
+        assert_eq!(item.note(2).unwrap().text, "b");
+        let json = serde_json::to_string(&item).unwrap();
+        assert_eq!(serde_json::from_str::<Item>(&json).unwrap(), item);
+    }
+
+    #[test]
+    fn book_case_insensitive_names() {
+        let mut book = Book::new();
+        book.add(Item::new("GitHub")).unwrap();
+        assert!(!book.has_item("github"));
+        book.add(Item::new("github")).unwrap();
+        book.remove("github").unwrap();
+
+        book.set_case_insensitive(true);
+        assert!(book.has_item("github"));
+        assert!(book.has_item("GITHUB"));
+        assert!(book.add(Item::new("gitHub")).is_err());
+        // Fullwidth and composed forms are normalized
+        book.add(Item::new("Cafe\u{301}")).unwrap();
+        assert!(book.has_item("caf\u{e9}"));
+        assert!(book.has_item("\u{ff27}itHub"));
+    }
+
+    #[test]
+    fn book_diff() {
+        let mut book1 = Book::new();
+        book1.add(Item::new("Test 1")).unwrap();
+        book1.add(Item::new("Test 2")).unwrap();
+        book1.add(Item::new("Test 3")).unwrap();
+        assert_eq!(book1.diff(&book1), vec![]);
+
+        let mut book2 = book1.clone();
+        book2.remove("Test 1").unwrap();
+        book2
+            .modify_by_name("Test 2", |it| {
+                it.name = "Test 2b".to_owned();
+                it.password = Some(Password::new("Pass"));
+                it.tags.insert("tag".to_owned());
+            })
+            .unwrap();
+        book2.add(Item::new("Test 4")).unwrap();
+
+        assert_eq!(book1.diff(&book2), vec![
+            ItemChange::Removed(Item::new("Test 1")),
+            ItemChange::Modified(ItemDiff {
+                name: "Test 2b".to_owned(),
+                changes: vec![
+                    FieldChange::Name("Test 2".to_owned()),
+                    FieldChange::Password,
+                    FieldChange::TagAdded("tag".to_owned()),
+                ],
+            }),
+            ItemChange::Added(Item::new("Test 4")),
+        ]);
+    }
+
+    #[test]
+    fn book_events() {
+        let mut book = Book::new();
+        let id = book.add(Item::new("Test 1")).unwrap();
+        book.remove("Test 1").unwrap();
+
+        let events: Vec<_> = book.events().map(|ef| ef.event().clone()).collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0], Event::Create(id));
+        assert!(matches!(events[1], Event::Update(_, _)));
+        assert_eq!(events[2], Event::Remove(id));
+        assert!(book.events().all(|ef| ef.event().item_id() == id));
+
+        // The serialized form is part of the public API
+        let j = serde_json::to_value(book.events().last().unwrap()).unwrap();
+        assert_eq!(j["event"]["Remove"], serde_json::to_value(id).unwrap());
+        assert!(j["time"].is_string());
+    }
+
+    #[test]
+    fn book_device() {
+        let device = DeviceId::generate();
+        let mut book = Book::new();
+        book.add(Item::new("Test 1")).unwrap();
+        book.set_device(device);
+        book.record_access("Test 1").unwrap();
+        let devices: Vec<_> = book.events().map(|ef| ef.device()).collect();
+        assert_eq!(devices, vec![None, None, Some(device)]);
+        assert_eq!(book.get_item_and_metadata("Test 1").unwrap().1.changed_by, None);
+
+        book.modify_by_name("Test 1", |it| {
+            it.add_note(None, "Note");
+        })
+            .unwrap();
+        assert_eq!(book.get_item_and_metadata("Test 1").unwrap().1.changed_by, Some(device));
+    }
+
+    #[test]
+    fn book_access_tracking() {
+        let mut book = Book::new();
+        book.add(Item::new("Test 1")).unwrap();
+        let (_, meta) = book.get_item_and_metadata("Test 1").unwrap();
+        assert_eq!((meta.last_used, meta.use_count), (None, 0));
+
+        book.record_access("Test 1").unwrap();
+        book.record_access("Test 1").unwrap();
+        let (_, meta2) = book.get_item_and_metadata("Test 1").unwrap();
+        assert!(meta2.last_used.is_some());
+        assert_eq!(meta2.use_count, 2);
+        assert_eq!(meta2.changed, meta.changed);
+    }
+
+    #[test]
+    fn book_dirty_flag() {
+        let mut book = Book::new();
+        let id = book.add(Item::new("Test 1")).unwrap();
+
+        let mut loaded: Book = serde_json::from_str(&serde_json::to_string(&book).unwrap()).unwrap();
+        assert!(!loaded.is_dirty());
+        assert_eq!(loaded, book);
+
+        // No effective change
+        loaded.modify(id, |_| {}).unwrap();
+        assert!(!loaded.is_dirty());
+        assert_eq!(loaded.event_count(), book.event_count());
+
+        loaded.modify(id, |it| {
+            it.add_note(None, "Note");
+        }).unwrap();
+        assert!(loaded.is_dirty());
+    }
+
+    #[test]
+    fn book_redact_history() -> Result<(), VersionMergeError> {
+        let mut book = Book::new();
+        let mut item = Item::new("Bank");
+        item.password = Some(Password::new("Plaintext1"));
+        book.add(item).unwrap();
+        book.modify_by_name("Bank", |it| it.password = Some(Password::new("Plaintext2"))).unwrap();
+        let unredacted = book.clone();
+
+        book.modify_by_name("Bank", |it| it.protect("passphrase")).unwrap();
+        book.redact_history("Bank").unwrap();
+        let json = serde_json::to_string(&book).unwrap();
+        assert!(!json.contains("Plaintext"));
+        let password = book.get_item_by_name("Bank").unwrap().unlock("passphrase").unwrap();
+        assert_eq!(password.unwrap().plaintext(), "Plaintext2");
+
+        // Merging a copy from before doesn't bring the passwords back
+        for merged in &[book.clone().merge_versions(&unredacted)?, unredacted.merge_versions(&book)?] {
+            assert!(!serde_json::to_string(merged).unwrap().contains("Plaintext"));
+            assert_eq!(merged.event_count(), book.event_count());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn book_update_nonexistent() {
+        let mut book = Book::new();
+        let id = book.add(Item::new("Test")).unwrap();
+        book.remove("Test").unwrap();
+        match book.update(id, Item::new("Test")) {
+            Err(Error::NoSuchItem(name)) => assert_eq!(name, "Test"),
+            other => panic!("Unexpected result {:?}", other),
+        }
+    }
+
     #[test]
     #[should_panic]
     fn book_remove_nonexistent() {
@@ -500,3 +2191,179 @@ mod tests {
         book.remove("Nonexistent").unwrap();
     }
 }
+
+/// Random concurrent histories on two replicas of a book
+#[cfg(test)]
+mod merge_properties {
+    use super::{Book, DeviceId, Item, Password};
+    use chrono::prelude::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+    use std::collections::{BTreeMap, HashSet};
+
+    /// Operation on a book, with the item chosen by index among the existing ones
+    #[derive(Debug, Clone)]
+    enum Op {
+        Add,
+        Modify(usize, u8),
+        Remove(usize),
+        Access(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        prop_oneof![
+            Just(Op::Add),
+            (any::<usize>(), any::<u8>()).prop_map(|(i, p)| Op::Modify(i, p)),
+            any::<usize>().prop_map(Op::Remove),
+            any::<usize>().prop_map(Op::Access),
+        ]
+    }
+
+    /// Expected result of merging all replicas: the latest change of an item wins,
+    /// and removing an item on any replica removes it everywhere
+    struct Model {
+        items: BTreeMap<String, Option<Password>>,
+        removed: HashSet<String>,
+        next_name: usize,
+        /// Operations get strictly increasing event times,
+        /// as the system clock may not advance between them
+        clock: DateTime<Utc>,
+    }
+    impl Model {
+        fn new() -> Self {
+            Self {
+                items: BTreeMap::new(),
+                removed: HashSet::new(),
+                next_name: 0,
+                clock: Utc.timestamp_opt(1_500_000_000, 0).unwrap(),
+            }
+        }
+
+        fn apply(&mut self, book: &mut Book, op: &Op) {
+            let mut names = book.item_names();
+            names.sort();
+            let pick = |i: usize| if names.is_empty() { None } else { Some(names[i % names.len()].clone()) };
+            let before = book.events.len();
+            match *op {
+                Op::Add => {
+                    let name = format!("item{}", self.next_name);
+                    self.next_name += 1;
+                    book.add(Item::new(&name)).unwrap();
+                    self.items.insert(name, None);
+                },
+                Op::Modify(i, p) => {
+                    if let Some(name) = pick(i) {
+                        let password = Password::new(&format!("pass{}", p));
+                        book.modify_by_name(&name, |it| it.password = Some(password.clone())).unwrap();
+                        // Nothing is recorded if the value doesn't change
+                        if book.events.len() > before {
+                            self.items.insert(name, Some(password));
+                        }
+                    }
+                },
+                Op::Remove(i) => {
+                    if let Some(name) = pick(i) {
+                        book.remove(&name).unwrap();
+                        self.removed.insert(name);
+                    }
+                },
+                Op::Access(i) => {
+                    if let Some(name) = pick(i) {
+                        book.record_access(&name).unwrap();
+                    }
+                },
+            }
+            self.clock += chrono::Duration::seconds(1);
+            for ef in &mut book.events[before..] {
+                ef.time = self.clock;
+            }
+        }
+
+        fn expected(&self) -> BTreeMap<String, Option<Password>> {
+            self.items
+                .iter()
+                .filter(|(name, _)| !self.removed.contains(*name))
+                .map(|(name, password)| (name.clone(), password.clone()))
+                .collect()
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn merge_concurrent_edits(base_ops in vec(op(), 0..10), ops in vec((any::<bool>(), op()), 0..30)) {
+            let mut model = Model::new();
+            let mut base = Book::new();
+            for op in &base_ops {
+                model.apply(&mut base, op);
+            }
+            let mut a = base.clone();
+            let mut b = base;
+            for (on_a, op) in &ops {
+                model.apply(if *on_a { &mut a } else { &mut b }, op);
+            }
+
+            let ab = a.clone().merge_versions(&b).unwrap();
+            let ba = b.clone().merge_versions(&a).unwrap();
+
+            // Convergence: both replicas end up with the same history
+            prop_assert_eq!(ab.events().collect::<Vec<_>>(), ba.events().collect::<Vec<_>>());
+
+            // Idempotence: merging again, or merging either side again, changes nothing
+            prop_assert!(ab.clone().merge_versions(&ab).unwrap() == ab);
+            prop_assert!(ab.clone().merge_versions(&a).unwrap() == ab);
+            prop_assert!(ab.clone().merge_versions(&b).unwrap() == ab);
+
+            // Latest change wins, and removed items are not resurrected by changes on the other side
+            let merged: BTreeMap<_, _> = ab.items().into_iter().map(|it| (it.name, it.password)).collect();
+            prop_assert_eq!(merged, model.expected());
+        }
+
+        #[test]
+        fn prune_does_not_resurrect(
+            ops in vec((any::<bool>(), any::<bool>(), op()), 0..40),
+            skewed in any::<bool>(),
+        ) {
+            let devices = [DeviceId::generate(), DeviceId::generate()];
+            let mut model = Model::new();
+            let mut remote = Book::new();
+            let mut local = [remote.clone(), remote.clone()];
+            for (book, device) in local.iter_mut().zip(&devices) {
+                book.set_device(*device);
+            }
+            let sync = |remote: &mut Book, local: &mut Book, device: DeviceId| {
+                let mut merged = remote.clone().merge_versions(local).unwrap();
+                merged.mark_synced(device);
+                merged.prune();
+                *remote = merged.clone();
+                *local = merged;
+                local.set_device(device);
+            };
+
+            for (on_b, sync_after, op) in &ops {
+                let i = *on_b as usize;
+                let before = local[i].events.len();
+                model.apply(&mut local[i], op);
+                // The clock of the second device is behind
+                if skewed && *on_b {
+                    for ef in &mut local[i].events[before..] {
+                        ef.time -= chrono::Duration::hours(1);
+                    }
+                }
+                if *sync_after {
+                    sync(&mut remote, &mut local[i], devices[i]);
+                }
+            }
+            for i in &[0, 1, 0] {
+                sync(&mut remote, &mut local[*i], devices[*i]);
+            }
+
+            // Removed items stay removed, even when their history has been pruned
+            let expected: Vec<String> = model.expected().keys().cloned().collect();
+            for book in &local {
+                let mut names = book.item_names();
+                names.sort();
+                prop_assert_eq!(&names, &expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,170 @@
+//! Payment card details, stored in entries of the card kind and shown with `vpass card show`.
+//! Numbers are checked with the Luhn checksum when entered, to catch typos.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cli::error::{Error, VResult};
+
+/// Card numbers are between 12 and 19 digits long
+const NUMBER_LENGTHS: (usize, usize) = (12, 19);
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Card {
+    /// Digits only
+    pub number: String,
+    /// Formatted as `MM/YY`
+    pub expiry: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cvv: Option<String>,
+    /// Name printed on the card
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holder: Option<String>,
+}
+impl Card {
+    /// Card from user input, with spaces and dashes allowed in the number
+    pub fn new(number: &str, expiry: &str, cvv: Option<&str>, holder: Option<&str>) -> VResult<Self> {
+        let cvv = cvv.map(str::trim).filter(|c| !c.is_empty());
+        if let Some(cvv) = cvv {
+            if !(3..=4).contains(&cvv.len()) || !cvv.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(Error::CardCvvInvalid);
+            }
+        }
+        Ok(Self {
+            number: parse_number(number)?,
+            expiry: parse_expiry(expiry)?,
+            cvv: cvv.map(str::to_owned),
+            holder: holder.map(str::trim).filter(|h| !h.is_empty()).map(str::to_owned),
+        })
+    }
+
+    /// Issuer recognized from the first digits
+    pub fn network(&self) -> Option<&'static str> {
+        let prefix = |len: usize| self.number[..len].parse::<u32>().unwrap();
+        match (prefix(1), prefix(2), prefix(4)) {
+            (4, _, _) => Some("Visa"),
+            (_, 34, _) | (_, 37, _) => Some("American Express"),
+            (_, 51..=55, _) | (_, _, 2221..=2720) => Some("Mastercard"),
+            (_, 65, _) | (_, _, 6011) => Some("Discover"),
+            _ => None,
+        }
+    }
+
+    /// Number in the groups printed on the card, like `4111 1111 1111 1111`
+    pub fn formatted_number(&self) -> String {
+        group(&self.number, self.groups())
+    }
+
+    /// Number with all but the last four digits hidden
+    pub fn masked_number(&self) -> String {
+        let visible = self.number.len() - 4;
+        let masked: String = self
+            .number
+            .chars()
+            .enumerate()
+            .map(|(i, c)| if i < visible { '*' } else { c })
+            .collect();
+        group(&masked, self.groups())
+    }
+
+    fn groups(&self) -> &'static [usize] {
+        match self.network() {
+            Some("American Express") => &[4, 6, 5],
+            _ => &[4, 4, 4, 4, 3],
+        }
+    }
+}
+impl fmt::Debug for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Card({})", self.masked_number())
+    }
+}
+
+/// Split `digits` into groups of the given sizes, with the remainder in the last group
+fn group(digits: &str, sizes: &[usize]) -> String {
+    let mut result = Vec::new();
+    let mut rest = digits;
+    for (i, &size) in sizes.iter().enumerate() {
+        if rest.is_empty() {
+            break;
+        }
+        let size = if i + 1 == sizes.len() { rest.len() } else { size.min(rest.len()) };
+        result.push(&rest[..size]);
+        rest = &rest[size..];
+    }
+    result.join(" ")
+}
+
+/// Whether `digits` are all digits, and the last one is the correct Luhn check digit
+pub fn luhn_valid(digits: &str) -> bool {
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .bytes()
+        .rev()
+        .enumerate()
+        .map(|(i, b)| {
+            // Every second digit from the right is doubled, summing the digits of the result
+            let d = u32::from(b - b'0') * if i % 2 == 1 { 2 } else { 1 };
+            if d > 9 {
+                d - 9
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// Digits of a card number, which may contain spaces or dashes between the groups
+fn parse_number(number: &str) -> VResult<String> {
+    let digits: String = number.chars().filter(|c| *c != ' ' && *c != '-').collect();
+    if !(NUMBER_LENGTHS.0..=NUMBER_LENGTHS.1).contains(&digits.len()) || !luhn_valid(&digits) {
+        return Err(Error::CardNumberInvalid);
+    }
+    Ok(digits)
+}
+
+/// Expiry like `MM/YY`, `MM/YYYY` or `MM-YY`, as `MM/YY`
+fn parse_expiry(expiry: &str) -> VResult<String> {
+    let invalid = || Error::CardExpiryInvalid(expiry.to_owned());
+    let mut parts = expiry.trim().splitn(2, ['/', '-']);
+    let month: u32 = parts.next().and_then(|m| m.parse().ok()).ok_or_else(invalid)?;
+    let year = parts.next().ok_or_else(invalid)?;
+    if !(1..=12).contains(&month) || ![2, 4].contains(&year.len()) || year.parse::<u32>().is_err() {
+        return Err(invalid());
+    }
+    Ok(format!("{:02}/{}", month, &year[year.len() - 2..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{luhn_valid, Card};
+
+    #[test]
+    fn card_numbers() {
+        assert!(luhn_valid("4111111111111111"));
+        assert!(luhn_valid("378282246310005"));
+        assert!(!luhn_valid("4111111111111112"));
+
+        let card = Card::new("4111-1111 1111-1111", "3/2027", Some("123"), Some(" Jane Doe ")).unwrap();
+        assert_eq!(card.number, "4111111111111111");
+        assert_eq!(card.expiry, "03/27");
+        assert_eq!(card.holder.as_ref().unwrap(), "Jane Doe");
+        assert_eq!(card.network(), Some("Visa"));
+        assert_eq!(card.formatted_number(), "4111 1111 1111 1111");
+        assert_eq!(card.masked_number(), "**** **** **** 1111");
+        assert_eq!(format!("{:?}", card), "Card(**** **** **** 1111)");
+
+        let amex = Card::new("378282246310005", "12/30", None, None).unwrap();
+        assert_eq!(amex.formatted_number(), "3782 822463 10005");
+        assert_eq!(amex.network(), Some("American Express"));
+
+        assert!(Card::new("4111111111111112", "03/27", None, None).is_err());
+        assert!(Card::new("4111111111111111", "13/27", None, None).is_err());
+        assert!(Card::new("4111111111111111", "0327", None, None).is_err());
+        assert!(Card::new("4111111111111111", "03/27", Some("12"), None).is_err());
+    }
+}
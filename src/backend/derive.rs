@@ -0,0 +1,89 @@
+//! Stateless derived passwords, computed from a master password, site name and counter.
+//! The same inputs always produce the same password, so derived passwords can be recovered
+//! with `vpass derive` even when the vault file is unavailable.
+//! Items store only the rule, and the vault password is used as the master password.
+
+use rust_sodium::crypto::{auth, hash::sha256, pwhash};
+use serde::{Deserialize, Serialize};
+
+use super::book::Password;
+use crate::cli::error::{Error, VResult};
+
+/// 64 characters, so that each byte maps to a character without bias
+const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Each character uses one byte of the authentication tag
+const MAX_LENGTH: usize = auth::TAGBYTES;
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct DerivationRule {
+    /// Site name used in derivation, stored separately so that renaming the item keeps the password
+    pub site: String,
+    /// Incremented to change the password
+    pub counter: u32,
+    /// Password length, at most 32 characters
+    pub length: usize,
+}
+impl DerivationRule {
+    pub fn new(site: &str, length: usize) -> VResult<Self> {
+        if length == 0 || length > MAX_LENGTH {
+            return Err(Error::DerivedLengthInvalid(length));
+        }
+        Ok(Self {
+            site: site.to_owned(),
+            counter: 1,
+            length,
+        })
+    }
+
+    /// Compute the password. This is slow by design, as it runs the key derivation function.
+    pub fn derive(&self, master_password: &str) -> Password {
+        let mut salt_input = b"vpass-derive:".to_vec();
+        salt_input.extend(self.site.as_bytes());
+        let salt = pwhash::Salt::from_slice(&sha256::hash(&salt_input).0[..pwhash::SALTBYTES]).unwrap();
+
+        let mut key = auth::Key([0; auth::KEYBYTES]);
+        {
+            let auth::Key(ref mut kb) = key;
+            pwhash::derive_key(
+                kb,
+                master_password.as_bytes(),
+                &salt,
+                pwhash::OPSLIMIT_INTERACTIVE,
+                pwhash::MEMLIMIT_INTERACTIVE,
+            )
+            .unwrap();
+        }
+
+        let tag = auth::authenticate(&self.counter.to_be_bytes(), &key);
+        let chars: String = tag
+            .0
+            .iter()
+            .take(self.length)
+            .map(|b| CHARSET[(*b as usize) % CHARSET.len()] as char)
+            .collect();
+        Password::new(&chars)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DerivationRule;
+
+    #[test]
+    fn derive_deterministic() {
+        let rule = DerivationRule::new("example.com", 20).unwrap();
+        let pw = rule.derive("master").plaintext();
+        assert_eq!(pw.len(), 20);
+        assert_eq!(rule.derive("master").plaintext(), pw);
+        assert_ne!(rule.derive("other").plaintext(), pw);
+        assert_ne!(DerivationRule::new("example.org", 20).unwrap().derive("master").plaintext(), pw);
+
+        let mut next = rule.clone();
+        next.counter += 1;
+        assert_ne!(next.derive("master").plaintext(), pw);
+
+        assert!(DerivationRule::new("example.com", 0).is_err());
+        assert!(DerivationRule::new("example.com", 33).is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! Random password generation, e.g. for `vpass generate` and `vpass rotate --generate`.
+//! Characters are picked uniformly with rejection sampling from the system random source.
+//! Pronounceable passwords are made of proquint words like `lusab-babad`, 16 bits each.
+
+use rust_sodium::randombytes::randombytes;
+use serde::{Deserialize, Serialize};
+
+use super::book::Password;
+use crate::cli::error::{Error, VResult};
+
+const LETTERS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const DIGITS: &[u8] = b"0123456789";
+/// Symbols commonly accepted by sites
+const SYMBOLS: &[u8] = b"!#$%&*+-=?@^_~";
+
+const CONSONANTS: &[u8] = b"bdfghjklmnprstvz";
+const VOWELS: &[u8] = b"aiou";
+/// Letters of a pronounceable word, followed by a separator
+const WORD: [&[u8]; 6] = [CONSONANTS, VOWELS, CONSONANTS, VOWELS, CONSONANTS, b"-"];
+
+pub const MAX_LENGTH: usize = 1024;
+
+/// Length and characters of generated passwords, stored by name in the config
+/// for sites with rules like "6 digits" or "no symbols"
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Preset {
+    pub length: usize,
+    pub letters: bool,
+    pub digits: bool,
+    pub symbols: bool,
+    /// Characters left out, like symbols rejected by a site
+    pub exclude: String,
+    /// Lowercase syllables that are easy to read aloud, instead of the character classes
+    pub pronounceable: bool,
+}
+impl Default for Preset {
+    fn default() -> Self {
+        Self {
+            length: 20,
+            letters: true,
+            digits: true,
+            symbols: true,
+            exclude: String::new(),
+            pronounceable: false,
+        }
+    }
+}
+impl Preset {
+    /// Enabled character classes, without the excluded characters
+    fn classes(&self) -> Vec<Vec<u8>> {
+        [(self.letters, LETTERS), (self.digits, DIGITS), (self.symbols, SYMBOLS)]
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, class)| class.iter().cloned().filter(|&c| !self.exclude.contains(c as char)).collect())
+            .filter(|class: &Vec<u8>| !class.is_empty())
+            .collect()
+    }
+
+    /// Generate a password. Each character class appears at least once if the password is long enough,
+    /// as sites often require that.
+    pub fn generate(&self) -> VResult<Password> {
+        if self.length == 0 || self.length > MAX_LENGTH {
+            return Err(Error::PasswordLengthInvalid(self.length));
+        }
+        if self.pronounceable {
+            let result: String = syllables(self.length).iter().map(|set| random_chars(set, 1)).collect();
+            return Ok(Password::new(&result));
+        }
+        let classes = self.classes();
+        let charset: Vec<u8> = classes.concat();
+        if charset.is_empty() {
+            return Err(Error::PresetNoCharacters);
+        }
+        loop {
+            let result = random_chars(&charset, self.length);
+            if self.length < classes.len()
+                || classes.iter().all(|class| result.bytes().any(|b| class.contains(&b)))
+            {
+                return Ok(Password::new(&result));
+            }
+        }
+    }
+
+    /// Bits of entropy in the generated passwords, slightly less with several character classes
+    /// as each must appear
+    pub fn entropy_bits(&self) -> f64 {
+        if self.pronounceable {
+            syllables(self.length).iter().map(|set| (set.len() as f64).log2()).sum()
+        } else {
+            self.length as f64 * (self.classes().concat().len() as f64).log2()
+        }
+    }
+}
+
+/// Characters allowed at each position of a pronounceable password
+fn syllables(length: usize) -> Vec<&'static [u8]> {
+    let mut result: Vec<_> = WORD.iter().cloned().cycle().take(length).collect();
+    // Ending with a separator would waste a character
+    if result.last() == Some(&&b"-"[..]) {
+        *result.last_mut().unwrap() = VOWELS;
+    }
+    result
+}
+
+/// Pick `length` characters from `charset` uniformly at random
+fn random_chars(charset: &[u8], length: usize) -> String {
+    // Bytes at or above the largest multiple of the charset size would bias the first characters
+    let limit = 256 - 256 % charset.len();
+    let mut result = String::with_capacity(length);
+    while result.len() < length {
+        for b in randombytes(length - result.len()) {
+            if (b as usize) < limit {
+                result.push(charset[b as usize % charset.len()] as char);
+            }
+        }
+    }
+    result
+}
+
+/// Generate a random password of `length` characters
+pub fn random_password(length: usize) -> VResult<Password> {
+    Preset {
+        length,
+        ..Preset::default()
+    }
+    .generate()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{random_chars, random_password, Preset, LETTERS, SYMBOLS};
+
+    #[test]
+    fn random_passwords() {
+        rust_sodium::init().expect("Sodium init failed");
+        let pw = random_password(32).unwrap().plaintext();
+        assert_eq!(pw.len(), 32);
+        assert!(pw.bytes().all(|b| b.is_ascii_alphanumeric() || SYMBOLS.contains(&b)));
+        assert_ne!(random_password(32).unwrap().plaintext(), pw);
+        assert_eq!(random_chars(b"ab", 100).len(), 100);
+
+        assert!(random_password(0).is_err());
+        assert!(random_password(1025).is_err());
+    }
+
+    #[test]
+    fn presets() {
+        rust_sodium::init().expect("Sodium init failed");
+        let pin = Preset {
+            length: 6,
+            letters: false,
+            symbols: false,
+            ..Preset::default()
+        };
+        let pw = pin.generate().unwrap().plaintext();
+        assert_eq!(pw.len(), 6);
+        assert!(pw.bytes().all(|b| b.is_ascii_digit()));
+
+        let legacy = Preset {
+            length: 16,
+            symbols: false,
+            exclude: "0O1l".to_owned(),
+            ..Preset::default()
+        };
+        for _ in 0..10 {
+            let pw = legacy.generate().unwrap().plaintext();
+            assert!(pw.bytes().all(|b| b.is_ascii_alphanumeric() && !b"0O1l".contains(&b)));
+            assert!(pw.bytes().any(|b| b.is_ascii_digit()) && pw.bytes().any(|b| LETTERS.contains(&b)));
+        }
+
+        let nothing = Preset {
+            digits: false,
+            ..pin
+        };
+        assert!(nothing.generate().is_err());
+    }
+
+    #[test]
+    fn pronounceable_passwords() {
+        rust_sodium::init().expect("Sodium init failed");
+        let mut preset = Preset {
+            length: 11,
+            pronounceable: true,
+            ..Preset::default()
+        };
+        let pw = preset.generate().unwrap().plaintext();
+        assert_eq!(pw.len(), 11);
+        assert_eq!(&pw[5..6], "-");
+        assert!(pw.bytes().all(|b| b.is_ascii_lowercase() || b == b'-'));
+        assert_eq!(preset.entropy_bits(), 32.0);
+
+        preset.length = 6;
+        assert!(!preset.generate().unwrap().plaintext().ends_with('-'));
+        assert_eq!(preset.entropy_bits(), 18.0);
+        assert_eq!(Preset::default().entropy_bits(), 20.0 * 76f64.log2());
+    }
+}
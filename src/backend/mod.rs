@@ -1,2 +1,9 @@
 pub mod book;
+pub mod card;
+pub mod derive;
+pub mod generate;
+pub mod shamir;
+pub mod strength;
+mod stream;
 pub mod vault;
+pub mod wifi;
@@ -0,0 +1,178 @@
+//! Shamir's secret sharing with `sharks`, splitting a secret into shares
+//! so that any `threshold` of them recover it, while fewer reveal nothing.
+//! Shares carry the id of their split, so that shares of different splits are not mixed,
+//! and are encoded with a checksum, so that a mistyped share is noticed before combining.
+
+use std::convert::TryFrom;
+
+use rust_sodium::crypto::hash::sha256;
+use rust_sodium::randombytes::randombytes_into;
+use serde::{Deserialize, Serialize};
+use sharks::Sharks;
+
+use crate::cli::error::{Error, VResult};
+
+/// Bytes of the checksum at the end of an encoded share
+const CHECKSUM_LEN: usize = 4;
+
+/// What the shared secret is, told apart by the prefix of encoded shares
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ShareKind {
+    /// Vault password, in an emergency kit
+    #[default]
+    Password,
+    /// Secret part of a vault recovery key
+    RecoveryKey,
+}
+impl ShareKind {
+    fn prefix(self) -> &'static str {
+        match self {
+            ShareKind::Password => "VPASS_SHARE_",
+            ShareKind::RecoveryKey => "VPASS_RECOVERY_SHARE_",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Share {
+    /// Stored in the prefix only
+    #[serde(skip)]
+    kind: ShareKind,
+    /// Random id, the same for all shares of one split
+    split_id: [u8; 8],
+    /// Number of shares required to recover the secret
+    threshold: u8,
+    /// Share as encoded by `sharks`: the evaluation point, then one value for each byte of the secret
+    data: Vec<u8>,
+}
+impl Share {
+    pub fn threshold(&self) -> u8 {
+        self.threshold
+    }
+
+    pub fn kind(&self) -> ShareKind {
+        self.kind
+    }
+
+    fn checksum(data: &[u8]) -> [u8; CHECKSUM_LEN] {
+        let mut result = [0; CHECKSUM_LEN];
+        result.copy_from_slice(&sha256::hash(data).0[..CHECKSUM_LEN]);
+        result
+    }
+
+    pub fn encode(&self) -> String {
+        let mut data = bincode::serialize(self).unwrap();
+        let checksum = Self::checksum(&data);
+        data.extend(&checksum);
+        format!("{}{}", self.kind.prefix(), base64::encode(&data))
+    }
+
+    pub fn decode(s: &str) -> VResult<Self> {
+        let kind = [ShareKind::Password, ShareKind::RecoveryKey]
+            .iter()
+            .cloned()
+            .find(|k| s.starts_with(k.prefix()))
+            .ok_or(Error::ShareInvalid)?;
+        let data = base64::decode(&s[kind.prefix().len()..])?;
+        if data.len() < CHECKSUM_LEN {
+            return Err(Error::ShareInvalid);
+        }
+        let (data, checksum) = data.split_at(data.len() - CHECKSUM_LEN);
+        if Self::checksum(data) != checksum {
+            return Err(Error::ShareChecksumMismatch);
+        }
+        let mut share: Self = bincode::deserialize(data)?;
+        share.kind = kind;
+        if share.data.len() < 2 || share.data[0] == 0 || share.threshold == 0 {
+            return Err(Error::ShareInvalid);
+        }
+        Ok(share)
+    }
+}
+
+/// Split into `count` shares, any `threshold` of which recover the secret
+pub fn split(secret: &[u8], kind: ShareKind, threshold: u8, count: u8) -> VResult<Vec<Share>> {
+    if threshold == 0 || threshold > count {
+        return Err(Error::ShareThresholdInvalid(threshold, count));
+    }
+    let mut split_id = [0; 8];
+    randombytes_into(&mut split_id);
+    Ok(Sharks(threshold)
+        .dealer(secret)
+        .take(count as usize)
+        .map(|share| Share {
+            kind,
+            split_id,
+            threshold,
+            data: Vec::from(&share),
+        })
+        .collect())
+}
+
+/// Recover the secret from shares of the same split
+pub fn combine(shares: &[Share]) -> VResult<Vec<u8>> {
+    let first = shares.first().ok_or(Error::NotEnoughShares(0, 1))?;
+    let mut unique: Vec<&Share> = Vec::new();
+    for share in shares {
+        if share.kind != first.kind || share.split_id != first.split_id || share.threshold != first.threshold {
+            return Err(Error::ShareInvalid);
+        }
+        // The same share given twice would break the interpolation
+        if !unique.iter().any(|s| s.data[0] == share.data[0]) {
+            unique.push(share);
+        }
+    }
+    if unique.len() < first.threshold as usize {
+        return Err(Error::NotEnoughShares(unique.len(), first.threshold as usize));
+    }
+    let shares = unique
+        .iter()
+        .map(|s| sharks::Share::try_from(&s.data[..]).map_err(|_| Error::ShareInvalid))
+        .collect::<VResult<Vec<_>>>()?;
+    Sharks(first.threshold).recover(&shares).map_err(|_| Error::ShareInvalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{combine, split, Share, ShareKind};
+    use crate::cli::error::Error;
+    use matches::matches;
+
+    #[test]
+    fn split_combine() {
+        let secret = b"correct horse battery staple";
+        let shares = split(secret, ShareKind::Password, 3, 5).unwrap();
+        assert_eq!(shares.len(), 5);
+
+        assert_eq!(combine(&shares[..3]).unwrap(), secret);
+        assert_eq!(combine(&shares[2..]).unwrap(), secret);
+        assert_eq!(combine(&[shares[4].clone(), shares[0].clone(), shares[2].clone()]).unwrap(), secret);
+        assert!(combine(&shares[..2]).is_err());
+        assert!(combine(&[shares[0].clone(), shares[0].clone(), shares[1].clone()]).is_err());
+
+        let decoded = Share::decode(&shares[0].encode()).unwrap();
+        assert_eq!(decoded, shares[0]);
+        assert!(split(secret, ShareKind::Password, 4, 3).is_err());
+
+        let recovery = split(secret, ShareKind::RecoveryKey, 2, 2).unwrap();
+        let decoded = Share::decode(&recovery[0].encode()).unwrap();
+        assert_eq!(decoded.kind(), ShareKind::RecoveryKey);
+        assert!(combine(&[shares[0].clone(), decoded]).is_err());
+    }
+
+    #[test]
+    fn share_mistakes() {
+        let secret = b"correct horse battery staple";
+        let shares = split(secret, ShareKind::Password, 2, 3).unwrap();
+        let other = split(secret, ShareKind::Password, 2, 3).unwrap();
+        // Shares of another split of the same secret, with the same threshold
+        assert!(matches!(combine(&[shares[0].clone(), other[1].clone()]), Err(Error::ShareInvalid)));
+
+        // A single changed character is caught by the checksum
+        let encoded = shares[0].encode();
+        let position = encoded.len() - 10;
+        let changed = if &encoded[position..=position] == "A" { "B" } else { "A" };
+        let mistyped = format!("{}{}{}", &encoded[..position], changed, &encoded[position + 1..]);
+        assert!(matches!(Share::decode(&mistyped), Err(Error::ShareChecksumMismatch)));
+    }
+}
@@ -0,0 +1,179 @@
+//! Chunked authenticated encryption for streaming vaults, format version 4.
+//! Each chunk is a frame of a flag byte marking the last chunk, the length of the sealed chunk
+//! as u32 LE, and the chunk sealed with secretbox. Nonces are derived from the vault nonce,
+//! the chunk index and the flag, so that reordered, dropped or truncated chunks fail to open.
+
+use rust_sodium::crypto::secretbox;
+use std::io::{self, Read, Write};
+
+/// Plaintext size of each chunk, only the last one may be shorter
+pub const CHUNK_SIZE: usize = 64 * 1024;
+
+fn chunk_nonce(base: &secretbox::Nonce, index: u64, last: bool) -> secretbox::Nonce {
+    let mut nonce = *base;
+    for (b, i) in nonce.0.iter_mut().zip(&index.to_le_bytes()) {
+        *b ^= i;
+    }
+    if last {
+        nonce.0[secretbox::NONCEBYTES - 1] ^= 0x80;
+    }
+    nonce
+}
+
+fn invalid_data() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Corrupted chunk")
+}
+
+/// Encrypts everything written to it in chunks. `finish` must be called to write the last chunk.
+pub struct ChunkWriter<W: Write> {
+    inner: W,
+    key: secretbox::Key,
+    nonce: secretbox::Nonce,
+    index: u64,
+    buffer: Vec<u8>,
+}
+impl<W: Write> ChunkWriter<W> {
+    pub fn new(inner: W, key: secretbox::Key, nonce: secretbox::Nonce) -> Self {
+        Self {
+            inner,
+            key,
+            nonce,
+            index: 0,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        }
+    }
+
+    fn write_chunk(&mut self, last: bool) -> io::Result<()> {
+        let sealed = secretbox::seal(&self.buffer, &chunk_nonce(&self.nonce, self.index, last), &self.key);
+        self.inner.write_all(&[last as u8])?;
+        self.inner.write_all(&(sealed.len() as u32).to_le_bytes())?;
+        self.inner.write_all(&sealed)?;
+        self.buffer.clear();
+        self.index += 1;
+        Ok(())
+    }
+
+    /// Write the last chunk, which may be empty, and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.write_chunk(true)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+impl<W: Write> Write for ChunkWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        // A full chunk is only written when more data follows, so that the last one is marked as such
+        if self.buffer.len() == CHUNK_SIZE {
+            self.write_chunk(false)?;
+        }
+        let n = data.len().min(CHUNK_SIZE - self.buffer.len());
+        self.buffer.extend_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Decrypts chunks written by `ChunkWriter` as they are read.
+/// Fails with `InvalidData` on corrupted chunks or data after the last chunk,
+/// and with `UnexpectedEof` if the last chunk is missing.
+pub struct ChunkReader<R: Read> {
+    inner: R,
+    key: secretbox::Key,
+    nonce: secretbox::Nonce,
+    index: u64,
+    buffer: Vec<u8>,
+    position: usize,
+    last: bool,
+}
+impl<R: Read> ChunkReader<R> {
+    pub fn new(inner: R, key: secretbox::Key, nonce: secretbox::Nonce) -> Self {
+        Self {
+            inner,
+            key,
+            nonce,
+            index: 0,
+            buffer: Vec::new(),
+            position: 0,
+            last: false,
+        }
+    }
+
+    fn read_chunk(&mut self) -> io::Result<()> {
+        let mut frame = [0u8; 5];
+        self.inner.read_exact(&mut frame)?;
+        let last = match frame[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid_data()),
+        };
+        let len = u32::from_le_bytes([frame[1], frame[2], frame[3], frame[4]]) as usize;
+        if len > CHUNK_SIZE + secretbox::MACBYTES {
+            return Err(invalid_data());
+        }
+        let mut sealed = vec![0; len];
+        self.inner.read_exact(&mut sealed)?;
+        let nonce = chunk_nonce(&self.nonce, self.index, last);
+        self.buffer = secretbox::open(&sealed, &nonce, &self.key).map_err(|_| invalid_data())?;
+        self.position = 0;
+        self.index += 1;
+        self.last = last;
+        Ok(())
+    }
+}
+impl<R: Read> Read for ChunkReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.buffer.len() {
+            if self.last {
+                // Nothing may follow the last chunk
+                let mut extra = [0u8; 1];
+                return match self.inner.read(&mut extra)? {
+                    0 => Ok(0),
+                    _ => Err(invalid_data()),
+                };
+            }
+            self.read_chunk()?;
+        }
+        let n = out.len().min(self.buffer.len() - self.position);
+        out[..n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+        self.position += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkReader, ChunkWriter, CHUNK_SIZE};
+    use rust_sodium::crypto::secretbox;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn chunk_roundtrip() {
+        rust_sodium::init().expect("Sodium init failed");
+        let key = secretbox::gen_key();
+        let nonce = secretbox::gen_nonce();
+        let read = |data: &[u8]| {
+            let mut result = Vec::new();
+            ChunkReader::new(data, key.clone(), nonce).read_to_end(&mut result).map(|_| result)
+        };
+
+        for &size in &[0, 1, CHUNK_SIZE, CHUNK_SIZE * 2 + 7] {
+            let plaintext: Vec<u8> = (0..size).map(|i| i as u8).collect();
+            let mut writer = ChunkWriter::new(Vec::new(), key.clone(), nonce);
+            writer.write_all(&plaintext).unwrap();
+            let sealed = writer.finish().unwrap();
+            assert_eq!(read(&sealed).unwrap(), plaintext);
+
+            // Truncated, extended or tampered
+            assert!(read(&sealed[..sealed.len() - 1]).is_err());
+            let mut extended = sealed.clone();
+            extended.push(0);
+            assert!(read(&extended).is_err());
+            let mut tampered = sealed.clone();
+            tampered[0] ^= 1;
+            assert!(read(&tampered).is_err());
+        }
+    }
+}
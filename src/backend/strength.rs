@@ -0,0 +1,86 @@
+//! Rough strength estimate of passwords entered by hand, shown by `vpass add` and `vpass edit`.
+//! Each character is worth the size of the character pool in use, except repeated and sequential
+//! characters and common words, which are worth little. Real cracking tools know more patterns,
+//! so the estimate errs on the high side.
+
+/// Words at the start of most password lists, in lowercase
+const COMMON_WORDS: &[&str] = &[
+    "password", "passw0rd", "qwerty", "asdf", "123456", "abc123", "111111", "letmein", "welcome", "admin",
+    "login", "secret", "iloveyou", "monkey", "dragon", "master", "sunshine", "football", "princess",
+];
+
+/// Guesses per second in an offline attack on a fast hash with a few GPUs
+pub const GUESSES_PER_SECOND: f64 = 1e10;
+
+/// Number of characters an attacker would have to try for each position
+fn pool_size(password: &str) -> usize {
+    type CharClass = (fn(&char) -> bool, usize);
+    let classes: [CharClass; 5] = [
+        (char::is_ascii_lowercase, 26),
+        (char::is_ascii_uppercase, 26),
+        (char::is_ascii_digit, 10),
+        (|c| c.is_ascii_punctuation() || *c == ' ', 33),
+        (|c| !c.is_ascii(), 100),
+    ];
+    classes
+        .iter()
+        .filter(|(matches, _)| password.chars().any(|c| matches(&c)))
+        .map(|(_, size)| size)
+        .sum()
+}
+
+/// Estimated bits of entropy
+pub fn entropy_bits(password: &str) -> f64 {
+    let chars: Vec<char> = password.chars().map(|c| c.to_ascii_lowercase()).collect();
+    // Characters in common words, which are guessed as a whole
+    let mut in_word = vec![false; chars.len()];
+    let mut word_count = 0;
+    for word in COMMON_WORDS {
+        let word: Vec<char> = word.chars().collect();
+        for start in 0..chars.len().saturating_sub(word.len() - 1) {
+            if chars[start..start + word.len()] == word[..] {
+                in_word[start..start + word.len()].iter_mut().for_each(|c| *c = true);
+                word_count += 1;
+            }
+        }
+    }
+
+    let pool = (pool_size(password) as f64).log2();
+    let mut bits = word_count as f64 * (COMMON_WORDS.len() as f64).log2();
+    for (i, c) in chars.iter().enumerate() {
+        if in_word[i] {
+            continue;
+        }
+        let previous = if i > 0 { Some(chars[i - 1] as i64) } else { None };
+        bits += match previous.map(|p| *c as i64 - p) {
+            // Repeated like `aaa`, or in sequence like `abc` or `321`
+            Some(-1..=1) => 1.0,
+            _ => pool,
+        };
+    }
+    bits
+}
+
+/// Average seconds to find a password with `bits` of entropy in an offline attack
+pub fn crack_seconds(bits: f64) -> f64 {
+    2f64.powf(bits - 1.0) / GUESSES_PER_SECOND
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{crack_seconds, entropy_bits};
+
+    #[test]
+    fn password_strength() {
+        assert!(entropy_bits("password") < 5.0);
+        assert!(entropy_bits("Password1") < 20.0);
+        assert!(entropy_bits("aaaaaaaaaaaaaaaa") < 20.0);
+        assert!(entropy_bits("abcdefgh12345678") < 25.0);
+        assert!(entropy_bits("correct horse battery staple") > 100.0);
+        assert!(entropy_bits("q7#Vx9!mKp2$Lw4z") > 100.0);
+        assert_eq!(entropy_bits(""), 0.0);
+
+        assert!(crack_seconds(entropy_bits("password")) < 1.0);
+        assert!(crack_seconds(80.0) > 1e9);
+    }
+}
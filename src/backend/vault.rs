@@ -1,21 +1,91 @@
 use rust_sodium::{
     self,
-    crypto::{pwhash, secretbox},
+    crypto::{auth, box_, hash::sha256, pwhash, scalarmult, sealedbox, secretbox},
+    randombytes::randombytes,
 };
 
 use flate2::read::GzDecoder;
 use flate2::write::GzEncoder;
 use flate2::Compression;
+use std::convert::TryInto;
 use std::fmt;
-use std::io::prelude::*;
+use std::io::{self, prelude::*};
+use std::time::{Duration, Instant};
 
+use chrono::prelude::*;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json;
 
-pub trait Content = fmt::Debug + Serialize + DeserializeOwned + Clone + PartialEq + Eq;
+use crate::cli::error::{Error, VResult};
+use crate::cli::timings::{self, Phase};
+
+use super::stream::{ChunkReader, ChunkWriter};
+
+pub trait Content: fmt::Debug + Serialize + DeserializeOwned + Clone + PartialEq + Eq {}
+impl<T: fmt::Debug + Serialize + DeserializeOwned + Clone + PartialEq + Eq> Content for T {}
 
 const MAGIC: u8 = 0xd7;
-const VERSION: u32 = 0;
+/// Version 0 always uses the interactive key derivation limits,
+/// version 1 stores the limits after the data,
+/// version 2 stores recipients of a shared vault after the limits,
+/// version 3 stores the vault key sealed for a recovery key after the recipients,
+/// and version 4 stores the data in chunks after the header, see `Vault::encrypt_to`
+const VERSION: u32 = 4;
+/// Feature bit of the version: key derivation limits of each recipient are stored after the recovery key.
+/// Only set if some recipient doesn't use the interactive limits, so that older versions can read the others.
+const RECIPIENT_LIMITS: u32 = 1 << 16;
+/// Streamed vaults are not read to memory, so the header is limited separately
+const STREAM_VERSION: u32 = 4;
+/// Limit for the header of a streamed vault, which has no data in it
+const MAX_HEADER_SIZE: u64 = 1024 * 1024;
+
+/// Limits for deriving the vault key from the password
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct KdfParams {
+    pub ops_limit: u64,
+    pub mem_limit: u64,
+}
+impl Default for KdfParams {
+    /// Interactive limits from libsodium
+    fn default() -> Self {
+        KdfParams {
+            ops_limit: pwhash::OPSLIMIT_INTERACTIVE.0 as u64,
+            mem_limit: pwhash::MEMLIMIT_INTERACTIVE.0 as u64,
+        }
+    }
+}
+impl KdfParams {
+    /// Smallest and largest multiple of the interactive limits used by `tune`
+    const TUNE_RANGE: (f64, f64) = (0.125, 64.0);
+
+    /// Time it takes to derive a key on this machine
+    pub fn measure(&self) -> Duration {
+        let start = Instant::now();
+        VaultKey::reconstruct("benchmark", pwhash::gen_salt(), *self);
+        start.elapsed()
+    }
+
+    /// Limits taking approximately `target` time to derive a key on this machine.
+    /// Both limits are scaled from the interactive limits by the same factor.
+    pub fn tune(target: Duration) -> Self {
+        let base = Self::default();
+        let micros = |d: Duration| d.as_micros().max(1) as f64;
+        let factor = (micros(target) / micros(base.measure()))
+            .max(Self::TUNE_RANGE.0)
+            .min(Self::TUNE_RANGE.1);
+        KdfParams {
+            ops_limit: (base.ops_limit as f64 * factor) as u64,
+            mem_limit: (base.mem_limit as f64 * factor) as u64,
+        }
+    }
+
+    /// Limits read from a vault file are not trusted: key derivation fails with zero limits,
+    /// and the sensitive limits of libsodium are above anything `tune` produces
+    fn is_supported(&self) -> bool {
+        (1..=pwhash::OPSLIMIT_SENSITIVE.0 as u64).contains(&self.ops_limit)
+            && (1..=pwhash::MEMLIMIT_SENSITIVE.0 as u64).contains(&self.mem_limit)
+    }
+}
 
 /// Vault encryption/decryption key+salt from password
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
@@ -24,41 +94,39 @@ struct VaultKey {
     salt: pwhash::Salt,
 }
 impl VaultKey {
-    pub fn new(password: &str) -> VaultKey {
-        let salt = pwhash::gen_salt();
-        let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
-        {
-            let secretbox::Key(ref mut kb) = key;
-            pwhash::derive_key(
-                kb,
-                password.as_bytes(),
-                &salt,
-                pwhash::OPSLIMIT_INTERACTIVE,
-                pwhash::MEMLIMIT_INTERACTIVE,
-            )
-            .unwrap();
-        }
-
-        VaultKey { key, salt }
+    pub fn new(password: &str, kdf: KdfParams) -> VaultKey {
+        Self::reconstruct(password, pwhash::gen_salt(), kdf)
     }
 
-    pub fn reconstruct(password: &str, salt: pwhash::Salt) -> VaultKey {
+    pub fn reconstruct(password: &str, salt: pwhash::Salt, kdf: KdfParams) -> VaultKey {
         let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
-        {
+        timings::measure(Phase::Kdf, || {
             let secretbox::Key(ref mut kb) = key;
             pwhash::derive_key(
                 kb,
                 password.as_bytes(),
                 &salt,
-                pwhash::OPSLIMIT_INTERACTIVE,
-                pwhash::MEMLIMIT_INTERACTIVE,
+                pwhash::OpsLimit(kdf.ops_limit as usize),
+                pwhash::MemLimit(kdf.mem_limit as usize),
             )
             .unwrap();
-        }
+        });
 
         VaultKey { key, salt }
     }
 }
+impl VaultKey {
+    /// Nonce derived from the content, so that identical content produces identical ciphertext.
+    /// Different contents never share a nonce, so this is as safe as random nonces,
+    /// but reveals whether two vault versions with the same key are equal.
+    fn derive_nonce(&self, content: &[u8]) -> secretbox::Nonce {
+        let mut subkey_input = b"vpass-nonce:".to_vec();
+        subkey_input.extend(&self.key.0);
+        let subkey = auth::Key(sha256::hash(&subkey_input).0);
+        let tag = auth::authenticate(content, &subkey);
+        secretbox::Nonce::from_slice(&tag.0[..secretbox::NONCEBYTES]).unwrap()
+    }
+}
 impl fmt::Debug for VaultKey {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "VaultKey {{ key: ****, salt: {:?} }}", self.salt)
@@ -74,29 +142,464 @@ impl<T: Content> Vault<T> {
         Self { content }
     }
 
+    fn compress(&self) -> Vec<u8> {
+        timings::measure(Phase::Compression, || {
+            let plaintext = serde_json::to_vec(&self).unwrap();
+            let mut e = GzEncoder::new(Vec::new(), Compression::best());
+            e.write_all(&plaintext).unwrap();
+            e.finish().unwrap()
+        })
+    }
+
     pub fn encrypt(&self, password: &str) -> EncryptedVault {
-        let key = VaultKey::new(password);
+        self.encrypt_with(password, KdfParams::default())
+    }
+
+    pub fn encrypt_with(&self, password: &str, kdf: KdfParams) -> EncryptedVault {
+        let key = VaultKey::new(password, kdf);
         let nonce = secretbox::gen_nonce();
-        let plaintext = serde_json::to_vec(&self).unwrap();
-        let mut e = GzEncoder::new(Vec::new(), Compression::best());
-        e.write_all(&plaintext).unwrap();
-        let compressed: Vec<u8> = e.finish().unwrap();
-        let data = secretbox::seal(&compressed, &nonce, &key.key);
-        EncryptedVault {
-            magic: MAGIC,
-            version: VERSION,
+        let compressed = self.compress();
+        let data = timings::measure(Phase::Encryption, || secretbox::seal(&compressed, &nonce, &key.key));
+        EncryptedVault::new(nonce, data, key.salt, kdf)
+    }
+
+    /// Encrypt reusing a salt, with the nonce derived from the content.
+    /// Same content, password, salt and limits always produce the same bytes.
+    pub fn encrypt_deterministic(&self, password: &str, salt: pwhash::Salt, kdf: KdfParams) -> EncryptedVault {
+        let key = VaultKey::reconstruct(password, salt, kdf);
+        let compressed = self.compress();
+        let (nonce, data) = timings::measure(Phase::Encryption, || {
+            let nonce = key.derive_nonce(&compressed);
+            (nonce, secretbox::seal(&compressed, &nonce, &key.key))
+        });
+        EncryptedVault::new(nonce, data, key.salt, kdf)
+    }
+
+    /// Encrypt to a stream in chunks, without holding the compressed or encrypted vault in memory.
+    /// Writes format version 4, which older versions of vpass can't read.
+    /// Returns the writer after the last chunk.
+    pub fn encrypt_to<W: Write>(
+        &self, mut writer: W, password: &str, kdf: KdfParams, recipients: &[Recipient],
+        recovery: Option<&RecoveryKey>,
+    ) -> VResult<W> {
+        let key = VaultKey::new(password, kdf);
+        let mut header = EncryptedVault::new(secretbox::gen_nonce(), Vec::new(), key.salt, kdf)
+            .with_recipients(recipients)
+            .with_recovery_key(recovery, &key);
+        header.version = STREAM_VERSION | (header.version & RECIPIENT_LIMITS);
+        writer.write_all(&header.to_bytes())?;
+        // Compression and encryption are interleaved, so both are measured as encryption
+        timings::measure(Phase::Encryption, || -> VResult<W> {
+            let mut gz = GzEncoder::new(ChunkWriter::new(writer, key.key, header.nonce), Compression::best());
+            serde_json::to_writer(&mut gz, self)?;
+            Ok(gz.finish()?.finish()?)
+        })
+    }
+}
+
+/// Public key of a shared vault member, given to the vault owner to be added as a recipient.
+/// The key pair is derived from the member's own password and a random salt,
+/// so members unlock shared vaults with their password, and no key files are needed.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct MemberKey {
+    salt: pwhash::Salt,
+    public_key: box_::PublicKey,
+    /// Limits for deriving the key pair, keys created by older versions use the interactive ones
+    #[serde(default)]
+    kdf: KdfParams,
+}
+impl MemberKey {
+    const PREFIX: &'static str = "vpass-member:";
+
+    /// New key for a password, with a random salt.
+    /// Use the limits of the vault, so that a key slot isn't easier to guess than the vault password.
+    pub fn new(password: &str, kdf: KdfParams) -> Self {
+        let salt = pwhash::gen_salt();
+        let (public_key, _) = Self::keypair(password, salt, kdf);
+        Self { salt, public_key, kdf }
+    }
+
+    pub fn kdf(&self) -> KdfParams {
+        self.kdf
+    }
+
+    fn keypair(password: &str, salt: pwhash::Salt, kdf: KdfParams) -> (box_::PublicKey, box_::SecretKey) {
+        let mut seed = [0; box_::SECRETKEYBYTES];
+        timings::measure(Phase::Kdf, || {
+            pwhash::derive_key(
+                &mut seed,
+                password.as_bytes(),
+                &salt,
+                pwhash::OpsLimit(kdf.ops_limit as usize),
+                pwhash::MemLimit(kdf.mem_limit as usize),
+            )
+            .unwrap();
+        });
+        let public = scalarmult::scalarmult_base(&scalarmult::Scalar(seed));
+        (box_::PublicKey(public.0), box_::SecretKey(seed))
+    }
+
+    /// Whether the key was created for `password`
+    pub fn matches(&self, password: &str) -> bool {
+        self.secret_key(password).is_some()
+    }
+
+    /// Secret key, if the password matches this key
+    fn secret_key(&self, password: &str) -> Option<box_::SecretKey> {
+        let (public_key, secret_key) = Self::keypair(password, self.salt, self.kdf);
+        if public_key == self.public_key {
+            Some(secret_key)
+        } else {
+            None
+        }
+    }
+
+    /// Limits are appended only if they are not the interactive ones, like in keys of older versions
+    pub fn encode(&self) -> String {
+        let mut bytes = self.salt.0.to_vec();
+        bytes.extend(&self.public_key.0);
+        if self.kdf != KdfParams::default() {
+            bytes.extend(&self.kdf.ops_limit.to_le_bytes());
+            bytes.extend(&self.kdf.mem_limit.to_le_bytes());
+        }
+        format!("{}{}", Self::PREFIX, base64::encode(&bytes))
+    }
+
+    pub fn decode(s: &str) -> VResult<Self> {
+        if !s.starts_with(Self::PREFIX) {
+            return Err(Error::MemberKeyInvalid);
+        }
+        let bytes = base64::decode(&s[Self::PREFIX.len()..])?;
+        let key_len = pwhash::SALTBYTES + box_::PUBLICKEYBYTES;
+        let kdf = match bytes.len().checked_sub(key_len) {
+            Some(0) => KdfParams::default(),
+            Some(16) => {
+                let limit = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+                KdfParams {
+                    ops_limit: limit(key_len),
+                    mem_limit: limit(key_len + 8),
+                }
+            },
+            _ => return Err(Error::MemberKeyInvalid),
+        };
+        if !kdf.is_supported() {
+            return Err(Error::MemberKeyInvalid);
+        }
+        let (salt, public_key) = bytes[..key_len].split_at(pwhash::SALTBYTES);
+        Ok(Self {
+            salt: pwhash::Salt::from_slice(salt).unwrap(),
+            public_key: box_::PublicKey::from_slice(public_key).unwrap(),
+            kdf,
+        })
+    }
+}
+
+/// Vault content password sealed for one member
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    member: MemberKey,
+    sealed_password: Vec<u8>,
+}
+impl Recipient {
+    fn unseal(&self, password: &str) -> Option<String> {
+        let secret_key = self.member.secret_key(password)?;
+        let plaintext = sealedbox::open(&self.sealed_password, &self.member.public_key, &secret_key).ok()?;
+        String::from_utf8(plaintext).ok()
+    }
+
+    pub fn member(&self) -> &MemberKey {
+        &self.member
+    }
+
+    /// Layout in the vault header, where the limits are stored separately
+    fn to_header(&self) -> HeaderRecipient {
+        (self.member.salt, self.member.public_key, self.sealed_password.clone())
+    }
+
+    fn from_header((salt, public_key, sealed_password): HeaderRecipient, kdf: KdfParams) -> Self {
+        Self {
+            member: MemberKey { salt, public_key, kdf },
+            sealed_password,
+        }
+    }
+}
+
+/// Salt, public key and sealed password of a recipient in the vault header
+type HeaderRecipient = (pwhash::Salt, box_::PublicKey, Vec<u8>);
+
+/// Content of a shared vault, or a vault with key slots, is encrypted with a random password,
+/// which is sealed separately for each member and key slot in the vault header.
+/// Stored in the book as well, so that it's kept when the book is encrypted again.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct SharedKey {
+    content_password: String,
+    /// Members of a shared vault, empty if the vault only has key slots
+    recipients: Vec<Recipient>,
+    /// Key slots, more passwords of the owner, like a recovery passphrase kept on paper
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    slots: Vec<Recipient>,
+    /// Removed members and key slots, so that merging an older version doesn't add them back
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    removed: Vec<MemberKey>,
+    /// Content password of the newer version is kept when merging
+    changed: DateTime<Utc>,
+}
+impl SharedKey {
+    /// Share with the owner only, who is using `password`
+    pub fn new(password: &str, kdf: KdfParams) -> Self {
+        let mut result = Self::without_members();
+        result.add(&MemberKey::new(password, kdf));
+        result
+    }
+
+    /// Key for a vault that is not shared, to be unlocked with key slots
+    pub fn without_members() -> Self {
+        Self {
+            content_password: Self::random_password(),
+            recipients: Vec::new(),
+            slots: Vec::new(),
+            removed: Vec::new(),
+            changed: Utc::now(),
+        }
+    }
+
+    fn random_password() -> String {
+        base64::encode(&randombytes(secretbox::KEYBYTES))
+    }
+
+    pub fn content_password(&self) -> &str {
+        &self.content_password
+    }
+
+    pub fn recipients(&self) -> &[Recipient] {
+        &self.recipients
+    }
+
+    pub fn slots(&self) -> &[Recipient] {
+        &self.slots
+    }
+
+    /// Whether the vault has members, instead of only key slots
+    pub fn is_shared(&self) -> bool {
+        !self.recipients.is_empty()
+    }
+
+    /// Members and key slots, for the vault header
+    pub fn header_recipients(&self) -> Vec<Recipient> {
+        self.recipients.iter().chain(&self.slots).cloned().collect()
+    }
+
+    fn seal(&self, member: &MemberKey) -> Recipient {
+        Recipient {
+            member: member.clone(),
+            sealed_password: sealedbox::seal(self.content_password.as_bytes(), &member.public_key),
+        }
+    }
+
+    pub fn changed(&self) -> DateTime<Utc> {
+        self.changed
+    }
+
+    /// Returns false if the member is already a recipient, or has been removed.
+    /// Removed member keys can't be added back, the member creates a new key instead.
+    pub fn add(&mut self, member: &MemberKey) -> bool {
+        if self.removed.contains(member) || self.recipients.iter().any(|r| r.member == *member) {
+            return false;
+        }
+        self.recipients.push(self.seal(member));
+        self.changed = Utc::now();
+        true
+    }
+
+    /// Returns false if the key is already in a slot, or has been removed
+    pub fn add_slot(&mut self, key: &MemberKey) -> bool {
+        if self.removed.contains(key) || self.slots.iter().any(|r| r.member == *key) {
+            return false;
+        }
+        self.slots.push(self.seal(key));
+        self.changed = Utc::now();
+        true
+    }
+
+    /// Remove a key slot, and rewrap a new content password like when removing a member.
+    /// Returns false if the key is not in a slot.
+    pub fn remove_slot(&mut self, key: &MemberKey) -> bool {
+        let count = self.slots.len();
+        self.slots.retain(|r| r.member != *key);
+        if self.slots.len() == count {
+            return false;
+        }
+        self.removed.push(key.clone());
+        self.rotate();
+        true
+    }
+
+    /// Remove a member, and rewrap a new content password for the remaining ones,
+    /// so that the removed member can't decrypt later versions.
+    /// Returns false if the member is not a recipient.
+    pub fn remove(&mut self, member: &MemberKey) -> bool {
+        let count = self.recipients.len();
+        self.recipients.retain(|r| r.member != *member);
+        if self.recipients.len() == count {
+            return false;
+        }
+        self.removed.push(member.clone());
+        self.rotate();
+        true
+    }
+
+    /// Replace the content password with a new random one, sealed again for each member
+    pub fn rotate(&mut self) {
+        self.content_password = Self::random_password();
+        self.recipients = self.recipients.iter().map(|r| self.seal(&r.member)).collect();
+        self.slots = self.slots.iter().map(|r| self.seal(&r.member)).collect();
+        self.changed = Utc::now();
+    }
+
+    /// Replace the member key and key slot matching `old_password` with new ones for `new_password`,
+    /// derived with the limits `kdf`. Returns false if neither matches.
+    pub fn change_member_password(&mut self, old_password: &str, new_password: &str, kdf: KdfParams) -> bool {
+        let mut changed = false;
+        for list in &mut [&mut self.recipients, &mut self.slots] {
+            if let Some(r) = list.iter_mut().find(|r| r.member.matches(old_password)) {
+                let member = MemberKey::new(new_password, kdf);
+                self.removed.push(r.member.clone());
+                r.sealed_password = sealedbox::seal(self.content_password.as_bytes(), &member.public_key);
+                r.member = member;
+                changed = true;
+            }
+        }
+        if changed {
+            self.changed = Utc::now();
+        }
+        changed
+    }
+
+    /// Merge two versions of the key member by member, a removal on either side winning over the other.
+    /// The newer content password is kept. If the newer version doesn't know of all removals,
+    /// a removed member may still know its content password, so a new one is created.
+    pub fn merge(&self, other: &Self) -> Self {
+        let key = |k: &Self| (k.changed, k.content_password.clone());
+        let (newer, older) = if key(self) >= key(other) { (self, other) } else { (other, self) };
+        let mut removed = newer.removed.clone();
+        for member in &older.removed {
+            if !removed.contains(member) {
+                removed.push(member.clone());
+            }
+        }
+
+        let mut result = Self {
+            content_password: newer.content_password.clone(),
+            recipients: Vec::new(),
+            slots: Vec::new(),
+            removed,
+            changed: newer.changed,
+        };
+        // Entries sealed for the kept content password are reused, preferring the smaller one
+        // so that both sides of a merge end up with the same entries
+        let same_password = older.content_password == newer.content_password;
+        let merge_list = |result: &Self, newer_list: &[Recipient], older_list: &[Recipient]| {
+            let mut merged: Vec<Recipient> = Vec::new();
+            let entries =
+                newer_list.iter().map(|r| (r, true)).chain(older_list.iter().map(|r| (r, same_password)));
+            for (r, reusable) in entries {
+                if result.removed.contains(&r.member) {
+                    continue;
+                }
+                match merged.iter_mut().find(|m| m.member == r.member) {
+                    Some(m) => {
+                        if reusable && r.sealed_password < m.sealed_password {
+                            *m = r.clone();
+                        }
+                    },
+                    None => merged.push(if reusable { r.clone() } else { result.seal(&r.member) }),
+                }
+            }
+            merged
+        };
+        result.recipients = merge_list(&result, &newer.recipients, &older.recipients);
+        result.slots = merge_list(&result, &newer.slots, &older.slots);
+
+        if result.removed.len() > newer.removed.len() {
+            result.rotate();
+        }
+        result
+    }
+}
+impl fmt::Debug for SharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SharedKey {{ content_password: ****, recipients: {}, slots: {} }}",
+            self.recipients.len(),
+            self.slots.len()
+        )
+    }
+}
+
+/// Public part of a recovery key, stored in the book.
+/// The secret part is split into shares with `shamir`, and is not stored anywhere.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct RecoveryKey {
+    public_key: box_::PublicKey,
+    /// Newer key is kept when merging
+    created: DateTime<Utc>,
+}
+impl RecoveryKey {
+    /// New recovery key, and its secret key bytes
+    pub fn generate() -> (Self, Vec<u8>) {
+        let (public_key, secret_key) = box_::gen_keypair();
+        let key = RecoveryKey {
+            public_key,
+            created: Utc::now(),
+        };
+        (key, secret_key.0.to_vec())
+    }
+
+    pub fn created(&self) -> DateTime<Utc> {
+        self.created
+    }
+
+    /// Seal a vault key. The ephemeral key is derived from the vault key,
+    /// so that the same key always produces the same bytes.
+    fn seal(&self, key: &VaultKey) -> SealedRecovery {
+        let mut seed_input = b"vpass-recovery:".to_vec();
+        seed_input.extend(&key.key.0);
+        seed_input.extend(&self.public_key.0);
+        let ephemeral_secret = box_::SecretKey(sha256::hash(&seed_input).0);
+        let ephemeral_public = scalarmult::scalarmult_base(&scalarmult::Scalar(ephemeral_secret.0));
+        let ephemeral_public = box_::PublicKey(ephemeral_public.0);
+        let mut nonce_input = b"vpass-recovery-nonce:".to_vec();
+        nonce_input.extend(&ephemeral_public.0);
+        let nonce = box_::Nonce::from_slice(&sha256::hash(&nonce_input).0[..box_::NONCEBYTES]).unwrap();
+        SealedRecovery {
+            data: box_::seal(&key.key.0, &nonce, &self.public_key, &ephemeral_secret),
+            ephemeral_public,
             nonce,
-            data,
-            salt: key.salt,
         }
     }
 }
 
+/// Vault key sealed for a recovery key
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct SealedRecovery {
+    ephemeral_public: box_::PublicKey,
+    nonce: box_::Nonce,
+    data: Vec<u8>,
+}
+impl SealedRecovery {
+    fn open(&self, secret_key: &[u8]) -> Option<secretbox::Key> {
+        let secret_key = box_::SecretKey::from_slice(secret_key)?;
+        let key = box_::open(&self.data, &self.nonce, &self.ephemeral_public, &secret_key).ok()?;
+        secretbox::Key::from_slice(&key)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct EncryptedVault {
     /// Magic byte 0xd7
     magic: u8,
-    /// Version, enough space for feature bits if needed in the future
+    /// Version, with feature bits like `RECIPIENT_LIMITS` above the lowest 16 bits
     version: u32,
     /// Salt for the password
     salt: pwhash::Salt,
@@ -104,36 +607,253 @@ pub struct EncryptedVault {
     nonce: secretbox::Nonce,
     /// The actual data as bytes
     data: Vec<u8>,
+    /// Stored after the other fields since version 1
+    #[serde(skip)]
+    kdf: KdfParams,
+    /// Stored after the limits since version 2, only in shared vaults
+    #[serde(skip)]
+    recipients: Vec<Recipient>,
+    /// Stored after the recipients since version 3, only if a recovery key is set
+    #[serde(skip)]
+    recovery: Option<SealedRecovery>,
 }
 impl EncryptedVault {
+    /// Version without the feature bits
+    fn format(&self) -> u32 {
+        self.version & !RECIPIENT_LIMITS
+    }
+
+    fn new(nonce: secretbox::Nonce, data: Vec<u8>, salt: pwhash::Salt, kdf: KdfParams) -> Self {
+        EncryptedVault {
+            magic: MAGIC,
+            // Version 0 is still used when possible, so that older versions can read the vault
+            version: if kdf == KdfParams::default() { 0 } else { 1 },
+            salt,
+            nonce,
+            data,
+            kdf,
+            recipients: Vec::new(),
+            recovery: None,
+        }
+    }
+
+    /// Members of a shared vault, who can decrypt it with their own password.
+    /// The vault must be encrypted with the content password of the shared key.
+    pub fn with_recipients(mut self, recipients: &[Recipient]) -> Self {
+        if !recipients.is_empty() {
+            self.version = self.format().max(2);
+            if recipients.iter().any(|r| r.member.kdf != KdfParams::default()) {
+                self.version |= RECIPIENT_LIMITS;
+            }
+            self.recipients = recipients.to_vec();
+        }
+        self
+    }
+
+    /// Seal the vault key for a recovery key. `password` must be the one the vault is encrypted with.
+    pub fn with_recovery(self, recovery: Option<&RecoveryKey>, password: &str) -> Self {
+        match recovery {
+            Some(_) => {
+                let key = VaultKey::reconstruct(password, self.salt, self.kdf);
+                self.with_recovery_key(recovery, &key)
+            },
+            None => self,
+        }
+    }
+
+    fn with_recovery_key(mut self, recovery: Option<&RecoveryKey>, key: &VaultKey) -> Self {
+        if let Some(recovery) = recovery {
+            self.version = self.format().max(3) | (self.version & RECIPIENT_LIMITS);
+            self.recovery = Some(recovery.seal(key));
+        }
+        self
+    }
+
+    pub fn has_recovery(&self) -> bool {
+        self.recovery.is_some()
+    }
+
+    /// Decrypt with the secret part of the recovery key, instead of a password
+    #[must_use]
+    pub fn decrypt_recovery<T: Content>(self, recovery_secret: &[u8]) -> Option<Vault<T>> {
+        let key = self.recovery.as_ref()?.open(recovery_secret)?;
+        self.decrypt_key(&key)
+    }
+
+    /// Password of a member is accepted for a shared vault
     #[must_use]
     pub fn decrypt<T: Content>(self, password: &str) -> Option<Vault<T>> {
-        let key = VaultKey::reconstruct(password, self.salt);
-        let compressed = secretbox::open(&self.data, &self.nonce, &key.key).ok()?;
-        let mut gz = GzDecoder::new(compressed.as_slice());
-        let mut plaintext: Vec<u8> = Vec::new();
-        gz.read_to_end(&mut plaintext).expect("Decompression failed");
-        let vault: Vault<T> = serde_json::from_slice(plaintext.as_slice()).expect("Invalid JSON");
-        Some(vault)
+        let key = self.key_for(password)?;
+        self.decrypt_key(&key)
+    }
+
+    /// Decrypt from a stream. Vaults written with `Vault::encrypt_to` are decrypted chunk by chunk,
+    /// and older versions are read to memory first.
+    /// Errors if the header is invalid, and returns None if the password is wrong or the data corrupted.
+    pub fn decrypt_from<T: Content, R: Read>(mut reader: R, password: &str) -> VResult<Option<Vault<T>>> {
+        // Magic byte and version
+        let mut prefix = [0u8; 5];
+        reader.read_exact(&mut prefix).map_err(|_| Error::VaultCorrupted)?;
+        let version = u32::from_le_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]);
+        if version & !RECIPIENT_LIMITS != STREAM_VERSION {
+            let mut data = prefix.to_vec();
+            reader.read_to_end(&mut data)?;
+            return Ok(Self::from_bytes(&data)?.decrypt(password));
+        }
+        let mut reader = (&prefix[..]).chain(reader);
+        let header = Self::read_header(&mut reader, MAX_HEADER_SIZE)?;
+        Ok(header
+            .key_for(password)
+            .and_then(|key| Self::open_stream(reader, header.nonce, &key)))
+    }
+
+    /// Vault key for a password, or for the password of a member of a shared vault
+    fn key_for(&self, password: &str) -> Option<secretbox::Key> {
+        let content_password;
+        let password = if self.recipients.is_empty() {
+            password
+        } else {
+            content_password = self.recipients.iter().find_map(|r| r.unseal(password))?;
+            &content_password
+        };
+        Some(VaultKey::reconstruct(password, self.salt, self.kdf).key)
+    }
+
+    /// Decrypt and decompress chunks of a streamed vault as they are read.
+    /// Decryption and decompression are interleaved, so both are measured as decryption.
+    fn open_stream<T: Content, R: Read>(
+        reader: R, nonce: secretbox::Nonce, key: &secretbox::Key,
+    ) -> Option<Vault<T>> {
+        timings::measure(Phase::Decryption, || {
+            let mut gz = GzDecoder::new(ChunkReader::new(reader, key.clone(), nonce));
+            let vault = serde_json::from_reader(&mut gz).ok()?;
+            // Truncated or extended streams are only detected after the last chunk
+            io::copy(&mut gz.into_inner(), &mut io::sink()).ok()?;
+            Some(vault)
+        })
+    }
+
+    fn decrypt_key<T: Content>(self, key: &secretbox::Key) -> Option<Vault<T>> {
+        if self.format() == STREAM_VERSION {
+            return Self::open_stream(self.data.as_slice(), self.nonce, key);
+        }
+        let compressed =
+            timings::measure(Phase::Decryption, || secretbox::open(&self.data, &self.nonce, key)).ok()?;
+        timings::measure(Phase::Decompression, || {
+            let mut gz = GzDecoder::new(compressed.as_slice());
+            let mut plaintext: Vec<u8> = Vec::new();
+            // Authenticated, but a member of a shared vault could still have written anything
+            gz.read_to_end(&mut plaintext).ok()?;
+            serde_json::from_slice(plaintext.as_slice()).ok()
+        })
+    }
+
+    pub fn salt(&self) -> pwhash::Salt {
+        self.salt
+    }
+
+    pub fn kdf(&self) -> KdfParams {
+        self.kdf
+    }
+
+    pub fn is_shared(&self) -> bool {
+        !self.recipients.is_empty()
     }
 
     pub fn to_bytes(&self) -> Vec<u8> {
-        bincode::serialize(self).unwrap()
+        let recipients: Vec<HeaderRecipient> = self.recipients.iter().map(Recipient::to_header).collect();
+        let mut bytes = match self.format() {
+            0 => bincode::serialize(self).unwrap(),
+            1 => bincode::serialize(&(self, self.kdf)).unwrap(),
+            2 => bincode::serialize(&(self, self.kdf, &recipients)).unwrap(),
+            3 => bincode::serialize(&(self, self.kdf, &recipients, &self.recovery)).unwrap(),
+            _ => {
+                // Same layout as the struct fields, but with empty data and the chunks after the header
+                let empty: &[u8] = &[];
+                bincode::serialize(&(
+                    self.magic,
+                    self.version,
+                    self.salt,
+                    self.nonce,
+                    empty,
+                    self.kdf,
+                    &recipients,
+                    &self.recovery,
+                ))
+                .unwrap()
+            },
+        };
+        if self.version & RECIPIENT_LIMITS != 0 {
+            let limits: Vec<KdfParams> = self.recipients.iter().map(|r| r.member.kdf).collect();
+            bytes.extend(bincode::serialize(&limits).unwrap());
+        }
+        if self.format() == STREAM_VERSION {
+            bytes.extend(&self.data);
+        }
+        bytes
     }
 
-    /// Only errors when decoding fails
-    #[must_use]
-    pub fn from_bytes(data: &[u8]) -> Result<Self, ()> {
-        let data: Self = bincode::deserialize(data).map_err(|_| ())?;
-        assert!(data.magic == MAGIC, "Invalid magic byte");
-        assert!(data.version == VERSION, "Unsupported version");
-        Ok(data)
+    /// Only errors when decoding fails, or the format is not supported
+    pub fn from_bytes(data: &[u8]) -> VResult<Self> {
+        let mut reader = data;
+        let mut result = Self::read_header(&mut reader, data.len() as u64)?;
+        if result.format() == STREAM_VERSION {
+            result.data = reader.to_vec();
+        }
+        Ok(result)
+    }
+
+    /// Read the header, including the data of versions before 4.
+    /// Each field is limited to `limit` bytes, so that lengths in a corrupted file
+    /// can't cause large allocations. Trailing bytes of newer versions are ignored.
+    fn read_header<R: Read>(reader: &mut R, limit: u64) -> VResult<Self> {
+        fn deserialize<R: Read, T: DeserializeOwned>(reader: &mut R, limit: u64) -> VResult<T> {
+            bincode::config()
+                .limit(limit)
+                .deserialize_from(reader)
+                .map_err(|_| Error::VaultCorrupted)
+        }
+
+        let mut result: Self = deserialize(reader, limit)?;
+        if result.magic != MAGIC {
+            return Err(Error::VaultCorrupted);
+        } else if result.format() > VERSION || (result.format() < 2 && result.version & RECIPIENT_LIMITS != 0)
+        {
+            return Err(Error::UnsupportedVersion(result.version));
+        }
+        if result.format() >= 1 {
+            result.kdf = deserialize(reader, limit)?;
+        }
+        let mut recipients: Vec<HeaderRecipient> = Vec::new();
+        if result.format() >= 2 {
+            recipients = deserialize(reader, limit)?;
+        }
+        if result.format() >= 3 {
+            result.recovery = deserialize(reader, limit)?;
+        }
+        let limits: Vec<KdfParams> = if result.version & RECIPIENT_LIMITS != 0 {
+            deserialize(reader, limit)?
+        } else {
+            vec![KdfParams::default(); recipients.len()]
+        };
+        if !result.kdf.is_supported()
+            || limits.len() != recipients.len()
+            || !limits.iter().all(KdfParams::is_supported)
+        {
+            return Err(Error::VaultCorrupted);
+        }
+        result.recipients = recipients
+            .into_iter()
+            .zip(limits)
+            .map(|(r, kdf)| Recipient::from_header(r, kdf))
+            .collect();
+        Ok(result)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{EncryptedVault, Vault};
+    use super::{EncryptedVault, KdfParams, MemberKey, RecoveryKey, SharedKey, Vault};
 
     #[test]
     fn encrypt_decrypt() {
@@ -150,6 +870,256 @@ mod tests {
         assert_eq!(v, v2);
     }
 
+    #[test]
+    fn encrypt_deterministic() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let password = "TestPass";
+        let salt = Vault::new(1u32).encrypt(password).salt();
+        let kdf = KdfParams::default();
+        let ec1 = Vault::new(1337u32).encrypt_deterministic(password, salt, kdf);
+        let ec2 = Vault::new(1337u32).encrypt_deterministic(password, salt, kdf);
+        assert_eq!(ec1.to_bytes(), ec2.to_bytes());
+
+        let ec3 = Vault::new(1338u32).encrypt_deterministic(password, salt, kdf);
+        assert_ne!(ec1.nonce, ec3.nonce);
+        assert_eq!(ec3.decrypt(password), Some(Vault::new(1338u32)));
+    }
+
+    #[test]
+    fn encrypt_stream() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let password = "TestPass";
+        // Poorly compressible, so that it takes multiple chunks
+        let v = Vault::new((0..100_000u32).map(|i| i.wrapping_mul(2_654_435_761)).collect::<Vec<_>>());
+        let (recovery, recovery_secret) = RecoveryKey::generate();
+        let bytes = v
+            .encrypt_to(Vec::new(), password, KdfParams::default(), &[], Some(&recovery))
+            .expect("Encrypt");
+        assert_eq!(EncryptedVault::decrypt_from(bytes.as_slice(), password).unwrap(), Some(v.clone()));
+        assert_eq!(EncryptedVault::decrypt_from::<Vec<u32>, _>(bytes.as_slice(), "WrongPass").unwrap(), None);
+
+        // Streamed vaults can be read to memory as well
+        let ec = EncryptedVault::from_bytes(&bytes).expect("Decode");
+        assert_eq!(ec.to_bytes(), bytes);
+        assert_eq!(ec.clone().decrypt(password), Some(v.clone()));
+        assert_eq!(ec.decrypt_recovery(&recovery_secret), Some(v.clone()));
+
+        let truncated = &bytes[..bytes.len() - 1];
+        assert_eq!(EncryptedVault::decrypt_from::<Vec<u32>, _>(truncated, password).unwrap(), None);
+
+        // Older versions are read from streams too
+        let old = Vault::new(1337u32).encrypt(password).to_bytes();
+        let decrypted = EncryptedVault::decrypt_from(old.as_slice(), password).unwrap();
+        assert_eq!(decrypted, Some(Vault::new(1337u32)));
+    }
+
+    #[test]
+    fn encrypt_kdf_params() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let password = "TestPass";
+        let default = Vault::new(1337u32).encrypt(password);
+        assert_eq!(default.version, 0);
+
+        let kdf = KdfParams {
+            ops_limit: KdfParams::default().ops_limit * 2,
+            mem_limit: KdfParams::default().mem_limit,
+        };
+        let ec = Vault::new(1337u32).encrypt_with(password, kdf);
+        assert_eq!(ec.version, 1);
+        let ec2 = EncryptedVault::from_bytes(&ec.to_bytes()).expect("Decode");
+        assert_eq!(ec2.kdf(), kdf);
+        assert_eq!(ec2.decrypt(password), Some(Vault::new(1337u32)));
+    }
+
+    #[test]
+    fn encrypt_shared() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut shared = SharedKey::new("OwnerPass", KdfParams::default());
+        let member = MemberKey::new("MemberPass", KdfParams::default());
+        assert_eq!(MemberKey::decode(&member.encode()).expect("Decode"), member);
+        assert!(shared.add(&member));
+        assert!(!shared.add(&member));
+
+        let encrypt = |shared: &SharedKey| {
+            let ec = Vault::new(1337u32)
+                .encrypt(shared.content_password())
+                .with_recipients(shared.recipients());
+            EncryptedVault::from_bytes(&ec.to_bytes()).expect("Decode")
+        };
+        let ec = encrypt(&shared);
+        assert!(ec.is_shared());
+        assert_eq!(ec.clone().decrypt("OwnerPass"), Some(Vault::new(1337u32)));
+        assert_eq!(ec.clone().decrypt("MemberPass"), Some(Vault::new(1337u32)));
+        assert_eq!(ec.decrypt::<u32>("WrongPass"), None);
+
+        let old_password = shared.content_password().to_owned();
+        assert!(shared.remove(&member));
+        assert_ne!(shared.content_password(), old_password);
+        let ec = encrypt(&shared);
+        assert_eq!(ec.clone().decrypt("OwnerPass"), Some(Vault::new(1337u32)));
+        assert_eq!(ec.decrypt::<u32>("MemberPass"), None);
+
+        assert!(shared.change_member_password("OwnerPass", "NewPass", KdfParams::default()));
+        assert!(!shared.change_member_password("OwnerPass", "NewPass", KdfParams::default()));
+        assert_eq!(encrypt(&shared).decrypt("NewPass"), Some(Vault::new(1337u32)));
+
+        let old_password = shared.content_password().to_owned();
+        shared.rotate();
+        assert_ne!(shared.content_password(), old_password);
+        assert_eq!(shared.recipients().len(), 1);
+        assert_eq!(encrypt(&shared).decrypt("NewPass"), Some(Vault::new(1337u32)));
+    }
+
+    #[test]
+    fn shared_key_merge() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let member = |password: &str| MemberKey::new(password, KdfParams::default());
+        let (removed, kept, added) = (member("RemovedPass"), member("KeptPass"), member("AddedPass"));
+        let mut base = SharedKey::new("OwnerPass", KdfParams::default());
+        base.add(&removed);
+        base.add(&kept);
+        let mut with_removal = base.clone();
+        assert!(with_removal.remove(&removed));
+        let mut with_addition = base.clone();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert!(with_addition.add(&added));
+
+        let merged = with_removal.merge(&with_addition);
+        let members =
+            |key: &SharedKey| key.recipients().iter().map(|r| r.member().clone()).collect::<Vec<_>>();
+        assert_eq!(members(&merged).len(), 3);
+        assert!(members(&merged).contains(&added) && !members(&merged).contains(&removed));
+        assert_eq!(members(&with_addition.merge(&with_removal)), members(&merged));
+        // The newer version still had the removed member, so its content password is replaced
+        assert_ne!(merged.content_password(), with_addition.content_password());
+        assert_ne!(merged.content_password(), base.content_password());
+        assert_eq!(merged.merge(&merged), merged);
+        assert_eq!(merged.merge(&base), merged);
+
+        let ec = Vault::new(1337u32)
+            .encrypt(merged.content_password())
+            .with_recipients(&merged.header_recipients());
+        let ec = EncryptedVault::from_bytes(&ec.to_bytes()).expect("Decode");
+        assert_eq!(ec.clone().decrypt("AddedPass"), Some(Vault::new(1337u32)));
+        assert_eq!(ec.decrypt::<u32>("RemovedPass"), None);
+
+        // Removed keys can't be added back
+        assert!(!merged.clone().add(&removed));
+    }
+
+    #[test]
+    fn encrypt_key_slots() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut shared = SharedKey::without_members();
+        let slot = MemberKey::new("SlotPass", KdfParams::default());
+        assert!(shared.add_slot(&MemberKey::new("OwnerPass", KdfParams::default())));
+        assert!(shared.add_slot(&slot));
+        assert!(!shared.is_shared());
+        assert!(shared.recipients().is_empty());
+
+        let encrypt = |shared: &SharedKey| {
+            let ec = Vault::new(1337u32)
+                .encrypt(shared.content_password())
+                .with_recipients(&shared.header_recipients());
+            EncryptedVault::from_bytes(&ec.to_bytes()).expect("Decode")
+        };
+        assert_eq!(encrypt(&shared).decrypt("SlotPass"), Some(Vault::new(1337u32)));
+
+        // Slots and members are kept apart
+        assert!(shared.add(&MemberKey::new("MemberPass", KdfParams::default())));
+        assert_eq!((shared.recipients().len(), shared.slots().len()), (1, 2));
+        assert!(shared.remove_slot(&slot));
+        assert!(!shared.remove(&slot));
+        let ec = encrypt(&shared);
+        assert_eq!(ec.clone().decrypt("OwnerPass"), Some(Vault::new(1337u32)));
+        assert_eq!(ec.clone().decrypt("MemberPass"), Some(Vault::new(1337u32)));
+        assert_eq!(ec.decrypt::<u32>("SlotPass"), None);
+    }
+
+    #[test]
+    fn key_slot_limits() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let kdf = KdfParams {
+            ops_limit: 1,
+            mem_limit: 1 << 20,
+        };
+        let slot = MemberKey::new("SlotPass", kdf);
+        assert_eq!(MemberKey::decode(&slot.encode()).expect("Decode"), slot);
+
+        let mut shared = SharedKey::without_members();
+        assert!(shared.add_slot(&MemberKey::new("OwnerPass", KdfParams::default())));
+        assert!(shared.add_slot(&slot));
+        let encrypt = |shared: &SharedKey, stream: bool| {
+            let v = Vault::new(1337u32);
+            let bytes = if stream {
+                let recipients = shared.header_recipients();
+                v.encrypt_to(Vec::new(), shared.content_password(), KdfParams::default(), &recipients, None)
+                    .expect("Encrypt")
+            } else {
+                v.encrypt(shared.content_password()).with_recipients(&shared.header_recipients()).to_bytes()
+            };
+            EncryptedVault::from_bytes(&bytes).expect("Decode")
+        };
+        for stream in [false, true] {
+            let ec = encrypt(&shared, stream);
+            assert_eq!(ec.recipients[1].member().kdf(), kdf);
+            assert_eq!(ec.clone().decrypt("SlotPass"), Some(Vault::new(1337u32)));
+            assert_eq!(ec.decrypt("OwnerPass"), Some(Vault::new(1337u32)));
+        }
+
+        // Limits are only stored when needed, so that older versions can still read the vault
+        assert!(shared.remove_slot(&slot));
+        assert_eq!(encrypt(&shared, false).version, 2);
+    }
+
+    #[test]
+    fn encrypt_recovery() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let (recovery, secret) = RecoveryKey::generate();
+        let (_, other_secret) = RecoveryKey::generate();
+        let salt = Vault::new(1u32).encrypt("TestPass").salt();
+        let kdf = KdfParams::default();
+        let encrypt = || {
+            Vault::new(1337u32)
+                .encrypt_deterministic("TestPass", salt, kdf)
+                .with_recovery(Some(&recovery), "TestPass")
+                .to_bytes()
+        };
+        assert_eq!(encrypt(), encrypt());
+
+        let ec = EncryptedVault::from_bytes(&encrypt()).expect("Decode");
+        assert!(ec.has_recovery());
+        assert_eq!(ec.clone().decrypt_recovery(&secret), Some(Vault::new(1337u32)));
+        assert_eq!(ec.clone().decrypt_recovery::<u32>(&other_secret), None);
+        assert_eq!(ec.decrypt("TestPass"), Some(Vault::new(1337u32)));
+    }
+
+    #[test]
+    fn decode_invalid() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut bytes = Vault::new(1337u32).encrypt("TestPass").to_bytes();
+        bytes[0] = 0;
+        assert!(EncryptedVault::from_bytes(&bytes).is_err());
+        assert!(EncryptedVault::from_bytes(&[1, 2, 3]).is_err());
+
+        let mut ec = Vault::new(1337u32).encrypt("TestPass");
+        ec.version = 1;
+        ec.kdf = KdfParams {
+            ops_limit: 0,
+            mem_limit: KdfParams::default().mem_limit,
+        };
+        assert!(EncryptedVault::from_bytes(&ec.to_bytes()).is_err());
+    }
+
     #[test]
     fn encrypt_decrypt_wrongpass() {
         rust_sodium::init().expect("Sodium init failed");
@@ -159,6 +1129,6 @@ mod tests {
         let bytes = ec.clone().to_bytes();
         let ec2 = EncryptedVault::from_bytes(&bytes).expect("Decode");
         assert_eq!(ec, ec2);
-        assert!(ec2.decrypt::<u32>("WrongPass") == None);
+        assert!(ec2.decrypt::<u32>("WrongPass").is_none());
     }
 }
@@ -0,0 +1,104 @@
+//! Wi-Fi networks, stored in entries of the wifi kind with the passphrase as the password,
+//! and shared as the `WIFI:` QR code that phones join networks with.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    /// WPA, WPA2 or WPA3 with a passphrase
+    Wpa,
+    Wep,
+    /// Open network
+    None,
+}
+impl Security {
+    /// Name used in the QR code
+    fn qr_name(self) -> &'static str {
+        match self {
+            Security::Wpa => "WPA",
+            Security::Wep => "WEP",
+            Security::None => "nopass",
+        }
+    }
+}
+impl fmt::Display for Security {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Security::Wpa => "wpa",
+            Security::Wep => "wep",
+            Security::None => "none",
+        };
+        write!(f, "{}", name)
+    }
+}
+impl FromStr for Security {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "wpa" | "wpa2" | "wpa3" => Ok(Security::Wpa),
+            "wep" => Ok(Security::Wep),
+            "none" | "open" | "nopass" => Ok(Security::None),
+            _ => Err(format!("Unknown security {}, expected wpa, wep or none", s)),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Wifi {
+    pub ssid: String,
+    pub security: Security,
+    /// Network doesn't broadcast its name, so it must be joined by name
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub hidden: bool,
+}
+impl Wifi {
+    /// Text of the QR code, like `WIFI:T:WPA;S:home;P:passphrase;;`
+    pub fn qr_text(&self, passphrase: Option<&str>) -> String {
+        let mut result = format!("WIFI:T:{};S:{};", self.security.qr_name(), escape(&self.ssid));
+        if let (Some(passphrase), false) = (passphrase, self.security == Security::None) {
+            result.push_str(&format!("P:{};", escape(passphrase)));
+        }
+        if self.hidden {
+            result.push_str("H:true;");
+        }
+        result.push(';');
+        result
+    }
+}
+
+/// Backslash before the characters that separate fields of the QR code
+fn escape(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    for c in s.chars() {
+        if ['\\', ';', ',', ':', '"'].contains(&c) {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Security, Wifi};
+
+    #[test]
+    fn wifi_qr_text() {
+        let mut wifi = Wifi {
+            ssid: "Home; 5GHz".to_owned(),
+            security: "WPA2".parse().unwrap(),
+            hidden: false,
+        };
+        assert_eq!(wifi.qr_text(Some("pass:word")), r"WIFI:T:WPA;S:Home\; 5GHz;P:pass\:word;;");
+
+        wifi.security = Security::None;
+        wifi.hidden = true;
+        assert_eq!(wifi.qr_text(Some("ignored")), r"WIFI:T:nopass;S:Home\; 5GHz;H:true;;");
+        assert!("wpa4".parse::<Security>().is_err());
+    }
+}
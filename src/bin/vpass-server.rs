@@ -0,0 +1,182 @@
+//! Self-hostable synchronization server for the `Http` provider.
+//!
+//! All requests require header `Authorization: Bearer <token>`.
+//! Keys are percent-encoded in the path, and can contain slashes.
+//! Revisions are given in `ETag` headers, as quoted hex strings.
+//!
+//! * `GET /ping`: Check access, returns `200 OK`
+//! * `GET /keys`: List keys as a JSON array
+//! * `GET /keys/{key}`: Read value, `404 Not Found` if missing
+//! * `HEAD /keys/{key}`: Size in `Content-Length` and revision in `ETag`, `404 Not Found` if missing
+//! * `PUT /keys/{key}` with `If-None-Match: *`: Create, `412 Precondition Failed` if the key exists
+//! * `PUT /keys/{key}` with `If-Match: "{revision}"`: Update, `412 Precondition Failed` on stale revision
+//! * `DELETE /keys/{key}` with `If-Match: "{revision}"`: Delete, `412 Precondition Failed` on stale revision
+//!
+//! Requests are handled one at a time, so revision checks and writes are atomic.
+//! The marker and lock files of the data directory are not keys, and requests for them are refused
+//! with `403 Forbidden`.
+
+use std::fs;
+use std::path::PathBuf;
+
+use rust_sodium::utils::memcmp;
+use serde_json::json;
+use structopt::StructOpt;
+use tiny_http::{Header, Method, Request, Response, Server, StatusCode};
+use tracing::{info, warn};
+
+use vpass::cli::logging::{self, LogFormat, Redacted};
+use vpass::sync::providers::filesystem::{is_reserved_key, FileSystem};
+use vpass::sync::providers::http::{decode_key, decode_revision, encode_revision};
+use vpass::sync::{Error, SyncProvider};
+
+#[derive(StructOpt, Debug)]
+#[structopt(rename_all = "kebab-case")]
+struct Opt {
+    /// Directory for stored data
+    #[structopt(short, long)]
+    dir: PathBuf,
+
+    /// Address to listen on
+    #[structopt(short, long, default_value = "127.0.0.1:8080")]
+    bind: String,
+
+    /// Access token required from clients
+    #[structopt(short, long, env = "VPASS_SERVER_TOKEN", hide_env_values = true)]
+    token: Redacted<String>,
+
+    /// Format of logs on stderr: text or json
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
+}
+
+type HttpResponse = Response<std::io::Cursor<Vec<u8>>>;
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn get_header(req: &Request, name: &'static str) -> Option<String> {
+    req.headers()
+        .iter()
+        .find(|h| h.field.equiv(name))
+        .map(|h| h.value.as_str().to_owned())
+}
+
+fn status(code: u16) -> HttpResponse {
+    Response::from_data(Vec::new()).with_status_code(code)
+}
+
+fn error_response(error: Error) -> HttpResponse {
+    match error {
+        Error::NoSuchKey(_) => status(404),
+        Error::InvalidUpdateKey | Error::KeyAlreadyExists(_) => status(412),
+        other => {
+            warn!("Storage error: {:?}", other);
+            status(500)
+        },
+    }
+}
+
+/// Compared in constant time, so that the token can't be guessed from response times
+fn authorized(req: &Request, token: &str) -> bool {
+    let expected = format!("Bearer {}", token);
+    get_header(req, "Authorization").is_some_and(|h| memcmp(h.as_bytes(), expected.as_bytes()))
+}
+
+fn handle(repo: &mut FileSystem, token: &str, req: &mut Request) -> HttpResponse {
+    if !authorized(req, token) {
+        return status(401);
+    }
+
+    let url = req.url().to_owned();
+    if url == "/ping" {
+        return status(200);
+    }
+    if url == "/keys" {
+        return match repo.list() {
+            Ok(keys) => Response::from_data(json!(keys).to_string().into_bytes())
+                .with_header(header("Content-Type", "application/json")),
+            Err(e) => error_response(e),
+        };
+    }
+
+    let key = match url.strip_prefix("/keys/").and_then(decode_key) {
+        Some(key) => key,
+        None => return status(404),
+    };
+    if is_reserved_key(&key) {
+        return status(403);
+    }
+    let if_match = get_header(req, "If-Match").and_then(|r| decode_revision(&r));
+
+    match req.method() {
+        Method::Get => match repo.read(&key) {
+            Ok((data, uk)) => Response::from_data(data).with_header(header("ETag", &encode_revision(&uk))),
+            Err(e) => error_response(e),
+        },
+        // The body is left out by tiny_http, but the length is still sent without chunking
+        Method::Head => match repo.stat(&key) {
+            Ok((size, uk)) => Response::new(
+                StatusCode(200),
+                vec![header("ETag", &encode_revision(&uk))],
+                std::io::Cursor::new(Vec::new()),
+                Some(size as usize),
+                None,
+            )
+            .with_chunked_threshold(usize::MAX),
+            Err(e) => error_response(e),
+        },
+        Method::Put => {
+            let mut data = Vec::new();
+            if req.as_reader().read_to_end(&mut data).is_err() {
+                return status(400);
+            }
+            let result = if let Some(rev) = if_match {
+                repo.update(&key, data, rev)
+            } else if get_header(req, "If-None-Match").as_deref() == Some("*") {
+                if repo.exists(&key).unwrap_or(false) {
+                    Err(Error::KeyAlreadyExists(key.clone()))
+                } else {
+                    repo.create(&key, data)
+                }
+            } else {
+                return status(428);
+            };
+            match result.and_then(|()| repo.read(&key)) {
+                Ok((_, uk)) => status(200).with_header(header("ETag", &encode_revision(&uk))),
+                Err(e) => error_response(e),
+            }
+        },
+        Method::Delete => match if_match {
+            Some(rev) => match repo.delete(&key, rev) {
+                Ok(()) => status(204),
+                Err(e) => error_response(e),
+            },
+            None => status(428),
+        },
+        _ => status(405),
+    }
+}
+
+fn main() {
+    rust_sodium::init().expect("Sodium init failed");
+    let opt = Opt::from_args();
+    logging::init(opt.log_format, 0);
+
+    fs::create_dir_all(&opt.dir).expect("Unable to create data directory");
+    if !opt.dir.join("VPassFile").exists() {
+        fs::write(opt.dir.join("VPassFile"), []).expect("Unable to write VPassFile");
+    }
+    let mut repo = FileSystem::load(&json!({ "path": opt.dir })).expect("Invalid data directory");
+
+    let server = Server::http(&opt.bind).expect("Unable to start server");
+    info!("Listening on {}", opt.bind);
+    for mut req in server.incoming_requests() {
+        info!("{} {}", req.method(), req.url());
+        let res = handle(&mut repo, &opt.token, &mut req);
+        if let Err(e) = req.respond(res) {
+            warn!("Unable to respond: {:?}", e);
+        }
+    }
+}
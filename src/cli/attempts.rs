@@ -0,0 +1,102 @@
+//! Slows down repeated unlock attempts with wrong passwords.
+//! Consecutive failures are counted per vault in a sidecar file in the vault directory,
+//! and after `FREE_ATTEMPTS` failures each attempt waits twice as long as the previous one.
+//! This doesn't stop attacks against a copied vault file, only repeated use of the CLI.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use tracing::warn;
+
+use super::{cfg, error::*, opt::OptRoot, paths, stdio};
+use crate::Book;
+
+/// Failures allowed before any delay, e.g. for typos
+const FREE_ATTEMPTS: u32 = 3;
+/// Upper limit for the delay
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+fn sidecar_path(args: &OptRoot) -> VResult<PathBuf> {
+    Ok(paths::data_dir(args)?.join("failed_attempts.json"))
+}
+
+/// Failure counts by vault path
+fn load(args: &OptRoot) -> VResult<HashMap<String, u32>> {
+    let p = sidecar_path(args)?;
+    if p.exists() {
+        Ok(serde_json::from_slice(&fs::read(p)?).unwrap_or_default())
+    } else {
+        Ok(HashMap::new())
+    }
+}
+
+fn save(args: &OptRoot, attempts: &HashMap<String, u32>) -> VResult<()> {
+    fs::write(sidecar_path(args)?, serde_json::to_vec(attempts).unwrap())?;
+    Ok(())
+}
+
+/// Delay before the next attempt after `failures` consecutive failures
+pub fn delay_for(failures: u32) -> Duration {
+    if failures < FREE_ATTEMPTS {
+        Duration::from_secs(0)
+    } else {
+        let exponent = (failures - FREE_ATTEMPTS).min(6);
+        Duration::from_secs(1 << exponent).min(MAX_DELAY)
+    }
+}
+
+/// Read a vault like `vpass::read`, delaying the attempt if previous ones have failed.
+/// Vaults piped through standard input are not slowed down.
+pub fn read(args: &OptRoot, path: &Path, password: &str) -> VResult<Book> {
+    if stdio::is_stdio(path) {
+        return stdio::read(password);
+    }
+    if !cfg::read(args)?.slow_down_wrong_passwords {
+        return crate::read(path, password);
+    }
+
+    let key = fs::canonicalize(path)
+        .unwrap_or_else(|_| path.to_owned())
+        .to_string_lossy()
+        .into_owned();
+    let mut attempts = load(args)?;
+    let failures = attempts.get(&key).cloned().unwrap_or(0);
+    let delay = delay_for(failures);
+    if delay > Duration::from_secs(0) {
+        warn!("{} failed unlock attempts, waiting {} s", failures, delay.as_secs());
+        thread::sleep(delay);
+    }
+
+    match crate::read(path, password) {
+        Err(Error::WrongPassword) => {
+            attempts.insert(key, failures + 1);
+            save(args, &attempts)?;
+            Err(Error::WrongPassword)
+        },
+        Ok(book) => {
+            if attempts.remove(&key).is_some() {
+                save(args, &attempts)?;
+            }
+            Ok(book)
+        },
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delay_for, MAX_DELAY};
+    use std::time::Duration;
+
+    #[test]
+    fn delays() {
+        assert_eq!(delay_for(0), Duration::from_secs(0));
+        assert_eq!(delay_for(2), Duration::from_secs(0));
+        assert_eq!(delay_for(3), Duration::from_secs(1));
+        assert_eq!(delay_for(5), Duration::from_secs(4));
+        assert_eq!(delay_for(100), MAX_DELAY);
+    }
+}
@@ -0,0 +1,128 @@
+//! Optional local log of vpass invocations, enabled with the `audit_log` config option.
+//! Each line is a JSON entry with the command, vault and item names, and whether it succeeded.
+//! Secrets or other arguments are never recorded.
+
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use super::opt::{CardSubCommand, NoteSubCommand, OptRoot, SubCommand, WifiSubCommand};
+use super::{cfg, error::*, paths};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    /// Subcommand, e.g. `show` or `sync resolve-conflicts`
+    pub command: String,
+    pub vault: Option<String>,
+    /// Names of items given as arguments
+    pub items: Vec<String>,
+    pub success: bool,
+}
+impl Entry {
+    fn new(args: &OptRoot, success: bool) -> Self {
+        let name: &'static str = (&args.subcommand).into();
+        let command = match args.subcommand {
+            SubCommand::Vault(ref v) => format!("{} {}", name, <&'static str>::from(&v.subcommand)),
+            SubCommand::Sync(ref s) => match s.subcommand {
+                Some(ref sc) => format!("{} {}", name, <&'static str>::from(sc)),
+                None => name.to_owned(),
+            },
+            SubCommand::Note(ref n) => format!("{} {}", name, <&'static str>::from(&n.subcommand)),
+            SubCommand::Card(ref n) => format!("{} {}", name, <&'static str>::from(&n.subcommand)),
+            SubCommand::Wifi(ref n) => format!("{} {}", name, <&'static str>::from(&n.subcommand)),
+            SubCommand::Show(ref c) if c.password => format!("{} --password", name),
+            _ => name.to_owned(),
+        };
+        let items = match args.subcommand {
+            SubCommand::Add(ref c) => vec![c.name.clone()],
+            SubCommand::Edit(ref c) => vec![c.name.clone()],
+            SubCommand::Rotate(ref c) => c.names.clone(),
+            SubCommand::Note(ref n) => match n.subcommand {
+                NoteSubCommand::Add(ref c) => vec![c.name.clone()],
+                NoteSubCommand::Edit(ref c) => vec![c.name.clone()],
+                NoteSubCommand::Rm(ref c) | NoteSubCommand::Show(ref c) => vec![c.name.clone()],
+                NoteSubCommand::New(ref c) => vec![c.name.clone()],
+                NoteSubCommand::Cat(ref c) | NoteSubCommand::Open(ref c) => vec![c.name.clone()],
+            },
+            SubCommand::Card(ref n) => match n.subcommand {
+                CardSubCommand::Add(ref c) => vec![c.name.clone()],
+                CardSubCommand::Show(ref c) => vec![c.name.clone()],
+                CardSubCommand::CopyNumber(ref c) => vec![c.name.clone()],
+            },
+            SubCommand::Wifi(ref n) => match n.subcommand {
+                WifiSubCommand::Add(ref c) => vec![c.name.clone()],
+                WifiSubCommand::Qr(ref c) => vec![c.name.clone()],
+                WifiSubCommand::Import(_) => Vec::new(),
+            },
+            SubCommand::Rename(ref c) => vec![c.old_name.clone(), c.new_name.clone()],
+            SubCommand::Remove(ref c) => c.names.clone(),
+            SubCommand::Archive(ref c) | SubCommand::Unarchive(ref c) => c.names.clone(),
+            SubCommand::Show(ref c) => c.names.clone(),
+            SubCommand::Get(ref c) => vec![c.name.clone()],
+            SubCommand::SshAdd(ref c) => vec![c.name.clone()],
+            SubCommand::SshAgent(ref c) => c.names.clone(),
+            SubCommand::Copy(ref c) => c.names.clone(),
+            SubCommand::Exec(ref c) => c.names.clone(),
+            SubCommand::Exists(ref c) => vec![c.name.clone()],
+            SubCommand::Publish(ref c) => vec![c.name.clone()],
+            _ => Vec::new(),
+        };
+        let vault = args
+            .vault_name
+            .clone()
+            .or_else(|| args.vault_file.as_ref().map(|p| p.to_string_lossy().into_owned()));
+        Entry {
+            time: Utc::now(),
+            command,
+            vault,
+            items,
+            success,
+        }
+    }
+}
+
+fn log_path(args: &OptRoot) -> VResult<PathBuf> {
+    Ok(paths::data_dir(args)?.join("audit.log"))
+}
+
+/// Append an entry for this invocation, if enabled in the config
+pub fn record(args: &OptRoot, success: bool) -> VResult<()> {
+    if args.disable_config || !paths::config_file(args)?.exists() || !cfg::read(args)?.audit_log {
+        return Ok(());
+    }
+    let mut options = OpenOptions::new();
+    options.append(true).create(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(log_path(args)?)?;
+    let mut line = serde_json::to_vec(&Entry::new(args, success))?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    Ok(())
+}
+
+/// All logged entries, oldest first
+pub fn read(args: &OptRoot) -> VResult<Vec<Entry>> {
+    let p = log_path(args)?;
+    if !p.exists() {
+        return Ok(Vec::new());
+    }
+    fs::read_to_string(p)?
+        .lines()
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+pub fn clear(args: &OptRoot) -> VResult<()> {
+    let p = log_path(args)?;
+    if p.exists() {
+        fs::remove_file(p)?;
+    }
+    Ok(())
+}
@@ -1,17 +1,189 @@
 #![allow(dead_code)]
 
 use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs;
+use std::process::Command;
 
-use super::{error::*, opt, paths};
+use super::{error::*, i18n, interactive, interrupt, opt, paths};
+use crate::backend::book::DeviceId;
+use crate::backend::generate::Preset;
+use crate::backend::vault::{EncryptedVault, KdfParams, Vault};
+
+/// Environment variable for the config passphrase, used instead of prompting
+pub const PASSWORD_ENV: &str = "VPASS_CONFIG_PASSWORD";
+
+thread_local! {
+    /// Passphrase is only asked once per invocation
+    static PASSWORD: RefCell<Option<String>> = const { RefCell::new(None) };
+    /// Encrypted config file contents and the config decrypted from them,
+    /// so that the key is derived only once per invocation, unless the file changes
+    static DECRYPTED: RefCell<Option<(Vec<u8>, Config)>> = const { RefCell::new(None) };
+}
+
+fn config_password(prompt: &str) -> VResult<String> {
+    if let Ok(pw) = std::env::var(PASSWORD_ENV) {
+        return Ok(pw);
+    }
+    if let Some(pw) = PASSWORD.with(|p| p.borrow().clone()) {
+        return Ok(pw);
+    }
+    let pw = interactive::prompt_password(prompt)?;
+    PASSWORD.with(|p| *p.borrow_mut() = Some(pw.clone()));
+    Ok(pw)
+}
+
+/// Overwrite the cached passphrase and decrypted config, called before exiting
+pub fn forget_password() {
+    PASSWORD.with(|p| {
+        if let Some(mut pw) = p.borrow_mut().take() {
+            interrupt::zeroize(&mut pw);
+        }
+    });
+    DECRYPTED.with(|d| {
+        if let Some((_, mut c)) = d.borrow_mut().take() {
+            if let Some(ref mut command) = c.password_command {
+                interrupt::zeroize(command);
+            }
+        }
+    });
+}
+
+fn decrypt(data: &[u8], encrypted: EncryptedVault) -> VResult<Config> {
+    let cached = DECRYPTED.with(|d| {
+        d.borrow()
+            .as_ref()
+            .filter(|(cached, _)| cached == data)
+            .map(|(_, c)| c.clone())
+    });
+    if let Some(c) = cached {
+        return Ok(c);
+    }
+    let c: Config = encrypted
+        .decrypt(&config_password(i18n::tr("Password [config]"))?)
+        .ok_or(Error::ConfigWrongPassword)?
+        .content;
+    DECRYPTED.with(|d| *d.borrow_mut() = Some((data.to_vec(), c.clone())));
+    Ok(c)
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Config {
     pub default_vault: Option<String>,
+    /// Match item names ignoring case and Unicode normalization form
+    #[serde(default)]
+    pub case_insensitive_names: bool,
+    /// Identifies changes made on this device
+    #[serde(default)]
+    pub device_id: Option<DeviceId>,
+    /// Shell command printing the vault password, used instead of prompting
+    #[serde(default)]
+    pub password_command: Option<String>,
+    /// Wait increasingly long before unlocking after consecutive wrong passwords
+    #[serde(default = "default_true")]
+    pub slow_down_wrong_passwords: bool,
+    /// Record invocations to a local log, see `vpass log`
+    #[serde(default)]
+    pub audit_log: bool,
+    /// Key derivation limits for new vaults and password changes, set by `bench-kdf --apply`
+    #[serde(default)]
+    pub kdf: Option<KdfParams>,
+    /// Keep an unencrypted list of entry names next to each vault for shell completion,
+    /// so that completing names doesn't need the password.
+    /// The names are then visible to anyone who can read the vault directory.
+    #[serde(default)]
+    pub name_cache: bool,
+    /// Language of prompts and messages, like `finnish` or `fi`, instead of the locale
+    #[serde(default)]
+    pub language: Option<String>,
+    /// Seconds between synchronizations done by `vpass daemon`
+    #[serde(default = "default_sync_interval")]
+    pub sync_interval: u64,
+    /// Vaults synchronized by `vpass daemon`, all vaults with synchronization set up if empty
+    #[serde(default)]
+    pub daemon_vaults: Vec<String>,
+    /// Seconds until `vpass ssh-agent` forgets its keys after they were last used,
+    /// 0 to keep them until the computer is suspended
+    #[serde(default = "default_lock_seconds")]
+    pub agent_lock_seconds: u64,
+    /// Seconds after startup until `vpass daemon` forgets the passwords asked on startup,
+    /// whether or not it has synchronized since, 0 to keep them until the computer is suspended
+    #[serde(default = "default_lock_seconds")]
+    pub daemon_lock_seconds: u64,
+    /// Lock `vpass daemon` and `vpass ssh-agent` also when the screen is locked,
+    /// where known from systemd-logind
+    #[serde(default = "default_true")]
+    pub lock_on_screen_lock: bool,
+    /// Vaults whose secrets are revealed only after confirming with Touch ID or Windows Hello
+    #[serde(default)]
+    pub biometric_vaults: Vec<String>,
+    /// Show desktop notifications, e.g. when synchronization fails or the clipboard is cleared
+    #[serde(default = "default_true")]
+    pub notifications: bool,
+    /// Named settings for generated passwords, see `vpass generate --save-preset`
+    #[serde(default)]
+    pub generator_presets: BTreeMap<String, Preset>,
+    /// Warn about passwords entered by hand with a lower estimated entropy, 0 to never warn
+    #[serde(default = "default_weak_password_bits")]
+    pub weak_password_bits: u32,
+    /// Seconds until `vpass copy` clears the clipboard, waiting until then, 0 to keep the contents
+    #[serde(default)]
+    pub clipboard_clear_seconds: u64,
+    /// Program choosing an entry for `vpass menu` from names given on stdin, like `dmenu`.
+    /// Defaults to `rofi -dmenu`, or `wofi --dmenu` on Wayland.
+    #[serde(default)]
+    pub menu_command: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_sync_interval() -> u64 {
+    15 * 60
+}
+
+fn default_lock_seconds() -> u64 {
+    15 * 60
+}
+
+fn default_weak_password_bits() -> u32 {
+    50
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_vault: None,
+            case_insensitive_names: false,
+            device_id: None,
+            password_command: None,
+            slow_down_wrong_passwords: true,
+            audit_log: false,
+            kdf: None,
+            name_cache: false,
+            language: None,
+            sync_interval: default_sync_interval(),
+            daemon_vaults: Vec::new(),
+            agent_lock_seconds: default_lock_seconds(),
+            daemon_lock_seconds: default_lock_seconds(),
+            lock_on_screen_lock: true,
+            biometric_vaults: Vec::new(),
+            notifications: true,
+            generator_presets: BTreeMap::new(),
+            weak_password_bits: default_weak_password_bits(),
+            clipboard_clear_seconds: 0,
+            menu_command: None,
+        }
+    }
 }
 impl Config {
-    pub fn default() -> Self {
-        Self { default_vault: None }
+    /// Default config with a new device id
+    pub fn new_device() -> Self {
+        Self {
+            device_id: Some(DeviceId::generate()),
+            ..Self::default()
+        }
     }
 
     pub fn to_json_pretty(&self) -> String {
@@ -25,6 +197,23 @@ impl Config {
     pub fn from_json_bytes(data: &[u8]) -> VResult<Self> {
         serde_json::from_slice(data).map_err(Error::ConfigInvalidJson)
     }
+
+    /// Set a field from `name=value`.
+    /// The value is parsed as JSON if possible, and used as a string otherwise.
+    pub fn set(&mut self, assignment: &str) -> VResult<()> {
+        let mut parts = assignment.splitn(2, '=');
+        let name = parts.next().unwrap();
+        let value = parts.next().ok_or(Error::ConfigInvalidAssignment)?;
+        let mut j = serde_json::to_value(&*self).unwrap();
+        let field = j
+            .as_object_mut()
+            .unwrap()
+            .get_mut(name)
+            .ok_or_else(|| Error::ConfigUnknownField(name.to_owned()))?;
+        *field = serde_json::from_str(value).unwrap_or_else(|_| serde_json::Value::String(value.to_owned()));
+        *self = serde_json::from_value(j).map_err(Error::ConfigInvalidJson)?;
+        Ok(())
+    }
 }
 
 pub fn read(args: &opt::OptRoot) -> VResult<Config> {
@@ -32,16 +221,98 @@ pub fn read(args: &opt::OptRoot) -> VResult<Config> {
         Ok(Config::default())
     } else {
         let p = paths::config_file(args)?;
-        Config::from_json_bytes(&fs::read(p)?)
+        let data = fs::read(p)?;
+        if let Ok(encrypted) = EncryptedVault::from_bytes(&data) {
+            decrypt(&data, encrypted)
+        } else {
+            Config::from_json_bytes(&data)
+        }
     }
 }
 
+/// Check whether the config file is encrypted
+pub fn is_encrypted(args: &opt::OptRoot) -> VResult<bool> {
+    let p = paths::config_file(args)?;
+    Ok(p.exists() && EncryptedVault::from_bytes(&fs::read(p)?).is_ok())
+}
+
+/// Write the config, keeping it encrypted if it already was
 pub fn write(args: &opt::OptRoot, c: Config) -> VResult<()> {
+    let encrypted = is_encrypted(args)?;
+    write_as(args, c, encrypted)
+}
+
+fn write_as(args: &opt::OptRoot, c: Config, encrypted: bool) -> VResult<()> {
     let p = paths::config_file(args)?;
-    fs::write(p, c.to_json_bytes())?;
+    if encrypted {
+        let pw = config_password(i18n::tr("Password [config]"))?;
+        let data = Vault::new(c.clone()).encrypt(&pw).to_bytes();
+        fs::write(p, &data)?;
+        DECRYPTED.with(|d| *d.borrow_mut() = Some((data, c)));
+    } else {
+        fs::write(p, c.to_json_bytes())?;
+    }
+    Ok(())
+}
+
+/// Encrypt the config file with a passphrase, or store it as plain JSON again
+pub fn set_encrypted(args: &opt::OptRoot, encrypted: bool) -> VResult<()> {
+    let c = read(args)?;
+    if encrypted && !is_encrypted(args)? {
+        let pw = match std::env::var(PASSWORD_ENV) {
+            Ok(pw) => pw,
+            // A mistyped passphrase would lock the config
+            Err(_) => interactive::prompt_new_password(i18n::tr("New password [config]"))?,
+        };
+        PASSWORD.with(|p| *p.borrow_mut() = Some(pw));
+    }
+    write_as(args, c, encrypted)
+}
+
+/// Device id of this device, `None` if the config doesn't have one yet.
+/// Configs created by older versions get one when a vault is first changed, see `ensure_device_id`.
+pub fn device_id(args: &opt::OptRoot) -> VResult<Option<DeviceId>> {
+    if args.disable_config {
+        return Ok(None);
+    }
+    Ok(read(args)?.device_id)
+}
+
+/// Give a device id to a config created by an older version, so that later changes are stamped with it.
+/// Called only when a vault is written, so that commands only reading never write the config.
+pub fn ensure_device_id(args: &opt::OptRoot) -> VResult<()> {
+    if !args.disable_config && read(args)?.device_id.is_none() {
+        modify(args, |c| c.device_id = Some(DeviceId::generate()))?;
+    }
     Ok(())
 }
 
+/// Run `password_command` from the config, if set, and return the first line of its output
+pub fn command_password(args: &opt::OptRoot) -> VResult<Option<String>> {
+    let command = match read(args)?.password_command {
+        Some(command) => command,
+        None => return Ok(None),
+    };
+    let output = if cfg!(windows) {
+        Command::new("cmd").arg("/C").arg(&command).output()?
+    } else {
+        Command::new("sh").arg("-c").arg(&command).output()?
+    };
+    if !output.status.success() {
+        return Err(Error::PasswordCommandFailed(output.status.code()));
+    }
+    let stdout = String::from_utf8(output.stdout).map_err(|_| Error::NonUnicodeInput)?;
+    Ok(Some(stdout.lines().next().unwrap_or("").to_owned()))
+}
+
+/// Generator preset by name from the config
+pub fn preset(args: &opt::OptRoot, name: &str) -> VResult<Preset> {
+    read(args)?
+        .generator_presets
+        .remove(name)
+        .ok_or_else(|| Error::PresetNotFound(name.to_owned()))
+}
+
 pub fn modify<F, R>(args: &opt::OptRoot, f: F) -> VResult<R>
 where F: FnOnce(&mut Config) -> R {
     let mut c = read(args)?;
@@ -1,21 +1,31 @@
+//! Clipboard access. Copied secrets are kept out of clipboard history and cloud clipboard where the
+//! platform has a way to mark them: with `ExcludeClipboardContentFromMonitorProcessing` and related
+//! formats on Windows, and with `org.nspasteboard.ConcealedType` on macOS.
+//! On X11 the selections are owned directly, so that both CLIPBOARD and PRIMARY can be cleared,
+//! and so that a secret can be served for a single paste with `vpass copy --once`.
+
 use std::process::Command;
+use std::time::Duration;
+
+use super::error::{Error, VResult};
 
-use clipboard::ClipboardContext;
-use clipboard::ClipboardProvider;
+/// Under WSL the clipboard is only reachable through Windows programs.
+/// Native Windows builds use the clipboard API directly.
+fn use_powershell() -> bool {
+    !cfg!(windows) && wsl::is_wsl()
+}
 
 /// Get clipboard contents
-#[allow(dead_code)]
 pub fn read() -> Option<String> {
-    if wsl::is_wsl() {
+    if use_powershell() {
         // Run powershell through cmd.exe to not reset terminal settings
         let p = Command::new("cmd.exe")
-            .args(&["/C", "powershell.exe", "Get-Clipboard"])
+            .args(["/C", "powershell.exe", "Get-Clipboard"])
             .output()
             .expect("Failed to execute powershell Get-Clipboard");
         String::from_utf8(p.stdout).ok()
     } else {
-        let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-        ctx.get_contents().ok()
+        platform::read()
     }
 }
 
@@ -25,15 +35,356 @@ fn powershell_quote(s: &str) -> String {
 }
 
 /// Set clipboard contents
-pub fn write(s: &str) {
-    if wsl::is_wsl() {
+pub fn write(s: &str) -> VResult<()> {
+    if use_powershell() {
         // Run powershell through cmd.exe to not reset terminal settings
         Command::new("cmd.exe")
-            .args(&["/C", "powershell.exe", "Set-Clipboard", &powershell_quote(s)])
+            .args(["/C", "powershell.exe", "Set-Clipboard", &powershell_quote(s)])
             .output()
             .expect("Failed to execute powershell Set-Clipboard");
+        Ok(())
+    } else {
+        platform::write(s)
+    }
+}
+
+/// Clear the clipboard if it still holds `secret`, so that anything copied since is kept
+pub fn clear(secret: &str) -> VResult<()> {
+    if use_powershell() {
+        if read().is_some_and(|s| s.trim_end_matches(&['\r', '\n'][..]) == secret) {
+            Command::new("cmd.exe").args(["/C", "clip.exe < NUL"]).output()?;
+        }
+        Ok(())
     } else {
-        let mut ctx: ClipboardContext = ClipboardProvider::new().unwrap();
-        ctx.set_contents(s.to_owned()).unwrap();
+        platform::clear(secret)
+    }
+}
+
+/// Offer `secret` for a single paste, clearing the clipboard right after it's pasted,
+/// when another program takes the clipboard, or after `timeout`. Returns whether it was pasted.
+/// Clipboard managers that save each copy count as a paste.
+pub fn offer_once(secret: &str, timeout: Option<Duration>) -> VResult<bool> {
+    if use_powershell() {
+        return Err(Error::CopyOnceUnsupported);
+    }
+    platform::offer_once(secret, timeout)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+mod platform {
+    use std::time::{Duration, Instant};
+
+    use x11_clipboard::xcb::{self, Atom};
+    use x11_clipboard::Clipboard;
+
+    use super::super::error::{Error, VResult};
+
+    /// Time between checks for selection requests
+    const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Time to wait for the owner of a selection to answer
+    const TIMEOUT: Duration = Duration::from_secs(3);
+
+    fn connect() -> VResult<Clipboard> {
+        Clipboard::new().map_err(|e| Error::ClipboardFailed(format!("{:?}", e)))
+    }
+
+    fn load(clipboard: &Clipboard, selection: Atom) -> Option<String> {
+        let atoms = &clipboard.getter.atoms;
+        let data = clipboard.load(selection, atoms.utf8_string, atoms.property, TIMEOUT).ok()?;
+        String::from_utf8(data).ok()
+    }
+
+    fn store(clipboard: &Clipboard, selection: Atom, s: &str) -> VResult<()> {
+        clipboard
+            .store(selection, clipboard.setter.atoms.utf8_string, s)
+            .map_err(|e| Error::ClipboardFailed(format!("{:?}", e)))
+    }
+
+    pub fn read() -> Option<String> {
+        let clipboard = connect().ok()?;
+        load(&clipboard, clipboard.getter.atoms.clipboard)
+    }
+
+    /// Own the CLIPBOARD selection. The contents are served by a thread until vpass exits.
+    pub fn write(s: &str) -> VResult<()> {
+        let clipboard = connect()?;
+        store(&clipboard, clipboard.setter.atoms.clipboard, s)
+    }
+
+    /// Clear CLIPBOARD and PRIMARY, as the secret ends up in PRIMARY when pasted text is selected
+    pub fn clear(secret: &str) -> VResult<()> {
+        let clipboard = connect()?;
+        let atoms = &clipboard.getter.atoms;
+        for &selection in &[atoms.clipboard, atoms.primary] {
+            if load(&clipboard, selection).is_some_and(|s| s == secret) {
+                store(&clipboard, selection, "")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Own CLIPBOARD with a window of our own, answering requests until the text is sent once.
+    /// Requests for the list of targets don't count, as programs make them before pasting.
+    pub fn offer_once(secret: &str, timeout: Option<Duration>) -> VResult<bool> {
+        let failed = |e: &dyn std::fmt::Debug| Error::ClipboardFailed(format!("{:?}", e));
+        let (conn, screen) = xcb::Connection::connect(None).map_err(|e| failed(&e))?;
+        let root = conn.get_setup().roots().nth(screen as usize);
+        let root = root.ok_or_else(|| Error::ClipboardFailed("screen".to_owned()))?;
+        let window = conn.generate_id();
+        xcb::create_window(
+            &conn,
+            xcb::COPY_FROM_PARENT as u8,
+            window,
+            root.root(),
+            0,
+            0,
+            1,
+            1,
+            0,
+            xcb::WINDOW_CLASS_INPUT_OUTPUT as u16,
+            root.root_visual(),
+            &[],
+        );
+        let atom = |name: &str| -> VResult<Atom> {
+            Ok(xcb::intern_atom(&conn, false, name).get_reply().map_err(|e| failed(&e))?.atom())
+        };
+        let (clipboard, targets, utf8_string) = (atom("CLIPBOARD")?, atom("TARGETS")?, atom("UTF8_STRING")?);
+        xcb::set_selection_owner(&conn, window, clipboard, xcb::CURRENT_TIME);
+        let owner = xcb::get_selection_owner(&conn, clipboard).get_reply().map_err(|e| failed(&e))?;
+        if owner.owner() != window {
+            return Err(Error::ClipboardFailed("SetSelectionOwner".to_owned()));
+        }
+
+        let start = Instant::now();
+        let pasted = loop {
+            let event = match conn.poll_for_event() {
+                Some(event) => event,
+                None if timeout.is_some_and(|t| start.elapsed() >= t) => break false,
+                None => {
+                    std::thread::sleep(POLL_INTERVAL);
+                    continue;
+                },
+            };
+            match event.response_type() & !0x80 {
+                // Another program took the clipboard
+                xcb::SELECTION_CLEAR => return Ok(false),
+                xcb::SELECTION_REQUEST => {
+                    let request: &xcb::SelectionRequestEvent = unsafe { xcb::cast_event(&event) };
+                    // Obsolete clients don't give a property, and expect the target to be used
+                    let property = match request.property() {
+                        xcb::ATOM_NONE => request.target(),
+                        property => property,
+                    };
+                    let target = request.target();
+                    let sent = if target == targets {
+                        let supported = [targets, utf8_string, xcb::ATOM_STRING];
+                        xcb::change_property(
+                            &conn,
+                            xcb::PROP_MODE_REPLACE as u8,
+                            request.requestor(),
+                            property,
+                            xcb::ATOM_ATOM,
+                            32,
+                            &supported,
+                        );
+                        Some(false)
+                    } else if target == utf8_string || target == xcb::ATOM_STRING {
+                        xcb::change_property(
+                            &conn,
+                            xcb::PROP_MODE_REPLACE as u8,
+                            request.requestor(),
+                            property,
+                            target,
+                            8,
+                            secret.as_bytes(),
+                        );
+                        Some(true)
+                    } else {
+                        None
+                    };
+                    let notify = xcb::SelectionNotifyEvent::new(
+                        request.time(),
+                        request.requestor(),
+                        request.selection(),
+                        target,
+                        if sent.is_some() { property } else { xcb::ATOM_NONE },
+                    );
+                    xcb::send_event(&conn, false, request.requestor(), xcb::EVENT_MASK_NO_EVENT, &notify);
+                    conn.flush();
+                    if sent == Some(true) {
+                        break true;
+                    }
+                },
+                _ => {},
+            }
+        };
+        xcb::set_selection_owner(&conn, xcb::NONE, clipboard, xcb::CURRENT_TIME);
+        conn.flush();
+        Ok(pasted)
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::ptr;
+    use std::time::Duration;
+
+    use clipboard::{ClipboardContext, ClipboardProvider};
+    use winapi::um::winbase::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+    use winapi::um::winuser::{
+        CloseClipboard, EmptyClipboard, OpenClipboard, RegisterClipboardFormatW, SetClipboardData,
+        CF_UNICODETEXT,
+    };
+
+    use super::super::error::{Error, VResult};
+
+    /// Formats telling clipboard history, cloud clipboard and clipboard monitors to skip the text
+    const EXCLUDE_FORMATS: [&str; 3] = [
+        "ExcludeClipboardContentFromMonitorProcessing",
+        "CanIncludeInClipboardHistory",
+        "CanUploadToCloudClipboard",
+    ];
+
+    pub fn read() -> Option<String> {
+        let mut ctx: ClipboardContext = ClipboardProvider::new().ok()?;
+        ctx.get_contents().ok()
+    }
+
+    /// Put `data` on the open clipboard in `format`, the clipboard taking ownership of the memory
+    unsafe fn set_data(format: u32, data: &[u8]) -> bool {
+        let handle = GlobalAlloc(GMEM_MOVEABLE, data.len());
+        if handle.is_null() {
+            return false;
+        }
+        ptr::copy_nonoverlapping(data.as_ptr(), GlobalLock(handle) as *mut u8, data.len());
+        GlobalUnlock(handle);
+        !SetClipboardData(format, handle).is_null()
+    }
+
+    pub fn write(s: &str) -> VResult<()> {
+        let text: Vec<u8> = s.encode_utf16().chain(Some(0)).flat_map(|c| c.to_le_bytes().to_vec()).collect();
+        unsafe {
+            if OpenClipboard(ptr::null_mut()) == 0 {
+                return Err(Error::ClipboardFailed("OpenClipboard".to_owned()));
+            }
+            let mut ok = EmptyClipboard() != 0 && set_data(CF_UNICODETEXT, &text);
+            for name in &EXCLUDE_FORMATS {
+                // Zero means "no" for the last two, and any data is enough for the first one
+                let name: Vec<u16> = name.encode_utf16().chain(Some(0)).collect();
+                ok = ok && set_data(RegisterClipboardFormatW(name.as_ptr()), &0u32.to_le_bytes());
+            }
+            CloseClipboard();
+            if !ok {
+                return Err(Error::ClipboardFailed("SetClipboardData".to_owned()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(secret: &str) -> VResult<()> {
+        if read().map_or(false, |s| s == secret) {
+            write("")?;
+        }
+        Ok(())
+    }
+
+    pub fn offer_once(_secret: &str, _timeout: Option<Duration>) -> VResult<bool> {
+        Err(Error::CopyOnceUnsupported)
+    }
+}
+
+#[cfg(target_os = "macos")]
+mod platform {
+    use std::time::Duration;
+
+    use clipboard::{ClipboardContext, ClipboardProvider};
+    use objc::runtime::{Object, BOOL, YES};
+    use objc::{class, msg_send, sel, sel_impl};
+
+    use super::super::error::{Error, VResult};
+
+    // NSPasteboard is part of AppKit
+    #[link(name = "AppKit", kind = "framework")]
+    extern "C" {}
+
+    const UTF8_ENCODING: usize = 4;
+
+    pub fn read() -> Option<String> {
+        let mut ctx: ClipboardContext = ClipboardProvider::new().ok()?;
+        ctx.get_contents().ok()
+    }
+
+    /// New `NSString`, to be released by the caller
+    unsafe fn ns_string(s: &str) -> *mut Object {
+        let string: *mut Object = msg_send![class!(NSString), alloc];
+        msg_send![string, initWithBytes:s.as_ptr() length:s.len() encoding:UTF8_ENCODING]
+    }
+
+    pub fn write(s: &str) -> VResult<()> {
+        unsafe {
+            let pasteboard: *mut Object = msg_send![class!(NSPasteboard), generalPasteboard];
+            let _: isize = msg_send![pasteboard, clearContents];
+            let strings = [
+                ns_string(s),
+                ns_string("public.utf8-plain-text"),
+                // Clipboard managers and Universal Clipboard skip pasteboards with this type
+                ns_string("org.nspasteboard.ConcealedType"),
+            ];
+            let text: BOOL = msg_send![pasteboard, setString:strings[0] forType:strings[1]];
+            let concealed: BOOL = msg_send![pasteboard, setString:strings[0] forType:strings[2]];
+            for string in &strings {
+                let _: () = msg_send![*string, release];
+            }
+            if text != YES || concealed != YES {
+                return Err(Error::ClipboardFailed("NSPasteboard".to_owned()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn clear(secret: &str) -> VResult<()> {
+        if read().map_or(false, |s| s == secret) {
+            unsafe {
+                let pasteboard: *mut Object = msg_send![class!(NSPasteboard), generalPasteboard];
+                let _: isize = msg_send![pasteboard, clearContents];
+            }
+        }
+        Ok(())
+    }
+
+    pub fn offer_once(_secret: &str, _timeout: Option<Duration>) -> VResult<bool> {
+        Err(Error::CopyOnceUnsupported)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod platform {
+    use std::time::Duration;
+
+    use clipboard::{ClipboardContext, ClipboardProvider};
+
+    use super::super::error::{Error, VResult};
+
+    pub fn read() -> Option<String> {
+        let mut ctx: ClipboardContext = ClipboardProvider::new().ok()?;
+        ctx.get_contents().ok()
+    }
+
+    pub fn write(s: &str) -> VResult<()> {
+        let mut ctx: ClipboardContext =
+            ClipboardProvider::new().map_err(|e| Error::ClipboardFailed(e.to_string()))?;
+        ctx.set_contents(s.to_owned()).map_err(|e| Error::ClipboardFailed(e.to_string()))
+    }
+
+    pub fn clear(secret: &str) -> VResult<()> {
+        if read().map_or(false, |s| s == secret) {
+            write("")?;
+        }
+        Ok(())
+    }
+
+    pub fn offer_once(_secret: &str, _timeout: Option<Duration>) -> VResult<bool> {
+        Err(Error::CopyOnceUnsupported)
     }
 }
@@ -0,0 +1,125 @@
+//! Reading passwords from the terminal without echoing them.
+//! On Windows the console is used directly, as the TTY emulation of rpassword
+//! doesn't work reliably with ConPTY terminals and redirected standard streams.
+
+use std::io;
+
+/// Console input mode flags, from `wincon.h`
+const ENABLE_PROCESSED_INPUT: u32 = 0x0001;
+const ENABLE_LINE_INPUT: u32 = 0x0002;
+const ENABLE_ECHO_INPUT: u32 = 0x0004;
+
+/// Console mode for reading a password: whole lines without echo, Ctrl-C still interrupting
+#[cfg_attr(not(windows), allow(dead_code))]
+fn password_mode(mode: u32) -> u32 {
+    (mode | ENABLE_LINE_INPUT | ENABLE_PROCESSED_INPUT) & !ENABLE_ECHO_INPUT
+}
+
+/// Line read from the console, which uses UTF-16, without the line terminator
+#[cfg_attr(not(windows), allow(dead_code))]
+fn decode_line(input: &[u16]) -> io::Result<String> {
+    let line = String::from_utf16(input).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+}
+
+/// Whether the terminal can be opened, so that a prompt can be answered.
+/// Passwords are read from it even if standard input is redirected.
+#[cfg(unix)]
+pub fn terminal_available() -> bool {
+    std::fs::OpenOptions::new().read(true).write(true).open("/dev/tty").is_ok()
+}
+
+/// Whether the console can be opened, so that a prompt can be answered
+#[cfg(windows)]
+pub fn terminal_available() -> bool {
+    std::fs::OpenOptions::new().read(true).write(true).open("CONIN$").is_ok()
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn terminal_available() -> bool {
+    true
+}
+
+/// Prompt for a password on the terminal, without echo
+#[cfg(not(windows))]
+pub fn read_password(prompt: &str) -> io::Result<String> {
+    rpassword::read_password_from_tty(Some(prompt))
+}
+
+/// Prompt for a password on the console, without echo.
+/// The console is opened directly, so this works even if standard input is redirected.
+#[cfg(windows)]
+pub fn read_password(prompt: &str) -> io::Result<String> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::consoleapi::{GetConsoleMode, ReadConsoleW, SetConsoleMode};
+
+    let input = OpenOptions::new().read(true).write(true).open("CONIN$")?;
+    let mut output = OpenOptions::new().write(true).open("CONOUT$")?;
+    let handle = input.as_raw_handle() as winapi::um::winnt::HANDLE;
+
+    let mut original = 0;
+    if unsafe { GetConsoleMode(handle, &mut original) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    write!(output, "{}", prompt)?;
+    output.flush()?;
+    if unsafe { SetConsoleMode(handle, password_mode(original)) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut buffer: Vec<u16> = Vec::new();
+    let mut chunk = [0u16; 256];
+    let result = loop {
+        let mut read = 0;
+        let ok = unsafe {
+            ReadConsoleW(
+                handle,
+                chunk.as_mut_ptr() as *mut _,
+                chunk.len() as u32,
+                &mut read,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            break Err(io::Error::last_os_error());
+        }
+        buffer.extend_from_slice(&chunk[..read as usize]);
+        if read == 0 || buffer.last() == Some(&(b'\n' as u16)) {
+            break decode_line(&buffer);
+        }
+    };
+    // Overwrite the password in memory before releasing it
+    for c in buffer.iter_mut().chain(chunk.iter_mut()) {
+        *c = 0;
+    }
+
+    unsafe { SetConsoleMode(handle, original) };
+    // The newline typed by the user was not echoed
+    writeln!(output)?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_line, password_mode, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT};
+    use std::io;
+
+    #[test]
+    fn console_password_mode() {
+        let default = ENABLE_PROCESSED_INPUT | ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT | 0x0080;
+        let mode = password_mode(default);
+        assert_eq!(mode & ENABLE_ECHO_INPUT, 0);
+        assert_ne!(mode & ENABLE_PROCESSED_INPUT, 0);
+        assert_ne!(mode & ENABLE_LINE_INPUT, 0);
+        assert_eq!(password_mode(0), ENABLE_PROCESSED_INPUT | ENABLE_LINE_INPUT);
+        // Other flags are kept
+        assert_ne!(mode & 0x0080, 0);
+
+        let utf16 = |s: &str| s.encode_utf16().collect::<Vec<u16>>();
+        assert_eq!(decode_line(&utf16("pässwörd\r\n")).unwrap(), "pässwörd");
+        assert_eq!(decode_line(&utf16("")).unwrap(), "");
+        assert_eq!(decode_line(&[0xd800]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
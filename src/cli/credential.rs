@@ -0,0 +1,58 @@
+//! Vault passwords sealed with `systemd-creds`, written by `vpass vault seal-password`
+//! and read with `--password-credential`. The credential is encrypted with a key held in the TPM
+//! of this machine, so unlike a `--password-file` it is useless when copied elsewhere,
+//! e.g. from a backup or a stolen disk.
+
+#[cfg(target_os = "linux")]
+use std::io::Write;
+use std::path::Path;
+#[cfg(target_os = "linux")]
+use std::process::{Command, Stdio};
+
+use super::error::{Error, VResult};
+
+/// Name embedded in the credential, which decryption checks
+#[cfg(target_os = "linux")]
+const NAME: &str = "--name=vpass";
+
+/// Seal `password` to `path`. `key` is passed to `--with-key`, e.g. `tpm2` or `host+tpm2`.
+#[cfg(target_os = "linux")]
+pub fn seal(password: &str, path: &Path, key: &str) -> VResult<()> {
+    let mut child = Command::new("systemd-creds")
+        .args(["encrypt", NAME, &format!("--with-key={}", key), "-"])
+        .arg(path)
+        .stdin(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(password.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::CredentialFailed(status.code()));
+    }
+    Ok(())
+}
+
+/// Decrypt the password sealed to `path`
+#[cfg(target_os = "linux")]
+pub fn unseal(path: &Path) -> VResult<String> {
+    let output = Command::new("systemd-creds")
+        .args(["decrypt", NAME])
+        .arg(path)
+        .arg("-")
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        return Err(Error::CredentialFailed(output.status.code()));
+    }
+    let stdout = String::from_utf8(output.stdout).map_err(|_| Error::NonUnicodeInput)?;
+    Ok(stdout.lines().next().unwrap_or("").to_owned())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn seal(_password: &str, _path: &Path, _key: &str) -> VResult<()> {
+    Err(Error::CredentialUnsupported)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn unseal(_path: &Path) -> VResult<String> {
+    Err(Error::CredentialUnsupported)
+}
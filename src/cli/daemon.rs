@@ -0,0 +1,200 @@
+//! Periodic background synchronization with `vpass daemon`,
+//! and service files for starting it on login with systemd or launchd.
+//! The daemon doesn't prompt after startup: vault passwords come from `password_command`,
+//! a password file or the environment, or are asked once when started from a terminal.
+
+use std::path::{Path, PathBuf};
+
+use super::{
+    error::*,
+    opt::{OptRoot, PasswordSource},
+    paths,
+};
+
+const SERVICE_NAME: &str = "vpass";
+const LAUNCHD_LABEL: &str = "com.github.dentosal.vpass";
+
+/// Name of the service, or its launchd label, with the profile added,
+/// so that each profile can have its own daemon
+fn profile_name(base: &str, args: &OptRoot) -> VResult<String> {
+    Ok(match paths::profile(args)? {
+        Some(profile) => format!("{}-{}", base, profile),
+        None => base.to_owned(),
+    })
+}
+
+/// Service manager of the current user session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceKind {
+    Systemd,
+    Launchd,
+}
+impl ServiceKind {
+    pub fn current() -> Option<Self> {
+        if cfg!(target_os = "macos") {
+            Some(ServiceKind::Launchd)
+        } else if cfg!(target_os = "linux") {
+            Some(ServiceKind::Systemd)
+        } else {
+            None
+        }
+    }
+
+    /// Where the service file of the selected profile is installed for the current user
+    pub fn path(self, args: &OptRoot) -> VResult<PathBuf> {
+        let home = dirs::home_dir().ok_or(Error::HomeDirectoryUnavailable)?;
+        Ok(match self {
+            ServiceKind::Systemd => dirs::config_dir()
+                .unwrap_or_else(|| home.join(".config"))
+                .join("systemd/user")
+                .join(format!("{}.service", profile_name(SERVICE_NAME, args)?)),
+            ServiceKind::Launchd => home
+                .join("Library/LaunchAgents")
+                .join(format!("{}.plist", profile_name(LAUNCHD_LABEL, args)?)),
+        })
+    }
+
+    /// Command starting the installed service, now and on future logins
+    pub fn enable_command(self, args: &OptRoot) -> VResult<String> {
+        Ok(match self {
+            ServiceKind::Systemd => {
+                format!("systemctl --user enable --now {}.service", profile_name(SERVICE_NAME, args)?)
+            },
+            ServiceKind::Launchd => format!("launchctl load -w {}", self.path(args)?.display()),
+        })
+    }
+}
+
+/// Vault password kept in memory by the daemon, overwritten when dropped
+pub struct CachedPassword(Vec<u8>);
+impl CachedPassword {
+    pub fn new(password: String) -> Self {
+        CachedPassword(password.into_bytes())
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).expect("Created from a string")
+    }
+}
+impl Drop for CachedPassword {
+    fn drop(&mut self) {
+        rust_sodium::utils::memzero(&mut self.0);
+    }
+}
+
+/// Arguments for running the daemon with the same vault directory, config and password file or credential
+fn daemon_args(exe: &Path, args: &OptRoot) -> VResult<Vec<String>> {
+    let mut result = vec![exe.to_string_lossy().into_owned(), "--no-input".to_owned()];
+    result.push("--vault-dir".to_owned());
+    result.push(paths::data_dir(args)?.to_string_lossy().into_owned());
+    // The directory of the profile is given directly, so `VPASS_PROFILE` in the session must not apply
+    result.push("--profile=".to_owned());
+    if let Some(ref config) = args.config {
+        result.push("--config".to_owned());
+        result.push(config.to_string_lossy().into_owned());
+    }
+    match args.password_source() {
+        Some(PasswordSource::File(path)) => {
+            result.push("--password-file".to_owned());
+            result.push(path.to_string_lossy().into_owned());
+        },
+        Some(PasswordSource::Credential(path)) => {
+            result.push("--password-credential".to_owned());
+            result.push(path.to_string_lossy().into_owned());
+        },
+        _ => {},
+    }
+    result.push("daemon".to_owned());
+    Ok(result)
+}
+
+/// Quote an argument for systemd `ExecStart`
+fn systemd_quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\"").replace('%', "%%"))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Service file running the daemon, `exe` being the path of this executable
+pub fn service_file(kind: ServiceKind, exe: &Path, args: &OptRoot) -> VResult<String> {
+    let daemon_args = daemon_args(exe, args)?;
+    Ok(match kind {
+        ServiceKind::Systemd => format!(
+            "[Unit]\n\
+             Description=vpass background synchronization\n\
+             After=network-online.target\n\
+             \n\
+             [Service]\n\
+             ExecStart={}\n\
+             Restart=on-failure\n\
+             RestartSec=60\n\
+             \n\
+             [Install]\n\
+             WantedBy=default.target\n",
+            daemon_args.iter().map(|a| systemd_quote(a)).collect::<Vec<_>>().join(" ")
+        ),
+        ServiceKind::Launchd => format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+             \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+             <plist version=\"1.0\">\n\
+             <dict>\n\
+             \x20   <key>Label</key>\n\
+             \x20   <string>{}</string>\n\
+             \x20   <key>ProgramArguments</key>\n\
+             \x20   <array>\n\
+             {}\
+             \x20   </array>\n\
+             \x20   <key>RunAtLoad</key>\n\
+             \x20   <true/>\n\
+             \x20   <key>KeepAlive</key>\n\
+             \x20   <true/>\n\
+             </dict>\n\
+             </plist>\n",
+            profile_name(LAUNCHD_LABEL, args)?,
+            daemon_args
+                .iter()
+                .map(|a| format!("        <string>{}</string>\n", xml_escape(a)))
+                .collect::<String>()
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{service_file, systemd_quote, xml_escape, ServiceKind};
+    use crate::cli::opt::OptRoot;
+    use std::path::Path;
+    use structopt::StructOpt;
+
+    #[test]
+    fn service_files() {
+        assert_eq!(systemd_quote("a \"b\" 100%"), "\"a \\\"b\\\" 100%%\"");
+        assert_eq!(xml_escape("<a&b>"), "&lt;a&amp;b&gt;");
+
+        let args = OptRoot::from_iter(&["vpass", "-d", "/tmp/my vaults", "daemon"]);
+        let exe = Path::new("/usr/bin/vpass");
+        let unit = service_file(ServiceKind::Systemd, exe, &args).unwrap();
+        assert!(unit.contains(concat!(
+            "ExecStart=\"/usr/bin/vpass\" \"--no-input\" ",
+            "\"--vault-dir\" \"/tmp/my vaults\" \"--profile=\" \"daemon\"\n"
+        )));
+        let plist = service_file(ServiceKind::Launchd, exe, &args).unwrap();
+        assert!(plist.contains("        <string>/tmp/my vaults</string>\n"));
+        assert!(plist.contains("    <key>KeepAlive</key>\n"));
+
+        let args = OptRoot::from_iter(&["vpass", "--password-credential", "/etc/vpass.cred", "daemon"]);
+        let unit = service_file(ServiceKind::Systemd, exe, &args).unwrap();
+        assert!(unit.contains(" \"--password-credential\" \"/etc/vpass.cred\" \"daemon\"\n"));
+
+        // Each profile has its own service
+        let args = OptRoot::from_iter(&["vpass", "-d", "/tmp/my vaults", "--profile", "work", "daemon"]);
+        let path = ServiceKind::Systemd.path(&args).unwrap();
+        assert!(path.ends_with("systemd/user/vpass-work.service"));
+        let plist = service_file(ServiceKind::Launchd, exe, &args).unwrap();
+        assert!(plist.contains("<string>com.github.dentosal.vpass-work</string>"));
+        assert!(plist.contains("        <string>/tmp/my vaults/profiles/work</string>\n"));
+    }
+}
@@ -0,0 +1,183 @@
+//! Printable emergency kit for a vault, with everything needed to recover it
+//! if the owner is not available: where the vault is, how to download it,
+//! and optionally a share of the vault password, with a separate kit for each trusted contact.
+
+use chrono::prelude::*;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Html,
+}
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "html" => Ok(Format::Html),
+            other => Err(format!("Unknown format {}, expected text or html", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Kit {
+    pub vault_name: String,
+    pub file_path: PathBuf,
+    pub created: DateTime<Utc>,
+    /// Transfer string protected with a passphrase, if the vault is synchronized
+    pub transfer_string: Option<String>,
+    /// Share of the vault password given to the holder of this kit
+    pub share: Option<KitShare>,
+}
+
+#[derive(Debug, Clone)]
+pub struct KitShare {
+    pub encoded: String,
+    /// Number of this share, from 1
+    pub number: usize,
+    pub count: u8,
+    pub threshold: u8,
+}
+
+/// Path of the kit with share `number`, like `kit-1.html` for `kit.html`
+pub fn numbered_path(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().map_or_else(String::new, |s| s.to_string_lossy().into_owned());
+    let name = match path.extension() {
+        Some(extension) => format!("{}-{}.{}", stem, number, extension.to_string_lossy()),
+        None => format!("{}-{}", stem, number),
+    };
+    path.with_file_name(name)
+}
+
+impl Kit {
+    /// Instructions as (heading, lines) sections
+    fn sections(&self) -> Vec<(String, Vec<String>)> {
+        let mut result = vec![(
+            format!("vpass emergency kit: {}", self.vault_name),
+            vec![
+                format!("Created: {}", self.created.format("%Y-%m-%d")),
+                format!("Vault file: {}", self.file_path.display()),
+                "Keep this document in a safe place.".to_owned(),
+            ],
+        )];
+        if let Some(ref ts) = self.transfer_string {
+            result.push((
+                "Downloading the vault".to_owned(),
+                vec![
+                    "Install vpass, and run `vpass init`. Then run".to_owned(),
+                    format!("vpass vault import {} {}", self.vault_name, ts),
+                    "and enter the passphrase of this kit, told separately, and the vault password.".to_owned(),
+                ],
+            ));
+        } else {
+            result.push((
+                "Finding the vault".to_owned(),
+                vec![
+                    "The vault is not synchronized, so a copy of the vault file is needed.".to_owned(),
+                    "Place it in the vault directory, and use `vpass -n <name> list`.".to_owned(),
+                ],
+            ));
+        }
+        if let Some(ref share) = self.share {
+            result.push((
+                "Vault password".to_owned(),
+                vec![
+                    format!(
+                        "The password is split into {} shares, each in a kit held by a different person.",
+                        share.count
+                    ),
+                    format!("Any {} of them recover it with", share.threshold),
+                    "vpass vault shamir-recover <share> <share> ...".to_owned(),
+                    "Don't keep this kit together with the other kits.".to_owned(),
+                ],
+            ));
+            result.push((
+                format!("Share {} of {} for vault {}", share.number, share.count, self.vault_name),
+                vec![share.encoded.clone()],
+            ));
+        }
+        result
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        match format {
+            Format::Text => self.render_text(),
+            Format::Html => self.render_html(),
+        }
+    }
+
+    fn render_text(&self) -> String {
+        let mut result = String::new();
+        for (i, (heading, lines)) in self.sections().into_iter().enumerate() {
+            if i > 0 {
+                result.push('\n');
+            }
+            result.push_str(&format!("{}\n{}\n", heading, "=".repeat(heading.len())));
+            for line in lines {
+                result.push_str(&line);
+                result.push('\n');
+            }
+        }
+        result
+    }
+
+    fn render_html(&self) -> String {
+        let escape = |s: &str| {
+            s.replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace('>', "&gt;")
+                .replace('"', "&quot;")
+        };
+        let mut result = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>vpass emergency kit</title>\n\
+             <style>section { page-break-inside: avoid; border-bottom: 1px dashed; } \
+             p { font-family: monospace; word-break: break-all; }</style>\n</head>\n<body>\n",
+        );
+        for (heading, lines) in self.sections() {
+            result.push_str(&format!("<section>\n<h2>{}</h2>\n", escape(&heading)));
+            for line in lines {
+                result.push_str(&format!("<p>{}</p>\n", escape(&line)));
+            }
+            result.push_str("</section>\n");
+        }
+        result.push_str("</body>\n</html>\n");
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{numbered_path, Format, Kit, KitShare};
+    use chrono::prelude::*;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn render_kit() {
+        let kit = Kit {
+            vault_name: "<personal>".to_owned(),
+            file_path: PathBuf::from("/tmp/personal.vpass"),
+            created: Utc::now(),
+            transfer_string: Some("VPASSP_abcd".to_owned()),
+            share: Some(KitShare {
+                encoded: "VPASS_SHARE_2".to_owned(),
+                number: 2,
+                count: 3,
+                threshold: 2,
+            }),
+        };
+        let text = kit.render(Format::Text);
+        assert!(text.contains("vpass vault import <personal> VPASSP_abcd"));
+        assert!(text.contains("Share 2 of 3"));
+        assert!(text.contains("VPASS_SHARE_2") && !text.contains("VPASS_SHARE_1"));
+
+        let html = kit.render(Format::Html);
+        assert!(html.contains("&lt;personal&gt;"));
+        assert!(!html.contains("<personal>"));
+
+        assert_eq!(numbered_path(Path::new("/tmp/kit.html"), 2), Path::new("/tmp/kit-2.html"));
+        assert_eq!(numbered_path(Path::new("kit"), 1), Path::new("kit-1"));
+    }
+}
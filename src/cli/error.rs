@@ -2,10 +2,9 @@ use std::io;
 use std::path::PathBuf;
 
 use super::validate::ValidationError;
-use crate::backend::book::VersionMergeError;
+use crate::backend::book::{ItemKind, VersionMergeError};
 use crate::sync;
 
-#[must_use]
 pub type VResult<T> = Result<T, Error>;
 
 #[derive(Debug)]
@@ -26,6 +25,61 @@ pub enum Error {
     ConfigNotFound(PathBuf),
     /// Invalid JSON in config file
     ConfigInvalidJson(serde_json::error::Error),
+    /// Config assignment must be in form `name=value`
+    ConfigInvalidAssignment,
+    /// No such config field
+    ConfigUnknownField(String),
+    /// Wrong passphrase for an encrypted config file
+    ConfigWrongPassword,
+    /// Operation not allowed in paranoid mode, with the reason
+    ParanoidRefused(&'static str),
+    /// Config `password_command` exited unsuccessfully, with this exit code if any
+    PasswordCommandFailed(Option<i32>),
+    /// Touch ID or Windows Hello was canceled or failed
+    PresenceNotConfirmed,
+    /// Touch ID or Windows Hello could not be used, with the reason
+    PresenceFailed(String),
+    /// `biometric_vaults` needs Touch ID or Windows Hello, which this platform doesn't have
+    PresenceUnsupported,
+    /// `systemd-creds` exited unsuccessfully, with this exit code if any
+    CredentialFailed(Option<i32>),
+    /// Sealed password credentials need `systemd-creds`, which is only available on Linux
+    CredentialUnsupported,
+    /// Command run by `exec` exited unsuccessfully, with this exit code if any
+    ExecFailed(Option<i32>),
+    /// Fields of the entries given to `exec` map to the same environment variable
+    ExecVariableConflict(String),
+    /// `gpg` exited unsuccessfully, with this exit code if any
+    GpgFailed(Option<i32>),
+    /// No gpg key ids given for the export, and the store has no `.gpg-id` file
+    GpgIdMissing,
+    /// KeePass database could not be opened, wrong password or key file, or not a database
+    KdbxInvalid(String),
+    /// Format can only be imported
+    ExportUnsupported,
+    /// Default browser profile not found, or it has no saved logins
+    BrowserProfileNotFound,
+    /// Saved logins of the browser could not be read or decrypted, with the reason
+    BrowserLoginsInvalid(String),
+    /// Key for the saved logins not available from the system key store
+    BrowserKeyUnavailable,
+    /// NSS library of Firefox not found
+    NssUnavailable,
+    /// Item or vault checked by `exists` doesn't exist, exits silently with code 1.
+    /// Other errors of `exists` exit with code 2.
+    DoesNotExist,
+    /// Derived password length must be between 1 and 32
+    DerivedLengthInvalid(usize),
+    /// Generated password length must be between 1 and 1024
+    PasswordLengthInvalid(usize),
+    /// Generator preset allows no characters
+    PresetNoCharacters,
+    /// No generator preset with this name in the config
+    PresetNotFound(String),
+    /// Item doesn't have a derived password
+    ItemNotDerived(String),
+    /// Malformed template placeholder
+    TemplateInvalid(String),
     /// Vault name or file missing
     VaultNotFound(String),
     /// Vault name or path not specified
@@ -36,24 +90,133 @@ pub enum Error {
     VaultCorrupted,
     /// Wrong password (file can also be corrupted, but unlikely)
     WrongPassword,
+    /// Wrong passphrase for a protected item
+    WrongPassphrase,
+    /// Recovery key doesn't match the vault, or the vault has no recovery key
+    WrongRecoveryKey,
+    /// Shared vault member key not valid
+    MemberKeyInvalid,
+    /// Not a recipient of the shared vault
+    NoSuchMember,
+    /// The only member of a shared vault, or the only key slot, can't be removed
+    LastMember,
+    /// Key slot number not in use
+    NoSuchKeySlot(usize),
+    /// Operation requires a shared vault
+    VaultNotShared,
     /// Vault already exists, duplicate vault names are not allowed
     VaultAlreadyExists(String),
+    /// Another process is writing the vault, or it crashed and left this lock file behind
+    VaultLocked(PathBuf),
     /// Item already exists, duplicate item names are not allowed
     ItemAlreadyExists(String),
     /// Item doesn't exist
     NoSuchItem(String),
+    /// No entries given by name or matching the given tags
+    NoItemsSelected,
     /// Input, path or filename contains non-unicode characters
     NonUnicodeInput,
+    /// Answer to this prompt is required, but prompting is disabled with `--no-input`,
+    /// standard input is not a terminal, or it ended
+    InputRequired(String),
+    /// Standard input can't be used for both the vault and the password
+    StdinConflict,
+    /// Operation requires a vault file, not one piped with `--file -`
+    StdioUnsupported,
+    /// Interrupted by a signal during a multi-step operation, which was rolled back
+    Interrupted,
     /// Path: Required directory, got file
     DirectoryRequired(PathBuf),
     /// Path: Required file, got directory
     FileRequired(PathBuf),
+    /// Path: File would be overwritten
+    FileExists(PathBuf),
     /// Name format not allowed
     VaultNameInvalid(ValidationError),
+    /// Profile name is not valid, with the reason
+    ProfileNameInvalid(ValidationError),
+    /// No profile with this name, create it with `vpass --profile <name> init`
+    ProfileNotFound(String),
+    /// Label is empty, too long or has control characters
+    LabelInvalid(ValidationError),
     /// Vault folder not initialized
     NotInitialized,
     /// No password set for item
     ItemNoPasswordSet,
+    /// Item doesn't have an SSH key
+    ItemNoSshKey(String),
+    /// Item has nothing to copy or show for its kind
+    ItemNoContent(String),
+    /// Contents of the item are binary, and can't be used as text
+    ItemDataBinary(String),
+    /// Item doesn't have card details
+    ItemNoCard(String),
+    /// Item doesn't have Wi-Fi network details
+    ItemNoWifi(String),
+    /// Entries of this kind are added with their own command, like `card add`
+    KindHasCommand(ItemKind),
+    /// Text doesn't fit in a QR code
+    QrDataTooLong,
+    /// Listing the known Wi-Fi networks with this program failed
+    WifiImportFailed(String),
+    /// Known Wi-Fi networks can't be read on this platform
+    WifiImportUnsupported,
+    /// Card number has the wrong length or a wrong check digit
+    CardNumberInvalid,
+    /// Card expiry is not like `MM/YY`
+    CardExpiryInvalid(String),
+    /// Card verification code must be 3 or 4 digits
+    CardCvvInvalid,
+    /// Item is not a note entry with a document
+    ItemNotDocument(String),
+    /// Document was left empty, so nothing was saved
+    DocumentEmpty,
+    /// Editor exited unsuccessfully, with this exit code if any
+    EditorFailed(Option<i32>),
+    /// Items of this kind store a file, given with `--from-file`
+    KindNeedsFile(ItemKind),
+    /// Items of this kind don't store a file
+    KindHasNoFile(ItemKind),
+    /// SSH key of the item is encrypted or not an Ed25519 key, so the built-in agent can't use it
+    SshKeyUnsupported(String),
+    /// `ssh-add` exited unsuccessfully, with this exit code if any
+    SshAddFailed(Option<i32>),
+    /// The built-in SSH agent needs unix sockets
+    SshAgentUnsupported,
+    /// Socket is in use by another running agent, or is not a socket
+    SocketInUse(PathBuf),
+    /// Directory for the socket is not owned by the user, or is accessible by others
+    SocketDirUnsafe(PathBuf),
+    /// Multiple items given to a command that needs `--sequence` for that
+    SequenceRequired,
+    /// Clipboard couldn't be accessed, with the failed call
+    ClipboardFailed(String),
+    /// `copy --once` needs to own the X11 selection
+    CopyOnceUnsupported,
+    /// Program typing the secret, `xdotool` or `wtype`, failed
+    TypingFailed(String),
+    /// Item has no note with this id
+    NoSuchNote(u32),
+    /// Vault file format version is not supported by this version of vpass
+    UnsupportedVersion(u32),
+    /// Secret share not valid, or shares from different splits
+    ShareInvalid,
+    /// Share was mistyped or damaged, its checksum doesn't match
+    ShareChecksumMismatch,
+    /// Share threshold must be between 1 and the share count (threshold, count)
+    ShareThresholdInvalid(u8, u8),
+    /// Not enough shares to recover the secret (given, required)
+    NotEnoughShares(usize, usize),
+    /// Operation requires synchronization to be set up for the vault
+    SyncNotSetUp,
+    /// `daemon install` doesn't support the service manager of this platform
+    ServiceUnsupported,
+    /// `daemon install` needs a password file, credential or `password_command`, as the service can't prompt
+    ServiceNoPassword,
+    /// `vpass daemon` has no vaults left to synchronize, as their passwords were wrong or forgotten
+    DaemonStopped,
+    /// Home directory of the user can't be determined
+    HomeDirectoryUnavailable,
     /// Synchronization transfer string not valid
     SynchronizationTransferString,
     /// Synchronization transfer string from an old version
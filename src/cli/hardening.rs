@@ -0,0 +1,54 @@
+//! Keeps plaintext secrets from reaching the disk outside of encrypted vaults.
+//! Core dumps are disabled on startup, as they would contain decrypted vault contents.
+//! In paranoid mode, failures to do so are errors, and operations that would
+//! write plaintext to temporary files are refused.
+
+use super::{error::*, opt::OptRoot};
+
+use tracing::warn;
+
+/// Disable core dumps of this process, and on Linux also ptrace attaching by other processes
+#[cfg(unix)]
+fn disable_core_dumps() -> bool {
+    let limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    unsafe { libc::setrlimit(libc::RLIMIT_CORE, &limit) == 0 && set_not_dumpable() }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn set_not_dumpable() -> bool {
+    libc::prctl(libc::PR_SET_DUMPABLE, 0, 0, 0, 0) == 0
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+unsafe fn set_not_dumpable() -> bool {
+    true
+}
+
+#[cfg(not(unix))]
+fn disable_core_dumps() -> bool {
+    false
+}
+
+/// Apply process hardening, called on startup
+pub fn apply(args: &OptRoot) -> VResult<()> {
+    if disable_core_dumps() {
+        Ok(())
+    } else if args.paranoid {
+        Err(Error::ParanoidRefused("core dumps could not be disabled"))
+    } else {
+        warn!("Unable to disable core dumps");
+        Ok(())
+    }
+}
+
+/// Called before any operation that writes plaintext to a temporary file
+pub fn check_temp_file_allowed(args: &OptRoot, operation: &'static str) -> VResult<()> {
+    if args.paranoid {
+        Err(Error::ParanoidRefused(operation))
+    } else {
+        Ok(())
+    }
+}
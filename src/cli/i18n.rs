@@ -0,0 +1,272 @@
+//! Translations of prompts and messages, gettext-style: the English text is the key
+//! to the catalog of the selected language, and untranslated texts are shown in English.
+//! The language is set in the config, or taken from the locale environment variables.
+
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tracing::warn;
+
+use super::{cfg, opt, paths};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Finnish,
+}
+impl Language {
+    const ALL: [Language; 2] = [Language::English, Language::Finnish];
+
+    /// Language of a locale name like `fi_FI.UTF-8`
+    pub fn from_locale(locale: &str) -> Option<Self> {
+        let code = locale.split(['_', '.', '@']).next().unwrap_or("");
+        match code.to_lowercase().as_str() {
+            "en" | "c" | "posix" => Some(Language::English),
+            "fi" => Some(Language::Finnish),
+            _ => None,
+        }
+    }
+
+    fn catalog(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Language::English => &[],
+            Language::Finnish => FINNISH,
+        }
+    }
+}
+impl std::str::FromStr for Language {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "english" => Ok(Language::English),
+            "finnish" | "suomi" => Ok(Language::Finnish),
+            other => Self::from_locale(other).ok_or_else(|| format!("Unknown language {}", s)),
+        }
+    }
+}
+
+/// Index to `Language::ALL`
+static SELECTED: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_language(language: Language) {
+    let index = Language::ALL.iter().position(|l| *l == language).unwrap();
+    SELECTED.store(index, Ordering::Relaxed);
+}
+
+pub fn language() -> Language {
+    Language::ALL[SELECTED.load(Ordering::Relaxed)]
+}
+
+/// Language of the first set locale variable, in the order the C library uses them
+fn from_env() -> Option<Language> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| Language::from_locale(&value))
+}
+
+/// Select the language from the config or the environment.
+/// Encrypted configs are not read, so that commands don't ask the config password just for this.
+pub fn init(args: &opt::OptRoot) {
+    let mut language = from_env();
+    if !args.disable_config
+        && paths::config_file(args).is_ok_and(|p| p.exists())
+        && cfg::is_encrypted(args).ok() == Some(false)
+    {
+        if let Some(configured) = cfg::read(args).ok().and_then(|c| c.language) {
+            match configured.parse() {
+                Ok(l) => language = Some(l),
+                Err(e) => warn!("{}, using the locale instead", e),
+            }
+        }
+    }
+    set_language(language.unwrap_or(Language::English));
+}
+
+/// Translate a text
+pub fn tr(text: &'static str) -> &'static str {
+    language()
+        .catalog()
+        .iter()
+        .find(|(key, _)| *key == text)
+        .map_or(text, |(_, translated)| *translated)
+}
+
+/// Translate a text, and replace each `{}` in it with the next argument
+pub fn trf(text: &'static str, args: &[&dyn fmt::Display]) -> String {
+    let mut result = String::new();
+    let mut args = args.iter();
+    let mut parts = tr(text).split("{}");
+    result.push_str(parts.next().unwrap());
+    for part in parts {
+        if let Some(arg) = args.next() {
+            result.push_str(&arg.to_string());
+        }
+        result.push_str(part);
+    }
+    result
+}
+
+const FINNISH: &[(&str, &str)] = &[
+    ("Password [vault]:", "Salasana [holvi]:"),
+    ("Password [{}]:", "Salasana [{}]:"),
+    ("Password [item]:", "Salasana [kohde]:"),
+    ("Token [item]:", "Tunniste [kohde]:"),
+    ("Card number [item]:", "Kortin numero [kohde]:"),
+    ("Expiry (MM/YY)", "Voimassa (KK/VV)"),
+    ("CVV [item]:", "Turvakoodi [kohde]:"),
+    ("Passphrase [network]:", "Salasana [verkko]:"),
+    ("Password [config]", "Salasana [asetukset]"),
+    ("Passphrase [{}]:", "Tunnuslause [{}]:"),
+    ("Passphrase [transfer string]:", "Tunnuslause [siirtomerkkijono]:"),
+    ("Passphrase [published entry]:", "Tunnuslause [julkaistu kohde]:"),
+    ("Passphrase for the transfer string in the kit:", "Pakkauksen siirtomerkkijonon tunnuslause:"),
+    ("New password:", "Uusi salasana:"),
+    ("New password for vault:", "Holvin uusi salasana:"),
+    ("New password [vault]:", "Uusi salasana [holvi]:"),
+    ("New sync password [vault]:", "Uusi synkronointisalasana [holvi]:"),
+    ("New password [{}]:", "Uusi salasana [{}]:"),
+    ("New password [config]", "Uusi salasana [asetukset]"),
+    ("Password for the new key slot:", "Uuden avainpaikan salasana:"),
+    ("Non-empty answer required", "Vastaus ei voi olla tyhjä"),
+    ("Non-empty password required", "Salasana ei voi olla tyhjä"),
+    ("Repeat to confirm:", "Toista vahvistukseksi:"),
+    ("Passwords don't match, try again", "Salasanat eivät täsmää, yritä uudelleen"),
+    ("Invalid option '{}'", "Virheellinen valinta '{}'"),
+    ("Directory not found {}", "Hakemistoa ei löydy {}"),
+    ("Synchronization is already configured ({})", "Synkronointi on jo määritetty ({})"),
+    ("Overwrite?", "Korvataanko?"),
+    ("Select a provider", "Valitse palvelu"),
+    ("Initialization complete", "Alustus valmis"),
+    ("Confirm vault deletion:", "Vahvista holvin poisto:"),
+    (
+        "Move the remote copy of this vault to trash? The local vault is kept, and `sync purge-trash` deletes the \
+         remote copy permanently.",
+        "Siirretäänkö holvin etäkopio roskakoriin? Paikallinen holvi säilytetään, ja `sync purge-trash` poistaa \
+         etäkopion pysyvästi.",
+    ),
+    ("Cancelled", "Peruutettu"),
+    ("File path: {}", "Tiedostopolku: {}"),
+    ("Description: {}", "Kuvaus: {}"),
+    ("Creation time: {}", "Luontiaika: {}"),
+    ("Item count: {}", "Kohteita: {}"),
+    ("Entropy: {} bits", "Entropia: {} bittiä"),
+    ("Clearing the clipboard in {} seconds", "Leikepöytä tyhjennetään {} sekunnin kuluttua"),
+    ("Clipboard cleared", "Leikepöytä tyhjennetty"),
+    ("{} was not pasted, clipboard cleared", "Kohdetta {} ei liitetty, leikepöytä tyhjennetty"),
+    ("Estimated entropy: {} bits, cracked offline in {}", "Arvioitu entropia: {} bittiä, murrettavissa {}"),
+    (
+        "Warning: weak password, less than {} bits of entropy",
+        "Varoitus: heikko salasana, entropiaa alle {} bittiä",
+    ),
+    ("centuries", "vuosisadoissa"),
+    ("{} years", "{} vuodessa"),
+    ("{} days", "{} päivässä"),
+    ("{} hours", "{} tunnissa"),
+    ("{} minutes", "{} minuutissa"),
+    ("{} seconds", "{} sekunnissa"),
+    ("less than a second", "alle sekunnissa"),
+    ("Shared with {} members", "Jaettu {} jäsenelle"),
+    ("Recovery key created: {}", "Palautusavain luotu: {}"),
+    ("Synchronization: {}", "Synkronointi: {}"),
+    ("Synchronization not set up", "Synkronointia ei ole määritetty"),
+    ("Merged {} conflict copies", "Yhdistettiin {} ristiriitakopiota"),
+    (
+        "Remote key changed, import the vault again on other devices",
+        "Etäavain vaihdettu, tuo holvi uudelleen muilla laitteilla",
+    ),
+    ("Provider: {}", "Palvelu: {}"),
+    ("Remote key: {}", "Etäavain: {}"),
+    ("Remote key encryption disabled", "Etäavaimen salaus ei käytössä"),
+    (
+        "Remote copy encrypted with a separate sync password",
+        "Etäkopio salattu erillisellä synkronointisalasanalla",
+    ),
+    (
+        "Sync password changed, import the vault again on other devices",
+        "Synkronointisalasana vaihdettu, tuo holvi uudelleen muilla laitteilla",
+    ),
+    ("Last synchronized: {}", "Viimeksi synkronoitu: {}"),
+    (
+        "Derived passwords of {} entries are now stored in the vault",
+        "{} kohteen johdetut salasanat on nyt tallennettu holviin",
+    ),
+    (
+        "Saved to config. Use `vault change-password` to apply to existing vaults.",
+        "Tallennettu asetuksiin. Käytä komentoa `vault change-password` olemassa oleviin holveihin.",
+    ),
+    ("Copied {}, next: {}", "Kopioitu {}, seuraava: {}"),
+    ("Interrupted, changes were rolled back", "Keskeytetty, muutokset peruttiin"),
+    ("Remote: {} bytes, {} deltas", "Etäholvi: {} tavua, {} muutosta"),
+    ("Not on the remote", "Ei etäpalvelussa"),
+    ("Up to date", "Ajan tasalla"),
+    (
+        "Remote unreachable, showing the state at the last sync",
+        "Etäpalveluun ei saada yhteyttä, näytetään edellisen synkronoinnin tila",
+    ),
+    ("Remote has changed since the last sync", "Etäholvi on muuttunut edellisen synkronoinnin jälkeen"),
+    ("Not synchronized on this device yet", "Ei vielä synkronoitu tällä laitteella"),
+    ("Installed {}, start it with `{}`", "Asennettu {}, käynnistä se komennolla `{}`"),
+    ("reveal secrets of {}", "paljastaa holvin {} salaisuudet"),
+    (
+        "Password sealed to {}, use it with `--password-credential`",
+        "Salasana sinetöity tiedostoon {}, käytä sitä valitsimella `--password-credential`",
+    ),
+    ("Synchronized {}", "Synkronoitu {}"),
+    ("Synchronization of {} failed", "Holvin {} synkronointi epäonnistui"),
+    (
+        "{} locked, restart the daemon to synchronize it",
+        "{} lukittu, käynnistä taustaprosessi uudelleen synkronoidaksesi sen",
+    ),
+    ("Agent locked", "Agentti lukittu"),
+    (
+        "{} conflict copies of {}, use `sync resolve-conflicts` to merge them",
+        "{} ristiriitakopiota holvista {}, yhdistä ne komennolla `sync resolve-conflicts`",
+    ),
+    ("Change the passwords on these sites:", "Vaihda salasanat näillä sivustoilla:"),
+    (
+        "Confirm each one with `rotate --done <name>` once changed",
+        "Vahvista jokainen vaihdetuksi komennolla `rotate --done <nimi>`",
+    ),
+    ("Imported {} entries", "Tuotiin {} kohdetta"),
+    ("Exported {} entries", "Vietiin {} kohdetta"),
+    ("never synchronized", "ei synkronoitu"),
+    ("Move {} to trash?", "Siirretäänkö {} roskakoriin?"),
+    ("Delete {}?", "Poistetaanko {}?"),
+    (
+        "Synchronization provider rate limit reached, retry at {}",
+        "Synkronointipalvelun pyyntöraja täynnä, yritä uudelleen klo {}",
+    ),
+    (
+        "Remote vault is not a copy of this vault, use `sync --force-adopt` to link them",
+        "Etäholvi ei ole tämän holvin kopio, yhdistä ne komennolla `sync --force-adopt`",
+    ),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::{set_language, tr, trf, Language, FINNISH};
+
+    #[test]
+    fn translate() {
+        assert_eq!(Language::from_locale("fi_FI.UTF-8"), Some(Language::Finnish));
+        assert_eq!(Language::from_locale("en_US"), Some(Language::English));
+        assert_eq!(Language::from_locale("C"), Some(Language::English));
+        assert_eq!(Language::from_locale("xx_XX"), None);
+        assert_eq!("Finnish".parse::<Language>(), Ok(Language::Finnish));
+        assert!("klingon".parse::<Language>().is_err());
+
+        // Translations must keep the placeholders
+        for (key, translated) in FINNISH {
+            assert_eq!(key.matches("{}").count(), translated.matches("{}").count(), "{}", key);
+        }
+
+        set_language(Language::Finnish);
+        assert_eq!(tr("Cancelled"), "Peruutettu");
+        assert_eq!(tr("Not in the catalog"), "Not in the catalog");
+        assert_eq!(trf("Copied {}, next: {}", &[&"a", &2]), "Kopioitu a, seuraava: 2");
+        set_language(Language::English);
+        assert_eq!(trf("Copied {}, next: {}", &[&"a", &2]), "Copied a, next: 2");
+    }
+}
@@ -0,0 +1,116 @@
+//! Locking of `vpass daemon` and `vpass ssh-agent`, which keep secrets in memory:
+//! the ssh-agent forgets them after being idle for `agent_lock_seconds`, the daemon `daemon_lock_seconds`
+//! after startup, and both when the computer resumes from suspend, or when the screen is locked
+//! if `lock_on_screen_lock` is set. The screen lock is known only on Linux with systemd-logind.
+
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often running agents check whether they should lock
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How much the wall clock may run ahead of the monotonic clock, which stops during suspend,
+/// before the difference is taken as a suspend
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(30);
+
+/// Expires after a timeout without use, or when a suspend or a screen lock is detected
+#[derive(Debug)]
+pub struct IdleTimer {
+    timeout: Option<Duration>,
+    last_use: Instant,
+    /// Both clocks at the previous check
+    checked: (Instant, SystemTime),
+    screen_lock: bool,
+}
+impl IdleTimer {
+    /// Timer expiring after `seconds` without use, or only on suspend if 0
+    pub fn new(seconds: u64) -> Self {
+        Self {
+            timeout: Some(Duration::from_secs(seconds)).filter(|t| t.as_secs() > 0),
+            last_use: Instant::now(),
+            checked: (Instant::now(), SystemTime::now()),
+            screen_lock: false,
+        }
+    }
+
+    /// Expire also when the screen is locked
+    pub fn lock_on_screen_lock(mut self, enabled: bool) -> Self {
+        self.screen_lock = enabled;
+        self
+    }
+
+    pub fn touch(&mut self) {
+        self.touch_at(Instant::now());
+    }
+
+    fn touch_at(&mut self, instant: Instant) {
+        self.last_use = instant;
+    }
+
+    /// Whether the timeout has passed, the computer has been suspended since the previous check,
+    /// or the screen is locked
+    pub fn expired(&mut self) -> bool {
+        self.expired_at(Instant::now(), SystemTime::now())
+    }
+
+    /// Check with the clocks read at `instant` and `time`
+    fn expired_at(&mut self, instant: Instant, time: SystemTime) -> bool {
+        let monotonic = instant.saturating_duration_since(self.checked.0);
+        let wall = time.duration_since(self.checked.1).unwrap_or_default();
+        self.checked = (instant, time);
+        wall > monotonic + SUSPEND_THRESHOLD
+            || self.timeout.is_some_and(|t| instant.saturating_duration_since(self.last_use) >= t)
+            || (self.screen_lock && screen_locked())
+    }
+}
+
+/// Whether the screen is locked, from the `LockedHint` of the login session in systemd-logind.
+/// Services outside a session get the graphical session of the user. False if it can't be determined.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn screen_locked() -> bool {
+    use dbus::stdintf::org_freedesktop_dbus::Properties;
+    use dbus::{BusType, Connection};
+
+    // Connected for each check, as the connection can't be shared between threads
+    Connection::get_private(BusType::System)
+        .and_then(|conn| {
+            conn.with_path("org.freedesktop.login1", "/org/freedesktop/login1/session/auto", 1000)
+                .get::<bool>("org.freedesktop.login1.Session", "LockedHint")
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(not(all(unix, not(target_os = "macos"))))]
+fn screen_locked() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdleTimer;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn idle_timeout() {
+        let mut timer = IdleTimer::new(0);
+        let (start, time) = timer.checked;
+        assert!(!timer.expired_at(start + Duration::from_secs(60 * 60), time + Duration::from_secs(60 * 60)));
+
+        let mut timer = IdleTimer::new(60);
+        let (start, time) = timer.checked;
+        let after = |seconds| (start + Duration::from_secs(seconds), time + Duration::from_secs(seconds));
+        timer.touch_at(after(40).0);
+        let (instant, time) = after(80);
+        assert!(!timer.expired_at(instant, time));
+        let (instant, time) = after(100);
+        assert!(timer.expired_at(instant, time));
+    }
+
+    #[test]
+    fn suspend_detected() {
+        let mut timer = IdleTimer::new(0);
+        // The wall clock has moved an hour since the previous check, but the monotonic clock hasn't
+        timer.checked.1 = SystemTime::now() - Duration::from_secs(60 * 60);
+        assert!(timer.expired());
+        assert!(!timer.expired());
+    }
+}
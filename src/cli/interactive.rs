@@ -1,93 +1,252 @@
+use super::i18n::{tr, trf};
+use super::{console, hardening, interrupt, opt::OptRoot};
 use crate::backend::book::Book;
 use crate::sync::config::{self, SyncConfig};
 use crate::sync::providers::Provider;
 use crate::{Error, VResult};
 
+use std::cell::RefCell;
+use std::env;
 use std::fmt;
+use std::fs;
 use std::io::prelude::*;
 use std::iter::Iterator;
 use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use serde_json::Value;
 use strum::IntoEnumIterator;
+use uuid::Uuid;
+
+/// Set with `--plain`
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Set with `--no-input`
+static NO_INPUT: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Standard input can only be read once
+    static STDIN_PASSWORD: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+pub fn set_plain(plain: bool) {
+    PLAIN.store(plain, Ordering::Relaxed);
+}
+
+/// Plain output for screen readers and braille terminals
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+pub fn set_no_input(no_input: bool) {
+    NO_INPUT.store(no_input, Ordering::Relaxed);
+}
+
+/// Fail instead of prompting if prompts are disabled with `--no-input`,
+/// or if there is no terminal to answer on, in which case an answer might never come
+pub fn require_input(prompt: &str) -> VResult<()> {
+    if NO_INPUT.load(Ordering::Relaxed) || !console::terminal_available() {
+        Err(input_required(prompt))
+    } else {
+        Ok(())
+    }
+}
+
+fn input_required(prompt: &str) -> Error {
+    Error::InputRequired(prompt.trim_end().trim_end_matches(':').to_owned())
+}
+
+/// Read a line, failing instead of asking again if the input has ended
+fn read_line(prompt: &str, buf: &mut String) -> VResult<()> {
+    if std::io::stdin().lock().read_line(buf)? == 0 {
+        return Err(input_required(prompt));
+    }
+    Ok(())
+}
+
+/// First line of standard input, for `--password-stdin`
+pub fn stdin_password() -> VResult<String> {
+    if let Some(pw) = STDIN_PASSWORD.with(|p| p.borrow().clone()) {
+        return Ok(pw);
+    }
+    let mut buf = String::new();
+    read_line("Password from standard input", &mut buf)?;
+    let pw = buf.trim_end_matches(['\r', '\n']).to_owned();
+    STDIN_PASSWORD.with(|p| *p.borrow_mut() = Some(pw.clone()));
+    Ok(pw)
+}
+
+/// Overwrite the password read with `--password-stdin`, called before exiting
+pub fn forget_stdin_password() {
+    STDIN_PASSWORD.with(|p| {
+        if let Some(mut pw) = p.borrow_mut().take() {
+            interrupt::zeroize(&mut pw);
+        }
+    });
+}
+
+/// Prompt ending with a colon and a space, so that the end of it is clear when read aloud
+pub fn terminated(prompt: &str) -> String {
+    format!("{}: ", prompt.trim_end().trim_end_matches(':'))
+}
 
 pub fn prompt_string(prompt: &str) -> VResult<String> {
+    require_input(prompt)?;
     let mut buf = String::new();
     loop {
         print!("{}: ", prompt);
         std::io::stdout().flush().unwrap();
-        std::io::stdin().lock().read_line(&mut buf).unwrap();
+        read_line(prompt, &mut buf)?;
         buf = buf.trim().to_owned();
         if !buf.is_empty() {
             return Ok(buf);
         }
-        println!("Non-empty answer required");
+        println!("{}", tr("Non-empty answer required"));
         buf.clear();
     }
 }
 
 pub fn prompt_password(prompt: &str) -> VResult<String> {
+    require_input(prompt)?;
+    loop {
+        let pass = console::read_password(&terminated(prompt))?;
+        // Standard output may be piped, e.g. the vault with `--file -`
+        eprintln!();
+        if !pass.is_empty() {
+            return Ok(pass);
+        }
+        eprintln!("{}", tr("Non-empty password required"));
+    }
+}
+
+/// Prompt for a new password twice, as a mistyped one would otherwise go unnoticed
+pub fn prompt_new_password(prompt: &str) -> VResult<String> {
     loop {
-        let pass = rpassword::read_password_from_tty(Some(&format!("{}: ", prompt)))?;
-        println!();
-        if pass != "" {
+        let pass = prompt_password(prompt)?;
+        if prompt_password(tr("Repeat to confirm:"))? == pass {
             return Ok(pass);
         }
-        println!("Non-empty password required");
+        eprintln!("{}", tr("Passwords don't match, try again"));
     }
 }
 
 pub fn prompt_boolean(prompt: &str) -> VResult<bool> {
+    require_input(prompt)?;
     let mut buf = String::new();
     loop {
-        print!("{} [y/n]: ", prompt);
+        if is_plain() {
+            print!("{} Answer yes or no: ", prompt);
+        } else {
+            print!("{} [y/n]: ", prompt);
+        }
         std::io::stdout().flush().unwrap();
-        std::io::stdin().lock().read_line(&mut buf).unwrap();
+        read_line(prompt, &mut buf)?;
         buf = buf.trim().to_owned();
         if buf == "y" || buf == "yes" {
             return Ok(true);
         } else if buf == "n" || buf == "no" {
             return Ok(false);
         }
-        println!("Invalid option '{}'", buf.trim());
+        println!("{}", trf("Invalid option '{}'", &[&buf.trim()]));
         buf.clear();
     }
 }
 
+/// Wait until the user presses enter
+pub fn wait_enter(prompt: &str) -> VResult<()> {
+    require_input(prompt)?;
+    if is_plain() {
+        print!("{}. Press enter to continue: ", prompt);
+    } else {
+        print!("{} [enter]", prompt);
+    }
+    std::io::stdout().flush()?;
+    read_line(prompt, &mut String::new())?;
+    Ok(())
+}
+
+/// Edit `text` in `$VISUAL` or `$EDITOR`, or `vi` if neither is set, and return the result.
+/// The editor needs a file, so the text is written to one readable only by the user,
+/// in the runtime directory if there is one, and removed afterwards.
+pub fn edit_text(args: &OptRoot, text: &str) -> VResult<String> {
+    hardening::check_temp_file_allowed(args, "the editor reads the text from a temporary file")?;
+    require_input("Editor")?;
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned());
+    // Editors are often given with arguments, like `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().unwrap_or("vi");
+
+    let path = dirs::runtime_dir()
+        .unwrap_or_else(env::temp_dir)
+        .join(format!("{}-{}.md", env!("CARGO_PKG_NAME"), Uuid::new_v4()));
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&path)?.write_all(text.as_bytes())?;
+
+    let result = Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .map_err(Error::from)
+        .and_then(|status| {
+            if status.success() {
+                Ok(fs::read_to_string(&path)?)
+            } else {
+                Err(Error::EditorFailed(status.code()))
+            }
+        });
+    fs::remove_file(&path)?;
+    result
+}
+
 pub fn prompt_enum<E: FromStr + IntoEnumIterator>(prompt: &str) -> VResult<E>
 where
     <E as IntoEnumIterator>::Iterator: Iterator,
     <<E as IntoEnumIterator>::Iterator as Iterator>::Item: fmt::Display,
 {
+    require_input(prompt)?;
     let mut buf = String::new();
     println!("{}:", prompt);
     for variant in E::iter() {
-        println!("* {}", variant);
+        if is_plain() {
+            println!("{}", variant);
+        } else {
+            println!("* {}", variant);
+        }
     }
     loop {
-        print!("> ");
+        print!("{}", if is_plain() { "Choice: " } else { "> " });
         std::io::stdout().flush().unwrap();
-        std::io::stdin().lock().read_line(&mut buf).unwrap();
+        read_line(prompt, &mut buf)?;
         if let Ok(v) = buf.trim().parse::<E>() {
             return Ok(v);
         }
-        println!("Invalid option '{}'", buf.trim());
+        println!("{}", trf("Invalid option '{}'", &[&buf.trim()]));
         buf.clear();
     }
 }
 
 pub fn prompt_dir_path(prompt: &str) -> VResult<PathBuf> {
+    require_input(prompt)?;
     let mut buf = String::new();
     loop {
         print!("{}: ", prompt);
         std::io::stdout().flush().unwrap();
-        std::io::stdin().lock().read_line(&mut buf).unwrap();
+        read_line(prompt, &mut buf)?;
         let p = Path::new(buf.trim());
         if p.exists() {
             return Ok(p.to_owned());
         }
-        println!("Directory not found {:?}", p);
+        println!("{}", trf("Directory not found {}", &[&format!("{:?}", p)]));
         buf.clear();
     }
 }
@@ -95,15 +254,20 @@ pub fn prompt_dir_path(prompt: &str) -> VResult<PathBuf> {
 /// Returns `Ok(None)` if cancelled
 pub fn sync_setup(book: &Book) -> VResult<Option<SyncConfig>> {
     if let Some(c) = config::book_read(book)? {
-        println!("Synchronization is already configured ({})", c.service);
-        if !prompt_boolean("Overwrite?")? {
+        println!("{}", trf("Synchronization is already configured ({})", &[&c.service]));
+        if !prompt_boolean(tr("Overwrite?"))? {
             return Ok(None);
         }
     }
 
-    let p = prompt_enum::<Provider>("Select a provider")?;
+    let p = prompt_enum::<Provider>(tr("Select a provider"))?;
     let data: Value = p.interactive_setup()?;
-    let mut service = p.load(&data);
+    let mut service = p.load(&data)?;
     service.ping()?;
-    Ok(Some(SyncConfig { service: p, data }))
+    Ok(Some(SyncConfig {
+        service: p,
+        data,
+        remote_key: None,
+        password: None,
+    }))
 }
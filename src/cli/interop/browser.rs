@@ -0,0 +1,577 @@
+//! Saved logins read directly from the profile of a web browser, without exporting them first.
+//! Chrome encrypts passwords with a key from the system key store: the keychain on macOS,
+//! the secret service on Linux (or a fixed key when none is running), and DPAPI on Windows.
+//! Firefox encrypts them with the key database of the profile, which is opened with the NSS library
+//! shipped with Firefox, and may ask for the primary password.
+//! Entries are named after the site, and usernames and urls are kept in a note.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use serde::Deserialize;
+
+use super::{deduplicate, Entry};
+use crate::cli::error::{Error, VResult};
+use crate::cli::{hardening, opt::OptRoot};
+use crate::ItemKind;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    Chrome,
+    Firefox,
+}
+impl FromStr for Browser {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chrome" => Ok(Browser::Chrome),
+            "firefox" => Ok(Browser::Firefox),
+            other => Err(format!("Unknown browser {}, expected chrome or firefox", other)),
+        }
+    }
+}
+impl fmt::Display for Browser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Browser::Chrome => write!(f, "Chrome"),
+            Browser::Firefox => write!(f, "Firefox"),
+        }
+    }
+}
+
+fn invalid<E: ToString>(e: E) -> Error {
+    Error::BrowserLoginsInvalid(e.to_string())
+}
+
+/// Entry for a saved login, named after the host of the site
+fn login_entry(url: &str, username: &str, password: &str) -> Entry {
+    let host = url.find("://").map_or(url, |i| &url[i + 3..]);
+    let host = host.split(['/', ':']).next().unwrap_or("");
+    let mut lines = Vec::new();
+    if !username.is_empty() {
+        lines.push(format!("username: {}", username));
+    }
+    lines.push(format!("url: {}", url));
+    Entry {
+        name: if host.is_empty() { url.to_owned() } else { host.to_owned() },
+        password: if password.is_empty() { None } else { Some(password.to_owned()) },
+        notes: vec![lines.join("\n")],
+        tags: Vec::new(),
+        kind: ItemKind::Login,
+        data: None,
+    }
+}
+
+/// Read all saved logins of the default profile, or the profile directory `profile`.
+/// `ask_password` is called if the logins are protected with a primary password.
+pub fn import(
+    args: &OptRoot, browser: Browser, profile: Option<&Path>, ask_password: &dyn Fn() -> VResult<String>,
+) -> VResult<Vec<Entry>> {
+    let profile = match profile {
+        Some(path) => path.to_owned(),
+        None => match browser {
+            Browser::Chrome => chrome::default_profile(),
+            Browser::Firefox => firefox::default_profile(),
+        }
+        .ok_or(Error::BrowserProfileNotFound)?,
+    };
+    if !profile.is_dir() {
+        return Err(Error::DirectoryRequired(profile));
+    }
+    let mut result = match browser {
+        Browser::Chrome => chrome::import(args, &profile)?,
+        Browser::Firefox => firefox::import(&profile, ask_password)?,
+    };
+    result.sort_by(|a, b| a.name.cmp(&b.name));
+    deduplicate(&mut result);
+    Ok(result)
+}
+
+mod chrome {
+    use rusqlite::{Connection, OpenFlags, NO_PARAMS};
+    use std::process::Command;
+
+    use super::*;
+
+    pub fn default_profile() -> Option<PathBuf> {
+        let base = if cfg!(windows) {
+            dirs::data_local_dir()?.join("Google/Chrome/User Data")
+        } else if cfg!(target_os = "macos") {
+            dirs::config_dir()?.join("Google/Chrome")
+        } else {
+            dirs::config_dir()?.join("google-chrome")
+        };
+        Some(base.join("Default")).filter(|p| p.is_dir())
+    }
+
+    /// Key derived from the key store secret, for AES-128-CBC
+    #[cfg(not(windows))]
+    fn cbc_key(secret: &[u8], rounds: usize) -> [u8; 16] {
+        let mut key = [0u8; 16];
+        pbkdf2::pbkdf2::<hmac::Hmac<sha1::Sha1>>(secret, b"saltysalt", rounds, &mut key);
+        key
+    }
+
+    #[cfg(not(windows))]
+    fn cbc_decrypt(key: &[u8; 16], data: &[u8]) -> VResult<Vec<u8>> {
+        use block_modes::{block_padding::Pkcs7, BlockMode, Cbc};
+        Cbc::<aes::Aes128, Pkcs7>::new_var(key, &[b' '; 16])
+            .map_err(|_| invalid("Invalid key"))?
+            .decrypt_vec(data)
+            .map_err(|_| invalid("Password could not be decrypted"))
+    }
+
+    /// Output of a key store command, without the trailing newline
+    #[cfg(not(windows))]
+    fn command_secret(command: &mut Command) -> Option<Vec<u8>> {
+        let output = command.output().ok().filter(|o| o.status.success())?;
+        let mut secret = output.stdout;
+        while secret.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+            secret.pop();
+        }
+        Some(secret).filter(|s| !s.is_empty())
+    }
+
+    /// Passwords are prefixed with `v10` when encrypted with the fixed key, and `v11` with the secret service
+    #[cfg(all(unix, not(target_os = "macos")))]
+    struct Decryptor {
+        v10: [u8; 16],
+        v11: Option<[u8; 16]>,
+    }
+    #[cfg(all(unix, not(target_os = "macos")))]
+    impl Decryptor {
+        fn new(_profile: &Path) -> VResult<Self> {
+            let mut command = Command::new("secret-tool");
+            command.args(["lookup", "application", "chrome"]);
+            let secret = command_secret(&mut command);
+            Ok(Self {
+                v10: cbc_key(b"peanuts", 1),
+                v11: secret.map(|s| cbc_key(&s, 1)),
+            })
+        }
+
+        fn decrypt(&self, data: &[u8]) -> VResult<Vec<u8>> {
+            match data.get(..3) {
+                Some(b"v10") => cbc_decrypt(&self.v10, &data[3..]),
+                Some(b"v11") => {
+                    let key = self.v11.as_ref().ok_or(Error::BrowserKeyUnavailable)?;
+                    cbc_decrypt(key, &data[3..])
+                },
+                _ => Ok(data.to_vec()),
+            }
+        }
+    }
+
+    #[cfg(target_os = "macos")]
+    struct Decryptor {
+        key: [u8; 16],
+    }
+    #[cfg(target_os = "macos")]
+    impl Decryptor {
+        fn new(_profile: &Path) -> VResult<Self> {
+            let mut command = Command::new("security");
+            command.args(&["find-generic-password", "-wa", "Chrome"]);
+            let secret = command_secret(&mut command).ok_or(Error::BrowserKeyUnavailable)?;
+            Ok(Self {
+                key: cbc_key(&secret, 1003),
+            })
+        }
+
+        fn decrypt(&self, data: &[u8]) -> VResult<Vec<u8>> {
+            match data.get(..3) {
+                Some(b"v10") => cbc_decrypt(&self.key, &data[3..]),
+                _ => Ok(data.to_vec()),
+            }
+        }
+    }
+
+    #[cfg(windows)]
+    fn dpapi_decrypt(data: &[u8]) -> VResult<Vec<u8>> {
+        use std::ptr::null_mut;
+        use winapi::um::{dpapi::CryptUnprotectData, winbase::LocalFree, wincrypt::CRYPTOAPI_BLOB};
+
+        let mut input = CRYPTOAPI_BLOB {
+            cbData: data.len() as u32,
+            pbData: data.as_ptr() as *mut u8,
+        };
+        let mut output = CRYPTOAPI_BLOB {
+            cbData: 0,
+            pbData: null_mut(),
+        };
+        let ok = unsafe {
+            CryptUnprotectData(&mut input, null_mut(), null_mut(), null_mut(), null_mut(), 0, &mut output)
+        };
+        if ok == 0 {
+            return Err(Error::BrowserKeyUnavailable);
+        }
+        let result = unsafe { std::slice::from_raw_parts(output.pbData, output.cbData as usize) }.to_vec();
+        unsafe { LocalFree(output.pbData as _) };
+        Ok(result)
+    }
+
+    /// Passwords are prefixed with `v10` when encrypted with AES-256-GCM, using a key stored
+    /// in `Local State` encrypted with DPAPI. Older ones are encrypted with DPAPI directly.
+    #[cfg(windows)]
+    struct Decryptor {
+        key: Option<Vec<u8>>,
+    }
+    #[cfg(windows)]
+    impl Decryptor {
+        fn new(profile: &Path) -> VResult<Self> {
+            #[derive(Deserialize)]
+            struct OsCrypt {
+                encrypted_key: String,
+            }
+            #[derive(Deserialize)]
+            struct LocalState {
+                os_crypt: Option<OsCrypt>,
+            }
+
+            let path = profile.parent().unwrap_or(profile).join("Local State");
+            let key = match fs::read(&path) {
+                Ok(data) => serde_json::from_slice::<LocalState>(&data).map_err(invalid)?.os_crypt,
+                Err(_) => None,
+            };
+            let key = match key {
+                Some(os_crypt) => {
+                    let encrypted = base64::decode(&os_crypt.encrypted_key).map_err(invalid)?;
+                    if !encrypted.starts_with(b"DPAPI") {
+                        return Err(invalid("Unknown key format in Local State"));
+                    }
+                    Some(dpapi_decrypt(&encrypted[5..])?)
+                },
+                None => None,
+            };
+            Ok(Self { key })
+        }
+
+        fn decrypt(&self, data: &[u8]) -> VResult<Vec<u8>> {
+            use aes_gcm::aead::{generic_array::GenericArray, Aead, NewAead};
+
+            if !data.starts_with(b"v10") {
+                return dpapi_decrypt(data);
+            }
+            let key = self.key.as_ref().ok_or(Error::BrowserKeyUnavailable)?;
+            if data.len() < 15 || key.len() != 32 {
+                return Err(invalid("Password could not be decrypted"));
+            }
+            let (nonce, ciphertext) = data[3..].split_at(12);
+            aes_gcm::Aes256Gcm::new(*GenericArray::from_slice(key))
+                .decrypt(GenericArray::from_slice(nonce), ciphertext)
+                .map_err(|_| invalid("Password could not be decrypted"))
+        }
+    }
+
+    pub fn import(args: &OptRoot, profile: &Path) -> VResult<Vec<Entry>> {
+        let database = profile.join("Login Data");
+        if !database.is_file() {
+            return Err(Error::BrowserProfileNotFound);
+        }
+        hardening::check_temp_file_allowed(args, "the login database is read from a temporary copy")?;
+        // The database is locked while Chrome is running, so a copy is read instead
+        let copy = TempCopy::create(&database)?;
+        let rows = read_logins(&copy.0);
+        drop(copy);
+
+        let decryptor = Decryptor::new(profile)?;
+        rows?
+            .into_iter()
+            .map(|(url, username, encrypted)| {
+                let password = String::from_utf8(decryptor.decrypt(&encrypted)?).map_err(invalid)?;
+                Ok(login_entry(&url, &username, &password))
+            })
+            .collect()
+    }
+
+    /// Copy of a file readable only by the user, in the runtime directory if there is one,
+    /// removed when dropped
+    struct TempCopy(PathBuf);
+    impl TempCopy {
+        fn create(source: &Path) -> VResult<Self> {
+            let path = dirs::runtime_dir()
+                .unwrap_or_else(std::env::temp_dir)
+                .join(format!("{}-login-data-{}", env!("CARGO_PKG_NAME"), uuid::Uuid::new_v4()));
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut file = options.open(&path)?;
+            let copy = TempCopy(path);
+            std::io::copy(&mut fs::File::open(source)?, &mut file)?;
+            Ok(copy)
+        }
+    }
+    impl Drop for TempCopy {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.0);
+        }
+    }
+
+    fn read_logins(database: &Path) -> VResult<Vec<(String, String, Vec<u8>)>> {
+        let connection =
+            Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(invalid)?;
+        let mut statement = connection
+            .prepare(
+                "SELECT origin_url, username_value, password_value FROM logins WHERE blacklisted_by_user = 0",
+            )
+            .map_err(invalid)?;
+        let rows = statement
+            .query_map(NO_PARAMS, |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(invalid)?;
+        rows.collect::<Result<_, _>>().map_err(invalid)
+    }
+}
+
+mod firefox {
+    use libloading::{Library, Symbol};
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_int, c_uchar, c_uint, c_void};
+    use std::ptr::null_mut;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Login {
+        hostname: String,
+        encrypted_username: String,
+        encrypted_password: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Logins {
+        logins: Vec<Login>,
+    }
+
+    fn profiles_dir() -> Option<PathBuf> {
+        if cfg!(windows) {
+            Some(dirs::config_dir()?.join("Mozilla/Firefox"))
+        } else if cfg!(target_os = "macos") {
+            Some(dirs::config_dir()?.join("Firefox"))
+        } else {
+            Some(dirs::home_dir()?.join(".mozilla/firefox"))
+        }
+    }
+
+    /// Path of the default profile in `profiles.ini`, relative to its directory.
+    /// Newer versions of Firefox have a default for each installation, older ones mark a profile as default.
+    fn parse_profiles_ini(content: &str) -> Option<String> {
+        let mut install_default = None;
+        let mut marked_default = None;
+        let mut first = None;
+        let mut section = "";
+        let mut path = None;
+        for line in content.lines().map(str::trim) {
+            if line.starts_with('[') {
+                section = line;
+                path = None;
+                continue;
+            }
+            let mut kv = line.splitn(2, '=');
+            let (key, value) = match (kv.next(), kv.next()) {
+                (Some(key), Some(value)) => (key, value),
+                _ => continue,
+            };
+            match key {
+                "Default" if section.starts_with("[Install") => {
+                    install_default = install_default.or_else(|| Some(value.to_owned()));
+                },
+                "Path" if section.starts_with("[Profile") => {
+                    path = Some(value.to_owned());
+                    first = first.or_else(|| path.clone());
+                },
+                "Default" if section.starts_with("[Profile") && value == "1" => {
+                    marked_default = marked_default.or_else(|| path.clone());
+                },
+                _ => {},
+            }
+        }
+        install_default.or(marked_default).or(first)
+    }
+
+    pub fn default_profile() -> Option<PathBuf> {
+        let dir = profiles_dir()?;
+        let content = fs::read_to_string(dir.join("profiles.ini")).ok()?;
+        Some(dir.join(parse_profiles_ini(&content)?)).filter(|p| p.is_dir())
+    }
+
+    #[cfg(all(unix, not(target_os = "macos")))]
+    const NSS_LIBRARIES: &[&str] = &["libnss3.so"];
+    #[cfg(target_os = "macos")]
+    const NSS_LIBRARIES: &[&str] =
+        &["/Applications/Firefox.app/Contents/MacOS/libnss3.dylib", "libnss3.dylib"];
+    #[cfg(windows)]
+    const NSS_LIBRARIES: &[&str] = &[
+        "C:\\Program Files\\Mozilla Firefox\\nss3.dll",
+        "C:\\Program Files (x86)\\Mozilla Firefox\\nss3.dll",
+        "nss3.dll",
+    ];
+
+    const SEC_SUCCESS: c_int = 0;
+
+    #[repr(C)]
+    struct SecItem {
+        kind: c_uint,
+        data: *mut c_uchar,
+        len: c_uint,
+    }
+
+    /// NSS initialized with a profile, shut down when dropped
+    struct Nss {
+        library: Library,
+    }
+    impl Nss {
+        fn open(profile: &Path) -> VResult<Self> {
+            let library = NSS_LIBRARIES
+                .iter()
+                .filter_map(|name| {
+                    // The libraries NSS depends on are next to it
+                    let dir = Path::new(name).parent().filter(|d| !d.as_os_str().is_empty());
+                    if let (true, Some(dir), Some(path)) = (cfg!(windows), dir, std::env::var_os("PATH")) {
+                        let mut paths: Vec<PathBuf> = std::env::split_paths(&path).collect();
+                        paths.insert(0, dir.to_owned());
+                        std::env::set_var("PATH", std::env::join_paths(paths).ok()?);
+                    }
+                    Library::new(name).ok()
+                })
+                .next()
+                .ok_or(Error::NssUnavailable)?;
+            let profile = profile.to_str().ok_or(Error::NonUnicodeInput)?;
+            let config = CString::new(format!("sql:{}", profile)).map_err(|_| Error::NonUnicodeInput)?;
+            let status = {
+                let init: Symbol<unsafe extern "C" fn(*const c_char) -> c_int> =
+                    unsafe { library.get(b"NSS_Init\0") }.map_err(|_| Error::NssUnavailable)?;
+                unsafe { init(config.as_ptr()) }
+            };
+            if status != SEC_SUCCESS {
+                return Err(invalid("Key database of the profile could not be opened"));
+            }
+            Ok(Self { library })
+        }
+
+        fn symbol<T>(&self, name: &[u8]) -> VResult<Symbol<'_, T>> {
+            unsafe { self.library.get(name) }.map_err(|_| Error::NssUnavailable)
+        }
+
+        /// Unlock the key database with the primary password, if it has one
+        fn login(&self, ask_password: &dyn Fn() -> VResult<String>) -> VResult<()> {
+            let get_slot: Symbol<unsafe extern "C" fn() -> *mut c_void> =
+                self.symbol(b"PK11_GetInternalKeySlot\0")?;
+            let free_slot: Symbol<unsafe extern "C" fn(*mut c_void)> = self.symbol(b"PK11_FreeSlot\0")?;
+            let need_login: Symbol<unsafe extern "C" fn(*mut c_void) -> c_int> =
+                self.symbol(b"PK11_NeedLogin\0")?;
+            let check_password: Symbol<unsafe extern "C" fn(*mut c_void, *const c_char) -> c_int> =
+                self.symbol(b"PK11_CheckUserPassword\0")?;
+
+            let slot = unsafe { get_slot() };
+            if slot.is_null() {
+                return Err(invalid("Key database of the profile could not be opened"));
+            }
+            let result = if unsafe { need_login(slot) } != 0 {
+                ask_password().and_then(|password| {
+                    let password = CString::new(password).map_err(|_| Error::WrongPassword)?;
+                    match unsafe { check_password(slot, password.as_ptr()) } {
+                        SEC_SUCCESS => Ok(()),
+                        _ => Err(Error::WrongPassword),
+                    }
+                })
+            } else {
+                Ok(())
+            };
+            unsafe { free_slot(slot) };
+            result
+        }
+
+        fn decrypt(&self, encrypted: &str) -> VResult<String> {
+            let decrypt: Symbol<unsafe extern "C" fn(*mut SecItem, *mut SecItem, *mut c_void) -> c_int> =
+                self.symbol(b"PK11SDR_Decrypt\0")?;
+            let free_item: Symbol<unsafe extern "C" fn(*mut SecItem, c_int)> =
+                self.symbol(b"SECITEM_FreeItem\0")?;
+
+            let data = base64::decode(encrypted).map_err(invalid)?;
+            let mut input = SecItem {
+                kind: 0,
+                data: data.as_ptr() as *mut c_uchar,
+                len: data.len() as c_uint,
+            };
+            let mut output = SecItem {
+                kind: 0,
+                data: null_mut(),
+                len: 0,
+            };
+            if unsafe { decrypt(&mut input, &mut output, null_mut()) } != SEC_SUCCESS {
+                return Err(invalid("Password could not be decrypted"));
+            }
+            let plaintext = unsafe { std::slice::from_raw_parts(output.data, output.len as usize) }.to_vec();
+            unsafe { free_item(&mut output, 0) };
+            String::from_utf8(plaintext).map_err(invalid)
+        }
+    }
+    impl Drop for Nss {
+        fn drop(&mut self) {
+            if let Ok(shutdown) = self.symbol::<unsafe extern "C" fn() -> c_int>(b"NSS_Shutdown\0") {
+                unsafe { shutdown() };
+            }
+        }
+    }
+
+    pub fn import(profile: &Path, ask_password: &dyn Fn() -> VResult<String>) -> VResult<Vec<Entry>> {
+        let path = profile.join("logins.json");
+        if !path.is_file() {
+            return Ok(Vec::new());
+        }
+        let logins: Logins = serde_json::from_slice(&fs::read(&path)?).map_err(invalid)?;
+        let nss = Nss::open(profile)?;
+        nss.login(ask_password)?;
+        logins
+            .logins
+            .iter()
+            .map(|login| {
+                let username = nss.decrypt(&login.encrypted_username)?;
+                let password = nss.decrypt(&login.encrypted_password)?;
+                Ok(login_entry(&login.hostname, &username, &password))
+            })
+            .collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::parse_profiles_ini;
+
+        #[test]
+        fn profiles_ini() {
+            let old = "[General]\nStartWithLastProfile=1\n\n\
+                       [Profile1]\nName=work\nIsRelative=1\nPath=abc.work\n\n\
+                       [Profile0]\nName=default\nIsRelative=1\nPath=xyz.default\nDefault=1\n";
+            assert_eq!(parse_profiles_ini(old).unwrap(), "xyz.default");
+            let new = format!("[Install4F96D1932A9F858E]\nDefault=def.default-release\nLocked=1\n\n{}", old);
+            assert_eq!(parse_profiles_ini(&new).unwrap(), "def.default-release");
+            assert_eq!(parse_profiles_ini("[Profile0]\nPath=only\n").unwrap(), "only");
+            assert_eq!(parse_profiles_ini(""), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::login_entry;
+
+    #[test]
+    fn login_entries() {
+        let entry = login_entry("https://accounts.example.com:8443/login", "me", "hunter2");
+        assert_eq!(entry.name, "accounts.example.com");
+        assert_eq!(entry.password.as_ref().unwrap(), "hunter2");
+        assert_eq!(entry.notes, vec!["username: me\nurl: https://accounts.example.com:8443/login"]);
+
+        let entry = login_entry("android://hash@com.example/", "", "");
+        assert_eq!(entry.name, "hash@com.example");
+        assert_eq!(entry.password, None);
+        assert_eq!(entry.notes, vec!["url: android://hash@com.example/"]);
+    }
+}
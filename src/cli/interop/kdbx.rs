@@ -0,0 +1,148 @@
+//! KeePass databases, KDBX 3.1 and 4, unlocked with a password and an optional key file.
+//! Groups become folders in entry names, and the recycle bin named in the database metadata is skipped.
+//! Usernames, urls, TOTP secrets and other custom fields are kept in a note,
+//! as vpass items have no fields for them.
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+
+use keepass::db::{Group, Node, Value};
+use keepass::{Database, DatabaseKey};
+
+use super::{deduplicate, Entry};
+use crate::cli::error::{Error, VResult};
+use crate::ItemKind;
+
+/// Fields shown first in the note, with their labels
+const KNOWN_FIELDS: &[(&str, &str)] = &[("UserName", "username"), ("URL", "url")];
+
+fn value_str(value: &Value) -> Option<String> {
+    match value {
+        Value::Unprotected(s) => Some(s.clone()),
+        Value::Protected(s) => String::from_utf8(s.unsecure().to_vec()).ok(),
+        Value::Bytes(_) => None,
+    }
+}
+
+/// Entry for the fields of a KeePass entry in `folder`
+fn to_entry(folder: &str, fields: &BTreeMap<String, String>) -> Entry {
+    let field = |key: &str| fields.get(key).map(String::as_str).filter(|v| !v.is_empty());
+    let title = field("Title").unwrap_or("untitled");
+    let mut lines = Vec::new();
+    for &(key, label) in KNOWN_FIELDS {
+        if let Some(value) = field(key) {
+            lines.push(format!("{}: {}", label, value));
+        }
+    }
+    // Custom fields, including TOTP secrets stored as `otp` or `TimeOtp-*`
+    for (key, value) in fields {
+        let standard = ["Title", "Password", "Notes"].contains(&key.as_str());
+        if !standard && !KNOWN_FIELDS.iter().any(|&(k, _)| k == key.as_str()) && !value.is_empty() {
+            lines.push(format!("{}: {}", key, value));
+        }
+    }
+    if let Some(notes) = field("Notes") {
+        lines.push(notes.to_owned());
+    }
+    Entry {
+        name: if folder.is_empty() { title.to_owned() } else { format!("{}/{}", folder, title) },
+        password: field("Password").map(str::to_owned),
+        notes: if lines.is_empty() { Vec::new() } else { vec![lines.join("\n")] },
+        tags: Vec::new(),
+        kind: ItemKind::Login,
+        data: None,
+    }
+}
+
+/// Entries of `group` and its subgroups, skipping groups for which `skip` returns true
+fn collect(group: &Group, folder: &str, skip: &dyn Fn(&Group) -> bool, result: &mut Vec<Entry>) {
+    let mut entries = Vec::new();
+    let mut children = Vec::new();
+    for node in &group.children {
+        match node {
+            Node::Entry(e) => {
+                let fields: BTreeMap<String, String> =
+                    e.fields.iter().filter_map(|(k, v)| Some((k.clone(), value_str(v)?))).collect();
+                entries.push(to_entry(folder, &fields));
+            },
+            Node::Group(g) if !skip(g) => children.push(g),
+            Node::Group(_) => {},
+        }
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    result.extend(entries);
+
+    children.sort_by(|a, b| a.name.cmp(&b.name));
+    for child in children {
+        let path = if folder.is_empty() { child.name.clone() } else { format!("{}/{}", folder, child.name) };
+        collect(child, &path, skip, result);
+    }
+}
+
+/// Read all entries of the database at `path`, except those in the recycle bin
+pub fn import(path: &Path, password: &str, keyfile: Option<&Path>) -> VResult<Vec<Entry>> {
+    let mut key = DatabaseKey::new().with_password(password);
+    if let Some(keyfile) = keyfile {
+        key = key.with_keyfile(&mut File::open(keyfile)?)?;
+    }
+    let db = Database::open(&mut File::open(path)?, key).map_err(|e| Error::KdbxInvalid(e.to_string()))?;
+
+    // The recycle bin is told apart by its id, as its name is translated and may be changed
+    let recycle_bin = db.meta.recyclebin_uuid;
+    let mut result = Vec::new();
+    // The root group is the database itself, not a folder
+    collect(&db.root, "", &|g| Some(g.uuid) == recycle_bin, &mut result);
+    deduplicate(&mut result);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_entry;
+    use crate::cli::interop::deduplicate;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn kdbx_fields() {
+        let fields: BTreeMap<String, String> = [
+            ("Title", "GitHub"),
+            ("UserName", "me"),
+            ("Password", "hunter2"),
+            ("URL", "https://github.com"),
+            ("Notes", "Recovery codes in the safe"),
+            ("otp", "otpauth://totp/GitHub?secret=ABC"),
+            ("Empty", ""),
+        ]
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+        let entry = to_entry("Internet/Dev", &fields);
+        assert_eq!(entry.name, "Internet/Dev/GitHub");
+        assert_eq!(entry.password.as_ref().unwrap(), "hunter2");
+        assert_eq!(
+            entry.notes,
+            vec![
+                "username: me\nurl: https://github.com\notp: otpauth://totp/GitHub?secret=ABC\n\
+                 Recovery codes in the safe"
+            ]
+        );
+
+        let mut entries = vec![to_entry("", &BTreeMap::new()), to_entry("", &BTreeMap::new())];
+        deduplicate(&mut entries);
+        assert_eq!(entries[0].name, "untitled");
+        assert_eq!(entries[1].name, "untitled_2");
+        assert_eq!(entries[1].password, None);
+
+        // Names that differ only in characters not allowed in item names
+        let titled = |title: &str| {
+            let mut fields = BTreeMap::new();
+            fields.insert("Title".to_owned(), title.to_owned());
+            to_entry("", &fields)
+        };
+        let mut entries = vec![titled("a b"), titled("a_b"), titled("a?b")];
+        deduplicate(&mut entries);
+        let names: Vec<&str> = entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["a_b", "a_b_2", "a_b_3"]);
+    }
+}
@@ -0,0 +1,177 @@
+//! `pass`, the standard unix password manager: a directory of files encrypted with gpg,
+//! named after the entries, with folders as subdirectories.
+//! The first line of each file is the password, and the rest is free-form text, kept as a note.
+//! Entries storing a file, like GPG keys, are exported as just the file.
+//! Files are encrypted and decrypted by running `gpg`, so the keys stay with the gpg agent.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use super::Entry;
+use crate::cli::error::{Error, VResult};
+use crate::ItemKind;
+
+const GPG: &str = "gpg";
+const EXTENSION: &str = "gpg";
+/// Lists the key ids that files in the store are encrypted to
+const GPG_ID_FILE: &str = ".gpg-id";
+
+/// Run gpg with `input` on stdin, and return its stdout.
+/// Stderr is passed through, as gpg may ask for a passphrase.
+fn gpg(args: &[OsString], input: &[u8]) -> VResult<Vec<u8>> {
+    let mut child = Command::new(GPG)
+        .args(["--quiet", "--yes", "--batch", "--use-agent"])
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().unwrap().write_all(input)?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(Error::GpgFailed(output.status.code()));
+    }
+    Ok(output.stdout)
+}
+
+fn parse(name: &str, content: &str) -> Entry {
+    let mut lines = content.splitn(2, '\n');
+    let password = lines.next().unwrap_or("").trim_end_matches('\r');
+    let rest = lines.next().unwrap_or("").trim();
+    Entry {
+        name: name.to_owned(),
+        password: if password.is_empty() { None } else { Some(password.to_owned()) },
+        notes: if rest.is_empty() { Vec::new() } else { vec![rest.to_owned()] },
+        tags: Vec::new(),
+        kind: ItemKind::Login,
+        data: None,
+    }
+}
+
+fn format(entry: &Entry) -> Vec<u8> {
+    if let Some(ref data) = entry.data {
+        return data.clone();
+    }
+    let mut result = entry.password.clone().unwrap_or_default();
+    result.push('\n');
+    for note in &entry.notes {
+        result.push_str(note);
+        result.push('\n');
+    }
+    result.into_bytes()
+}
+
+/// Encrypted files under `dir`, skipping hidden files and directories like `.git`
+fn find_files(dir: &Path, result: &mut Vec<PathBuf>) -> VResult<()> {
+    for dir_entry in fs::read_dir(dir)? {
+        let path = dir_entry?.path();
+        if path.file_name().is_none_or(|n| n.to_string_lossy().starts_with('.')) {
+            continue;
+        }
+        if path.is_dir() {
+            find_files(&path, result)?;
+        } else if path.extension().is_some_and(|e| e == EXTENSION) {
+            result.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Decrypt all entries of the store at `dir`
+pub fn import(dir: &Path) -> VResult<Vec<Entry>> {
+    if !dir.is_dir() {
+        return Err(Error::DirectoryRequired(dir.to_owned()));
+    }
+    let mut files = Vec::new();
+    find_files(dir, &mut files)?;
+    files.sort();
+
+    let mut result = Vec::new();
+    for file in files {
+        let name = file
+            .strip_prefix(dir)
+            .unwrap()
+            .with_extension("")
+            .components()
+            .map(|c| c.as_os_str().to_str().ok_or(Error::NonUnicodeInput))
+            .collect::<VResult<Vec<_>>>()?
+            .join("/");
+        let plaintext = gpg(&["--decrypt".into(), file.into_os_string()], &[])?;
+        let content = String::from_utf8(plaintext).map_err(|_| Error::NonUnicodeInput)?;
+        result.push(parse(&name, &content));
+    }
+    Ok(result)
+}
+
+/// Encrypt `entries` to the store at `dir`, creating it if needed.
+/// Without `recipients`, the key ids are read from the `.gpg-id` file of the store.
+pub fn export(dir: &Path, entries: &[Entry], recipients: &[String], force: bool) -> VResult<()> {
+    let gpg_id_path = dir.join(GPG_ID_FILE);
+    let recipients: Vec<String> = if recipients.is_empty() {
+        if !gpg_id_path.is_file() {
+            return Err(Error::GpgIdMissing);
+        }
+        fs::read_to_string(&gpg_id_path)?
+            .lines()
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(str::to_owned)
+            .collect()
+    } else {
+        recipients.to_vec()
+    };
+    if recipients.is_empty() {
+        return Err(Error::GpgIdMissing);
+    }
+
+    let paths: Vec<PathBuf> = entries
+        .iter()
+        .map(|e| dir.join(format!("{}.{}", e.name, EXTENSION)))
+        .collect();
+    if !force {
+        if let Some(path) = paths.iter().find(|p| p.exists()) {
+            return Err(Error::FileExists(path.clone()));
+        }
+    }
+
+    fs::create_dir_all(dir)?;
+    if !gpg_id_path.exists() {
+        fs::write(&gpg_id_path, recipients.join("\n") + "\n")?;
+    }
+    for (entry, path) in entries.iter().zip(paths) {
+        fs::create_dir_all(path.parent().unwrap())?;
+        let mut args: Vec<OsString> = vec!["--encrypt".into(), "--output".into(), path.into_os_string()];
+        for id in &recipients {
+            args.push("--recipient".into());
+            args.push(id.into());
+        }
+        gpg(&args, &format(entry))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, parse};
+    use crate::ItemKind;
+
+    #[test]
+    fn pass_content() {
+        let entry = parse("web/example.com", "hunter2\nlogin: me\nurl: example.com\n");
+        assert_eq!(entry.password.as_ref().unwrap(), "hunter2");
+        assert_eq!(entry.notes, vec!["login: me\nurl: example.com"]);
+        assert_eq!(format(&entry), &b"hunter2\nlogin: me\nurl: example.com\n"[..]);
+
+        let entry = parse("empty", "\r\n");
+        assert_eq!(entry.password, None);
+        assert!(entry.notes.is_empty());
+        assert_eq!(format(&entry), &b"\n"[..]);
+
+        let mut entry = parse("certs/example.com", "");
+        entry.kind = ItemKind::Certificate;
+        entry.data = Some(b"-----BEGIN CERTIFICATE-----\n".to_vec());
+        assert_eq!(format(&entry), entry.data.unwrap());
+    }
+}
@@ -0,0 +1,94 @@
+//! Ctrl-C and termination handling for multi-step operations.
+//! Outside of critical sections a signal exits immediately, as usual.
+//! Inside them, the signal is recorded and the operation stops at the next `check`,
+//! so that its rollback runs and local and remote copies stay consistent.
+//! A second signal exits immediately, in case the rollback itself hangs.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use super::error::*;
+
+/// Exit code of a process terminated by SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Number of active critical sections
+static CRITICAL: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(unix)]
+extern "C" fn handle_signal(_: libc::c_int) {
+    // Only async-signal-safe operations are allowed here
+    if INTERRUPTED.swap(true, Ordering::SeqCst) || CRITICAL.load(Ordering::SeqCst) == 0 {
+        unsafe { libc::_exit(EXIT_CODE) };
+    }
+}
+
+/// Install the handler for SIGINT and SIGTERM, called on startup
+#[cfg(unix)]
+pub fn install() {
+    for signal in &[libc::SIGINT, libc::SIGTERM] {
+        unsafe { libc::signal(*signal, handle_signal as *const () as libc::sighandler_t) };
+    }
+}
+
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Signals are deferred while this is alive
+pub struct Critical(());
+impl Drop for Critical {
+    fn drop(&mut self) {
+        CRITICAL.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Start a critical section, which ends when the returned guard is dropped
+#[must_use]
+pub fn critical() -> Critical {
+    CRITICAL.fetch_add(1, Ordering::SeqCst);
+    Critical(())
+}
+
+/// Fail if a signal was received, called between the steps of a critical section
+pub fn check() -> VResult<()> {
+    check_flag(&INTERRUPTED)
+}
+
+/// Fail if the flag is set. Tests check their own flag,
+/// as setting the global one would interrupt transactions in tests running in parallel.
+fn check_flag(interrupted: &AtomicBool) -> VResult<()> {
+    if interrupted.load(Ordering::SeqCst) {
+        Err(Error::Interrupted)
+    } else {
+        Ok(())
+    }
+}
+
+/// Overwrite a cached secret with zeros and clear it
+pub fn zeroize(secret: &mut String) {
+    rust_sodium::utils::memzero(unsafe { secret.as_bytes_mut() });
+    secret.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, check_flag, critical, zeroize, CRITICAL};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn critical_section() {
+        let guard = critical();
+        assert!(CRITICAL.load(Ordering::SeqCst) > 0);
+        assert!(check().is_ok());
+        let interrupted = AtomicBool::new(false);
+        assert!(check_flag(&interrupted).is_ok());
+        interrupted.store(true, Ordering::SeqCst);
+        assert!(check_flag(&interrupted).is_err());
+        drop(guard);
+
+        let mut secret = "password".to_owned();
+        zeroize(&mut secret);
+        assert!(secret.is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+//! Entry lists for desktop launchers, printed by `vpass launcher-feed`.
+//! The launcher runs `vpass copy <name>` on the selected entry.
+//! `vpass menu` runs a dmenu-like program itself, and copies or types the selected secret.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use serde_json::json;
+
+use super::error::{Error, VResult};
+use crate::Item;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Script filter JSON of Alfred
+    Alfred,
+    /// Entry names one per line, for rofi, wofi, dmenu and similar
+    Rofi,
+}
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "alfred" => Ok(Format::Alfred),
+            "rofi" | "wofi" | "dmenu" | "lines" => Ok(Format::Rofi),
+            other => Err(format!("Unknown launcher format {}, expected alfred or rofi", other)),
+        }
+    }
+}
+
+fn sorted_tags(item: &Item) -> Vec<&str> {
+    let mut tags: Vec<&str> = item.tags.iter().map(String::as_str).collect();
+    tags.sort();
+    tags
+}
+
+/// Subtitle shown under the name: the kind if not a login, and the tags
+fn subtitle(item: &Item) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    if item.kind != crate::ItemKind::Login {
+        parts.push(item.kind.to_string());
+    }
+    parts.extend(sorted_tags(item).iter().map(|tag| format!("#{}", tag)));
+    parts.join(" ")
+}
+
+/// Feed of `items` in the order given
+pub fn feed(items: &[Item], format: Format) -> String {
+    match format {
+        Format::Alfred => {
+            let items: Vec<_> = items
+                .iter()
+                .map(|item| {
+                    let title = match item.label {
+                        Some(ref label) => format!("{} {}", label, item.name),
+                        None => item.name.clone(),
+                    };
+                    json!({
+                        "uid": item.name,
+                        "title": title,
+                        "subtitle": subtitle(item),
+                        "arg": item.name,
+                        "autocomplete": item.name,
+                        "match": format!("{} {}", item.name, sorted_tags(item).join(" ")),
+                    })
+                })
+                .collect();
+            format!("{}\n", json!({ "items": items }))
+        },
+        Format::Rofi => items.iter().map(|item| format!("{}\n", item.name)).collect(),
+    }
+}
+
+/// Menu program for `vpass menu` when `menu_command` is not set in the config
+pub fn default_menu_command() -> &'static str {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        "wofi --dmenu"
+    } else {
+        "rofi -dmenu"
+    }
+}
+
+fn shell(command: &str) -> Command {
+    if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C").arg(command);
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c").arg(command);
+        c
+    }
+}
+
+/// Run `command` with `choices` as its input, and return the selected line.
+/// Menus exit unsuccessfully when closed without a selection, which gives `None`.
+pub fn choose(command: &str, choices: &str) -> VResult<Option<String>> {
+    let mut child = shell(command).stdin(Stdio::piped()).stdout(Stdio::piped()).spawn()?;
+    child.stdin.take().unwrap().write_all(choices.as_bytes())?;
+    let output = child.wait_with_output()?;
+    let selection = String::from_utf8(output.stdout).map_err(|_| Error::NonUnicodeInput)?;
+    let selection = selection.trim_end_matches(&['\r', '\n'][..]);
+    if !output.status.success() || selection.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(selection.to_owned()))
+}
+
+/// Type `text` into the focused window, with `wtype` on Wayland and `xdotool` on X11
+pub fn type_text(text: &str) -> VResult<()> {
+    let (program, args): (&str, &[&str]) = if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        ("wtype", &["-"])
+    } else {
+        ("xdotool", &["type", "--clearmodifiers", "--file", "-"])
+    };
+    let mut child = Command::new(program).args(args).stdin(Stdio::piped()).spawn()?;
+    child.stdin.take().unwrap().write_all(text.as_bytes())?;
+    if !child.wait()?.success() {
+        return Err(Error::TypingFailed(program.to_owned()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{choose, feed, Format};
+    use crate::{Item, ItemKind};
+
+    #[test]
+    fn launcher_feeds() {
+        let mut bank = Item::new("bank");
+        bank.tags.insert("money".to_owned());
+        bank.label = Some("🏦".to_owned());
+        let mut key = Item::new("server");
+        key.kind = ItemKind::SshKey;
+        let items = [bank, key];
+
+        assert_eq!(feed(&items, Format::Rofi), "bank\nserver\n");
+        let j: serde_json::Value = serde_json::from_str(&feed(&items, Format::Alfred)).unwrap();
+        assert_eq!(j["items"][0]["title"], "🏦 bank");
+        assert_eq!(j["items"][0]["arg"], "bank");
+        assert_eq!(j["items"][0]["subtitle"], "#money");
+        assert_eq!(j["items"][1]["subtitle"], "ssh-key");
+        assert!("raycast".parse::<Format>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn menu_selection() {
+        assert_eq!(choose("head -n 1", "bank\nmail\n").unwrap(), Some("bank".to_owned()));
+        assert_eq!(choose("grep site", "bank\nmail\n").unwrap(), None);
+    }
+}
@@ -0,0 +1,143 @@
+//! Sorting and grouping of entries for `vpass list`, and file sizes for `vpass vault list --status`
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::{Item, ItemMetadata};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Created,
+    Changed,
+    /// Never used entries first
+    LastUsed,
+}
+impl std::str::FromStr for SortKey {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "name" => Ok(SortKey::Name),
+            "created" => Ok(SortKey::Created),
+            "changed" => Ok(SortKey::Changed),
+            "last-used" => Ok(SortKey::LastUsed),
+            other => Err(format!(
+                "Unknown sort key {}, expected name, created, changed or last-used",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// Entries with multiple tags are in multiple groups
+    Tag,
+    /// Folder is the part of the name before the last `/`
+    Folder,
+}
+impl std::str::FromStr for GroupBy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tag" => Ok(GroupBy::Tag),
+            "folder" => Ok(GroupBy::Folder),
+            other => Err(format!("Unknown grouping {}, expected tag or folder", other)),
+        }
+    }
+}
+
+/// Sort entries, ties broken by name
+pub fn sort(items: &mut [(Item, ItemMetadata)], key: SortKey, reverse: bool) {
+    items.sort_by(|(a, am), (b, bm)| {
+        let order = match key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Created => am.created.cmp(&bm.created),
+            SortKey::Changed => am.changed.cmp(&bm.changed),
+            SortKey::LastUsed => am.last_used.cmp(&bm.last_used),
+        };
+        order.then_with(|| a.name.cmp(&b.name))
+    });
+    if reverse {
+        items.reverse();
+    }
+}
+
+/// Groups by name, keeping the order of entries within each group.
+/// Entries without a tag or folder are in the group with an empty name.
+pub fn group(items: Vec<(Item, ItemMetadata)>, by: GroupBy) -> BTreeMap<String, Vec<(Item, ItemMetadata)>> {
+    let mut result: BTreeMap<String, Vec<(Item, ItemMetadata)>> = BTreeMap::new();
+    for entry in items {
+        let names: Vec<String> = match by {
+            GroupBy::Tag if entry.0.tags.is_empty() => vec![String::new()],
+            GroupBy::Tag => entry.0.tags.iter().cloned().collect(),
+            GroupBy::Folder => vec![entry.0.name.rfind('/').map_or("", |i| &entry.0.name[..i]).to_owned()],
+        };
+        for name in names {
+            result.entry(name).or_default().push(entry.clone());
+        }
+    }
+    result
+}
+
+/// Size in bytes, or in KiB or MiB with one decimal
+pub fn file_size(bytes: u64) -> String {
+    const UNITS: [&str; 2] = ["KiB", "MiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{file_size, group, sort, GroupBy, SortKey};
+    use crate::{Item, ItemMetadata};
+    use chrono::prelude::*;
+
+    fn entry(name: &str, tags: &[&str], created: i64) -> (Item, ItemMetadata) {
+        let mut item = Item::new(name);
+        item.tags = tags.iter().map(|t| (*t).to_owned()).collect();
+        let time = Utc.timestamp_opt(created, 0).unwrap();
+        let meta = ItemMetadata {
+            created: time,
+            changed: time,
+            changed_by: None,
+            last_used: None,
+            use_count: 0,
+        };
+        (item, meta)
+    }
+
+    #[test]
+    fn sort_and_group() {
+        let mut items = vec![entry("b", &["x"], 1), entry("a", &["x", "y"], 2), entry("work/c", &[], 0)];
+        sort(&mut items, SortKey::Created, false);
+        let names: Vec<&str> = items.iter().map(|(i, _)| i.name.as_str()).collect();
+        assert_eq!(names, vec!["work/c", "b", "a"]);
+        sort(&mut items, SortKey::Name, true);
+        let names: Vec<&str> = items.iter().map(|(i, _)| i.name.as_str()).collect();
+        assert_eq!(names, vec!["work/c", "b", "a"]);
+
+        let by_tag = group(items.clone(), GroupBy::Tag);
+        let sizes: Vec<(&str, usize)> = by_tag.iter().map(|(k, v)| (k.as_str(), v.len())).collect();
+        assert_eq!(sizes, vec![("", 1), ("x", 2), ("y", 1)]);
+        let by_folder = group(items, GroupBy::Folder);
+        assert_eq!(by_folder.keys().collect::<Vec<_>>(), vec!["", "work"]);
+    }
+
+    #[test]
+    fn file_sizes() {
+        assert_eq!(file_size(512), "512 B");
+        assert_eq!(file_size(1536), "1.5 KiB");
+        assert_eq!(file_size(3 * 1024 * 1024), "3.0 MiB");
+    }
+}
@@ -0,0 +1,108 @@
+//! Diagnostic logging on stderr, as text or as one JSON object per line with `--log-format json`.
+//! Each command runs in a span, and so does each call to a synchronization provider.
+//! The level is set with `-v`, or with `RUST_LOG` for finer filtering.
+//! Secrets are kept in `Redacted`, which can't be formatted, so that they never end up in logs.
+
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use tracing_subscriber::{EnvFilter, FmtSubscriber};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, including the fields of the enclosing spans
+    Json,
+}
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!("Unknown log format {}, expected text or json", other)),
+        }
+    }
+}
+
+/// Level for the number of `-v` flags, errors only by default
+fn level(verbose: u8) -> &'static str {
+    match verbose {
+        0 => "error",
+        1 => "warn",
+        2 => "info",
+        3 => "debug",
+        _ => "trace",
+    }
+}
+
+/// Install the logger. Records of dependencies using the `log` crate are included as well.
+pub fn init(format: LogFormat, verbose: u8) {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(ref directives) if verbose == 0 => EnvFilter::new(directives),
+        _ => EnvFilter::new(level(verbose)),
+    };
+    let builder = FmtSubscriber::builder().with_env_filter(filter).with_writer(std::io::stderr);
+    let result = match format {
+        LogFormat::Text => tracing::subscriber::set_global_default(builder.finish()),
+        LogFormat::Json => tracing::subscriber::set_global_default(builder.json().finish()),
+    };
+    result.expect("Logger already installed");
+    tracing_log::LogTracer::init().expect("Logger already installed");
+}
+
+/// A secret, formatted as `****` with both `Display` and `Debug`.
+/// The value is only available through `Deref`, so it can't be formatted by accident.
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Redacted<T>(T);
+impl<T> Redacted<T> {
+    pub fn new(value: T) -> Self {
+        Redacted(value)
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+impl<T> Deref for Redacted<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+impl<T> fmt::Debug for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "****")
+    }
+}
+impl<T> fmt::Display for Redacted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "****")
+    }
+}
+/// For command line arguments
+impl<T: FromStr> FromStr for Redacted<T> {
+    type Err = T::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Redacted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogFormat, Redacted};
+
+    #[test]
+    fn redacted_formatting() {
+        let secret: Redacted<String> = "hunter2".parse().unwrap();
+        assert_eq!(format!("{} {:?}", secret, Some(&secret)), "**** Some(****)");
+        assert_eq!(secret.len(), 7);
+        assert_eq!(secret.into_inner(), "hunter2");
+        assert_eq!("json".parse(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
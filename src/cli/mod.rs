@@ -1,9 +1,34 @@
+pub mod attempts;
+pub mod audit_log;
 pub mod cfg;
 pub mod clipboard;
+pub mod console;
+pub mod credential;
+pub mod daemon;
+pub mod emergency_kit;
 pub mod error;
+pub mod hardening;
+pub mod i18n;
+pub mod idle;
 pub mod interactive;
+pub mod interop;
+pub mod interrupt;
+pub mod launcher;
+pub mod listing;
+pub mod logging;
+pub mod name_cache;
+pub mod notify;
 pub mod opt;
 pub mod paths;
+pub mod presence;
+pub mod progress;
+pub mod ssh;
+pub mod stdio;
+pub mod sync_state;
+pub mod template;
+pub mod timings;
+pub mod transaction;
 pub mod validate;
+pub mod wifi;
 
 pub use error::{Error, VResult};
@@ -0,0 +1,70 @@
+//! Opt-in cache of item names, stored unencrypted next to each vault,
+//! so that shell completion can list entries without unlocking the vault.
+//! Enabled with the `name_cache` config option. Anyone who can read the vault directory
+//! can then read the entry names, so leave it off if the names themselves are sensitive.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use super::{cfg, error::*, opt::OptRoot, stdio};
+use crate::Book;
+
+fn path(vault_path: &Path) -> PathBuf {
+    vault_path.with_extension("vpass_names")
+}
+
+/// Write the names of a book to the cache, or remove the cache if it's disabled.
+/// Vaults piped through standard input have no cache.
+pub fn update(args: &OptRoot, vault_path: &Path, book: &Book) -> VResult<()> {
+    if stdio::is_stdio(vault_path) {
+        return Ok(());
+    }
+    let p = path(vault_path);
+    if cfg::read(args)?.name_cache {
+        let mut names = book.item_names();
+        names.sort();
+        let content = serde_json::to_string(&names).unwrap();
+        if fs::read_to_string(&p).ok().as_ref() != Some(&content) {
+            write_private(&p, &content)?;
+        }
+    } else {
+        remove(vault_path)?;
+    }
+    Ok(())
+}
+
+/// Readable only by the owner, like the vault
+fn write_private(p: &Path, content: &str) -> VResult<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // An existing file keeps its mode when opened, e.g. one written by older versions
+        if p.exists() {
+            fs::set_permissions(p, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(p)?.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+/// Cached names, empty if the cache is disabled or not written yet
+pub fn read(vault_path: &Path) -> VResult<Vec<String>> {
+    let p = path(vault_path);
+    if p.exists() {
+        Ok(serde_json::from_str(&fs::read_to_string(p)?)?)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+pub fn remove(vault_path: &Path) -> VResult<()> {
+    let p = path(vault_path);
+    if p.exists() {
+        fs::remove_file(p)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,41 @@
+//! Desktop notifications, for events happening when no terminal is being watched,
+//! like failures of `vpass daemon` or clearing the clipboard after `clipboard_clear_seconds`.
+//! Disabled with the `notifications` config option. Notifications never include secrets,
+//! as they may be shown on the lock screen or stored by the notification daemon.
+
+use tracing::debug;
+
+use super::{cfg, opt::OptRoot};
+
+#[cfg(not(windows))]
+fn show(summary: &str, body: &str) -> Result<(), String> {
+    notify_rust::Notification::new()
+        .appname(env!("CARGO_PKG_NAME"))
+        .summary(summary)
+        .body(body)
+        .show()
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Toast notification, shown under the PowerShell app id, as vpass doesn't register its own
+#[cfg(windows)]
+fn show(summary: &str, body: &str) -> Result<(), String> {
+    use winrt_notification::Toast;
+    Toast::new(Toast::POWERSHELL_APP_ID)
+        .title(summary)
+        .text1(body)
+        .show()
+        .map_err(|e| format!("{:?}", e))
+}
+
+/// Show a notification, unless disabled in the config.
+/// Failures are only logged, as notifications are not available in every session.
+pub fn send(args: &OptRoot, summary: &str, body: &str) {
+    if !cfg::read(args).map_or(true, |c| c.notifications) {
+        return;
+    }
+    if let Err(e) = show(summary, body) {
+        debug!("Unable to show a notification: {}", e);
+    }
+}
@@ -1,5 +1,46 @@
+use std::env;
 use std::path::PathBuf;
 use structopt::StructOpt;
+use strum_macros::IntoStaticStr;
+
+use super::emergency_kit;
+use super::interop;
+use super::launcher;
+use super::listing;
+use super::logging::{self, Redacted};
+use super::template;
+use crate::backend::wifi;
+use crate::ItemKind;
+
+/// Parse an age like `30d`, `2w`, `6m` or `1y`
+fn parse_age(s: &str) -> Result<chrono::Duration, String> {
+    let split = s.len() - s.chars().last().map(char::len_utf8).unwrap_or(0);
+    let count: i64 = s[..split].parse().map_err(|_| format!("Invalid age {:?}", s))?;
+    match &s[split..] {
+        "d" => Ok(chrono::Duration::days(count)),
+        "w" => Ok(chrono::Duration::weeks(count)),
+        "m" => Ok(chrono::Duration::days(count * 30)),
+        "y" => Ok(chrono::Duration::days(count * 365)),
+        _ => Err(format!("Invalid age unit in {:?}, use d, w, m or y", s)),
+    }
+}
+
+/// Parse a vault annotation like `key=value`
+fn parse_annotation(s: &str) -> Result<(String, String), String> {
+    match s.find('=') {
+        Some(i) if i > 0 => Ok((s[..i].to_owned(), s[i + 1..].to_owned())),
+        _ => Err(format!("Invalid annotation {:?}, use key=value", s)),
+    }
+}
+
+/// Parse a share threshold and count like `3/5`
+fn parse_shamir(s: &str) -> Result<(u8, u8), String> {
+    let invalid = || format!("Invalid share count {:?}, use threshold/count, e.g. 3/5", s);
+    let mut parts = s.splitn(2, '/');
+    let threshold = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+    let count = parts.next().and_then(|p| p.parse().ok()).ok_or_else(invalid)?;
+    Ok((threshold, count))
+}
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
 #[structopt(rename_all = "kebab-case")]
@@ -12,11 +53,30 @@ pub struct OptRoot {
     #[structopt(short, long, group = "loudness", parse(from_occurrences))]
     pub verbose: u8,
 
+    /// Plain output for screen readers and braille terminals:
+    /// progress is printed as separate lines instead of animations, and prompt choices are spelled out
+    #[structopt(long)]
+    pub plain: bool,
+
+    /// Print how long key derivation, decryption, synchronization and the command itself took.
+    /// Printed on stderr after the command, nothing is sent anywhere.
+    #[structopt(long)]
+    pub timings: bool,
+
+    /// Format of diagnostic logs on stderr: text or json
+    #[structopt(long, default_value = "text")]
+    pub log_format: logging::LogFormat,
+
     /// Overrides vault directory path.
     /// Config file is only looked here as well, if not specified separately.
     #[structopt(short = "d", long, env = "VPASS_VAULT_DIR")]
     pub vault_dir: Option<PathBuf>,
 
+    /// Use a named profile, with its own vaults and config in the `profiles` folder of the vault directory.
+    /// An empty name selects the vault directory itself, overriding `profile use`.
+    #[structopt(long, env = "VPASS_PROFILE")]
+    pub profile: Option<String>,
+
     /// Overrides config file path
     #[structopt(short, long, group = "config_xor")]
     pub config: Option<PathBuf>,
@@ -29,21 +89,88 @@ pub struct OptRoot {
     #[structopt(short = "n", long = "vault", group = "vault")]
     pub vault_name: Option<String>,
 
-    /// Select vault by file path
+    /// Select vault by file path, or `-` to read it from stdin and write changes to stdout
     #[structopt(short = "f", long = "file", group = "vault")]
     pub vault_file: Option<PathBuf>,
 
-    /// Vault password, takes password as argument instead of prompt
-    #[structopt(short, long)]
-    pub password: Option<String>,
+    /// Paranoid mode: refuse operations that would write plaintext to temporary files,
+    /// and fail if core dumps can't be disabled
+    #[structopt(long)]
+    pub paranoid: bool,
+
+    /// Vault password, takes password as argument instead of prompt.
+    /// Can also be given in `VPASS_PASSWORD`, which is less secure than prompting,
+    /// but doesn't show the password in the process list like the argument does.
+    /// Password options override `VPASS_PASSWORD`, `VPASS_PASSWORD_FILE` and `VPASS_PASSWORD_CREDENTIAL`,
+    /// which are used in this order.
+    #[structopt(short, long, group = "password_xor")]
+    pub password: Option<Redacted<String>>,
+
+    /// Read vault password from the first line of a file, also `VPASS_PASSWORD_FILE`
+    #[structopt(long, group = "password_xor")]
+    pub password_file: Option<PathBuf>,
+
+    /// Read vault password from a credential sealed to the TPM of this machine
+    /// with `vault seal-password`. Linux only, needs `systemd-creds`. Also `VPASS_PASSWORD_CREDENTIAL`.
+    #[structopt(long, group = "password_xor")]
+    pub password_credential: Option<PathBuf>,
+
+    /// Read vault password from the first line of standard input
+    #[structopt(long, group = "password_xor")]
+    pub password_stdin: bool,
+
+    /// Never prompt: fail with an error telling which input was needed instead.
+    /// Prompts also fail when standard input is not a terminal.
+    #[structopt(long)]
+    pub no_input: bool,
+
+    /// Passphrase of protected entries, takes passphrase as argument instead of prompt
+    #[structopt(long, env = "VPASS_PROTECT_PASSWORD", hide_env_values = true)]
+    pub protect_password: Option<Redacted<String>>,
 
     /// Subcommand
     #[structopt(subcommand)]
     pub subcommand: SubCommand,
 }
+impl OptRoot {
+    /// Where the vault password is given, if anywhere.
+    /// The password options are exclusive, and override the environment variables,
+    /// which are checked in a fixed order, so that a variable set for another use doesn't make an error.
+    pub fn password_source(&self) -> Option<PasswordSource> {
+        let var = |name: &str| env::var_os(name).filter(|v| !v.is_empty());
+        if let Some(ref password) = self.password {
+            Some(PasswordSource::Value(password.clone()))
+        } else if let Some(ref path) = self.password_file {
+            Some(PasswordSource::File(path.clone()))
+        } else if let Some(ref path) = self.password_credential {
+            Some(PasswordSource::Credential(path.clone()))
+        } else if self.password_stdin {
+            Some(PasswordSource::Stdin)
+        } else if let Some(value) = var("VPASS_PASSWORD") {
+            Some(PasswordSource::Value(Redacted::new(value.to_string_lossy().into_owned())))
+        } else if let Some(path) = var("VPASS_PASSWORD_FILE") {
+            Some(PasswordSource::File(PathBuf::from(path)))
+        } else {
+            var("VPASS_PASSWORD_CREDENTIAL").map(|path| PasswordSource::Credential(PathBuf::from(path)))
+        }
+    }
+}
 
-#[derive(StructOpt, Debug, Clone, PartialEq)]
+/// Source of the vault password other than a prompt, see `OptRoot::password_source`
+#[derive(Debug, Clone, PartialEq)]
+pub enum PasswordSource {
+    Value(Redacted<String>),
+    /// First line of a file
+    File(PathBuf),
+    /// Credential sealed with `vault seal-password`
+    Credential(PathBuf),
+    /// First line of standard input
+    Stdin,
+}
+
+#[derive(StructOpt, IntoStaticStr, Debug, Clone, PartialEq)]
 #[structopt(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab_case")]
 pub enum SubCommand {
     /// Initialize: create necessary directories and config file
     Init,
@@ -57,27 +184,127 @@ pub enum SubCommand {
     /// Update existing entry
     Edit(OptEdit),
 
+    /// Change passwords of many entries at once, e.g. after a breach,
+    /// and print a checklist of the sites where they must still be changed
+    Rotate(OptRotate),
+
+    /// Add, edit, remove or show notes of an entry by their ids
+    Note(OptNote),
+
     /// Rename entry
     Rename(OptRename),
 
-    /// Remove entry
+    /// Remove entries
     Remove(OptRemove),
 
+    /// Hide entries from `list` and `count` without removing them, e.g. old accounts
+    Archive(OptArchive),
+
+    /// Show archived entries in listings again
+    Unarchive(OptArchive),
+
     /// List entries
     List(OptList),
 
-    /// Display contents of an entry
+    /// Exit successfully if an entry exists, and with code 1 if not, without printing anything.
+    /// Errors, like a wrong password, exit with code 2.
+    Exists(OptExists),
+
+    /// Print the number of entries
+    Count(OptCount),
+
+    /// Print entry names starting with a prefix from the name cache, for shell completion.
+    /// Never prompts for the password, and prints nothing unless `name_cache` is enabled in config.
+    #[structopt(raw(setting = "structopt::clap::AppSettings::Hidden"))]
+    Complete(OptComplete),
+
+    /// Display contents of entries
     Show(OptShow),
 
-    /// Copy password of an entry
+    /// Print a single value of an entry as is, without labels,
+    /// for command substitution like `PGPASSWORD=$(vpass get db)`
+    Get(OptGet),
+
+    /// Copy password of an entry, or multiple entries in turn with `--sequence` or `--once`
     Copy(OptCopy),
 
+    /// Print entries for desktop launchers like Alfred or rofi, which run `vpass copy` on the selection
+    LauncherFeed(OptLauncherFeed),
+
+    /// Choose an entry with dmenu, rofi or wofi, and copy or type its password
+    Menu(OptMenu),
+
+    /// Run a command with passwords of entries in its environment, as `VPASS_<NAME>_PASSWORD`.
+    /// Fields written as `key: value` lines in notes, like `username: me`, are given as `VPASS_<NAME>_<KEY>`.
+    Exec(OptExec),
+
+    /// Store payment cards, and show or copy their details
+    Card(OptCard),
+
+    /// Store Wi-Fi networks, and share them as QR codes
+    Wifi(OptWifi),
+
+    /// Load the SSH key of an entry into the running ssh-agent, without writing it to disk
+    SshAdd(OptSshAdd),
+
+    /// Act as an SSH agent serving the SSH keys of entries from the vault, until interrupted,
+    /// or until the keys have been unused for `agent_lock_seconds` from the config.
+    /// Prints the `SSH_AUTH_SOCK` setting for clients. Only unencrypted Ed25519 keys are supported.
+    SshAgent(OptSshAgent),
+
+    /// Fill placeholders like `{{ site.password }}` or `{{ vault:site.password }}` in a template file
+    Render(OptRender),
+
+    /// Compute a derived password without a vault, using the vault password as the master password
+    Derive(OptDerive),
+
+    /// Print a random password, using a preset from the config if given
+    Generate(OptGenerate),
+
+    /// List entries by last use, to find unused ones for cleanup
+    Audit(OptAudit),
+
+    /// Show changes between this vault and another vault, or the remote copy
+    Diff(OptDiff),
+
+    /// Add entries from another password manager, e.g. a `pass` store.
+    /// Nothing is added if any of the entries already exists.
+    Import(OptImport),
+
+    /// Write entries for another password manager, e.g. to a `pass` store.
+    /// The passwords are then only as safe as the target.
+    Export(OptExport),
+
+    /// Measure key derivation speed, and suggest limits for a target unlock time
+    BenchKdf(OptBenchKdf),
+
     /// Edit synchronization settings of a vault
     Sync(OptSync),
 
+    /// Encrypt a single entry with a passphrase and upload it to a remote given as a transfer string,
+    /// e.g. for sharing it with someone without sharing the vault.
+    /// Publishing again replaces the uploaded entry.
+    Publish(OptPublish),
+
+    /// Download an entry uploaded with `publish`, and add it to the vault
+    FetchItem(OptFetchItem),
+
+    /// Synchronize vaults periodically until interrupted, every `sync_interval` seconds from the config.
+    /// Synchronizes the vaults in `daemon_vaults`, or all vaults with synchronization set up.
+    /// Failures and conflict copies on the remote are shown as desktop notifications.
+    /// Passwords asked on startup are forgotten after `daemon_lock_seconds`, stopping their synchronization.
+    Daemon(OptDaemon),
+
     /// Create or edit config.
     /// By default, creates configuration file if it doesn't exist.
     Config(OptConfig),
+
+    /// Show or clear the local log of vpass invocations, enabled with `audit_log` config option
+    Log(OptLog),
+
+    /// List profiles, or choose the one used without `--profile`.
+    /// Create a profile with `vpass --profile <name> init`.
+    Profile(OptProfile),
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
@@ -87,13 +314,17 @@ pub struct OptVault {
     pub subcommand: VaultSubCommand,
 }
 
-#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[derive(StructOpt, IntoStaticStr, Debug, Clone, PartialEq)]
 #[structopt(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab_case")]
 pub enum VaultSubCommand {
     /// Create a new vault
     Create(OptVaultCreate),
     /// Import a vault from synchronization json
     Import(OptVaultImport),
+    /// Download a vault from the remote of the selected vault, without a transfer string.
+    /// Use `sync remote-list` to see the available vaults.
+    Clone(OptVaultClone),
     /// Rename a vault.
     Rename(OptVaultRename),
     /// Delete a vault locally. Doesn't delete the remote copy.
@@ -103,10 +334,37 @@ pub enum VaultSubCommand {
     /// Change vault password.
     /// This is always synchronized.
     ChangePassword(OptVaultChangePassword),
+    /// Encrypt the vault again with all random material regenerated, keeping the password:
+    /// a new salt and nonce, and a new content key for the members of a shared vault.
+    /// The remote copy is replaced as well, and restored if writing the local vault fails.
+    Rekey(OptVaultRekey),
     /// List vaults
     List(OptVaultList),
+    /// Exit successfully if a vault exists, and with code 1 if not. Doesn't need the password.
+    /// Errors exit with code 2.
+    Exists(OptVaultExists),
     /// Show vault metadata
     Show(OptVaultShow),
+    /// Set the description or custom `key=value` annotations of a vault.
+    /// An empty value removes the annotation.
+    Annotate(OptVaultAnnotate),
+    /// Manage members of a shared vault, who unlock it with their own password
+    Recipients(OptVaultRecipients),
+    /// Manage key slots, each unlocking the vault with a different password
+    Key(OptVaultKey),
+    /// Seal the vault password to the TPM of this machine with `systemd-creds`,
+    /// for unlocking without a prompt with `--password-credential`. Linux only.
+    /// The sealed file can't be decrypted on other machines, unlike a `--password-file`.
+    SealPassword(OptVaultSealPassword),
+    /// Create a printable document with instructions for recovering the vault
+    EmergencyKit(OptVaultEmergencyKit),
+    /// Create a recovery key for the vault, and split it into shares for trusted contacts.
+    /// Any `threshold` shares can later be combined to decrypt the vault and set a new password.
+    /// Replaces the previous recovery key.
+    ShamirSplit(OptVaultShamirSplit),
+    /// Recover a vault password from shares in an emergency kit,
+    /// or set a new password for a vault from recovery key shares
+    ShamirRecover(OptVaultShamirRecover),
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
@@ -115,7 +373,11 @@ pub struct OptVaultCreate {
 
     /// Give password as argument instead of prompt
     #[structopt(short, long)]
-    pub password: Option<String>,
+    pub password: Option<Redacted<String>>,
+
+    /// Description shown in `vault show` and `vault list --long`
+    #[structopt(long)]
+    pub description: Option<String>,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
@@ -129,12 +391,19 @@ pub struct OptVaultImport {
     pub import_string: String,
 }
 
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptVaultClone {
+    /// Name of the vault on the remote, also used locally.
+    /// Vaults using remote key encryption need a transfer string, see `vault import`.
+    pub name: String,
+}
+
 #[derive(StructOpt, Debug, Clone, PartialEq)]
 pub struct OptVaultRename {
     pub old_name: String,
     pub new_name: String,
 
-    /// Keep old version on remote
+    /// Keep old version on remote, instead of moving it to trash
     #[structopt(long)]
     pub remote_keep_old: bool,
 }
@@ -147,7 +416,8 @@ pub struct OptVaultDelete {
     #[structopt(long, group = "exclusive")]
     pub force: bool,
 
-    /// Delete the remote copy too
+    /// Move the remote copy to trash too.
+    /// Use `sync purge-trash` to remove it permanently.
     #[structopt(long, group = "exclusive")]
     pub remote: bool,
 }
@@ -166,7 +436,7 @@ pub struct OptVaultChangePassword {
 
     /// Give password as argument instead of prompt
     #[structopt(short, long)]
-    pub password: Option<String>,
+    pub password: Option<Redacted<String>>,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
@@ -174,6 +444,47 @@ pub struct OptVaultList {
     /// Output as json
     #[structopt(short, long)]
     pub json: bool,
+
+    /// Show descriptions, which requires the password of each vault.
+    /// With `--status`, entry counts and synchronization providers are shown as well.
+    #[structopt(short, long)]
+    pub long: bool,
+
+    /// Show the file size, last modification and last synchronization of each vault,
+    /// and mark the default vault with `*`. Doesn't need passwords.
+    #[structopt(short, long)]
+    pub status: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptVaultRekey {
+    pub name: String,
+
+    /// Replace the remote key too, if the vault has one.
+    /// Other devices must import the vault again using a new transfer string from `sync export`.
+    #[structopt(long)]
+    pub rotate_remote_key: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptVaultExists {
+    pub name: String,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptVaultSealPassword {
+    pub name: String,
+
+    /// Sealed credential file to write
+    #[structopt(short, long)]
+    pub output: PathBuf,
+
+    /// Keys to seal with, passed to `systemd-creds --with-key`.
+    /// `host+tpm2` also needs the host key, which only root can read.
+    #[structopt(long, default_value = "tpm2")]
+    pub with_key: String,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
@@ -185,6 +496,137 @@ pub struct OptVaultShow {
     pub json: bool,
 }
 
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptVaultAnnotate {
+    pub name: String,
+
+    /// Annotations as `key=value`
+    #[structopt(parse(try_from_str = "parse_annotation"))]
+    pub annotations: Vec<(String, String)>,
+
+    /// Set the description, or remove it if empty
+    #[structopt(long)]
+    pub description: Option<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptVaultEmergencyKit {
+    pub name: String,
+
+    /// Output format, text or html
+    #[structopt(long, default_value = "text")]
+    pub format: emergency_kit::Format,
+
+    /// Write to this file instead of printing to stdout
+    #[structopt(short, long)]
+    pub out: Option<PathBuf>,
+
+    /// Split the vault password into shares, any `threshold` of `count` recovering it, e.g. `3/5`.
+    /// Each share gets its own kit, written to `--out` numbered like `kit-1.html`.
+    #[structopt(long, parse(try_from_str = "parse_shamir"), requires = "out")]
+    pub shamir: Option<(u8, u8)>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptVaultShamirSplit {
+    pub name: String,
+
+    /// Number of shares to create
+    #[structopt(short = "n", long = "shares")]
+    pub count: u8,
+
+    /// Number of shares required to recover the vault
+    #[structopt(short, long)]
+    pub threshold: u8,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptVaultShamirRecover {
+    #[structopt(raw(required = "true"))]
+    pub shares: Vec<String>,
+
+    /// Vault to set a new password for, required with recovery key shares
+    #[structopt(long)]
+    pub vault: Option<String>,
+
+    /// Give the new password as argument instead of prompt
+    #[structopt(short, long)]
+    pub password: Option<Redacted<String>>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptVaultKey {
+    /// Subcommand
+    #[structopt(subcommand)]
+    pub subcommand: KeySubCommand,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub enum KeySubCommand {
+    /// List key slots, marking the one matching the given password
+    List(OptRecipientsList),
+    /// Add a key slot for another password, e.g. a long recovery passphrase kept on paper
+    Add(OptKeyAdd),
+    /// Remove a key slot. The vault is encrypted with a new key, so its password can't read later versions.
+    Remove(OptKeyRemove),
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptKeyAdd {
+    pub name: String,
+
+    /// Give the password of the new slot as argument instead of prompt
+    #[structopt(long)]
+    pub new_password: Option<Redacted<String>>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptKeyRemove {
+    pub name: String,
+
+    /// Slot number, from `vault key list`
+    pub slot: usize,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptVaultRecipients {
+    /// Subcommand
+    #[structopt(subcommand)]
+    pub subcommand: RecipientsSubCommand,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub enum RecipientsSubCommand {
+    /// Print a new member key for your password, to be added to a shared vault by its owner
+    Key,
+    /// List member keys of a shared vault
+    List(OptRecipientsList),
+    /// Add a member. A vault becomes shared when the first member is added.
+    Add(OptRecipientsChange),
+    /// Remove a member. The vault is encrypted with a new key, so the member can't read later versions.
+    Remove(OptRecipientsChange),
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptRecipientsList {
+    pub name: String,
+
+    /// Output as json
+    #[structopt(short, long)]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptRecipientsChange {
+    pub name: String,
+
+    /// Member key, from `vault recipients key`
+    pub key: String,
+}
+
 #[derive(StructOpt, Debug, Clone, PartialEq)]
 pub struct OptAdd {
     /// Name of the entry
@@ -200,11 +642,48 @@ pub struct OptAdd {
 
     /// Give password as argument instead of prompt
     #[structopt(short, long, group = "password_exclusive")]
-    pub password: Option<String>,
+    pub password: Option<Redacted<String>>,
 
     /// Skip password
     #[structopt(short, long, group = "password_exclusive")]
     pub skip_password: bool,
+
+    /// Derive the password from the vault password and entry name instead of storing it,
+    /// so that it can be recovered with `vpass derive` without the vault
+    #[structopt(long, group = "password_exclusive")]
+    pub derive: bool,
+
+    /// Generate a random password
+    #[structopt(short, long, group = "password_exclusive")]
+    pub generate: bool,
+
+    /// Generate the password with this preset from the config
+    #[structopt(long, requires = "generate")]
+    pub preset: Option<String>,
+
+    /// Length of the derived or generated password, unless a preset is given
+    #[structopt(long, default_value = "20")]
+    pub length: usize,
+
+    /// Encrypt the password with a separate passphrase, required to reveal it
+    #[structopt(long, conflicts_with = "derive")]
+    pub protect: bool,
+
+    /// Store the private SSH key from this file, for `ssh-add` and `ssh-agent`
+    #[structopt(long = "ssh-key")]
+    pub ssh_key: Option<PathBuf>,
+
+    /// What the entry stores: login, note, ssh-key, gpg-key, api-token, certificate or blob
+    #[structopt(long, default_value = "login")]
+    pub kind: ItemKind,
+
+    /// Store the contents of this file, required for gpg-key, certificate and blob entries
+    #[structopt(long = "from-file")]
+    pub from_file: Option<PathBuf>,
+
+    /// Short label shown before the name in long listings, like an emoji
+    #[structopt(long)]
+    pub label: Option<String>,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
@@ -224,112 +703,899 @@ pub struct OptEdit {
     #[structopt(short, long = "note")]
     pub notes: Vec<String>,
 
-    /// Remove note by index (zero-indexed)
+    /// Remove note by id, as shown by `show`
     #[structopt(name = "remove-note", long = "remove-note")]
-    pub remove_notes: Vec<usize>,
+    pub remove_notes: Vec<u32>,
 
     /// Change password, takes password as argument instead of prompt
     #[structopt(short, long, group = "password_exclusive")]
-    pub password: Option<String>,
+    pub password: Option<Redacted<String>>,
 
     /// Change password, prompts for a new one
     #[structopt(short, long, group = "password_exclusive")]
     pub change_password: bool,
+
+    /// Change derived password by incrementing its counter
+    #[structopt(long = "next-counter", group = "password_exclusive")]
+    pub next_counter: bool,
+
+    /// Encrypt the password with a separate passphrase, required to reveal it
+    #[structopt(long, group = "protect_xor")]
+    pub protect: bool,
+
+    /// Remove passphrase protection from the password
+    #[structopt(long, group = "protect_xor")]
+    pub unprotect: bool,
+
+    /// Store the private SSH key from this file, replacing the current one
+    #[structopt(long = "ssh-key", group = "ssh_key_xor")]
+    pub ssh_key: Option<PathBuf>,
+
+    /// Remove the SSH key
+    #[structopt(long = "remove-ssh-key", group = "ssh_key_xor")]
+    pub remove_ssh_key: bool,
+
+    /// Change what the entry stores
+    #[structopt(long)]
+    pub kind: Option<ItemKind>,
+
+    /// Change the label shown in long listings, empty to remove it
+    #[structopt(long)]
+    pub label: Option<String>,
+
+    /// Replace the stored file with the contents of this file
+    #[structopt(long = "from-file")]
+    pub from_file: Option<PathBuf>,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
 #[structopt(rename_all = "kebab-case")]
-pub struct OptRename {
-    /// Current name of the entry
-    pub old_name: String,
+pub struct OptRotate {
+    /// Entries to rotate, in addition to those selected with `--tag`
+    pub names: Vec<String>,
 
-    /// New name for the entry
-    pub new_name: String,
+    /// Rotate all entries with all of these tags
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Generate random passwords instead of prompting for each entry.
+    /// Derived passwords are always changed by incrementing their counter.
+    #[structopt(short, long)]
+    pub generate: bool,
+
+    /// Generate the passwords with this preset from the config
+    #[structopt(long, requires = "generate")]
+    pub preset: Option<String>,
+
+    /// Length of generated passwords, unless a preset is given
+    #[structopt(long, default_value = "20")]
+    pub length: usize,
+
+    /// Mark the entries "rotation pending" until confirmed with `--done`
+    #[structopt(long)]
+    pub pending: bool,
+
+    /// Confirm that the passwords of the given entries were changed on the sites,
+    /// instead of rotating them
+    #[structopt(long, raw(conflicts_with_all = r#"&["generate", "pending"]"#))]
+    pub done: bool,
+
+    /// Print the checklist as json
+    #[structopt(short, long)]
+    pub json: bool,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
-pub struct OptRemove {
-    pub name: String,
+pub struct OptNote {
+    /// Subcommand
+    #[structopt(subcommand)]
+    pub subcommand: NoteSubCommand,
 }
 
-#[derive(StructOpt, Debug, Clone, PartialEq)]
-pub struct OptList {
-    /// Output as json
-    #[structopt(short, long)]
-    pub json: bool,
+#[derive(StructOpt, IntoStaticStr, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab_case")]
+pub enum NoteSubCommand {
+    /// Add a note, and print its id
+    Add(OptNoteAdd),
+    /// Change text or title of a note
+    Edit(OptNoteEdit),
+    /// Remove a note
+    Rm(OptNoteId),
+    /// Print text of a note
+    Show(OptNoteId),
+    /// Create an entry that is a single document, written in $EDITOR
+    New(OptNoteNew),
+    /// Print the document of a note entry
+    Cat(OptNoteName),
+    /// Edit the document of a note entry in $EDITOR
+    Open(OptNoteName),
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
-pub struct OptShow {
+pub struct OptNoteAdd {
+    /// Name of the entry
     pub name: String,
 
-    /// Display password in plaintext
-    #[structopt(short, long)]
-    pub password: bool,
+    pub text: String,
 
-    /// Output as JSON
+    /// Short title shown before the text
     #[structopt(short, long)]
-    pub json: bool,
+    pub title: Option<String>,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
-pub struct OptCopy {
+#[structopt(rename_all = "kebab-case")]
+pub struct OptNoteEdit {
+    /// Name of the entry
     pub name: String,
-}
 
-#[derive(StructOpt, Debug, Clone, PartialEq)]
-pub struct OptSync {
-    /// Subcommand
-    #[structopt(subcommand)]
-    pub subcommand: Option<SyncSubCommand>,
+    /// Note id, as shown by `show`
+    pub id: u32,
+
+    /// New text
+    pub text: Option<String>,
+
+    /// New title
+    #[structopt(short, long, group = "title_xor")]
+    pub title: Option<String>,
+
+    /// Remove the title
+    #[structopt(long, group = "title_xor")]
+    pub remove_title: bool,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
-#[structopt(rename_all = "kebab-case")]
-pub enum SyncSubCommand {
-    /// Set up synchronization for the vault
-    Setup(OptSyncSetup),
-    /// Export provider settings as a transfer string.
-    /// Use `vault import` to download this vault on another device.
-    /// If enabling sync when the same vault has manually been copied to another device,
-    /// or changing to another synchronization service, use  `sync setup --import`.
-    /// Includes per-provider data, most likely containing access tokens etc.
-    Export,
-    /// Remove synchronization from the vault
-    Detach,
-    /// Delete remote vault
-    Delete,
-    /// Overwrite remote changes, "force push"
-    Overwrite,
-    /// Show synchronization target.
-    Show(OptSyncShow),
+pub struct OptNoteId {
+    /// Name of the entry
+    pub name: String,
+
+    /// Note id, as shown by `show`
+    pub id: u32,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
-pub struct OptSyncSetup {
-    /// Give options an import string from `sync export`
-    #[structopt(short, long, group = "exclusive")]
-    pub import: Option<String>,
+pub struct OptNoteNew {
+    /// Name of the entry
+    pub name: String,
 
-    /// Give options as json blob
-    #[structopt(short, long, group = "exclusive")]
-    pub json: Option<String>,
+    /// One-word tags
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Read the document from this file instead of the editor
+    #[structopt(long = "from-file")]
+    pub from_file: Option<PathBuf>,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
-pub struct OptSyncShow {
-    /// Print as JSON. This includes private access tokens etc. from integration config.
-    #[structopt(short, long)]
-    pub json: bool,
+pub struct OptNoteName {
+    /// Name of the entry
+    pub name: String,
 }
 
 #[derive(StructOpt, Debug, Clone, PartialEq)]
-pub struct OptConfig {
-    /// Print config as JSON
-    #[structopt(short, long, group = "exclusive")]
-    pub json: bool,
+#[structopt(rename_all = "kebab-case")]
+pub struct OptRename {
+    /// Current name of the entry
+    pub old_name: String,
+
+    /// New name for the entry
+    pub new_name: String,
+
+    /// If an entry with the new name exists, merge notes and tags into it
+    /// and remove the old entry. The password of the existing entry is kept if it has one.
+    #[structopt(long)]
+    pub merge_into: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptRemove {
+    /// Entries to remove. Nothing is removed if any of them doesn't exist.
+    #[structopt(raw(required = "true"))]
+    pub names: Vec<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptArchive {
+    /// Nothing is changed if any of the entries doesn't exist
+    #[structopt(raw(required = "true"))]
+    pub names: Vec<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptList {
+    /// Output as json
+    #[structopt(short, long)]
+    pub json: bool,
+
+    /// Show last use and use count of each entry
+    #[structopt(short, long)]
+    pub long: bool,
+
+    /// Sort by name, created, changed or last-used
+    #[structopt(long, default_value = "name")]
+    pub sort: listing::SortKey,
+
+    /// Reverse the sort order
+    #[structopt(short, long)]
+    pub reverse: bool,
+
+    /// Group entries by tag or folder
+    #[structopt(long)]
+    pub group_by: Option<listing::GroupBy>,
+
+    /// Include archived entries
+    #[structopt(long)]
+    pub archived: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptExists {
+    /// Name of the entry
+    pub name: String,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptComplete {
+    #[structopt(default_value = "")]
+    pub prefix: String,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptCount {
+    /// Count only entries with all of these tags
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Include archived entries
+    #[structopt(long)]
+    pub archived: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptDiff {
+    /// Name of the other vault, must have the same password.
+    /// Compares to the remote copy of the vault if not given.
+    pub other: Option<String>,
+
+    /// Output as json, one change per line
+    #[structopt(short, long)]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptBenchKdf {
+    /// Target unlock time in milliseconds
+    #[structopt(long, default_value = "500")]
+    pub target_ms: u64,
+
+    /// Save the limits to config, to be used for new vaults and `vault change-password`
+    #[structopt(long)]
+    pub apply: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptAudit {
+    /// Only list entries not used within `--older-than`
+    #[structopt(long)]
+    pub unused: bool,
+
+    /// Age limit for `--unused`, e.g. `90d`, `6m` or `1y`
+    #[structopt(long, default_value = "1y", parse(try_from_str = "parse_age"))]
+    pub older_than: chrono::Duration,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptShow {
+    /// Entries to show. With `--json`, each entry is printed on its own line.
+    #[structopt(raw(required = "true"))]
+    pub names: Vec<String>,
+
+    /// Display password in plaintext
+    #[structopt(short, long)]
+    pub password: bool,
+
+    /// Output as JSON
+    #[structopt(short, long)]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptGet {
+    pub name: String,
+
+    /// Value to print: password, notes, tags, data or note:N for a single note by its id
+    #[structopt(short, long, default_value = "password")]
+    pub field: template::Field,
+
+    /// Don't print a trailing newline
+    #[structopt(short = "n", long)]
+    pub no_newline: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptCopy {
+    #[structopt(raw(required = "true"))]
+    pub names: Vec<String>,
+
+    /// Copy each entry in turn, waiting for enter before the next one
+    #[structopt(short, long)]
+    pub sequence: bool,
+
+    /// Offer the secret for a single paste, clearing the clipboard right after it.
+    /// Entries are copied in turn, each after the previous one was pasted. Needs X11.
+    #[structopt(long)]
+    pub once: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptLauncherFeed {
+    /// Output format: alfred for script filter JSON, or rofi for one name per line
+    #[structopt(long, default_value = "rofi")]
+    pub format: launcher::Format,
+
+    /// Only entries with all of these tags
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptMenu {
+    /// Type the password into the focused window instead of copying it
+    #[structopt(long = "type")]
+    pub type_text: bool,
+
+    /// Only entries with all of these tags
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptExec {
+    #[structopt(raw(required = "true"))]
+    pub names: Vec<String>,
+
+    /// Command and its arguments, after `--`
+    #[structopt(raw(required = "true", last = "true"))]
+    pub command: Vec<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptImport {
+    /// Format of the source: pass or kdbx
+    #[structopt(short, long, raw(required_unless = r#""from-browser""#))]
+    pub format: Option<interop::Format>,
+
+    /// Source, the store directory for pass or the database file for kdbx
+    #[structopt(raw(required_unless = r#""from-browser""#))]
+    pub path: Option<PathBuf>,
+
+    /// Key file unlocking a kdbx database, in addition to its password
+    #[structopt(short, long)]
+    pub keyfile: Option<PathBuf>,
+
+    /// Read the saved logins of a browser directly: chrome or firefox
+    #[structopt(long, raw(conflicts_with_all = r#"&["format", "path"]"#))]
+    pub from_browser: Option<interop::browser::Browser>,
+
+    /// Browser profile directory, instead of the default profile
+    #[structopt(long, requires = "from-browser")]
+    pub profile: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptExport {
+    /// Format of the target: pass
+    #[structopt(short, long)]
+    pub format: interop::Format,
+
+    /// Target, the store directory for pass
+    pub path: PathBuf,
+
+    /// Export only entries with all of these tags
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Gpg key to encrypt to with pass, instead of those in the `.gpg-id` file of the store
+    #[structopt(name = "gpg-id", long = "gpg-id")]
+    pub gpg_ids: Vec<String>,
+
+    /// Overwrite existing entries in the target
+    #[structopt(long)]
+    pub force: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptCard {
+    /// Subcommand
+    #[structopt(subcommand)]
+    pub subcommand: CardSubCommand,
+}
+
+#[derive(StructOpt, IntoStaticStr, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab_case")]
+pub enum CardSubCommand {
+    /// Add a card entry, prompting for the number and CVV. Numbers are checked for typos.
+    Add(OptCardAdd),
+    /// Print the card details, with the number in groups
+    Show(OptCardShow),
+    /// Copy the card number to the clipboard, without spaces
+    CopyNumber(OptCardName),
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptCardAdd {
+    /// Name of the entry
+    pub name: String,
+
+    /// One-word tags
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+
+    /// Give the number as argument instead of prompt
+    #[structopt(long)]
+    pub number: Option<Redacted<String>>,
+
+    /// Expiry date, like `03/27`
+    #[structopt(short, long)]
+    pub expiry: Option<String>,
+
+    /// Give the CVV as argument instead of prompt
+    #[structopt(long)]
+    pub cvv: Option<Redacted<String>>,
+
+    /// Skip the CVV
+    #[structopt(long = "no-cvv", conflicts_with = "cvv")]
+    pub no_cvv: bool,
+
+    /// Name printed on the card
+    #[structopt(long)]
+    pub holder: Option<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptCardShow {
+    /// Name of the entry
+    pub name: String,
+
+    /// Show the full number and CVV instead of hiding them
+    #[structopt(short, long)]
+    pub reveal: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptCardName {
+    /// Name of the entry
+    pub name: String,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptWifi {
+    /// Subcommand
+    #[structopt(subcommand)]
+    pub subcommand: WifiSubCommand,
+}
+
+#[derive(StructOpt, IntoStaticStr, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab_case")]
+pub enum WifiSubCommand {
+    /// Add a Wi-Fi entry, prompting for the passphrase
+    Add(OptWifiAdd),
+    /// Print the QR code that phones join the network with
+    Qr(OptWifiQr),
+    /// Add entries for the networks known to the operating system, under `wifi/`.
+    /// Networks that already have an entry are skipped.
+    Import(OptWifiImport),
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptWifiAdd {
+    /// Name of the entry
+    pub name: String,
+
+    /// Name of the network
+    #[structopt(long)]
+    pub ssid: String,
+
+    /// Security type: wpa, wep or none
+    #[structopt(long, default_value = "wpa")]
+    pub security: wifi::Security,
+
+    /// Network doesn't broadcast its name
+    #[structopt(long)]
+    pub hidden: bool,
+
+    /// Give the passphrase as argument instead of prompt
+    #[structopt(short, long)]
+    pub password: Option<Redacted<String>>,
+
+    /// One-word tags
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptWifiQr {
+    /// Name of the entry
+    pub name: String,
+
+    /// Print the text of the code instead, for other QR code tools
+    #[structopt(long)]
+    pub text: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptWifiImport {
+    /// One-word tags added to the imported entries
+    #[structopt(name = "tag", short, long = "tag")]
+    pub tags: Vec<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSshAdd {
+    /// Name of the entry
+    pub name: String,
+
+    /// Remove the key from the agent after this time, in seconds or like `30m` or `1h`
+    #[structopt(short = "t", long)]
+    pub lifetime: Option<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSshAgent {
+    /// Entries whose keys are served, all entries with SSH keys if not given
+    pub names: Vec<String>,
+
+    /// Path of the agent socket, in the runtime directory by default,
+    /// or in a directory of the user in the temporary directory if there's none.
+    /// The default name includes the profile, if one is used.
+    /// The socket is removed when the agent exits.
+    #[structopt(short, long)]
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptRender {
+    pub template: PathBuf,
+
+    /// Write to this file, readable only by the owner, instead of printing to stdout
+    #[structopt(short, long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptDerive {
+    /// Site name, the entry name when the password was created
+    pub site: String,
+
+    /// Counter, incremented each time the password was changed
+    #[structopt(long, default_value = "1")]
+    pub counter: u32,
+
+    /// Password length
+    #[structopt(long, default_value = "20")]
+    pub length: usize,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptGenerate {
+    /// Start from this preset in the config instead of the defaults
+    #[structopt(long)]
+    pub preset: Option<String>,
+
+    /// Password length
+    #[structopt(short, long)]
+    pub length: Option<usize>,
+
+    /// Leave out letters
+    #[structopt(long)]
+    pub no_letters: bool,
+
+    /// Leave out digits
+    #[structopt(long)]
+    pub no_digits: bool,
+
+    /// Leave out symbols
+    #[structopt(long)]
+    pub no_symbols: bool,
+
+    /// Leave out these characters, like symbols rejected by a site
+    #[structopt(long)]
+    pub exclude: Option<String>,
+
+    /// Syllables that are easy to read aloud, like `lusab-babad`, instead of random characters
+    #[structopt(long)]
+    pub pronounceable: bool,
+
+    /// Save the settings to the config as a preset with this name
+    #[structopt(long)]
+    pub save_preset: Option<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub struct OptSync {
+    /// Link with a separately created remote vault, instead of refusing to merge unrelated vaults.
+    /// Local entries are copied on top of the remote ones, replacing entries with the same name.
+    #[structopt(long)]
+    pub force_adopt: bool,
+
+    /// Subcommand
+    #[structopt(subcommand)]
+    pub subcommand: Option<SyncSubCommand>,
+}
+
+#[derive(StructOpt, IntoStaticStr, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab_case")]
+pub enum SyncSubCommand {
+    /// Set up synchronization for the vault
+    Setup(OptSyncSetup),
+    /// Export provider settings as a transfer string.
+    /// Use `vault import` to download this vault on another device.
+    /// If enabling sync when the same vault has manually been copied to another device,
+    /// or changing to another synchronization service, use  `sync setup --import`.
+    /// Includes per-provider data, most likely containing access tokens etc.
+    Export,
+    /// Remove synchronization from the vault
+    Detach,
+    /// Move the remote copy of the vault to trash, keeping the local one.
+    /// Use `sync purge-trash` to delete it permanently.
+    /// Synchronization settings are kept as well unless `--detach` is given,
+    /// so the next `sync` uploads the vault again.
+    Delete(OptSyncDelete),
+    /// Overwrite remote changes, "force push"
+    Overwrite,
+    /// Show synchronization settings, and when the vault was last synchronized on this device.
+    /// Access tokens and other secrets are hidden unless `--secrets` is given.
+    Show(OptSyncShow),
+    /// Check whether the remote vault has changed since the last `sync` on this device.
+    /// Only reads sizes and update keys, without downloading the vault, unless `--changes` is given.
+    Status(OptSyncStatus),
+    /// Serve a local folder to other machines using the Lan provider, until interrupted.
+    /// Prints a pairing code to be entered on the other machine, accepted once within ten minutes.
+    Listen(OptSyncListen),
+    /// List vaults on the remote, which can be downloaded with `vault clone`
+    RemoteList,
+    /// Check the remote for leftovers: vaults not present locally, e.g. from old renames,
+    /// vaults that are not valid encrypted vaults, and deltas and chunks of removed vaults.
+    /// Vault contents are not decrypted.
+    CheckRemote(OptSyncCheckRemote),
+    /// Merge conflict copies created by file synchronization tools into the vault,
+    /// and remove them from the remote. Lists the items changed by the merge.
+    ResolveConflicts,
+    /// Check that synchronization works, printing the result and duration of each step.
+    /// Writes, reads and deletes a temporary key on the remote.
+    Test,
+    /// Permanently delete trashed remote copies of a vault.
+    /// Vaults are moved to trash by `vault delete --remote` and `vault rename`.
+    PurgeTrash(OptSyncPurgeTrash),
+    /// Encrypt remote data with a new random remote key, in addition to the vault password.
+    /// Other devices must import the vault again using a new transfer string from `sync export`.
+    RotateRemoteKey(OptSyncRotateRemoteKey),
+    /// Encrypt the remote copy with a separate sync password instead of the vault password,
+    /// e.g. a stronger one than the password used on this device.
+    /// Other devices must import the vault again, giving the sync password as the vault password.
+    SetPassword(OptSyncSetPassword),
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncSetup {
+    /// Give options an import string from `sync export`
+    #[structopt(short, long, group = "exclusive")]
+    pub import: Option<String>,
+
+    /// Give options as json blob
+    #[structopt(short, long, group = "exclusive")]
+    pub json: Option<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptPublish {
+    /// Entry name
+    pub name: String,
+
+    /// Transfer string of the remote, from `sync export`
+    #[structopt(long)]
+    pub provider: String,
+
+    /// Remote key, the entry name by default. `.vpass_item` is appended if missing.
+    #[structopt(long)]
+    pub key: Option<String>,
+
+    /// Give the passphrase as an argument instead of prompting
+    #[structopt(long)]
+    pub passphrase: Option<Redacted<String>>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptFetchItem {
+    /// Remote key given to `publish`, the entry name by default
+    pub key: String,
+
+    /// Transfer string of the remote, from `sync export`
+    #[structopt(long)]
+    pub provider: String,
+
+    /// Replace an existing entry with the same name, keeping its history
+    #[structopt(long)]
+    pub replace: bool,
+
+    /// Give the passphrase as an argument instead of prompting
+    #[structopt(long)]
+    pub passphrase: Option<Redacted<String>>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncShow {
+    /// Print as JSON
+    #[structopt(short, long)]
+    pub json: bool,
+
+    /// Show access tokens, passwords and the remote key instead of hiding them
+    #[structopt(long)]
+    pub secrets: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncDelete {
+    /// Don't ask for confirmation
+    #[structopt(short, long)]
+    pub yes: bool,
+
+    /// Also remove synchronization settings from the vault
+    #[structopt(long)]
+    pub detach: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncStatus {
+    /// Print as JSON
+    #[structopt(short, long)]
+    pub json: bool,
+
+    /// Download the remote vault, and list items that `sync` would change in the local vault
+    #[structopt(long)]
+    pub changes: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncCheckRemote {
+    /// Print as JSON
+    #[structopt(short, long, group = "exclusive")]
+    pub json: bool,
+
+    /// Ask whether to clean up each problem: vaults are moved to trash, and leftovers deleted
+    #[structopt(long, group = "exclusive")]
+    pub clean: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncListen {
+    /// Folder to serve, defaults to `lan` in the vault directory.
+    /// Local vaults can use it with the FileSystem provider.
+    pub dir: Option<PathBuf>,
+
+    /// TCP port to listen on, instead of the default port of the Lan provider
+    #[structopt(long)]
+    pub port: Option<u16>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncPurgeTrash {
+    /// Name of the trashed vault, defaults to the selected vault
+    pub name: Option<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncRotateRemoteKey {
+    /// Remove the remote key instead, leaving only the vault password encryption
+    #[structopt(long)]
+    pub disable: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptSyncSetPassword {
+    /// Give the sync password as argument instead of prompt
+    #[structopt(short, long)]
+    pub password: Option<Redacted<String>>,
+
+    /// Remove the sync password instead, encrypting the remote copy with the vault password again
+    #[structopt(long, conflicts_with = "password")]
+    pub disable: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptDaemon {
+    /// Synchronize once and exit, e.g. when scheduled by cron
+    #[structopt(long)]
+    pub once: bool,
+
+    /// Subcommand
+    #[structopt(subcommand)]
+    pub subcommand: Option<DaemonSubCommand>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub enum DaemonSubCommand {
+    /// Install a systemd user service, or a launchd agent on macOS, running the daemon on login.
+    /// The service can't prompt, so set `password_command` in the config or use `--password-file`.
+    Install(OptDaemonInstall),
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptDaemonInstall {
+    /// Print the service file instead of installing it
+    #[structopt(long)]
+    pub print: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub enum OptLog {
+    /// Print log entries, oldest first
+    Show(OptLogShow),
+    /// Remove all log entries
+    Clear,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+#[structopt(rename_all = "kebab-case")]
+pub enum OptProfile {
+    /// List profiles, marking the selected one with `*`
+    List(OptProfileList),
+    /// Use a profile when `--profile` is not given, or the vault directory itself if no name is given
+    Use(OptProfileUse),
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptProfileList {
+    /// Output as json
+    #[structopt(short, long)]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptProfileUse {
+    pub name: Option<String>,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptLogShow {
+    /// Output as json, one entry per line
+    #[structopt(short, long)]
+    pub json: bool,
+}
+
+#[derive(StructOpt, Debug, Clone, PartialEq)]
+pub struct OptConfig {
+    /// Print config as JSON
+    #[structopt(short, long, group = "exclusive")]
+    pub json: bool,
 
     /// Override the config file with default config
     #[structopt(long, group = "exclusive")]
     pub clear: bool,
+
+    /// Set a config value, e.g. `case_insensitive_names=true`
+    #[structopt(long, group = "exclusive")]
+    pub set: Option<String>,
+
+    /// Encrypt the config file with a passphrase.
+    /// The passphrase is asked when needed, or read from `VPASS_CONFIG_PASSWORD`.
+    #[structopt(long, group = "exclusive")]
+    pub encrypt: bool,
+
+    /// Store the config file as plain JSON again
+    #[structopt(long, group = "exclusive")]
+    pub decrypt: bool,
 }
@@ -1,16 +1,80 @@
+use std::fs;
 use std::path::PathBuf;
 
-use super::{error::*, opt::OptRoot};
+use super::{error::*, opt::OptRoot, validate};
 
-pub fn data_dir(args: &OptRoot) -> VResult<PathBuf> {
+/// Directory given with `--vault-dir`, or the default one. Profiles are in its `profiles` directory.
+fn base_dir(args: &OptRoot) -> PathBuf {
     if let Some(ref v) = args.vault_dir {
-        Ok(v.into())
+        v.into()
     } else if cfg!(windows) {
-        Ok(dirs::data_dir().unwrap().join(env!("CARGO_PKG_NAME")))
+        dirs::data_dir().unwrap().join(env!("CARGO_PKG_NAME"))
     } else {
-        Ok(dirs::home_dir()
+        dirs::home_dir()
             .expect("Home directory not available")
-            .join(concat!(".", env!("CARGO_PKG_NAME"))))
+            .join(concat!(".", env!("CARGO_PKG_NAME")))
+    }
+}
+
+/// File in the base directory naming the profile used when `--profile` is not given.
+/// It's kept outside the config files, as each profile has its own config.
+fn default_profile_file(args: &OptRoot) -> PathBuf {
+    base_dir(args).join("profile")
+}
+
+fn default_profile(args: &OptRoot) -> Option<String> {
+    let name = fs::read_to_string(default_profile_file(args)).ok()?;
+    Some(name.trim().to_owned())
+}
+
+/// Use profile `name` when `--profile` is not given, or the base directory if `None`
+pub fn set_default_profile(args: &OptRoot, name: Option<&str>) -> VResult<()> {
+    let p = default_profile_file(args);
+    match name {
+        Some(name) => {
+            validate::profile_name(name)?;
+            fs::write(p, name)?;
+        },
+        None if p.exists() => fs::remove_file(p)?,
+        None => {},
+    }
+    Ok(())
+}
+
+/// Profile selected with `--profile`, or with `vpass profile use`.
+/// An empty name selects the base directory.
+pub fn profile(args: &OptRoot) -> VResult<Option<String>> {
+    let name = match args.profile.clone().or_else(|| default_profile(args)) {
+        Some(ref name) if name.is_empty() => return Ok(None),
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    validate::profile_name(&name)?;
+    Ok(Some(name))
+}
+
+/// Names of the profiles in the base directory, sorted
+pub fn profiles(args: &OptRoot) -> VResult<Vec<String>> {
+    let dir = base_dir(args).join("profiles");
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut result = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            result.extend(entry.file_name().to_str().map(str::to_owned));
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+pub fn data_dir(args: &OptRoot) -> VResult<PathBuf> {
+    let base = base_dir(args);
+    match profile(args)? {
+        Some(name) => Ok(base.join("profiles").join(name)),
+        None => Ok(base),
     }
 }
 pub fn config_file(args: &OptRoot) -> VResult<PathBuf> {
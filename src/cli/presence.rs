@@ -0,0 +1,72 @@
+//! Confirming that the user is at the keyboard with Touch ID on macOS and Windows Hello on Windows,
+//! before secrets of the vaults in `biometric_vaults` are revealed. With `password_command` or
+//! `--password-credential` the vault unlocks without a prompt, and this keeps its secrets from being
+//! one command away for anyone else at the keyboard.
+
+use super::error::{Error, VResult};
+
+/// Ask the user to confirm with Touch ID, or with the login password if Touch ID is not available.
+/// `reason` completes the sentence "vpass is trying to".
+#[cfg(target_os = "macos")]
+pub fn confirm(reason: &str) -> VResult<()> {
+    use std::sync::mpsc;
+
+    use block::ConcreteBlock;
+    use objc::runtime::{Object, BOOL, YES};
+    use objc::{class, msg_send, sel, sel_impl};
+
+    #[link(name = "LocalAuthentication", kind = "framework")]
+    extern "C" {}
+
+    /// `LAPolicyDeviceOwnerAuthentication`, which falls back to the password unlike the biometrics policy
+    const POLICY: isize = 2;
+    const UTF8_ENCODING: usize = 4;
+
+    let (sender, receiver) = mpsc::channel();
+    // The reply is called on another thread
+    let reply = ConcreteBlock::new(move |success: BOOL, _error: *mut Object| {
+        let _ = sender.send(success == YES);
+    })
+    .copy();
+    unsafe {
+        let context: *mut Object = msg_send![class!(LAContext), new];
+        let reason_string: *mut Object = msg_send![class!(NSString), alloc];
+        let reason_string: *mut Object =
+            msg_send![reason_string, initWithBytes:reason.as_ptr() length:reason.len() encoding:UTF8_ENCODING];
+        let _: () = msg_send![context, evaluatePolicy:POLICY localizedReason:reason_string reply:&*reply];
+        let confirmed = receiver.recv().unwrap_or(false);
+        let _: () = msg_send![reason_string, release];
+        let _: () = msg_send![context, release];
+        if confirmed {
+            Ok(())
+        } else {
+            Err(Error::PresenceNotConfirmed)
+        }
+    }
+}
+
+/// Ask the user to confirm with Windows Hello: face, fingerprint or PIN
+#[cfg(windows)]
+pub fn confirm(reason: &str) -> VResult<()> {
+    use winrt::windows::security::credentials::ui::{UserConsentVerificationResult, UserConsentVerifier};
+    use winrt::{FastHString, RtAsyncOperation, RuntimeContext};
+
+    let _runtime = RuntimeContext::init();
+    let message = FastHString::new(reason);
+    let result = UserConsentVerifier::request_verification_async(&message)
+        .and_then(|operation| operation.blocking_get())
+        .map_err(|e| Error::PresenceFailed(format!("{:?}", e)))?;
+    match result {
+        UserConsentVerificationResult::Verified => Ok(()),
+        UserConsentVerificationResult::Canceled | UserConsentVerificationResult::RetriesExhausted => {
+            Err(Error::PresenceNotConfirmed)
+        },
+        other => Err(Error::PresenceFailed(format!("{:?}", other))),
+    }
+}
+
+/// No biometric service is common enough elsewhere
+#[cfg(not(any(target_os = "macos", windows)))]
+pub fn confirm(_reason: &str) -> VResult<()> {
+    Err(Error::PresenceUnsupported)
+}
@@ -0,0 +1,47 @@
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::sync::progress::{self, Phase, Progress};
+
+/// Render synchronization progress on stderr.
+/// Nothing is drawn if stderr is not a terminal.
+/// In plain mode each phase is printed on its own line, and transfers are not shown.
+pub fn install(plain: bool) {
+    if plain {
+        let mut previous: Option<Phase> = None;
+        progress::set_handler(move |p| match p {
+            Progress::Phase(phase) if previous != Some(phase) => {
+                eprintln!("{}...", phase);
+                previous = Some(phase);
+            },
+            Progress::Done => previous = None,
+            _ => {},
+        });
+        return;
+    }
+    let mut bar: Option<ProgressBar> = None;
+    progress::set_handler(move |p| match p {
+        Progress::Phase(phase) => {
+            let b = bar.get_or_insert_with(|| {
+                let b = ProgressBar::new_spinner();
+                b.enable_steady_tick(100);
+                b
+            });
+            b.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}"));
+            b.set_message(&phase.to_string());
+        },
+        Progress::Transfer { done, total } => {
+            if let Some(ref b) = bar {
+                if let Some(total) = total {
+                    b.set_style(ProgressStyle::default_bar().template("{msg} [{bar:30}] {bytes}/{total_bytes}"));
+                    b.set_length(total);
+                }
+                b.set_position(done);
+            }
+        },
+        Progress::Done => {
+            if let Some(b) = bar.take() {
+                b.finish_and_clear();
+            }
+        },
+    });
+}
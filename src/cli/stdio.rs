@@ -0,0 +1,47 @@
+//! Vaults piped through standard input and output with `--file -`,
+//! e.g. for pulling the vault from a custom transport and pushing it back without temporary files.
+//! The vault is written to standard output only if the command changed it,
+//! and accesses are not recorded, so that commands printing secrets don't write the vault as well.
+
+use std::cell::RefCell;
+use std::io::{self, Read};
+use std::path::Path;
+
+use super::error::*;
+use crate::Book;
+
+thread_local! {
+    /// Vault bytes, as standard input can only be read once
+    static INPUT: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// Vault path selecting standard input and output
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Vault bytes from standard input, read on first use
+pub fn input() -> VResult<Vec<u8>> {
+    if let Some(data) = INPUT.with(|i| i.borrow().clone()) {
+        return Ok(data);
+    }
+    let mut data = Vec::new();
+    io::stdin().read_to_end(&mut data)?;
+    INPUT.with(|i| *i.borrow_mut() = Some(data.clone()));
+    Ok(data)
+}
+
+pub fn read(password: &str) -> VResult<Book> {
+    let mut book = crate::decrypt_from(input()?.as_slice(), password)?;
+    book.set_ignore_access(true);
+    Ok(book)
+}
+
+/// Write the book to standard output if it has changed, keeping the salt of the input
+pub fn write_changes(password: &str, book: Book) -> VResult<()> {
+    if book.is_dirty() {
+        crate::write_to(io::stdout(), password, book, Some(&input()?))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! Remote state seen by the last synchronization on this device, stored next to each vault.
+//! `sync status` compares it to the current remote version using metadata requests only,
+//! and shows it when the remote is unreachable.
+//! The downloaded snapshot is kept as well, still encrypted with the vault password,
+//! so that the next `sync` doesn't download it again if it hasn't changed.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::error::*;
+use crate::sync::{RemoteState, Snapshot, UpdateKey};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    state: RemoteState,
+    /// Base64 encoded UpdateKey and encrypted data of the snapshot
+    snapshot: Option<(String, String)>,
+    /// Missing from records written by older versions
+    #[serde(default)]
+    synced: Option<DateTime<Utc>>,
+}
+
+fn path(vault_path: &Path) -> PathBuf {
+    vault_path.with_extension("vpass_sync")
+}
+
+fn load(vault_path: &Path) -> VResult<Option<Record>> {
+    let p = path(vault_path);
+    if p.exists() {
+        Ok(serde_json::from_slice(&fs::read(p)?).ok())
+    } else {
+        Ok(None)
+    }
+}
+
+/// Record the remote state after a synchronization, or remove the record if there is none
+pub fn update(vault_path: &Path, state: Option<RemoteState>, snapshot: Option<Snapshot>) -> VResult<()> {
+    if let Some(state) = state {
+        let record = Record {
+            state,
+            snapshot: snapshot.map(|s| (base64::encode(s.update_key.as_bytes()), base64::encode(&s.data))),
+            synced: Some(Utc::now()),
+        };
+        fs::write(path(vault_path), serde_json::to_vec(&record).unwrap())?;
+        Ok(())
+    } else {
+        remove(vault_path)
+    }
+}
+
+/// Remote state at the last synchronization, if any
+pub fn read(vault_path: &Path) -> VResult<Option<RemoteState>> {
+    Ok(load(vault_path)?.map(|r| r.state))
+}
+
+/// Time of the last synchronization on this device, if known
+pub fn last_sync(vault_path: &Path) -> VResult<Option<DateTime<Utc>>> {
+    Ok(load(vault_path)?.and_then(|r| r.synced))
+}
+
+/// Snapshot downloaded by the last synchronization, if any
+pub fn snapshot(vault_path: &Path) -> VResult<Option<Snapshot>> {
+    Ok(load(vault_path)?.and_then(|r| r.snapshot).and_then(|(update_key, data)| {
+        Some(Snapshot {
+            update_key: UpdateKey::from_bytes(&base64::decode(&update_key).ok()?),
+            data: base64::decode(&data).ok()?,
+        })
+    }))
+}
+
+pub fn remove(vault_path: &Path) -> VResult<()> {
+    let p = path(vault_path);
+    if p.exists() {
+        fs::remove_file(p)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Templates with placeholders referring to vault items, used by `vpass render`.
+//! Placeholders look like `{{ site.password }}`, or `{{ work:site.password }}`
+//! to read from another vault. Item names may contain dots, the last one separates the field.
+//! Fields are named like in `vpass get --field`.
+
+use std::str::FromStr;
+
+use super::error::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Password,
+    /// All notes, separated by newlines
+    Notes,
+    /// Sorted tags, separated by commas
+    Tags,
+    /// A single note by its id, `note:N`
+    Note(u32),
+    /// Stored file as text, like a certificate
+    Data,
+}
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "password" => Ok(Field::Password),
+            "notes" => Ok(Field::Notes),
+            "tags" => Ok(Field::Tags),
+            "data" => Ok(Field::Data),
+            other if other.starts_with("note:") => other[5..]
+                .parse()
+                .map(Field::Note)
+                .map_err(|_| format!("Invalid note id {}", &other[5..])),
+            other => Err(format!("Unknown field {}, expected password, notes, tags, data or note:N", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placeholder {
+    /// Vault name, if not the default one
+    pub vault: Option<String>,
+    pub item: String,
+    pub field: Field,
+}
+impl Placeholder {
+    fn parse(text: &str) -> VResult<Self> {
+        let invalid = || Error::TemplateInvalid(text.to_owned());
+        let dot = text.rfind('.').ok_or_else(invalid)?;
+        let field = text[dot + 1..].parse().map_err(|_| invalid())?;
+        // Fields may contain colons as well, so the vault is only looked for before the field
+        let (vault, item) = match text[..dot].find(':') {
+            Some(i) => (Some(text[..i].to_owned()), &text[i + 1..dot]),
+            None => (None, &text[..dot]),
+        };
+        if item.is_empty() || vault.as_ref().is_some_and(|v| v.is_empty()) {
+            return Err(invalid());
+        }
+        Ok(Placeholder {
+            vault,
+            item: item.to_owned(),
+            field,
+        })
+    }
+}
+
+/// All placeholders in a template, in order
+pub fn placeholders(template: &str) -> VResult<Vec<Placeholder>> {
+    let mut result = Vec::new();
+    render(template, |p| {
+        result.push(p.clone());
+        Ok(String::new())
+    })?;
+    Ok(result)
+}
+
+/// Replace each placeholder with the value returned by `lookup`
+pub fn render<F>(template: &str, mut lookup: F) -> VResult<String>
+where F: FnMut(&Placeholder) -> VResult<String> {
+    let mut result = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find("}}")
+            .ok_or_else(|| Error::TemplateInvalid(after.lines().next().unwrap_or("").to_owned()))?;
+        result.push_str(&lookup(&Placeholder::parse(after[..end].trim())?)?);
+        rest = &after[end + 2..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{placeholders, render, Field, Placeholder};
+    use crate::cli::Error;
+    use matches::matches;
+
+    #[test]
+    fn template_render() {
+        let template = "A={{ site.password }}\nB={{work:a.b.notes}}\n";
+        assert_eq!(
+            placeholders(template).unwrap(),
+            vec![
+                Placeholder {
+                    vault: None,
+                    item: "site".to_owned(),
+                    field: Field::Password,
+                },
+                Placeholder {
+                    vault: Some("work".to_owned()),
+                    item: "a.b".to_owned(),
+                    field: Field::Notes,
+                },
+            ]
+        );
+        let result = render(template, |p| Ok(p.item.to_uppercase())).unwrap();
+        assert_eq!(result, "A=SITE\nB=A.B\n");
+
+        assert_eq!(render("no placeholders", |_| unreachable!()).unwrap(), "no placeholders");
+        assert!(matches!(placeholders("{{ site.password "), Err(Error::TemplateInvalid(_))));
+        assert!(matches!(placeholders("{{ site }}"), Err(Error::TemplateInvalid(_))));
+        assert!(matches!(placeholders("{{ site.username }}"), Err(Error::TemplateInvalid(_))));
+        assert!(matches!(placeholders("{{ :site.password }}"), Err(Error::TemplateInvalid(_))));
+        assert!(matches!(placeholders("{{ site.note:x }}"), Err(Error::TemplateInvalid(_))));
+        assert_eq!(placeholders("{{ w:site.note:2 }}").unwrap()[0].field, Field::Note(2));
+        assert_eq!(placeholders("{{ w:site.note:2 }}").unwrap()[0].vault, Some("w".to_owned()));
+    }
+}
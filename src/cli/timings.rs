@@ -0,0 +1,117 @@
+//! Time spent in each phase of a command, printed with `--timings`,
+//! e.g. for choosing key derivation limits. Only printed locally, nothing is recorded.
+//! Each phase gets its own time only: a phase running inside another,
+//! like key derivation during synchronization, is not counted twice.
+
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Deriving vault keys from passwords
+    Kdf,
+    Decryption,
+    /// Decompressing and parsing decrypted vaults
+    Decompression,
+    /// Serializing and compressing vaults before encryption
+    Compression,
+    Encryption,
+    /// Requests to the synchronization provider
+    Sync,
+}
+impl Phase {
+    const ALL: [Phase; 6] = [
+        Phase::Kdf,
+        Phase::Decryption,
+        Phase::Decompression,
+        Phase::Compression,
+        Phase::Encryption,
+        Phase::Sync,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Kdf => "kdf",
+            Phase::Decryption => "decryption",
+            Phase::Decompression => "decompression",
+            Phase::Compression => "compression",
+            Phase::Encryption => "encryption",
+            Phase::Sync => "sync",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Time of each phase, indexed like `Phase::ALL`
+    static TOTALS: RefCell<[Duration; 6]> = const { RefCell::new([Duration::from_secs(0); 6]) };
+    /// Time spent in phases nested in the currently measured one
+    static NESTED: Cell<Duration> = const { Cell::new(Duration::from_secs(0)) };
+}
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Run `f`, adding its duration to `phase`, without the phases nested in it
+pub fn measure<T, F>(phase: Phase, f: F) -> T
+where F: FnOnce() -> T {
+    if !is_enabled() {
+        return f();
+    }
+    let outer_nested = NESTED.with(|n| n.replace(Duration::from_secs(0)));
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    let nested = NESTED.with(|n| n.replace(outer_nested + elapsed));
+    let index = Phase::ALL.iter().position(|p| *p == phase).unwrap();
+    TOTALS.with(|t| t.borrow_mut()[index] += elapsed - nested);
+    result
+}
+
+/// Durations of the phases, and of the rest of the command, given its total duration
+pub fn summary(total: Duration) -> Vec<(&'static str, Duration)> {
+    let totals = TOTALS.with(|t| *t.borrow());
+    let mut result: Vec<_> = Phase::ALL.iter().map(|p| p.name()).zip(totals.iter().cloned()).collect();
+    let measured: Duration = totals.iter().sum();
+    result.insert(3, ("command", total.checked_sub(measured).unwrap_or_default()));
+    result.push(("total", total));
+    result
+}
+
+/// Print the summary on stderr, so that it doesn't mix with the command output
+pub fn report(total: Duration) {
+    for (name, duration) in summary(total) {
+        eprintln!("{:>14}: {:>6} ms", name, duration.as_millis());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{measure, set_enabled, summary, Phase};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn nested_phases() {
+        set_enabled(true);
+        measure(Phase::Sync, || {
+            sleep(Duration::from_millis(20));
+            measure(Phase::Kdf, || sleep(Duration::from_millis(50)));
+        });
+        let s = summary(Duration::from_millis(100));
+        let get = |name| s.iter().find(|(n, _)| *n == name).unwrap().1;
+        assert!(get("kdf") >= Duration::from_millis(50));
+        assert!(get("sync") >= Duration::from_millis(20));
+        assert!(get("sync") < Duration::from_millis(50));
+        assert_eq!(get("total"), Duration::from_millis(100));
+        assert_eq!(get("command"), Duration::from_millis(100) - get("kdf") - get("sync"));
+        set_enabled(false);
+    }
+}
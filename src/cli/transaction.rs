@@ -0,0 +1,111 @@
+//! Multi-step operations that either complete or are rolled back.
+//! Each step can register a compensation undoing it. If a later step fails,
+//! or the user interrupts between steps, compensations run in reverse order.
+//! The whole transaction is a critical section, see `interrupt`.
+
+use tracing::warn;
+
+use super::error::*;
+use super::interrupt;
+
+type Compensation<'a> = Box<dyn FnOnce() -> VResult<()> + 'a>;
+
+pub struct Transaction<'a> {
+    compensations: Vec<Compensation<'a>>,
+    _critical: interrupt::Critical,
+}
+impl<'a> Transaction<'a> {
+    /// Start a transaction, deferring signals until it's dropped
+    pub fn begin() -> Self {
+        Self {
+            compensations: Vec::new(),
+            _critical: interrupt::critical(),
+        }
+    }
+
+    /// Run a step, rolling back the previous ones if it fails
+    pub fn step<T, F>(&mut self, f: F) -> VResult<T>
+    where F: FnOnce() -> VResult<T> {
+        match interrupt::check().and_then(|()| f()) {
+            Ok(value) => Ok(value),
+            Err(e) => Err(self.rollback(e)),
+        }
+    }
+
+    /// Register a compensation for the latest step
+    pub fn on_rollback<F>(&mut self, f: F)
+    where F: FnOnce() -> VResult<()> + 'a {
+        self.compensations.push(Box::new(f));
+    }
+
+    /// Finish successfully, dropping the compensations
+    pub fn commit(mut self) {
+        self.compensations.clear();
+    }
+
+    /// Run compensations, newest first, and return the error to report.
+    /// If a compensation fails, the rest are not attempted,
+    /// as they may depend on it, and its error is reported instead.
+    fn rollback(&mut self, error: Error) -> Error {
+        while let Some(compensation) = self.compensations.pop() {
+            if let Err(e) = compensation() {
+                warn!("Rollback failed after {:?}", error);
+                self.compensations.clear();
+                return e;
+            }
+        }
+        error
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Transaction;
+    use crate::cli::Error;
+    use matches::matches;
+    use std::cell::RefCell;
+
+    #[test]
+    fn rollback_in_reverse() {
+        let log = RefCell::new(Vec::new());
+        let mut t = Transaction::begin();
+        t.step(|| {
+            log.borrow_mut().push("step 1");
+            Ok(())
+        })
+        .unwrap();
+        t.on_rollback(|| {
+            log.borrow_mut().push("undo 1");
+            Ok(())
+        });
+        t.step(|| {
+            log.borrow_mut().push("step 2");
+            Ok(())
+        })
+        .unwrap();
+        t.on_rollback(|| {
+            log.borrow_mut().push("undo 2");
+            Ok(())
+        });
+        let result: Result<(), _> = t.step(|| Err(Error::NotInitialized));
+        assert!(matches!(result, Err(Error::NotInitialized)));
+        assert_eq!(*log.borrow(), vec!["step 1", "step 2", "undo 2", "undo 1"]);
+
+        // Failed compensation is reported, and earlier ones are skipped
+        let log = RefCell::new(Vec::new());
+        let mut t = Transaction::begin();
+        t.on_rollback(|| {
+            log.borrow_mut().push("undo 1");
+            Ok(())
+        });
+        t.on_rollback(|| Err(Error::VaultNotShared));
+        let result: Result<(), _> = t.step(|| Err(Error::NotInitialized));
+        assert!(matches!(result, Err(Error::VaultNotShared)));
+        assert!(log.borrow().is_empty());
+
+        let mut t = Transaction::begin();
+        t.on_rollback(|| unreachable!());
+        t.step(|| Ok(())).unwrap();
+        t.commit();
+    }
+}
@@ -6,10 +6,13 @@ pub enum ValidationError {
     Empty,
     InvalidCharacters,
     InvalidPattern,
+    TooLong,
 }
 
+/// Labels are only meant for a symbol or a short word
+pub const MAX_LABEL_LENGTH: usize = 8;
+
 /// Validate vault name, [a-zA-Z0-9._]+ no adjacent dots
-#[must_use]
 pub fn vault_name(name: &str) -> VResult<()> {
     if name.is_empty() {
         Err(Error::VaultNameInvalid(ValidationError::Empty))
@@ -25,19 +28,39 @@ pub fn vault_name(name: &str) -> VResult<()> {
     }
 }
 
-/// Validate item name, [a-zA-Z0-9/_]+ no adjacent, leading or trailing slashes
-#[must_use]
+/// Validate profile name, with the same rules as vault names
+pub fn profile_name(name: &str) -> VResult<()> {
+    vault_name(name).map_err(|e| match e {
+        Error::VaultNameInvalid(reason) => Error::ProfileNameInvalid(reason),
+        e => e,
+    })
+}
+
+/// Validate item name, [a-zA-Z0-9._/]+ no adjacent dots, no adjacent, leading or trailing slashes
 pub fn item_name(name: &str) -> VResult<()> {
     if name.is_empty() {
         Err(Error::VaultNameInvalid(ValidationError::Empty))
     } else if !name
         .chars()
-        .all(|c| c.is_ascii_alphanumeric() || "_.".contains(c))
+        .all(|c| c.is_ascii_alphanumeric() || "_./".contains(c))
     {
         Err(Error::VaultNameInvalid(ValidationError::InvalidCharacters))
-    } else if name.contains("..") {
+    } else if name.contains("..") || name.split('/').any(str::is_empty) {
         Err(Error::VaultNameInvalid(ValidationError::InvalidPattern))
     } else {
         Ok(())
     }
 }
+
+/// Validate item label, at most `MAX_LABEL_LENGTH` characters without control characters
+pub fn label(label: &str) -> VResult<()> {
+    if label.trim().is_empty() {
+        Err(Error::LabelInvalid(ValidationError::Empty))
+    } else if label.chars().any(char::is_control) {
+        Err(Error::LabelInvalid(ValidationError::InvalidCharacters))
+    } else if label.chars().count() > MAX_LABEL_LENGTH {
+        Err(Error::LabelInvalid(ValidationError::TooLong))
+    } else {
+        Ok(())
+    }
+}
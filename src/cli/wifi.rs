@@ -0,0 +1,284 @@
+//! Sharing Wi-Fi entries as QR codes drawn in the terminal with `vpass wifi qr`,
+//! and importing the networks known to the operating system with `vpass wifi import`.
+//! Networks are read with `nmcli` on Linux, `networksetup` and `security` on macOS,
+//! and `netsh` on Windows. Enterprise networks are skipped, as they have no shared passphrase.
+
+use std::process::Command;
+
+use qrcode::{Color, QrCode};
+
+use super::error::{Error, VResult};
+use super::opt::OptRoot;
+use super::interop::item_name;
+use crate::backend::wifi::{Security, Wifi};
+
+/// Light modules around the code, which scanners need to find it
+const QUIET_ZONE: usize = 2;
+
+/// A network known to the system, with its passphrase if it has one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownNetwork {
+    pub wifi: Wifi,
+    pub passphrase: Option<String>,
+}
+impl KnownNetwork {
+    /// Name of the entry for the network, in the `wifi` folder
+    pub fn entry_name(&self) -> String {
+        format!("wifi/{}", item_name(&self.wifi.ssid.replace('/', "_")))
+    }
+}
+
+/// Draw `text` as a QR code with half blocks, two rows of modules per line.
+/// Light modules are drawn, so that the code shows correctly on dark terminal backgrounds.
+pub fn render_qr(text: &str) -> VResult<String> {
+    let code = QrCode::new(text.as_bytes()).map_err(|_| Error::QrDataTooLong)?;
+    let colors = code.to_colors();
+    let width = code.width();
+    let size = width + 2 * QUIET_ZONE;
+    let light = |x: usize, y: usize| {
+        if y >= size {
+            return false;
+        }
+        let inside = |v: usize| v >= QUIET_ZONE && v < QUIET_ZONE + width;
+        !(inside(x) && inside(y) && colors[(y - QUIET_ZONE) * width + x - QUIET_ZONE] == Color::Dark)
+    };
+    let mut result = String::new();
+    for y in (0..size).step_by(2) {
+        for x in 0..size {
+            result.push(match (light(x, y), light(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+/// Standard output of a command, failing if it exits unsuccessfully
+fn run(program: &str, args: &[&str]) -> VResult<String> {
+    let output = Command::new(program).args(args).output()?;
+    if !output.status.success() {
+        return Err(Error::WifiImportFailed(program.to_owned()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Remove the backslash escapes of terse `nmcli` output
+#[cfg(any(target_os = "linux", test))]
+fn nmcli_unescape(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => result.extend(chars.next()),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Name of a wireless connection from a `NAME:TYPE` line of `nmcli -t`
+#[cfg(any(target_os = "linux", test))]
+fn nmcli_wireless_name(line: &str) -> Option<String> {
+    const SUFFIX: &str = ":802-11-wireless";
+    line.strip_suffix(SUFFIX).map(nmcli_unescape)
+}
+
+/// Network from the values of the fields in `NMCLI_FIELDS`, one per line
+#[cfg(any(target_os = "linux", test))]
+fn nmcli_network(values: &str) -> Option<KnownNetwork> {
+    let values: Vec<String> = values.lines().map(nmcli_unescape).collect();
+    let value = |i: usize| values.get(i).map(String::as_str).unwrap_or("");
+    let (security, passphrase) = match value(2) {
+        "wpa-psk" | "sae" => (Security::Wpa, value(3)),
+        "none" => (Security::Wep, value(4)),
+        "" => (Security::None, ""),
+        // Enterprise networks log in with user accounts instead
+        _ => return None,
+    };
+    Some(KnownNetwork {
+        wifi: Wifi {
+            ssid: value(0).to_owned(),
+            security,
+            hidden: value(1) == "yes",
+        },
+        passphrase: if passphrase.is_empty() { None } else { Some(passphrase.to_owned()) },
+    })
+}
+
+#[cfg(target_os = "linux")]
+const NMCLI_FIELDS: &str = "802-11-wireless.ssid,802-11-wireless.hidden,802-11-wireless-security.key-mgmt,\
+                            802-11-wireless-security.psk,802-11-wireless-security.wep-key0";
+
+/// Networks saved in NetworkManager. Passphrases are shown to the user owning the connection.
+#[cfg(target_os = "linux")]
+pub fn known_networks(_args: &OptRoot) -> VResult<Vec<KnownNetwork>> {
+    let mut result = Vec::new();
+    for name in run("nmcli", &["-t", "-f", "NAME,TYPE", "connection", "show"])?
+        .lines()
+        .filter_map(nmcli_wireless_name)
+    {
+        let values = run("nmcli", &["-s", "-g", NMCLI_FIELDS, "connection", "show", "id", &name])?;
+        result.extend(nmcli_network(&values));
+    }
+    Ok(result)
+}
+
+/// Exit code of `security find-generic-password` when the keychain has no such item
+#[cfg(target_os = "macos")]
+const SECURITY_ITEM_NOT_FOUND: i32 = 44;
+
+/// Preferred networks of the Wi-Fi interface. The keychain asks for permission for each passphrase,
+/// and networks whose passphrase can't be read, e.g. when permission is denied, are skipped.
+#[cfg(target_os = "macos")]
+pub fn known_networks(_args: &OptRoot) -> VResult<Vec<KnownNetwork>> {
+    use tracing::warn;
+
+    let ports = run("networksetup", &["-listallhardwareports"])?;
+    let device = ports
+        .lines()
+        .skip_while(|line| !line.ends_with("Wi-Fi") && !line.ends_with("AirPort"))
+        .find(|line| line.starts_with("Device: "))
+        .map(|line| line["Device: ".len()..].trim().to_owned())
+        .ok_or_else(|| Error::WifiImportFailed("networksetup".to_owned()))?;
+    let mut result = Vec::new();
+    // The first line is a header, and the network names are indented
+    for ssid in run("networksetup", &["-listpreferredwirelessnetworks", &device])?
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+    {
+        let output = Command::new("security")
+            .args(&["find-generic-password", "-D", "AirPort network password", "-w", "-a", ssid])
+            .output()?;
+        let passphrase = match output.status.code() {
+            Some(0) => {
+                let passphrase = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+                Some(passphrase).filter(|p| !p.is_empty())
+            },
+            // Open networks have no passphrase in the keychain
+            Some(SECURITY_ITEM_NOT_FOUND) => None,
+            _ => {
+                warn!("Skipping {}, its passphrase couldn't be read from the keychain", ssid);
+                continue;
+            },
+        };
+        result.push(KnownNetwork {
+            wifi: Wifi {
+                ssid: ssid.to_owned(),
+                security: if passphrase.is_none() { Security::None } else { Security::Wpa },
+                hidden: false,
+            },
+            passphrase,
+        });
+    }
+    Ok(result)
+}
+
+/// Text of the first `<tag>` element in `xml`, without unescaping entities
+#[cfg(any(windows, test))]
+fn xml_text<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let start = xml.find(&format!("<{}>", tag))? + tag.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", tag))?;
+    Some(&xml[start..end])
+}
+
+/// Network from a profile exported by `netsh wlan export profile`
+#[cfg(any(windows, test))]
+fn netsh_network(xml: &str) -> Option<KnownNetwork> {
+    let ssid = xml_text(xml_text(xml, "SSID")?, "name")?;
+    let security = match xml_text(xml, "authentication")? {
+        "open" if xml_text(xml, "encryption") == Some("WEP") => Security::Wep,
+        "open" => Security::None,
+        "shared" => Security::Wep,
+        "WPAPSK" | "WPA2PSK" | "WPA3SAE" => Security::Wpa,
+        _ => return None,
+    };
+    let unescape = |s: &str| {
+        s.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    };
+    Some(KnownNetwork {
+        wifi: Wifi {
+            ssid: unescape(ssid),
+            security,
+            hidden: xml_text(xml, "nonBroadcast") == Some("true"),
+        },
+        passphrase: xml_text(xml, "keyMaterial").map(unescape),
+    })
+}
+
+/// Saved wireless profiles. They are exported with their keys to a temporary directory,
+/// as that output is not localized unlike `netsh wlan show profile`, and removed right after.
+#[cfg(windows)]
+pub fn known_networks(args: &OptRoot) -> VResult<Vec<KnownNetwork>> {
+    use std::fs;
+
+    super::hardening::check_temp_file_allowed(args, "wireless profiles are exported to temporary files")?;
+    let dir = std::env::temp_dir().join(format!("{}-wifi-{}", env!("CARGO_PKG_NAME"), uuid::Uuid::new_v4()));
+    fs::create_dir(&dir)?;
+    let folder = format!("folder={}", dir.display());
+    let result = run("netsh", &["wlan", "export", "profile", "key=clear", &folder]).and_then(|_| {
+        let mut result = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            result.extend(netsh_network(&fs::read_to_string(entry?.path())?));
+        }
+        Ok(result)
+    });
+    fs::remove_dir_all(&dir)?;
+    result
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn known_networks(_args: &OptRoot) -> VResult<Vec<KnownNetwork>> {
+    Err(Error::WifiImportUnsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{netsh_network, nmcli_network, nmcli_wireless_name, render_qr};
+    use crate::backend::wifi::Security;
+
+    #[test]
+    fn qr_rendering() {
+        let qr = render_qr("WIFI:T:WPA;S:home;P:secret;;").unwrap();
+        let lines: Vec<&str> = qr.lines().collect();
+        // Codes have an odd number of modules, and each line has two rows of them
+        assert!(lines.iter().all(|line| line.chars().count() == lines.len() * 2 - 1));
+        assert!(lines[0].chars().all(|c| c == '█'));
+        assert!(lines[lines.len() - 1].chars().all(|c| c == '▀'));
+    }
+
+    #[test]
+    fn system_networks() {
+        assert_eq!(nmcli_wireless_name(r"Cafe\: guest:802-11-wireless").unwrap(), "Cafe: guest");
+        assert_eq!(nmcli_wireless_name("Wired connection 1:802-3-ethernet"), None);
+
+        let network = nmcli_network("home\nno\nwpa-psk\npass\\:word\n\n").unwrap();
+        assert_eq!(network.wifi.ssid, "home");
+        assert_eq!(network.entry_name(), "wifi/home");
+        assert_eq!(network.wifi.security, Security::Wpa);
+        assert_eq!(network.passphrase.unwrap(), "pass:word");
+        assert_eq!(nmcli_network("open\nyes\n\n\n\n").unwrap().passphrase, None);
+        assert_eq!(nmcli_network("work\nno\nwpa-eap\n\n\n"), None);
+
+        let xml = "<WLANProfile><name>home</name><SSIDConfig><SSID><hex>686F6D65</hex><name>home &amp; co</name>\
+                   </SSID><nonBroadcast>true</nonBroadcast></SSIDConfig><MSM><security><authEncryption>\
+                   <authentication>WPA2PSK</authentication><encryption>AES</encryption></authEncryption>\
+                   <sharedKey><keyType>passPhrase</keyType><keyMaterial>secret</keyMaterial></sharedKey>\
+                   </security></MSM></WLANProfile>";
+        let network = netsh_network(xml).unwrap();
+        assert_eq!(network.wifi.ssid, "home & co");
+        assert_eq!(network.entry_name(), "wifi/home___co");
+        assert!(network.wifi.hidden);
+        assert_eq!(network.wifi.security, Security::Wpa);
+        assert_eq!(network.passphrase.unwrap(), "secret");
+    }
+}
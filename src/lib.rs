@@ -1,9 +1,3 @@
-#![feature(trait_alias)]
-#![feature(bind_by_move_pattern_guards)]
-#![feature(vec_remove_item)]
-#![feature(unsized_locals)]
-#![feature(box_patterns)]
-#![feature(box_syntax)]
 #![deny(bare_trait_objects)]
 #![deny(unused_must_use)]
 #![warn(clippy::all)]
@@ -14,50 +8,315 @@ pub mod cli;
 pub mod sync;
 
 use std::fs;
-use std::io;
-use std::path::Path;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
 
-pub use backend::book::{Book, Item, ItemMetadata, Password};
-use backend::vault::{EncryptedVault, Vault};
+use rust_sodium::crypto::hash::sha256;
+use tracing::debug;
+
+pub use backend::book::{
+    Blob, Book, DeviceId, Event, EventFrame, FieldChange, Item, ItemChange, ItemDiff, ItemId, ItemKind,
+    ItemMetadata, Note, OriginId, Password, VersionMergeError,
+};
+pub use backend::card::Card;
+pub use backend::derive::DerivationRule;
+pub use backend::generate;
+pub use backend::shamir;
+pub use backend::strength;
+pub use backend::vault::{EncryptedVault, KdfParams, MemberKey, Recipient, RecoveryKey, SharedKey};
+pub use backend::wifi::{self, Wifi};
+use backend::vault::Vault;
 use cli::error::{Error, VResult};
 
 /// Decrypt vault bytes to a book
 pub fn decrypt(data: &[u8], password: &str) -> VResult<Book> {
-    Ok(EncryptedVault::from_bytes(data)
-        .map_err(|_| Error::VaultCorrupted)?
+    Ok(EncryptedVault::from_bytes(data)?
         .decrypt(password)
         .ok_or(Error::WrongPassword)?
         .content)
 }
 
-/// Encrypt a book to vault bytes
+/// Decrypt a book from a stream, e.g. standard input.
+/// Vaults written with `encrypt_to` are decrypted chunk by chunk, older formats are read to memory first.
+pub fn decrypt_from<R: Read>(reader: R, password: &str) -> VResult<Book> {
+    Ok(EncryptedVault::decrypt_from(reader, password)?
+        .ok_or(Error::WrongPassword)?
+        .content)
+}
+
+/// Recipients for the header of a shared vault or a vault with key slots
+fn recipients(book: &Book) -> Vec<Recipient> {
+    book.shared().map(|s| s.header_recipients()).unwrap_or_default()
+}
+
+/// Encrypt a book to vault bytes.
+/// Shared vaults are encrypted with their content password instead of `password`,
+/// and the vault key is sealed for the recovery key of the book, if any.
 pub fn encrypt(password: &str, book: Book) -> VResult<Vec<u8>> {
-    Ok(Vault::new(book).encrypt(password).to_bytes())
+    let password = book.content_password(password).to_owned();
+    let recipients = recipients(&book);
+    let recovery = book.recovery().cloned();
+    Ok(Vault::new(book)
+        .encrypt(&password)
+        .with_recipients(&recipients)
+        .with_recovery(recovery.as_ref(), &password)
+        .to_bytes())
 }
 
-/// Read an encrypted book from a file
-pub fn read(path: &Path, password: &str) -> VResult<Book> {
-    Ok(EncryptedVault::from_bytes(&fs::read(path)?)
-        .map_err(|_| Error::VaultCorrupted)?
-        .decrypt(password)
-        .ok_or(Error::WrongPassword)?
+/// Encrypt a book to a stream in chunks, without holding the whole encrypted vault in memory.
+/// Writes a newer format version than `encrypt`, which older versions of vpass can't read.
+/// The key is derived with `kdf`, e.g. the limits of the vault the book was read from, or those in the config.
+pub fn encrypt_to<W: Write>(writer: W, password: &str, book: Book, kdf: KdfParams) -> VResult<()> {
+    let password = book.content_password(password).to_owned();
+    let recipients = recipients(&book);
+    let recovery = book.recovery().cloned();
+    Vault::new(book).encrypt_to(writer, &password, kdf, &recipients, recovery.as_ref())?;
+    Ok(())
+}
+
+/// Read an encrypted book from a file with the secret part of its recovery key
+pub fn recover(path: &Path, recovery_secret: &[u8]) -> VResult<Book> {
+    Ok(EncryptedVault::from_bytes(&fs::read(path)?)?
+        .decrypt_recovery(recovery_secret)
+        .ok_or(Error::WrongRecoveryKey)?
         .content)
 }
 
-/// Write a book to an encrypted file
+/// Hashes everything read through it
+struct HashingReader<R> {
+    inner: R,
+    state: sha256::State,
+}
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.state.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Read an encrypted book from a file.
+/// The book remembers the file contents, so that `write` can merge changes written by others since.
+pub fn read(path: &Path, password: &str) -> VResult<Book> {
+    let mut reader = HashingReader {
+        inner: fs::File::open(path)?,
+        state: sha256::State::new(),
+    };
+    let mut book = decrypt_from(&mut reader, password)?;
+    io::copy(&mut reader, &mut io::sink())?;
+    book.set_source(reader.state.finalize().0);
+    Ok(book)
+}
+
+/// Encrypt a book to vault bytes, reusing the salt and key derivation limits of `existing` vault bytes.
+/// An unchanged book then produces identical bytes.
+fn encrypt_reusing(existing: Option<&[u8]>, password: &str, book: Book) -> Vec<u8> {
+    let header = existing
+        .and_then(|data| EncryptedVault::from_bytes(data).ok())
+        .map(|ev| (ev.salt(), ev.kdf()));
+    let password = book.content_password(password).to_owned();
+    let recipients = recipients(&book);
+    let recovery = book.recovery().cloned();
+    match header {
+        Some((salt, kdf)) => Vault::new(book).encrypt_deterministic(&password, salt, kdf),
+        None => Vault::new(book).encrypt(&password),
+    }
+    .with_recipients(&recipients)
+    .with_recovery(recovery.as_ref(), &password)
+    .to_bytes()
+}
+
+/// Write a book to an encrypted file.
+/// If another process, like `vpass daemon`, has written the file since the book was read,
+/// its version is merged with the book, so that neither change is lost.
+/// The salt and key derivation limits of an existing file are reused,
+/// so that an unchanged book produces identical bytes, and the file is not rewritten at all.
 pub fn write(path: &Path, password: &str, book: Book) -> VResult<()> {
-    let encrypted = Vault::new(book).encrypt(password);
-    fs::write(path, encrypted.to_bytes()).map_err(Error::from)
+    let _lock = WriteLock::acquire(path)?;
+    let existing = fs::read(path).ok();
+    let book = match existing {
+        Some(ref data) if book.source().is_some_and(|source| source != sha256::hash(data).0) => {
+            debug!("Vault changed since it was read, merging");
+            book.merge_versions(&decrypt(data, password)?)?
+        },
+        _ => book,
+    };
+    let encrypted = encrypt_reusing(existing.as_deref(), password, book);
+    if existing.as_ref() == Some(&encrypted) {
+        return Ok(());
+    }
+    replace_file(path, &encrypted)
+}
+
+/// Lock file of a vault, held while the vault file is written. Removed when dropped.
+struct WriteLock {
+    path: PathBuf,
+}
+impl WriteLock {
+    /// Lock files older than this were left behind by a crashed process
+    const STALE_SECONDS: u64 = 60;
+    const RETRIES: usize = 100;
+
+    /// Wait until no other process is writing the vault
+    fn acquire(vault: &Path) -> VResult<Self> {
+        let path = sibling_path(vault, ".lock");
+        for _ in 0..Self::RETRIES {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(WriteLock { path }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| t.elapsed().ok())
+                        .is_some_and(|age| age.as_secs() > Self::STALE_SECONDS);
+                    if stale {
+                        let _ = fs::remove_file(&path);
+                    } else {
+                        thread::sleep(Duration::from_millis(100));
+                    }
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(Error::VaultLocked(path))
+    }
+}
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Path next to `path`, with `suffix` appended to the file name
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Replace a vault file while holding its lock, see `replace_file`
+fn write_atomic(path: &Path, data: &[u8]) -> VResult<()> {
+    let _lock = WriteLock::acquire(path)?;
+    replace_file(path, data)
+}
+
+/// Replace a vault file, so that it's never left partially written:
+/// the data is written to a temporary file next to it, which is then renamed over it.
+/// Permissions of an existing file are kept. The caller holds the lock of the vault.
+fn replace_file(path: &Path, data: &[u8]) -> VResult<()> {
+    let tmp = sibling_path(path, ".tmp");
+    let result = (|| -> io::Result<()> {
+        let mut file = fs::File::create(&tmp)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(data)?;
+        file.sync_all()?;
+        fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.map_err(Error::from)
+}
+
+/// Write a book to an encrypted stream, e.g. standard output.
+/// The salt and key derivation limits of the vault bytes it was read from are reused, if given.
+pub fn write_to<W: Write>(mut writer: W, password: &str, book: Book, existing: Option<&[u8]>) -> VResult<()> {
+    writer.write_all(&encrypt_reusing(existing, password, book))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write a book to an encrypted file with a new salt.
+/// Key derivation limits of an existing file are kept if `kdf` is not given.
+pub fn write_new_key(path: &Path, password: &str, book: Book, kdf: Option<KdfParams>) -> VResult<()> {
+    let kdf = kdf.unwrap_or_else(|| {
+        fs::read(path)
+            .ok()
+            .and_then(|data| EncryptedVault::from_bytes(&data).ok())
+            .map(|ev| ev.kdf())
+            .unwrap_or_default()
+    });
+    let password = book.content_password(password).to_owned();
+    let recipients = recipients(&book);
+    let recovery = book.recovery().cloned();
+    write_atomic(
+        path,
+        &Vault::new(book)
+            .encrypt_with(&password, kdf)
+            .with_recipients(&recipients)
+            .with_recovery(recovery.as_ref(), &password)
+            .to_bytes(),
+    )
+}
+
+/// Write a book to an encrypted file, only if it has been changed since it was read
+pub fn write_changes(path: &Path, password: &str, book: Book) -> VResult<()> {
+    if book.is_dirty() {
+        write(path, password, book)
+    } else {
+        Ok(())
+    }
 }
 
 /// Creates a new, empty vault to given path
 pub fn create(path: &Path, password: &str) -> VResult<()> {
+    create_with_kdf(path, password, KdfParams::default())
+}
+
+/// Creates a new, empty vault to given path, with given key derivation limits
+pub fn create_with_kdf(path: &Path, password: &str, kdf: KdfParams) -> VResult<()> {
+    create_from(path, password, Book::new(), kdf)
+}
+
+/// Creates a new vault to given path from a book, with given key derivation limits
+pub fn create_from(path: &Path, password: &str, book: Book, kdf: KdfParams) -> VResult<()> {
     if path.exists() {
         Err(Error::from(io::Error::new(
             io::ErrorKind::AlreadyExists,
             "Vault already exists",
         )))
     } else {
-        write(path, password, Book::new())
+        write_new_key(path, password, book, Some(kdf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+    use tempfile::tempdir;
+
+    #[test]
+    fn concurrent_writers_merged() {
+        rust_sodium::init().expect("Sodium init failed");
+        let td = tempdir().unwrap();
+        let path = td.path().join("test.vpass_vault");
+        create(&path, "pw").unwrap();
+
+        // Both writers read the same version before either writes
+        let barrier = Arc::new(Barrier::new(2));
+        let writers: Vec<_> = ["first", "second"]
+            .iter()
+            .map(|name| {
+                let (path, barrier) = (path.clone(), barrier.clone());
+                thread::spawn(move || {
+                    let mut book = read(&path, "pw").unwrap();
+                    book.add(Item::new(name)).unwrap();
+                    barrier.wait();
+                    write_changes(&path, "pw", book).unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let book = read(&path, "pw").unwrap();
+        assert!(book.has_item("first"));
+        assert!(book.has_item("second"));
     }
 }
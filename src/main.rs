@@ -1,19 +1,28 @@
 #![deny(unused_must_use)]
 
-use vpass::{self, cli::*, Password};
+use vpass::{self, cli::logging::Redacted, cli::*, ItemKind, Password};
 
+use matches::matches;
 use serde_json::json;
-use std::collections::HashSet;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
-#[must_use]
 fn prompt_password(prompt: &str) -> VResult<String> {
+    interactive::require_input(prompt)?;
+    let prompt = if interactive::is_plain() {
+        interactive::terminated(prompt)
+    } else {
+        prompt.to_owned()
+    };
     loop {
-        let pass = rpassword::read_password_from_tty(Some(prompt))?;
-        println!();
-        if pass != "" {
+        let pass = console::read_password(&prompt)?;
+        // Not on stdout, which may be captured or carry a piped vault
+        eprintln!();
+        if !pass.is_empty() {
             return Ok(pass);
         }
     }
@@ -90,7 +99,7 @@ impl Vaults {
 
 fn get_vault_path(args: &opt::OptRoot) -> VResult<PathBuf> {
     if let Some(p) = args.vault_file.clone() {
-        if p.is_file() {
+        if p.is_file() || stdio::is_stdio(&p) {
             Ok(p)
         } else {
             Err(Error::FileRequired(p))
@@ -109,19 +118,449 @@ fn get_vault_path(args: &opt::OptRoot) -> VResult<PathBuf> {
     }
 }
 
+/// Read a book for item operations, applying item name matching settings
+/// and the device id from the config.
+/// Synchronization settings stored as an item by older versions are moved to the book metadata.
+fn read_book(args: &opt::OptRoot, path: &Path, password: &str) -> VResult<vpass::Book> {
+    let mut book = attempts::read(args, path, password)?;
+    book.set_case_insensitive(cfg::read(args)?.case_insensitive_names);
+    if let Some(device) = cfg::device_id(args)? {
+        book.set_device(device);
+    }
+    vpass::sync::config::book_migrate(&mut book)?;
+    name_cache::update(args, path, &book)?;
+    Ok(book)
+}
+
+/// Write a book if it has changed, updating the name cache
+fn write_changes(args: &opt::OptRoot, path: &Path, password: &str, book: vpass::Book) -> VResult<()> {
+    if book.is_dirty() && book.device().is_none() {
+        cfg::ensure_device_id(args)?;
+    }
+    if stdio::is_stdio(path) {
+        return stdio::write_changes(password, book);
+    }
+    // The cache is written after the vault, so a failed write doesn't leave names of unsaved items
+    vpass::write_changes(path, password, book.clone())?;
+    name_cache::update(args, path, &book)
+}
+
+/// Vault password given as an argument, environment variable or file, if any
+fn given_password(args: &opt::OptRoot) -> VResult<Option<String>> {
+    match args.password_source() {
+        Some(opt::PasswordSource::Value(pw)) => Ok(Some(pw.into_inner())),
+        Some(opt::PasswordSource::File(path)) => {
+            let content = fs::read_to_string(path)?;
+            Ok(Some(content.lines().next().unwrap_or("").to_owned()))
+        },
+        Some(opt::PasswordSource::Credential(path)) => Ok(Some(credential::unseal(&path)?)),
+        Some(opt::PasswordSource::Stdin) => Ok(Some(interactive::stdin_password()?)),
+        None => Ok(None),
+    }
+}
+
+/// Passphrase for a protected item
+fn protect_passphrase(args: &opt::OptRoot, name: &str) -> VResult<String> {
+    match args.protect_password {
+        Some(ref passphrase) => Ok(passphrase.as_str().to_owned()),
+        None => prompt_password(&i18n::trf("Passphrase [{}]:", &[&name])),
+    }
+}
+
+/// Random password with a preset from the config, or with the default characters and `length`
+fn generate_password(args: &opt::OptRoot, preset: &Option<String>, length: usize) -> VResult<Password> {
+    match preset {
+        Some(name) => cfg::preset(args, name)?,
+        None => vpass::generate::Preset {
+            length,
+            ..Default::default()
+        },
+    }
+    .generate()
+}
+
+/// Average time to crack a password offline, in the largest whole unit
+fn crack_time_text(seconds: f64) -> String {
+    const UNITS: [(f64, &str); 4] = [
+        (365.25 * 24.0 * 3600.0, "{} years"),
+        (24.0 * 3600.0, "{} days"),
+        (3600.0, "{} hours"),
+        (60.0, "{} minutes"),
+    ];
+    if seconds >= 1000.0 * UNITS[0].0 {
+        return i18n::tr("centuries").to_owned();
+    }
+    for &(unit, text) in &UNITS {
+        if seconds >= unit {
+            return i18n::trf(text, &[&((seconds / unit) as u64)]);
+        }
+    }
+    if seconds >= 1.0 {
+        i18n::trf("{} seconds", &[&(seconds as u64)])
+    } else {
+        i18n::tr("less than a second").to_owned()
+    }
+}
+
+/// Show the estimated strength of a password entered by hand, and warn if it's weak
+fn check_strength(args: &opt::OptRoot, password: &str) -> VResult<()> {
+    if args.quiet {
+        return Ok(());
+    }
+    let bits = vpass::strength::entropy_bits(password);
+    let crack_time = crack_time_text(vpass::strength::crack_seconds(bits));
+    let text = "Estimated entropy: {} bits, cracked offline in {}";
+    eprintln!("{}", i18n::trf(text, &[&format!("{:.0}", bits), &crack_time]));
+    let limit = cfg::read(args)?.weak_password_bits;
+    if bits < f64::from(limit) {
+        eprintln!("{}", i18n::trf("Warning: weak password, less than {} bits of entropy", &[&limit]));
+    }
+    Ok(())
+}
+
+/// Entries for launchers and menus, recently used first as they keep the order
+fn launcher_items(book: &vpass::Book, tags: &[String]) -> Vec<vpass::Item> {
+    let mut items = book.items_metadata();
+    items.retain(|(item, _)| !item.archived);
+    items.retain(|(item, _)| tags.iter().all(|t| item.tags.contains(t)));
+    listing::sort(&mut items, listing::SortKey::LastUsed, true);
+    items.into_iter().map(|(item, _)| item).collect()
+}
+
+/// Wait for `clipboard_clear_seconds` from the config if set, and clear `secret` from the clipboard
+fn clear_clipboard_later(args: &opt::OptRoot, secret: &str) -> VResult<()> {
+    let clear_seconds = cfg::read(args)?.clipboard_clear_seconds;
+    if clear_seconds > 0 {
+        if !args.quiet {
+            eprintln!("{}", i18n::trf("Clearing the clipboard in {} seconds", &[&clear_seconds]));
+        }
+        std::thread::sleep(std::time::Duration::from_secs(clear_seconds));
+        clipboard::clear(secret)?;
+        notify::send(args, "vpass", i18n::tr("Clipboard cleared"));
+    }
+    Ok(())
+}
+
+thread_local! {
+    /// Vaults whose secrets have been revealed by this command after confirming presence
+    static PRESENCE_CONFIRMED: RefCell<HashSet<PathBuf>> = RefCell::new(HashSet::new());
+}
+
+/// Ask for Touch ID or Windows Hello before revealing secrets of a vault in `biometric_vaults`.
+/// Asked once for each vault, even if the command reveals several secrets.
+fn confirm_presence(args: &opt::OptRoot, path: &Path) -> VResult<()> {
+    if PRESENCE_CONFIRMED.with(|c| c.borrow().contains(path)) {
+        return Ok(());
+    }
+    for name in cfg::read(args)?.biometric_vaults {
+        if vault_path(args, &name)? == path {
+            presence::confirm(&i18n::trf("reveal secrets of {}", &[&name]))?;
+            PRESENCE_CONFIRMED.with(|c| c.borrow_mut().insert(path.to_owned()));
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+/// Passphrase of an entry shared with `publish`
+fn publish_passphrase(given: &Option<Redacted<String>>) -> VResult<String> {
+    match given {
+        Some(passphrase) => Ok(passphrase.as_str().to_owned()),
+        None => prompt_password(i18n::tr("Passphrase [published entry]:")),
+    }
+}
+
+/// Password of an item, stored, derived from the vault password or protected by a passphrase
+fn reveal_password(
+    args: &opt::OptRoot, path: &Path, item: &vpass::Item, vault_password: &str,
+) -> VResult<Option<Password>> {
+    confirm_presence(args, path)?;
+    if item.protected.is_some() {
+        item.unlock(&protect_passphrase(args, &item.name)?)
+    } else {
+        Ok(item.password_for(vault_password))
+    }
+}
+
+/// Value of a field of an item, as used by `get` and templates.
+/// Revealing the password is recorded as an access.
+fn field_value(
+    args: &opt::OptRoot, path: &Path, book: &mut vpass::Book, name: &str, field: template::Field,
+    vault_password: &str,
+) -> VResult<String> {
+    let item = book.get_item_by_name(name)?;
+    if field != template::Field::Tags {
+        confirm_presence(args, path)?;
+    }
+    Ok(match field {
+        template::Field::Password => {
+            let password =
+                reveal_password(args, path, &item, vault_password)?.ok_or(Error::ItemNoPasswordSet)?;
+            book.record_access(name)?;
+            password.plaintext()
+        },
+        template::Field::Notes => item.notes.iter().map(|n| n.text.as_str()).collect::<Vec<_>>().join("\n"),
+        template::Field::Tags => {
+            let mut tags: Vec<String> = item.tags.into_iter().collect();
+            tags.sort();
+            tags.join(",")
+        },
+        template::Field::Note(id) => item.note(id)?.text.clone(),
+        template::Field::Data => {
+            let data = item.data.as_ref().ok_or_else(|| Error::ItemNoContent(name.to_owned()))?;
+            let text = data.text().ok_or_else(|| Error::ItemDataBinary(name.to_owned()))?.to_owned();
+            book.record_access(name)?;
+            text
+        },
+    })
+}
+
+/// What `copy` copies of an item: the document or notes of a note, the key of an ssh-key,
+/// the number of a card, the file of kinds that store one, and the password otherwise
+fn secret_text(
+    args: &opt::OptRoot, path: &Path, item: &vpass::Item, vault_password: &str,
+) -> VResult<String> {
+    confirm_presence(args, path)?;
+    let missing = || Error::ItemNoContent(item.name.clone());
+    match item.kind {
+        ItemKind::Note if item.data.is_some() => document_text(item),
+        ItemKind::Note if item.notes.is_empty() => Err(missing()),
+        ItemKind::Note => Ok(item.notes.iter().map(|n| n.text.as_str()).collect::<Vec<_>>().join("\n")),
+        ItemKind::SshKey => {
+            let key = item.ssh_key.as_ref().ok_or_else(|| Error::ItemNoSshKey(item.name.clone()))?;
+            Ok(key.plaintext())
+        },
+        ItemKind::Card => {
+            let card = item.card.as_ref().ok_or_else(|| Error::ItemNoCard(item.name.clone()))?;
+            Ok(card.number.clone())
+        },
+        kind if kind.has_data() => {
+            let data = item.data.as_ref().ok_or_else(missing)?;
+            data.text()
+                .map(str::to_owned)
+                .ok_or_else(|| Error::ItemDataBinary(item.name.clone()))
+        },
+        _ => Ok(reveal_password(args, path, item, vault_password)?
+            .ok_or(Error::ItemNoPasswordSet)?
+            .plaintext()),
+    }
+}
+
+/// Document of a note entry, empty if it only has notes
+fn document_text(item: &vpass::Item) -> VResult<String> {
+    if item.kind != ItemKind::Note {
+        return Err(Error::ItemNotDocument(item.name.clone()));
+    }
+    match item.data {
+        Some(ref data) => Ok(data.text().ok_or_else(|| Error::ItemDataBinary(item.name.clone()))?.to_owned()),
+        None => Ok(String::new()),
+    }
+}
+
+/// Store the contents of `path` as the file of the item, or as the key of an ssh-key item
+fn store_file(item: &mut vpass::Item, path: &Path) -> VResult<()> {
+    match item.kind {
+        ItemKind::SshKey => item.ssh_key = Some(Password::new(&fs::read_to_string(path)?)),
+        ItemKind::Note => item.data = Some(vpass::Blob::new(fs::read_to_string(path)?.into_bytes())),
+        kind if kind.has_data() => item.data = Some(vpass::Blob::new(fs::read(path)?)),
+        kind => return Err(Error::KindHasNoFile(kind)),
+    }
+    Ok(())
+}
+
+/// Write a file readable only by the owner
+fn write_private(path: &Path, content: &str) -> VResult<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+        // An existing file keeps its mode when opened, so restrict it before writing
+        if path.exists() {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+        }
+    }
+    options.open(path)?.write_all(content.as_bytes())?;
+    Ok(())
+}
+
+/// Decode a transfer string, asking for the passphrase if it's protected
+fn decode_transfer_string(s: &str) -> VResult<vpass::sync::config::SyncConfig> {
+    if vpass::sync::transfer_string::is_protected(s) {
+        let passphrase = prompt_password(i18n::tr("Passphrase [transfer string]:"))?;
+        vpass::sync::transfer_string::decode_protected(s, &passphrase)
+    } else {
+        vpass::sync::transfer_string::decode(s)
+    }
+}
+
+/// Variables configuring vpass itself, which are not passed on to commands run by `exec`
+const EXEC_REMOVED_VARS: &[&str] = &[
+    "VPASS_PASSWORD",
+    "VPASS_PASSWORD_FILE",
+    "VPASS_PASSWORD_CREDENTIAL",
+    "VPASS_CONFIG_PASSWORD",
+    "VPASS_PROTECT_PASSWORD",
+];
+
+/// Environment variable for a field of an item in `exec`, e.g. `VPASS_MY_SITE_PASSWORD`
+fn exec_env_var(name: &str, field: &str) -> String {
+    let upper = |s: &str| -> String {
+        s.chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+            .collect()
+    };
+    format!("VPASS_{}_{}", upper(name), upper(field))
+}
+
+/// Item change as shown by `diff`, `sync status --changes` and `sync resolve-conflicts`.
+/// Added and removed items are listed by name only, to avoid printing passwords.
+fn change_json(change: &vpass::ItemChange) -> serde_json::Value {
+    match change {
+        vpass::ItemChange::Added(item) => json!({"added": item.name}),
+        vpass::ItemChange::Removed(item) => json!({"removed": item.name}),
+        vpass::ItemChange::Modified(diff) => json!({"modified": diff}),
+    }
+}
+
+fn change_line(change: &vpass::ItemChange) -> String {
+    match change {
+        vpass::ItemChange::Added(item) => format!("+ {}", item.name),
+        vpass::ItemChange::Removed(item) => format!("- {}", item.name),
+        vpass::ItemChange::Modified(diff) => format!(
+            "~ {}: {}",
+            diff.name,
+            diff.changes.iter().map(|fc| fc.to_string()).collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
 fn vault_filename(name: &str) -> String {
     debug_assert!(!name.ends_with(".vpass_vault")); // Sanity check
     format!("{}.vpass_vault", name)
 }
 
 fn vault_path(args: &opt::OptRoot, name: &str) -> VResult<PathBuf> {
-    Ok(paths::data_dir(&args)?.join(vault_filename(name)))
+    Ok(paths::data_dir(args)?.join(vault_filename(name)))
+}
+
+/// Key derivation limits for new member keys and key slots: those from the config, or else the ones of the
+/// vault file, so that an added password isn't cheaper to guess than the vault password
+fn member_kdf(args: &opt::OptRoot, p: &Path) -> VResult<vpass::KdfParams> {
+    match cfg::read(args)?.kdf {
+        Some(kdf) => Ok(kdf),
+        None => Ok(vpass::EncryptedVault::from_bytes(&fs::read(p)?)?.kdf()),
+    }
+}
+
+/// Write a book whose key has changed, overwriting the remote copy first.
+/// If the local file can't be written, or the user interrupts, the remote is rolled back to `old_book`.
+fn write_rekeyed(
+    args: &opt::OptRoot, name: &str, password: &str, book: vpass::Book, old_book: &vpass::Book,
+) -> VResult<()> {
+    let filename = vault_filename(name);
+    let p = vault_path(args, name)?;
+    let mut t = transaction::Transaction::begin();
+    t.step(|| vpass::sync::vault_overwrite(&filename, &book, password))?;
+    t.on_rollback(|| vpass::sync::vault_overwrite(&filename, old_book, password));
+    t.step(|| vpass::write_new_key(&p, password, book, None))?;
+    t.commit();
+    // Old keys can still decrypt the cached snapshot
+    sync_state::remove(&p)
+}
+
+/// One synchronization of a vault by `vpass daemon`.
+/// Returns the number of conflict copies on the remote, or `None` if the vault is not synchronized.
+fn daemon_sync(args: &opt::OptRoot, name: &str, password: &str) -> VResult<Option<usize>> {
+    let p = vault_path(args, name)?;
+    let mut book = read_book(args, &p, password)?;
+    if vpass::sync::config::book_read(&book)?.is_none() {
+        return Ok(None);
+    }
+    let key = vault_filename(name);
+    if let Some(snapshot) = sync_state::snapshot(&p)? {
+        vpass::sync::cache_snapshot(&key, snapshot);
+    }
+    vpass::sync::vault(&key, &mut book, password)?;
+    let state = vpass::sync::remote_state(&key, &book)?;
+    sync_state::update(&p, state, vpass::sync::cached_snapshot(&key))?;
+    let conflicts = vpass::sync::conflict_copies(&key, &book)?.len();
+    write_changes(args, &p, password, book)?;
+    Ok(Some(conflicts))
 }
 
 fn main() -> VResult<()> {
-    pretty_env_logger::init();
     rust_sodium::init().expect("Sodium init failed");
-    run_command(opt::OptRoot::from_args())
+    let args = opt::OptRoot::from_args();
+    logging::init(args.log_format, args.verbose);
+    hardening::apply(&args)?;
+    interrupt::install();
+    i18n::init(&args);
+    interactive::set_plain(args.plain);
+    interactive::set_no_input(args.no_input);
+    if !args.quiet {
+        progress::install(args.plain);
+    }
+    timings::set_enabled(args.timings);
+    let start = std::time::Instant::now();
+    let command: &'static str = (&args.subcommand).into();
+    let result = tracing::info_span!("command", name = command).in_scope(|| run_command(args.clone()));
+    if args.timings {
+        timings::report(start.elapsed());
+    }
+    vpass::sync::progress::report(vpass::sync::progress::Progress::Done);
+    // Log commands are not recorded, so that clearing leaves the log empty,
+    // and neither are completions, which run on every key press
+    if !matches!(
+        args.subcommand,
+        opt::SubCommand::Init | opt::SubCommand::Log(_) | opt::SubCommand::Complete(_)
+    ) {
+        if let Err(e) = audit_log::record(&args, result.is_ok()) {
+            eprintln!("Unable to write audit log: {:?}", e);
+        }
+    }
+    cfg::forget_password();
+    interactive::forget_stdin_password();
+    if let Err(Error::Interrupted) = result {
+        eprintln!("{}", i18n::tr("Interrupted, changes were rolled back"));
+        std::process::exit(interrupt::EXIT_CODE);
+    }
+    if let Err(Error::Sync(vpass::sync::Error::ApiRateLimit(when))) = result {
+        eprintln!(
+            "{}",
+            i18n::trf(
+                "Synchronization provider rate limit reached, retry at {}",
+                &[&when.with_timezone(&chrono::Local).format("%H:%M")]
+            )
+        );
+        std::process::exit(1);
+    }
+    if let Err(Error::ExecFailed(code)) = result {
+        // Exit silently with the same code as the command
+        std::process::exit(code.unwrap_or(1));
+    }
+    if let Err(Error::DoesNotExist) = result {
+        std::process::exit(1);
+    }
+    // Other errors of `exists` must not be mistaken for a missing entry or vault
+    let exists_command = match args.subcommand {
+        opt::SubCommand::Exists(_) => true,
+        opt::SubCommand::Vault(ref c) => matches!(c.subcommand, opt::VaultSubCommand::Exists(_)),
+        _ => false,
+    };
+    if let (true, Err(e)) = (exists_command, &result) {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(2);
+    }
+    if let Err(Error::BookVersionMergeError(vpass::VersionMergeError::DifferentOrigins)) = result {
+        eprintln!(
+            "{}",
+            i18n::tr("Remote vault is not a copy of this vault, use `sync --force-adopt` to link them")
+        );
+        std::process::exit(1);
+    }
+    result
 }
 
 fn run_command(args: opt::OptRoot) -> VResult<()> {
@@ -130,44 +569,98 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
 
     macro_rules! prompt_vault_password {
         () => {
-            args.password
-                .clone()
-                .unwrap_or_else(|| prompt_password("Password [vault]:").expect("Unable to read password"))
+            match given_password(&args)? {
+                Some(pw) => pw,
+                None => match cfg::command_password(&args)? {
+                    Some(pw) => pw,
+                    None => prompt_password(i18n::tr("Password [vault]:"))?,
+                },
+            }
         };
     }
 
-    if args.subcommand != SubCommand::Init
-        && !(paths::data_dir(&args)?.is_dir() && paths::config_file(&args)?.exists())
+    if !matches!(args.subcommand, SubCommand::Init | SubCommand::Derive(_) | SubCommand::Profile(_))
+        && (!paths::data_dir(&args)?.is_dir() || !paths::config_file(&args)?.exists())
     {
         return Err(Error::NotInitialized);
     }
 
+    // With `--file -` a changed vault is written to standard output,
+    // so other output of the commands changing it goes to standard error
+    let vault_piped = args.vault_file.as_ref().is_some_and(|p| stdio::is_stdio(p));
+    macro_rules! outln {
+        ($($arg:tt)*) => {
+            if vault_piped {
+                eprintln!($($arg)*)
+            } else {
+                println!($($arg)*)
+            }
+        };
+    }
+
+    if vault_piped {
+        if args.password_stdin {
+            return Err(Error::StdinConflict);
+        }
+        // Synchronization state is kept next to the vault file
+        if matches!(args.subcommand, SubCommand::Sync(_)) {
+            return Err(Error::StdioUnsupported);
+        }
+    }
+
     match args.subcommand {
         SubCommand::Init => {
             fs::create_dir_all(&paths::data_dir(&args)?)?;
-            cfg::write(&args, cfg::Config::default())?;
+            cfg::write(&args, cfg::Config::new_device())?;
             if !args.quiet {
-                println!("Initialization complete");
+                println!("{}", i18n::tr("Initialization complete"));
             }
         },
         SubCommand::Vault(ref sc) => match sc.subcommand {
             VaultSubCommand::Create(ref c) => {
                 validate::vault_name(&c.name)?;
-                let pw = c.password.clone().unwrap_or_else(|| {
-                    prompt_password("New password for vault:").expect("Unable to read password")
-                });
+                let given = c.password.clone().map(Redacted::into_inner);
+                let pw = match given.map_or_else(|| given_password(&args), |pw| Ok(Some(pw)))? {
+                    Some(pw) => pw,
+                    None => prompt_password(i18n::tr("New password for vault:"))?,
+                };
                 let p = vault_path(&args, &c.name)?;
-                vpass::create(&p, &pw)?;
+                let mut book = vpass::Book::new();
+                if let Some(ref description) = c.description {
+                    book.set_description(Some(description));
+                }
+                vpass::create_from(&p, &pw, book, cfg::read(&args)?.kdf.unwrap_or_default())?;
             },
             VaultSubCommand::Import(ref c) => {
                 validate::vault_name(&c.name)?;
                 let p = vault_path(&args, &c.name)?;
                 let pw = prompt_vault_password!();
-                let transfer_options = sync::transfer_string::decode(&c.import_string)?;
+                let transfer_options = decode_transfer_string(&c.import_string)?;
                 // TODO: non-default locations?
                 let name = p.file_name().unwrap().to_str().unwrap();
                 let book = vpass::sync::download_book(name, transfer_options, &pw)?;
-                vpass::write(&p, &pw, book)?;
+                vpass::write(&p, &pw, book.clone())?;
+                name_cache::update(&args, &p, &book)?;
+            },
+            VaultSubCommand::Clone(ref c) => {
+                validate::vault_name(&c.name)?;
+                Vaults::new(&args)?.verify_not_exists(&c.name)?;
+                let from_pw = prompt_vault_password!();
+                let from = attempts::read(&args, &get_vault_path(&args)?, &from_pw)?;
+                let mut config = vpass::sync::config::book_read(&from)?.ok_or(Error::SyncNotSetUp)?;
+                // The remote key and sync password of the selected vault don't apply to other vaults
+                config.remote_key = None;
+                config.password = None;
+
+                // A password given as an argument or in the environment is used for both vaults
+                let pw = match given_password(&args)? {
+                    Some(pw) => pw,
+                    None => prompt_password(&i18n::trf("Password [{}]:", &[&c.name]))?,
+                };
+                let p = vault_path(&args, &c.name)?;
+                let book = vpass::sync::download_book(&vault_filename(&c.name), config, &pw)?;
+                vpass::write(&p, &pw, book.clone())?;
+                name_cache::update(&args, &p, &book)?;
             },
             VaultSubCommand::Rename(ref c) => {
                 validate::vault_name(&c.new_name)?;
@@ -179,24 +672,26 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
                 let old_p = vault_path(&args, &c.old_name)?;
                 let new_p = vault_path(&args, &c.new_name)?;
                 let pw = prompt_vault_password!();
-                let book = vpass::read(&old_p, &pw)?;
+                let book = attempts::read(&args, &old_p, &pw)?;
                 vpass::sync::check_rename(&vault_filename(&c.new_name), &book)?;
+                if !c.remote_keep_old {
+                    vpass::sync::check_delete(&book)?;
+                }
 
+                let new_filename = vault_filename(&c.new_name);
+                let mut t = transaction::Transaction::begin();
                 // Push the new vault to remote
-                vpass::sync::create(&vault_filename(&c.new_name), &book, &pw)?;
+                t.step(|| vpass::sync::create(&new_filename, &book, &pw))?;
+                t.on_rollback(|| vpass::sync::vault_delete(&new_filename, &book));
                 // Rename local vault
-                match fs::rename(&old_p, &new_p) {
-                    Ok(()) => {
-                        // Local file renamed, remove old file from remote
-                        if !c.remote_keep_old {
-                            vpass::sync::vault_delete(&vault_filename(&c.old_name), &book)?;
-                        }
-                    },
-                    Err(e) => {
-                        // Could not rename local file: Roll back remote changes
-                        vpass::sync::vault_delete(&vault_filename(&c.new_name), &book)?;
-                        return Err(e.into());
-                    },
+                t.step(|| Ok(fs::rename(&old_p, &new_p)?))?;
+                t.commit();
+
+                name_cache::remove(&old_p)?;
+                sync_state::remove(&old_p)?;
+                // Local file renamed, move old file to trash on remote
+                if !c.remote_keep_old {
+                    vpass::sync::vault_trash(&vault_filename(&c.old_name), &book)?;
                 }
             },
             VaultSubCommand::Delete(ref c) => {
@@ -204,16 +699,19 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
                 vaults.verify_exists(&c.name)?;
                 let p = vault_path(&args, &c.name)?;
                 if !c.force {
-                    println!("Confirm vault deletion:");
+                    println!("{}", i18n::tr("Confirm vault deletion:"));
                     let pw = prompt_vault_password!();
-                    let book = vpass::read(&p, &pw)?;
+                    let book = attempts::read(&args, &p, &pw)?;
                     if c.remote {
-                        // Delete remote first, as if there are errors,
+                        // Trash remote first, as if there are errors,
                         // retry isn't possible withtout a local copy
-                        vpass::sync::vault_delete(&vault_filename(&c.name), &book)?;
+                        vpass::sync::check_delete(&book)?;
+                        vpass::sync::vault_trash(&vault_filename(&c.name), &book)?;
                     }
                 }
-                fs::remove_file(&p).unwrap();
+                fs::remove_file(&p)?;
+                name_cache::remove(&p)?;
+                sync_state::remove(&p)?;
             },
             VaultSubCommand::Copy(ref c) => {
                 validate::vault_name(&c.new_name)?;
@@ -225,47 +723,201 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
                 let old_p = vault_path(&args, &c.old_name)?;
                 let new_p = vault_path(&args, &c.new_name)?;
 
+                let mut t = transaction::Transaction::begin();
                 // Copy the file
-                fs::copy(&old_p, &new_p)?;
+                t.step(|| Ok(fs::copy(&old_p, &new_p)?))?;
+                t.on_rollback(|| Ok(fs::remove_file(&new_p)?));
 
                 // Run detach command on the new file
                 let mut args_inner = args.clone();
                 args_inner.vault_file = Some(new_p.clone());
                 args_inner.subcommand = SubCommand::Sync(OptSync {
+                    force_adopt: false,
                     subcommand: Some(SyncSubCommand::Detach),
                 });
-                match run_command(args_inner) {
-                    Ok(()) => {},
-                    Err(e) => {
-                        // Could not detach: remove the new copy
-                        fs::remove_file(&new_p)?;
-                        return Err(e);
-                    },
-                }
+                t.step(|| run_command(args_inner))?;
+                t.commit();
             },
             VaultSubCommand::ChangePassword(ref c) => {
                 let vaults = Vaults::new(&args)?;
                 vaults.verify_exists(&c.name)?;
                 let p = vault_path(&args, &c.name)?;
                 let old_pw = prompt_vault_password!();
-                let book = vpass::read(&p, &old_pw)?;
+                let mut book = attempts::read(&args, &p, &old_pw)?;
                 let new_pw = if let Some(ref x) = c.password {
-                    x.clone()
+                    x.as_str().to_owned()
                 } else {
-                    prompt_password("New password [vault]:")?
+                    prompt_password(i18n::tr("New password [vault]:"))?
                 };
 
+                // Derived passwords would change with the vault password, so keep the current ones
+                let stored = book.store_derived_passwords(&old_pw)?;
+                if stored > 0 && !args.quiet {
+                    println!(
+                        "{}",
+                        i18n::trf("Derived passwords of {} entries are now stored in the vault", &[&stored])
+                    );
+                }
+
+                // In a shared vault, only the member key of this user changes
+                let old_book = book.clone();
+                if let Some(mut shared) = book.shared().cloned() {
+                    shared.change_member_password(&old_pw, &new_pw, member_kdf(&args, &p)?);
+                    book.set_shared(shared);
+                }
+
+                let kdf = cfg::read(&args)?.kdf;
+                let filename = vault_filename(&c.name);
+                let mut t = transaction::Transaction::begin();
                 // Push the new version to remote
-                vpass::sync::vault_overwrite(&vault_filename(&c.name), &book, &new_pw)?;
-
-                // Change local vault password
-                match vpass::write(&p, &new_pw, book.clone()) {
-                    Ok(()) => {},
-                    Err(e) => {
-                        // Could not change local file password: Roll back remote changes
-                        vpass::sync::vault_overwrite(&vault_filename(&c.name), &book, &old_pw)?;
-                        return Err(e);
-                    },
+                t.step(|| vpass::sync::vault_overwrite(&filename, &book, &new_pw))?;
+                t.on_rollback(|| vpass::sync::vault_overwrite(&filename, &old_book, &old_pw));
+
+                // Change local vault password, applying key derivation limits from config
+                t.step(|| vpass::write_new_key(&p, &new_pw, book, kdf))?;
+                t.commit();
+                // The cached snapshot is encrypted with the old password
+                sync_state::remove(&p)?;
+            },
+            VaultSubCommand::Rekey(ref c) => {
+                Vaults::new(&args)?.verify_exists(&c.name)?;
+                let p = vault_path(&args, &c.name)?;
+                let pw = prompt_vault_password!();
+                let mut book = attempts::read(&args, &p, &pw)?;
+                let filename = vault_filename(&c.name);
+                // Changes on the remote would be lost when it's overwritten
+                vpass::sync::vault(&filename, &mut book, &pw)?;
+
+                let old_book = book.clone();
+                if let Some(mut shared) = book.shared().cloned() {
+                    shared.rotate();
+                    // Member keys have a random salt as well
+                    shared.change_member_password(&pw, &pw, member_kdf(&args, &p)?);
+                    book.set_shared(shared);
+                }
+                let old_config = sync::config::book_read(&book)?;
+                let rotate_remote_key = c.rotate_remote_key
+                    && old_config.as_ref().is_some_and(|config| config.remote_key.is_some());
+                if rotate_remote_key {
+                    let new_key = sync::remote_key::RemoteKey::generate();
+                    vpass::sync::config::book_set_remote_key(&mut book, Some(new_key))?;
+                }
+                let new_config = sync::config::book_read(&book)?;
+
+                let kdf = cfg::read(&args)?.kdf;
+                let mut t = transaction::Transaction::begin();
+                t.step(|| vpass::sync::vault_overwrite(&filename, &book, &pw))?;
+                t.on_rollback(|| vpass::sync::vault_overwrite(&filename, &old_book, &pw));
+                if let (true, Some(old), Some(new)) = (rotate_remote_key, &old_config, &new_config) {
+                    t.step(|| vpass::sync::reencrypt_trash(&filename, old, new))?;
+                    let filename = &filename;
+                    t.on_rollback(move || vpass::sync::reencrypt_trash(filename, new, old));
+                }
+                // Compressed and encrypted again with a new salt and nonce
+                t.step(|| vpass::write_new_key(&p, &pw, book, kdf))?;
+                t.commit();
+                // The cached snapshot is encrypted with the old keys
+                sync_state::remove(&p)?;
+                if rotate_remote_key && !args.quiet {
+                    println!("{}", i18n::tr("Remote key changed, import the vault again on other devices"));
+                }
+            },
+            VaultSubCommand::Exists(ref c) => {
+                // Only the file is checked, so no password is needed
+                if !Vaults::new(&args)?.contains(&c.name) {
+                    return Err(Error::DoesNotExist);
+                }
+            },
+            VaultSubCommand::List(ref c) if c.long || c.status => {
+                // A password given as an argument, in the environment or by a command is used for all vaults
+                let common_pw = match given_password(&args)? {
+                    Some(pw) => Some(pw),
+                    None if c.long => cfg::command_password(&args)?,
+                    None => None,
+                };
+                let default_vault = cfg::read(&args)?.default_vault;
+                let mut entries = Vec::new();
+                for name in Vaults::new(&args)?.to_vec() {
+                    let p = vault_path(&args, &name)?;
+                    // Vaults with a different password are listed without details
+                    let book = if c.long {
+                        let pw = match common_pw {
+                            Some(ref pw) => pw.clone(),
+                            None => prompt_password(&i18n::trf("Password [{}]:", &[&name]))?,
+                        };
+                        match attempts::read(&args, &p, &pw) {
+                            Ok(book) => Some(book),
+                            Err(Error::WrongPassword) => None,
+                            Err(e) => return Err(e),
+                        }
+                    } else {
+                        None
+                    };
+                    let provider = match book {
+                        Some(ref book) => sync::config::book_read(book)?.map(|config| config.service),
+                        None => None,
+                    };
+                    let metadata = fs::metadata(&p)?;
+                    let modified = chrono::DateTime::<chrono::Local>::from(metadata.modified()?);
+                    let last_sync = sync_state::last_sync(&p)?;
+                    entries.push((name, book, provider, metadata.len(), modified, last_sync));
+                }
+                if c.json {
+                    let j: Vec<_> = entries
+                        .iter()
+                        .map(|(name, book, provider, size, modified, last_sync)| {
+                            json!({
+                                "name": name,
+                                "default": default_vault.as_ref() == Some(name),
+                                "locked": book.is_none(),
+                                "description": book.as_ref().and_then(|b| b.description()),
+                                "label": book.as_ref().and_then(|b| b.label()),
+                                "annotations": book.as_ref().map(|b| b.annotations()),
+                                "items": book.as_ref().map(|b| b.item_count()),
+                                "provider": provider,
+                                "size": size,
+                                "modified": modified,
+                                "last_sync": last_sync,
+                            })
+                        })
+                        .collect();
+                    println!("{}", serde_json::to_string(&j).unwrap());
+                } else if c.status {
+                    let time = |t: chrono::DateTime<chrono::Local>| t.format("%Y-%m-%d %H:%M").to_string();
+                    for (name, book, provider, size, modified, last_sync) in entries {
+                        let marker = if default_vault.as_ref() == Some(&name) { "*" } else { " " };
+                        let label = book.as_ref().and_then(|b| b.label()).map(|l| format!("{} ", l));
+                        let mut columns = vec![
+                            format!("{} {}{}", marker, label.unwrap_or_default(), name),
+                            listing::file_size(size),
+                            time(modified),
+                            last_sync.map_or_else(
+                                || i18n::tr("never synchronized").to_owned(),
+                                |t| time(t.with_timezone(&chrono::Local)),
+                            ),
+                        ];
+                        if c.long {
+                            match book {
+                                Some(ref book) => {
+                                    columns.push(book.item_count().to_string());
+                                    columns.push(provider.map_or_else(|| "-".to_owned(), |p| p.to_string()));
+                                    columns.push(book.description().unwrap_or("").to_owned());
+                                },
+                                None => columns.push("(locked)".to_owned()),
+                            }
+                        }
+                        println!("{}", columns.join("\t"));
+                    }
+                } else {
+                    for (name, book, ..) in entries {
+                        match book {
+                            Some(book) => {
+                                let label = book.label().map(|l| format!("{} ", l)).unwrap_or_default();
+                                println!("{}{}\t{}", label, name, book.description().unwrap_or(""))
+                            },
+                            None => println!("{}\t(locked)", name),
+                        }
+                    }
                 }
             },
             VaultSubCommand::List(ref c) => {
@@ -284,14 +936,19 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
                 vaults.verify_exists(&c.name)?;
                 let p = vault_path(&args, &c.name)?;
                 let pw = prompt_vault_password!();
-                let book = vpass::read(&p, &pw)?;
+                let book = attempts::read(&args, &p, &pw)?;
                 if c.json {
                     println!(
                         "{}",
                         serde_json::json!({
                             "file_path": p,
+                            "description": book.description(),
+                            "annotations": book.annotations(),
                             "creation_time": book.creation_time(),
                             "item_count": book.item_names().len(),
+                            "member_count":
+                                book.shared().filter(|s| s.is_shared()).map(|s| s.recipients().len()),
+                            "recovery_key_created": book.recovery().map(|r| r.created()),
                             "synchronization_service":
                                 if let Some(config) = vpass::sync::config::book_read(&book)? {
                                     serde_json::Value::String(format!("{:?}", config.service))
@@ -299,18 +956,297 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
                                     serde_json::Value::Null
                                 },
                         })
-                        .to_string()
                     )
                 } else {
-                    println!("File path: {:?}", p);
-                    println!("Creation time: {}", book.creation_time());
-                    println!("Item count: {}", book.item_names().len());
+                    println!("{}", i18n::trf("File path: {}", &[&format!("{:?}", p)]));
+                    if let Some(description) = book.description() {
+                        println!("{}", i18n::trf("Description: {}", &[&description]));
+                    }
+                    for (key, value) in book.annotations() {
+                        println!("{}: {}", key, value);
+                    }
+                    println!("{}", i18n::trf("Creation time: {}", &[&book.creation_time()]));
+                    println!("{}", i18n::trf("Item count: {}", &[&book.item_names().len()]));
+                    if let Some(shared) = book.shared().filter(|s| s.is_shared()) {
+                        println!("{}", i18n::trf("Shared with {} members", &[&shared.recipients().len()]));
+                    }
+                    if let Some(recovery) = book.recovery() {
+                        println!("{}", i18n::trf("Recovery key created: {}", &[&recovery.created()]));
+                    }
                     if let Some(config) = vpass::sync::config::book_read(&book)? {
-                        println!("Synchronization: {:?}", config.service);
+                        println!("{}", i18n::trf("Synchronization: {}", &[&format!("{:?}", config.service)]));
+                    } else {
+                        println!("{}", i18n::tr("Synchronization not set up"));
+                    }
+                }
+            },
+            VaultSubCommand::Annotate(ref c) => {
+                Vaults::new(&args)?.verify_exists(&c.name)?;
+                let p = vault_path(&args, &c.name)?;
+                let pw = prompt_vault_password!();
+                let mut book = attempts::read(&args, &p, &pw)?;
+                if let Some(ref description) = c.description {
+                    book.set_description(Some(description.as_str()).filter(|d| !d.is_empty()));
+                }
+                for (key, value) in &c.annotations {
+                    if key == "label" && !value.is_empty() {
+                        validate::label(value)?;
+                    }
+                    book.annotate(key, Some(value.as_str()).filter(|v| !v.is_empty()));
+                }
+                write_changes(&args, &p, &pw, book)?;
+            },
+            VaultSubCommand::SealPassword(ref c) => {
+                Vaults::new(&args)?.verify_exists(&c.name)?;
+                let p = vault_path(&args, &c.name)?;
+                let pw = prompt_vault_password!();
+                // Sealing a wrong password would only fail later, without a prompt to retry
+                attempts::read(&args, &p, &pw)?;
+                credential::seal(&pw, &c.output, &c.with_key)?;
+                if !args.quiet {
+                    eprintln!(
+                        "{}",
+                        i18n::trf(
+                            "Password sealed to {}, use it with `--password-credential`",
+                            &[&c.output.display()]
+                        )
+                    );
+                }
+            },
+            VaultSubCommand::EmergencyKit(ref c) => {
+                Vaults::new(&args)?.verify_exists(&c.name)?;
+                let p = vault_path(&args, &c.name)?;
+                let pw = prompt_vault_password!();
+                let book = attempts::read(&args, &p, &pw)?;
+                let transfer_string = match sync::config::book_read(&book)? {
+                    Some(config) => {
+                        // Only needed when the kit is used, so a typo would be found too late
+                        let passphrase = interactive::prompt_new_password(i18n::tr(
+                            "Passphrase for the transfer string in the kit:",
+                        ))?;
+                        Some(sync::transfer_string::encode_protected(&config, &passphrase))
+                    },
+                    None => None,
+                };
+                let mut kit = emergency_kit::Kit {
+                    vault_name: c.name.clone(),
+                    file_path: p,
+                    created: chrono::Utc::now(),
+                    transfer_string,
+                    share: None,
+                };
+                if let Some((threshold, count)) = c.shamir {
+                    let kind = vpass::shamir::ShareKind::Password;
+                    let shares = vpass::shamir::split(pw.as_bytes(), kind, threshold, count)?;
+                    // Required with `--shamir` by the argument parser
+                    let out = c.out.as_ref().unwrap();
+                    for (i, share) in shares.iter().enumerate() {
+                        kit.share = Some(emergency_kit::KitShare {
+                            encoded: share.encode(),
+                            number: i + 1,
+                            count,
+                            threshold,
+                        });
+                        let path = emergency_kit::numbered_path(out, i + 1);
+                        write_private(&path, &kit.render(c.format))?;
+                        outln!("{}", path.display());
+                    }
+                } else if let Some(ref out) = c.out {
+                    write_private(out, &kit.render(c.format))?;
+                } else {
+                    print!("{}", kit.render(c.format));
+                }
+            },
+            VaultSubCommand::ShamirSplit(ref c) => {
+                Vaults::new(&args)?.verify_exists(&c.name)?;
+                let p = vault_path(&args, &c.name)?;
+                let pw = prompt_vault_password!();
+                let mut book = attempts::read(&args, &p, &pw)?;
+                let (recovery, secret) = vpass::RecoveryKey::generate();
+                let shares = vpass::shamir::split(
+                    &secret,
+                    vpass::shamir::ShareKind::RecoveryKey,
+                    c.threshold,
+                    c.count,
+                )?;
+                let old_book = book.clone();
+                book.set_recovery(recovery);
+
+                // A new vault key is used, so that shares of a previous recovery key
+                // can't open the new versions
+                write_rekeyed(&args, &c.name, &pw, book, &old_book)?;
+                for share in shares {
+                    println!("{}", share.encode());
+                }
+            },
+            VaultSubCommand::ShamirRecover(ref c) => {
+                let shares = c
+                    .shares
+                    .iter()
+                    .map(|s| vpass::shamir::Share::decode(s))
+                    .collect::<VResult<Vec<_>>>()?;
+                let secret = vpass::shamir::combine(&shares)?;
+                if shares[0].kind() == vpass::shamir::ShareKind::Password {
+                    let password = String::from_utf8(secret).map_err(|_| Error::ShareInvalid)?;
+                    println!("{}", printable_password(&password));
+                    return Ok(());
+                }
+
+                let name = c.vault.clone().ok_or(Error::VaultNotSpecified)?;
+                Vaults::new(&args)?.verify_exists(&name)?;
+                let p = vault_path(&args, &name)?;
+                let mut book = vpass::recover(&p, &secret)?;
+                let new_pw = if let Some(ref x) = c.password {
+                    x.as_str().to_owned()
+                } else {
+                    prompt_password(i18n::tr("New password [vault]:"))?
+                };
+
+                // Shared vaults keep their content key, and get a member key or key slot for the new password
+                if let Some(mut shared) = book.shared().cloned() {
+                    let member = vpass::MemberKey::new(&new_pw, member_kdf(&args, &p)?);
+                    if shared.is_shared() {
+                        shared.add(&member);
                     } else {
-                        println!("Synchronization not set up");
+                        shared.add_slot(&member);
                     }
+                    book.set_shared(shared);
                 }
+
+                // The old password is not known, so remote changes can't be rolled back on failure
+                vpass::sync::vault_overwrite(&vault_filename(&name), &book, &new_pw)?;
+                vpass::write_new_key(&p, &new_pw, book, None)?;
+                sync_state::remove(&p)?;
+            },
+            VaultSubCommand::Recipients(ref rc) => match rc.subcommand {
+                RecipientsSubCommand::Key => {
+                    let pw = prompt_vault_password!();
+                    let kdf = cfg::read(&args)?.kdf.unwrap_or_default();
+                    println!("{}", vpass::MemberKey::new(&pw, kdf).encode());
+                },
+                RecipientsSubCommand::List(ref c) => {
+                    Vaults::new(&args)?.verify_exists(&c.name)?;
+                    let p = vault_path(&args, &c.name)?;
+                    let pw = prompt_vault_password!();
+                    let book = attempts::read(&args, &p, &pw)?;
+                    let keys: Vec<String> = book
+                        .shared()
+                        .map(|s| s.recipients().iter().map(|r| r.member().encode()).collect())
+                        .unwrap_or_default();
+                    if c.json {
+                        println!("{}", serde_json::to_string(&keys).unwrap());
+                    } else if !keys.is_empty() {
+                        println!("{}", keys.join("\n"));
+                    }
+                },
+                RecipientsSubCommand::Add(ref c) | RecipientsSubCommand::Remove(ref c) => {
+                    Vaults::new(&args)?.verify_exists(&c.name)?;
+                    let p = vault_path(&args, &c.name)?;
+                    let pw = prompt_vault_password!();
+                    let mut book = attempts::read(&args, &p, &pw)?;
+                    let member = vpass::MemberKey::decode(&c.key)?;
+                    let old_book = book.clone();
+
+                    if let RecipientsSubCommand::Add(_) = rc.subcommand {
+                        // The owner is the first member
+                        let mut shared =
+                            book.shared().cloned().unwrap_or_else(vpass::SharedKey::without_members);
+                        if !shared.is_shared() {
+                            shared.add(&vpass::MemberKey::new(&pw, member_kdf(&args, &p)?));
+                        }
+                        if !shared.add(&member) {
+                            return Ok(());
+                        }
+                        book.set_shared(shared);
+                    } else {
+                        let mut shared =
+                            book.shared().filter(|s| s.is_shared()).cloned().ok_or(Error::VaultNotShared)?;
+                        if shared.recipients().len() == 1 {
+                            return Err(Error::LastMember);
+                        }
+                        if !shared.remove(&member) {
+                            return Err(Error::NoSuchMember);
+                        }
+                        book.set_shared(shared);
+                    }
+
+                    // The content key may change, so remote is overwritten like when changing password
+                    write_rekeyed(&args, &c.name, &pw, book, &old_book)?;
+                },
+            },
+            VaultSubCommand::Key(ref kc) => match kc.subcommand {
+                KeySubCommand::List(ref c) => {
+                    Vaults::new(&args)?.verify_exists(&c.name)?;
+                    let p = vault_path(&args, &c.name)?;
+                    let pw = prompt_vault_password!();
+                    let book = attempts::read(&args, &p, &pw)?;
+                    // A vault without slots is unlocked by its password only
+                    let slots: Vec<(String, bool)> = match book.shared().filter(|s| !s.slots().is_empty()) {
+                        Some(shared) => shared
+                            .slots()
+                            .iter()
+                            .map(|r| (r.member().encode(), r.member().matches(&pw)))
+                            .collect(),
+                        None => vec![(String::new(), true)],
+                    };
+                    if c.json {
+                        let slots: Vec<serde_json::Value> = slots
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (key, current))| json!({"slot": i + 1, "key": key, "current": current}))
+                            .collect();
+                        println!("{}", serde_json::to_string(&slots).unwrap());
+                    } else {
+                        for (i, (key, current)) in slots.iter().enumerate() {
+                            println!("{}: {}{}", i + 1, key, if *current { " (current)" } else { "" });
+                        }
+                    }
+                },
+                KeySubCommand::Add(ref c) => {
+                    Vaults::new(&args)?.verify_exists(&c.name)?;
+                    let p = vault_path(&args, &c.name)?;
+                    let pw = prompt_vault_password!();
+                    let mut book = attempts::read(&args, &p, &pw)?;
+                    let new_pw = if let Some(ref x) = c.new_password {
+                        x.as_str().to_owned()
+                    } else {
+                        prompt_password(i18n::tr("Password for the new key slot:"))?
+                    };
+                    let old_book = book.clone();
+
+                    // Slots are sealed like members of a shared vault, but kept apart from them,
+                    // the current password being the first one
+                    let mut shared = book.shared().cloned().unwrap_or_else(vpass::SharedKey::without_members);
+                    let kdf = member_kdf(&args, &p)?;
+                    if shared.slots().is_empty() {
+                        shared.add_slot(&vpass::MemberKey::new(&pw, kdf));
+                    }
+                    if !shared.add_slot(&vpass::MemberKey::new(&new_pw, kdf)) {
+                        return Ok(());
+                    }
+                    book.set_shared(shared);
+                    write_rekeyed(&args, &c.name, &pw, book, &old_book)?;
+                },
+                KeySubCommand::Remove(ref c) => {
+                    Vaults::new(&args)?.verify_exists(&c.name)?;
+                    let p = vault_path(&args, &c.name)?;
+                    let pw = prompt_vault_password!();
+                    let mut book = attempts::read(&args, &p, &pw)?;
+                    let old_book = book.clone();
+
+                    let mut shared = book.shared().cloned().ok_or(Error::LastMember)?;
+                    let member = match c.slot.checked_sub(1).and_then(|i| shared.slots().get(i)) {
+                        Some(r) => r.member().clone(),
+                        None => return Err(Error::NoSuchKeySlot(c.slot)),
+                    };
+                    // Members of a shared vault can still unlock it without slots
+                    if shared.slots().len() == 1 && !shared.is_shared() {
+                        return Err(Error::LastMember);
+                    }
+                    shared.remove_slot(&member);
+                    book.set_shared(shared);
+                    write_rekeyed(&args, &c.name, &pw, book, &old_book)?;
+                },
             },
         },
         SubCommand::Add(ref c) => {
@@ -318,58 +1254,135 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
             let pw = prompt_vault_password!();
 
             validate::item_name(&c.name)?;
+            if let Some(ref label) = c.label {
+                validate::label(label)?;
+            }
 
-            let mut book = vpass::read(&p, &pw)?;
+            let mut book = read_book(&args, &p, &pw)?;
             if book.has_item(&c.name) {
                 return Err(Error::ItemAlreadyExists(c.name.clone()));
             }
-            book.add(vpass::Item {
+            let mut item = vpass::Item {
                 name: c.name.clone(),
                 tags: c.tags.iter().cloned().collect(),
-                notes: c.notes.clone(),
-                password: c
-                    .password
-                    .clone()
-                    .or_else(|| {
-                        if c.skip_password {
-                            None
-                        } else {
-                            Some(prompt_password("Password [item]:").expect("Unable to read password"))
-                        }
-                    })
-                    .map(|pass| Password::new(&pass)),
-            })?;
-            vpass::write(&p, &pw, book)?;
+                notes: Vec::new(),
+                next_note_id: 0,
+                derived: if c.derive {
+                    Some(vpass::DerivationRule::new(&c.name, c.length)?)
+                } else {
+                    None
+                },
+                password: if c.generate {
+                    Some(generate_password(&args, &c.preset, c.length)?)
+                } else if let Some(ref password) = c.password {
+                    Some(Password::new(password.as_str()))
+                } else if c.skip_password || c.derive || !c.kind.has_password() {
+                    None
+                } else if c.kind == ItemKind::ApiToken {
+                    Some(Password::new(&prompt_password(i18n::tr("Token [item]:"))?))
+                } else {
+                    Some(Password::new(&prompt_password(i18n::tr("Password [item]:"))?))
+                },
+                protected: None,
+                archived: false,
+                rotation_pending: false,
+                ssh_key: match c.ssh_key {
+                    Some(ref path) => Some(Password::new(&fs::read_to_string(path)?)),
+                    None => None,
+                },
+                kind: c.kind,
+                data: None,
+                card: None,
+                wifi: None,
+                label: c.label.clone(),
+            };
+            if c.kind == ItemKind::Login && !c.generate {
+                if let Some(ref password) = item.password {
+                    check_strength(&args, &password.plaintext())?;
+                }
+            }
+            if c.kind == ItemKind::Card || c.kind == ItemKind::Wifi {
+                // Their details are validated, so they are only entered with their own commands
+                return Err(Error::KindHasCommand(c.kind));
+            }
+            if let Some(ref path) = c.from_file {
+                store_file(&mut item, path)?;
+            } else if c.kind.has_data() || (c.kind == ItemKind::SshKey && item.ssh_key.is_none()) {
+                return Err(Error::KindNeedsFile(c.kind));
+            }
+            for note in &c.notes {
+                item.add_note(None, note);
+            }
+            if c.protect {
+                item.protect(&protect_passphrase(&args, &c.name)?);
+            }
+            book.add(item)?;
+            write_changes(&args, &p, &pw, book)?;
         },
         SubCommand::Edit(ref c) => {
             let p = get_vault_path(&args)?;
             let pw = prompt_vault_password!();
 
-            let mut book = vpass::read(&p, &pw)?;
+            let mut book = read_book(&args, &p, &pw)?;
             book.modify_by_name(&c.name, |item| -> VResult<()> {
-                if let Some(ref new_pw) = c.password {
-                    item.password = Some(Password::new(new_pw));
+                let new_pw = if let Some(ref new_pw) = c.password {
+                    Some(new_pw.as_str().to_owned())
                 } else if c.change_password {
-                    item.password = Some(Password::new(&prompt_password("New password:")?));
-                }
+                    Some(prompt_password(i18n::tr("New password:"))?)
+                } else {
+                    None
+                };
+                if let Some(new_pw) = new_pw {
+                    check_strength(&args, &new_pw)?;
+                    // A new password for a protected entry is protected as well, unless removed
+                    let protect = item.protected.is_some() && !c.unprotect;
+                    item.password = Some(Password::new(&new_pw));
+                    item.derived = None;
+                    item.protected = None;
+                    if protect {
+                        item.protect(&protect_passphrase(&args, &item.name)?);
+                    }
+                } else if c.next_counter {
+                    match item.derived {
+                        Some(ref mut rule) => rule.counter += 1,
+                        None => return Err(Error::ItemNotDerived(item.name.clone())),
+                    }
+                }
+                if c.protect && item.protected.is_none() {
+                    if item.password.is_none() {
+                        return Err(Error::ItemNoPasswordSet);
+                    }
+                    item.protect(&protect_passphrase(&args, &item.name)?);
+                } else if c.unprotect && item.protected.is_some() {
+                    item.unprotect(&protect_passphrase(&args, &item.name)?)?;
+                }
 
-                let indices = c.remove_notes.clone();
-                assert!(
-                    indices
-                        .iter()
-                        .max()
-                        .map(|m| *m < item.notes.len())
-                        .unwrap_or(true)
-                );
-                item.notes = item
-                    .notes
-                    .iter()
-                    .enumerate()
-                    .filter(|(i, _)| !indices.contains(i))
-                    .map(|(_, v)| v)
-                    .chain(c.notes.iter())
-                    .cloned()
-                    .collect();
+                if let Some(ref path) = c.ssh_key {
+                    item.ssh_key = Some(Password::new(&fs::read_to_string(path)?));
+                } else if c.remove_ssh_key {
+                    item.ssh_key = None;
+                }
+                if let Some(kind) = c.kind {
+                    item.kind = kind;
+                }
+                if let Some(ref path) = c.from_file {
+                    store_file(item, path)?;
+                }
+                if let Some(ref label) = c.label {
+                    if label.is_empty() {
+                        item.label = None;
+                    } else {
+                        validate::label(label)?;
+                        item.label = Some(label.clone());
+                    }
+                }
+
+                for id in &c.remove_notes {
+                    item.remove_note(*id)?;
+                }
+                for note in &c.notes {
+                    item.add_note(None, note);
+                }
 
                 item.tags = item
                     .tags
@@ -379,10 +1392,151 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
                     .cloned()
                     .collect();
                 Ok(())
-            })
-            .unwrap()?;
+            })??;
+            if c.protect {
+                // Earlier versions of the entry still have the password in plaintext
+                book.redact_history(&c.name)?;
+            }
+
+            write_changes(&args, &p, &pw, book)?;
+        },
+        SubCommand::Rotate(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+
+            let mut book = read_book(&args, &p, &pw)?;
+            for name in &c.names {
+                book.verify_exists(name)?;
+            }
+            let mut names = c.names.clone();
+            if !c.tags.is_empty() {
+                // Confirming by tag only applies to the entries still pending
+                let mut tagged: Vec<String> = book
+                    .items()
+                    .into_iter()
+                    .filter(|item| c.tags.iter().all(|t| item.tags.contains(t)))
+                    .filter(|item| !c.done || item.rotation_pending)
+                    .map(|item| item.name)
+                    .filter(|name| !c.names.iter().any(|n| book.names_match(n, name)))
+                    .collect();
+                tagged.sort();
+                names.extend(tagged);
+            }
+            if names.is_empty() {
+                return Err(Error::NoItemsSelected);
+            }
+
+            if c.done {
+                for name in &names {
+                    book.modify_by_name(name, |item| item.rotation_pending = false)?;
+                }
+                write_changes(&args, &p, &pw, book)?;
+                return Ok(());
+            }
+
+            let mut checklist = Vec::new();
+            for name in &names {
+                let entry = book.modify_by_name(name, |item| -> VResult<serde_json::Value> {
+                    if let Some(ref mut rule) = item.derived {
+                        rule.counter += 1;
+                    } else {
+                        let new_pw = if c.generate {
+                            generate_password(&args, &c.preset, c.length)?
+                        } else {
+                            Password::new(&prompt_password(&i18n::trf("New password [{}]:", &[&item.name]))?)
+                        };
+                        // The old password stays in the history of the entry
+                        let protect = item.protected.is_some();
+                        item.password = Some(new_pw);
+                        item.protected = None;
+                        if protect {
+                            item.protect(&protect_passphrase(&args, &item.name)?);
+                        }
+                    }
+                    if c.pending {
+                        item.rotation_pending = true;
+                    }
+                    Ok(json!({"name": item.name, "pending": item.rotation_pending}))
+                })??;
+                checklist.push(entry);
+            }
+            write_changes(&args, &p, &pw, book)?;
 
-            vpass::write(&p, &pw, book)?;
+            if c.json {
+                outln!("{}", serde_json::to_string(&checklist).unwrap());
+            } else {
+                outln!("{}", i18n::tr("Change the passwords on these sites:"));
+                for entry in &checklist {
+                    outln!("[ ] {}", entry["name"].as_str().unwrap());
+                }
+                if c.pending {
+                    outln!("{}", i18n::tr("Confirm each one with `rotate --done <name>` once changed"));
+                }
+            }
+        },
+        SubCommand::Note(ref nc) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            match nc.subcommand {
+                NoteSubCommand::Add(ref c) => {
+                    let id = book.modify_by_name(&c.name, |item| item.add_note(c.title.clone(), &c.text))?;
+                    outln!("{}", id);
+                },
+                NoteSubCommand::Edit(ref c) => {
+                    book.modify_by_name(&c.name, |item| -> VResult<()> {
+                        let note = item.note_mut(c.id)?;
+                        if let Some(ref text) = c.text {
+                            note.text = text.clone();
+                        }
+                        if c.title.is_some() || c.remove_title {
+                            note.title = c.title.clone();
+                        }
+                        Ok(())
+                    })??;
+                },
+                NoteSubCommand::Rm(ref c) => {
+                    book.modify_by_name(&c.name, |item| item.remove_note(c.id))??;
+                },
+                NoteSubCommand::Show(ref c) => {
+                    confirm_presence(&args, &p)?;
+                    println!("{}", book.get_item_by_name(&c.name)?.note(c.id)?.text);
+                    return Ok(());
+                },
+                NoteSubCommand::New(ref c) => {
+                    validate::item_name(&c.name)?;
+                    book.verify_not_exists(&c.name)?;
+                    let text = match c.from_file {
+                        Some(ref path) => fs::read_to_string(path)?,
+                        None => interactive::edit_text(&args, "")?,
+                    };
+                    if text.trim().is_empty() {
+                        return Err(Error::DocumentEmpty);
+                    }
+                    let mut item = vpass::Item::new(&c.name);
+                    item.kind = ItemKind::Note;
+                    item.tags = c.tags.iter().cloned().collect();
+                    item.data = Some(vpass::Blob::new(text.into_bytes()));
+                    book.add(item)?;
+                },
+                NoteSubCommand::Cat(ref c) => {
+                    confirm_presence(&args, &p)?;
+                    print!("{}", document_text(&book.get_item_by_name(&c.name)?)?);
+                    book.record_access(&c.name)?;
+                },
+                NoteSubCommand::Open(ref c) => {
+                    confirm_presence(&args, &p)?;
+                    let document = document_text(&book.get_item_by_name(&c.name)?)?;
+                    let text = interactive::edit_text(&args, &document)?;
+                    if text.trim().is_empty() {
+                        return Err(Error::DocumentEmpty);
+                    }
+                    book.modify_by_name(&c.name, |item| {
+                        item.data = Some(vpass::Blob::new(text.into_bytes()));
+                    })?;
+                },
+            }
+            write_changes(&args, &p, &pw, book)?;
         },
         SubCommand::Rename(ref c) => {
             let p = get_vault_path(&args)?;
@@ -390,54 +1544,342 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
 
             validate::item_name(&c.new_name)?;
 
-            let mut book = vpass::read(&p, &pw)?;
-            book.verify_not_exists(&c.new_name)?;
-            book.modify_by_name(&c.old_name, |item| {
-                item.name = c.new_name.clone();
-            })?;
+            let mut book = read_book(&args, &p, &pw)?;
+            // Changing only the case of a name is allowed with case-insensitive names
+            let same_name = book.names_match(&c.old_name, &c.new_name);
+            if c.merge_into && !same_name && book.has_item(&c.new_name) {
+                book.merge_into(&c.old_name, &c.new_name)?;
+            } else {
+                if !same_name {
+                    book.verify_not_exists(&c.new_name)?;
+                }
+                book.modify_by_name(&c.old_name, |item| {
+                    item.name = c.new_name.clone();
+                })?;
+            }
+            write_changes(&args, &p, &pw, book)?;
         },
         SubCommand::Remove(ref c) => {
             let p = get_vault_path(&args)?;
             let pw = prompt_vault_password!();
 
-            let mut book = vpass::read(&p, &pw)?;
-            book.remove(&c.name)?;
-            vpass::write(&p, &pw, book)?;
+            let mut book = read_book(&args, &p, &pw)?;
+            for name in &c.names {
+                book.verify_exists(name)?;
+            }
+            for name in &c.names {
+                book.remove(name)?;
+            }
+            write_changes(&args, &p, &pw, book)?;
+        },
+        SubCommand::Archive(ref c) | SubCommand::Unarchive(ref c) => {
+            let archived = matches!(args.subcommand, SubCommand::Archive(_));
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+
+            let mut book = read_book(&args, &p, &pw)?;
+            for name in &c.names {
+                book.verify_exists(name)?;
+            }
+            for name in &c.names {
+                book.modify_by_name(name, |item| item.archived = archived)?;
+            }
+            write_changes(&args, &p, &pw, book)?;
         },
         SubCommand::List(ref c) => {
             let p = get_vault_path(&args)?;
             let pw = prompt_vault_password!();
-            let book = vpass::read(&p, &pw)?;
+            let book = read_book(&args, &p, &pw)?;
+
+            let mut items = book.items_metadata();
+            items.retain(|(item, _)| c.archived || !item.archived);
+            listing::sort(&mut items, c.sort, c.reverse);
+
+            let to_json = |entries: &[(vpass::Item, vpass::ItemMetadata)]| -> serde_json::Value {
+                entries
+                    .iter()
+                    .map(|(item, meta)| {
+                        if c.long {
+                            json!({"name": item.name, "label": item.label, "meta": meta})
+                        } else {
+                            json!(item.name)
+                        }
+                    })
+                    .collect()
+            };
+            let to_lines = |entries: &[(vpass::Item, vpass::ItemMetadata)]| -> Vec<String> {
+                entries
+                    .iter()
+                    .map(|(item, meta)| {
+                        if c.long {
+                            let last_used =
+                                meta.last_used.map(|t| t.to_string()).unwrap_or_else(|| "never".to_owned());
+                            let label = item.label.as_ref().map(|l| format!("{} ", l)).unwrap_or_default();
+                            format!(
+                                "{}{}\tlast used: {}\tuses: {}",
+                                label, item.name, last_used, meta.use_count
+                            )
+                        } else {
+                            item.name.clone()
+                        }
+                    })
+                    .collect()
+            };
+            match c.group_by {
+                None if c.json => println!("{}", to_json(&items)),
+                None => println!("{}", to_lines(&items).join("\n")),
+                Some(by) => {
+                    let groups = listing::group(items, by);
+                    if c.json {
+                        let j: serde_json::Map<_, _> =
+                            groups.iter().map(|(name, entries)| (name.clone(), to_json(entries))).collect();
+                        println!("{}", serde_json::Value::Object(j));
+                    } else {
+                        for (name, entries) in groups {
+                            println!("{}:", if name.is_empty() { "(none)" } else { &name });
+                            for line in to_lines(&entries) {
+                                println!("    {}", line);
+                            }
+                        }
+                    }
+                },
+            }
+        },
+        SubCommand::Complete(ref c) => {
+            // Only the cache is read, so that completion never prompts for a password
+            let p = get_vault_path(&args)?;
+            for name in name_cache::read(&p)? {
+                if name.starts_with(&c.prefix) {
+                    println!("{}", name);
+                }
+            }
+        },
+        SubCommand::Exists(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let book = read_book(&args, &p, &pw)?;
+            if !book.has_item(&c.name) {
+                return Err(Error::DoesNotExist);
+            }
+        },
+        SubCommand::Count(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let book = attempts::read(&args, &p, &pw)?;
+            let count = book
+                .items()
+                .iter()
+                .filter(|item| c.tags.iter().all(|t| item.tags.contains(t)))
+                .filter(|item| c.archived || !item.archived)
+                .count();
+            println!("{}", count);
+        },
+        SubCommand::Audit(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let book = read_book(&args, &p, &pw)?;
 
-            println!(
-                "{}",
+            let limit = chrono::Utc::now() - c.older_than;
+            let mut items: Vec<_> = book
+                .items_metadata()
+                .into_iter()
+                .filter(|(_, meta)| !c.unused || meta.last_activity() < limit)
+                .collect();
+            items.sort_by_key(|(_, meta)| meta.last_activity());
+            for (item, meta) in items {
+                match meta.last_used {
+                    Some(t) => println!("{}\tlast used {}", item.name, t),
+                    None => println!("{}\tnever used, created {}", item.name, meta.created),
+                }
+            }
+        },
+        SubCommand::BenchKdf(ref c) => {
+            let default = vpass::KdfParams::default();
+            let tuned = vpass::KdfParams::tune(std::time::Duration::from_millis(c.target_ms));
+            for (name, kdf) in &[("Default", default), ("Suggested", tuned)] {
+                println!(
+                    "{}: ops_limit={}, mem_limit={} MiB, {} ms",
+                    name,
+                    kdf.ops_limit,
+                    kdf.mem_limit / (1024 * 1024),
+                    kdf.measure().as_millis()
+                );
+            }
+            if c.apply {
+                cfg::modify(&args, |config| config.kdf = Some(tuned))?;
+                if !args.quiet {
+                    println!(
+                        "{}",
+                        i18n::tr("Saved to config. Use `vault change-password` to apply to existing vaults.")
+                    );
+                }
+            }
+        },
+        SubCommand::Diff(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let book = attempts::read(&args, &p, &pw)?;
+            let other = if let Some(ref name) = c.other {
+                Vaults::new(&args)?.verify_exists(name)?;
+                attempts::read(&args, &vault_path(&args, name)?, &pw)?
+            } else {
+                let config = sync::config::book_read(&book)?.ok_or(vpass::sync::Error::NoRemoteSet)?;
+                // TODO: non-default locations?
+                let name = p.file_name().unwrap().to_str().unwrap();
+                vpass::sync::download_book(name, config, &pw)?
+            };
+
+            for change in book.diff(&other) {
                 if c.json {
-                    serde_json::to_string(&book.item_names()).unwrap()
+                    println!("{}", serde_json::to_string(&change_json(&change)).unwrap());
                 } else {
-                    book.item_names().join("\n")
+                    println!("{}", change_line(&change));
                 }
-            );
+            }
+        },
+        SubCommand::Import(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+
+            let entries = if let Some(browser) = c.from_browser {
+                let ask_password = || prompt_password(&i18n::trf("Password [{}]:", &[&browser]));
+                let profile = c.profile.as_deref();
+                interop::browser::import(&args, browser, profile, &ask_password)?
+            } else {
+                // Both are required by the argument parser without `--from-browser`
+                let (format, path) = (c.format.unwrap(), c.path.as_ref().unwrap());
+                let source_password = if format.needs_password() {
+                    Some(Redacted::new(prompt_password(&i18n::trf("Password [{}]:", &[&path.display()]))?))
+                } else {
+                    None
+                };
+                interop::import(
+                    format,
+                    path,
+                    source_password.as_ref().map(|p| p.as_str()),
+                    c.keyfile.as_deref(),
+                )?
+            };
+            let items: Vec<vpass::Item> = entries.into_iter().map(interop::Entry::into_item).collect();
+            for item in &items {
+                validate::item_name(&item.name)?;
+                book.verify_not_exists(&item.name)?;
+            }
+            let count = items.len();
+            for item in items {
+                book.add(item)?;
+            }
+            write_changes(&args, &p, &pw, book)?;
+            outln!("{}", i18n::trf("Imported {} entries", &[&count]));
+        },
+        SubCommand::Export(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            // Notes and other fields are exported even for entries without a password
+            confirm_presence(&args, &p)?;
+
+            let mut entries = Vec::new();
+            for item in book.items() {
+                if !c.tags.iter().all(|t| item.tags.contains(t)) {
+                    continue;
+                }
+                let password = reveal_password(&args, &p, &item, &pw)?;
+                entries.push(interop::Entry::from_item(&item, password));
+            }
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            interop::export(c.format, &c.path, &entries, &c.gpg_ids, c.force)?;
+            for entry in &entries {
+                book.record_access(&entry.name)?;
+            }
+            write_changes(&args, &p, &pw, book)?;
+            println!("{}", i18n::trf("Exported {} entries", &[&entries.len()]));
         },
         SubCommand::Show(ref c) => {
             let p = get_vault_path(&args)?;
             let pw = prompt_vault_password!();
-            let book = vpass::read(&p, &pw)?;
-            let (item, meta) = book.get_item_and_metadata(&c.name)?;
-            if c.json {
-                let mut j = serde_json::to_value(&item).unwrap();
-                j.as_object_mut().unwrap().insert("meta".to_owned(), json!(meta));
-                if !c.password {
-                    j.as_object_mut().unwrap().remove("password");
+            let mut book = read_book(&args, &p, &pw)?;
+            let entries = c
+                .names
+                .iter()
+                .map(|name| book.get_item_and_metadata(name))
+                .collect::<VResult<Vec<_>>>()?;
+            if c.password {
+                confirm_presence(&args, &p)?;
+            }
+            for (i, (item, meta)) in entries.into_iter().enumerate() {
+                if c.json {
+                    let mut j = serde_json::to_value(&item).unwrap();
+                    j.as_object_mut().unwrap().insert("meta".to_owned(), json!(meta));
+                    j.as_object_mut().unwrap().remove("protected");
+                    if !c.password {
+                        j.as_object_mut().unwrap().remove("password");
+                        j.as_object_mut().unwrap().remove("ssh_key");
+                        j.as_object_mut().unwrap().remove("data");
+                        j.as_object_mut().unwrap().remove("card");
+                    } else if item.derived.is_some() || item.protected.is_some() {
+                        j.as_object_mut()
+                            .unwrap()
+                            .insert("password".to_owned(), json!(reveal_password(&args, &p, &item, &pw)?));
+                    }
+                    println!("{}", serde_json::to_string(&j).unwrap());
+                    continue;
+                }
+                if i > 0 {
+                    println!();
                 }
-                println!("{}", serde_json::to_string(&j).unwrap());
-            } else {
                 println!("{}", item.name);
-                if item.password.is_none() {
-                    println!("password not stored");
+                if item.kind != ItemKind::Login {
+                    println!("kind: {}", item.kind);
+                }
+                if let Some(ref label) = item.label {
+                    println!("label: {}", label);
+                }
+                if let Some(ref rule) = item.derived {
+                    println!("password derived from {} (counter {})", rule.site, rule.counter);
+                }
+                if item.protected.is_some() {
+                    println!("password protected with a passphrase");
+                }
+                if item.archived {
+                    println!("archived");
+                }
+                if item.rotation_pending {
+                    println!("rotation pending");
+                }
+                if let Some(ref key) = item.ssh_key {
+                    if c.password {
+                        println!("ssh key:\n{}", key.plaintext().trim_end());
+                    } else {
+                        println!("ssh key: ********");
+                    }
+                }
+                if let Some(ref card) = item.card {
+                    if c.password {
+                        println!("card: {} (expires {})", card.formatted_number(), card.expiry);
+                    } else {
+                        println!("card: {} (expires {})", card.masked_number(), card.expiry);
+                    }
+                }
+                if let Some(ref network) = item.wifi {
+                    println!("wifi: {} ({})", network.ssid, network.security);
+                }
+                if let Some(ref data) = item.data {
+                    match data.text().filter(|_| c.password) {
+                        Some(text) => println!("data:\n{}", text.trim_end()),
+                        None => println!("data: {} bytes", data.bytes().len()),
+                    }
+                }
+                if item.password.is_none() && item.derived.is_none() && item.protected.is_none() {
+                    // Kinds without a password don't need one
+                    if item.kind.has_password() {
+                        println!("password not stored");
+                    }
                 } else if c.password {
                     println!(
                         "password: {}",
-                        printable_password(&item.password.clone().unwrap().plaintext())
+                        printable_password(&reveal_password(&args, &p, &item, &pw)?.unwrap().plaintext())
                     );
                 } else {
                     println!("password: ********");
@@ -455,53 +1897,490 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
                 }
                 println!("created: {}", meta.created);
                 println!("changed: {}", meta.changed);
+                if let Some(device) = meta.changed_by {
+                    println!("changed by: {}", device);
+                }
+                if let Some(last_used) = meta.last_used {
+                    println!("last used: {} ({} uses)", last_used, meta.use_count);
+                }
+            }
+            if c.password {
+                for name in &c.names {
+                    book.record_access(name)?;
+                }
+                write_changes(&args, &p, &pw, book)?;
+            }
+        },
+        SubCommand::Get(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            let value = field_value(&args, &p, &mut book, &c.name, c.field, &pw)?;
+            if c.no_newline {
+                print!("{}", value);
+            } else {
+                println!("{}", value);
             }
+            write_changes(&args, &p, &pw, book)?;
         },
         SubCommand::Copy(ref c) => {
+            if c.names.len() > 1 && !c.sequence && !c.once {
+                return Err(Error::SequenceRequired);
+            }
             let p = get_vault_path(&args)?;
             let pw = prompt_vault_password!();
-            let book = vpass::read(&p, &pw)?;
+            let mut book = read_book(&args, &p, &pw)?;
+            // Check all items before copying anything
+            let secrets = c
+                .names
+                .iter()
+                .map(|name| {
+                    let item = book.get_item_by_name(name)?;
+                    secret_text(&args, &p, &item, &pw)
+                })
+                .collect::<VResult<Vec<_>>>()?;
+            let clear_seconds = cfg::read(&args)?.clipboard_clear_seconds;
+            if c.once {
+                // Each entry is offered until pasted, so no confirmation is needed between them
+                let timeout = Some(std::time::Duration::from_secs(clear_seconds)).filter(|t| t.as_secs() > 0);
+                for (name, secret) in c.names.iter().zip(&secrets) {
+                    let pasted = clipboard::offer_once(secret, timeout)?;
+                    if !pasted {
+                        eprintln!("{}", i18n::trf("{} was not pasted, clipboard cleared", &[name]));
+                        break;
+                    }
+                    book.record_access(name)?;
+                }
+                write_changes(&args, &p, &pw, book)?;
+                return Ok(());
+            }
+            for (i, (name, secret)) in c.names.iter().zip(&secrets).enumerate() {
+                clipboard::write(secret)?;
+                book.record_access(name)?;
+                if c.sequence && i + 1 < c.names.len() {
+                    let next = i18n::trf("Copied {}, next: {}", &[name, &c.names[i + 1]]);
+                    vpass::cli::interactive::wait_enter(&next)?;
+                }
+            }
+            write_changes(&args, &p, &pw, book)?;
+            clear_clipboard_later(&args, secrets.last().unwrap())?;
+        },
+        SubCommand::LauncherFeed(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let book = attempts::read(&args, &p, &pw)?;
+            print!("{}", launcher::feed(&launcher_items(&book, &c.tags), c.format));
+        },
+        SubCommand::Menu(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            let names = launcher::feed(&launcher_items(&book, &c.tags), launcher::Format::Rofi);
+            let command = cfg::read(&args)?.menu_command;
+            let command = command.as_ref().map_or(launcher::default_menu_command(), String::as_str);
+            let name = match launcher::choose(command, &names)? {
+                Some(name) => name,
+                None => return Ok(()),
+            };
+            let item = book.get_item_by_name(&name)?;
+            let secret = secret_text(&args, &p, &item, &pw)?;
+            if c.type_text {
+                launcher::type_text(&secret)?;
+            } else {
+                clipboard::write(&secret)?;
+            }
+            book.record_access(&name)?;
+            write_changes(&args, &p, &pw, book)?;
+            if !c.type_text {
+                clear_clipboard_later(&args, &secret)?;
+            }
+        },
+        SubCommand::Derive(ref c) => {
+            let mut rule = vpass::DerivationRule::new(&c.site, c.length)?;
+            rule.counter = c.counter;
+            // Config might not be available either, so password command is not used
+            let pw = match given_password(&args)? {
+                Some(pw) => pw,
+                None => prompt_password(i18n::tr("Password [vault]:"))?,
+            };
+            println!("{}", printable_password(&rule.derive(&pw).plaintext()));
+        },
+        SubCommand::Generate(ref c) => {
+            let mut preset = match c.preset {
+                Some(ref name) => cfg::preset(&args, name)?,
+                None => vpass::generate::Preset::default(),
+            };
+            if let Some(length) = c.length {
+                preset.length = length;
+            }
+            preset.letters &= !c.no_letters;
+            preset.digits &= !c.no_digits;
+            preset.symbols &= !c.no_symbols;
+            if let Some(ref exclude) = c.exclude {
+                preset.exclude = exclude.clone();
+            }
+            preset.pronounceable |= c.pronounceable;
+            let password = preset.generate()?;
+            if !args.quiet {
+                let bits = format!("{:.0}", preset.entropy_bits());
+                eprintln!("{}", i18n::trf("Entropy: {} bits", &[&bits]));
+            }
+            if let Some(ref name) = c.save_preset {
+                cfg::modify(&args, |config| config.generator_presets.insert(name.clone(), preset))?;
+            }
+            println!("{}", printable_password(&password.plaintext()));
+        },
+        SubCommand::Exec(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            // Check all items before running anything
+            let mut vars: BTreeMap<String, String> = BTreeMap::new();
+            for name in &c.names {
+                let item = book.get_item_by_name(name)?;
+                let item_pw = reveal_password(&args, &p, &item, &pw)?.ok_or(Error::ItemNoPasswordSet)?;
+                let mut fields = item.note_fields();
+                fields.retain(|(key, _)| !key.eq_ignore_ascii_case("password"));
+                fields.insert(0, ("password".to_owned(), item_pw.plaintext()));
+                for (field, value) in fields {
+                    // Different names may map to the same variable, like `db-1` and `db_1`
+                    let var = exec_env_var(name, &field);
+                    if vars.insert(var.clone(), value).is_some() {
+                        return Err(Error::ExecVariableConflict(var));
+                    }
+                }
+                book.record_access(name)?;
+            }
+            write_changes(&args, &p, &pw, book)?;
+            // Passwords of vpass itself are not passed on to the child
+            let mut command = std::process::Command::new(&c.command[0]);
+            for var in EXEC_REMOVED_VARS {
+                command.env_remove(var);
+            }
+            let status = command.args(&c.command[1..]).envs(vars).status()?;
+            if !status.success() {
+                return Err(Error::ExecFailed(status.code()));
+            }
+        },
+        SubCommand::Card(ref cc) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            match cc.subcommand {
+                CardSubCommand::Add(ref c) => {
+                    validate::item_name(&c.name)?;
+                    book.verify_not_exists(&c.name)?;
+                    let number = match c.number {
+                        Some(ref number) => number.as_str().to_owned(),
+                        None => prompt_password(i18n::tr("Card number [item]:"))?,
+                    };
+                    let expiry = match c.expiry {
+                        Some(ref expiry) => expiry.clone(),
+                        None => interactive::prompt_string(i18n::tr("Expiry (MM/YY)"))?,
+                    };
+                    let cvv = match c.cvv {
+                        Some(ref cvv) => Some(cvv.as_str().to_owned()),
+                        None if c.no_cvv => None,
+                        None => Some(prompt_password(i18n::tr("CVV [item]:"))?),
+                    };
+                    let card = vpass::Card::new(&number, &expiry, cvv.as_deref(), c.holder.as_deref())?;
+                    let mut item = vpass::Item::new(&c.name);
+                    item.kind = ItemKind::Card;
+                    item.tags = c.tags.iter().cloned().collect();
+                    item.card = Some(card);
+                    book.add(item)?;
+                },
+                CardSubCommand::Show(ref c) => {
+                    if c.reveal {
+                        confirm_presence(&args, &p)?;
+                    }
+                    let item = book.get_item_by_name(&c.name)?;
+                    let card = item.card.ok_or_else(|| Error::ItemNoCard(c.name.clone()))?;
+                    println!("{}", item.name);
+                    if let Some(network) = card.network() {
+                        println!("{}", network);
+                    }
+                    if c.reveal {
+                        println!("number: {}", card.formatted_number());
+                    } else {
+                        println!("number: {}", card.masked_number());
+                    }
+                    println!("expiry: {}", card.expiry);
+                    match card.cvv {
+                        Some(ref cvv) if c.reveal => println!("cvv: {}", cvv),
+                        Some(_) => println!("cvv: ***"),
+                        None => {},
+                    }
+                    if let Some(ref holder) = card.holder {
+                        println!("holder: {}", holder);
+                    }
+                    if !c.reveal {
+                        return Ok(());
+                    }
+                    book.record_access(&c.name)?;
+                },
+                CardSubCommand::CopyNumber(ref c) => {
+                    confirm_presence(&args, &p)?;
+                    let item = book.get_item_by_name(&c.name)?;
+                    let card = item.card.ok_or_else(|| Error::ItemNoCard(c.name.clone()))?;
+                    clipboard::write(&card.number)?;
+                    book.record_access(&c.name)?;
+                },
+            }
+            write_changes(&args, &p, &pw, book)?;
+        },
+        SubCommand::Wifi(ref wc) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            match wc.subcommand {
+                WifiSubCommand::Add(ref c) => {
+                    validate::item_name(&c.name)?;
+                    book.verify_not_exists(&c.name)?;
+                    let passphrase = match c.password {
+                        Some(ref password) => Some(password.as_str().to_owned()),
+                        None if c.security == vpass::wifi::Security::None => None,
+                        None => Some(prompt_password(i18n::tr("Passphrase [network]:"))?),
+                    };
+                    let mut item = vpass::Item::new(&c.name);
+                    item.kind = ItemKind::Wifi;
+                    item.tags = c.tags.iter().cloned().collect();
+                    item.password = passphrase.map(|p| Password::new(&p));
+                    item.wifi = Some(vpass::Wifi {
+                        ssid: c.ssid.clone(),
+                        security: c.security,
+                        hidden: c.hidden,
+                    });
+                    book.add(item)?;
+                },
+                WifiSubCommand::Qr(ref c) => {
+                    let item = book.get_item_by_name(&c.name)?;
+                    let network = item.wifi.as_ref().ok_or_else(|| Error::ItemNoWifi(c.name.clone()))?;
+                    let passphrase = reveal_password(&args, &p, &item, &pw)?.map(|p| p.plaintext());
+                    let text = network.qr_text(passphrase.as_deref());
+                    if c.text {
+                        println!("{}", text);
+                    } else {
+                        print!("{}", wifi::render_qr(&text)?);
+                        println!("{}", network.ssid);
+                    }
+                    book.record_access(&c.name)?;
+                },
+                WifiSubCommand::Import(ref c) => {
+                    let mut count = 0;
+                    for network in wifi::known_networks(&args)? {
+                        let name = network.entry_name();
+                        if book.has_item(&name) {
+                            continue;
+                        }
+                        let mut item = vpass::Item::new(&name);
+                        item.kind = ItemKind::Wifi;
+                        item.tags = c.tags.iter().cloned().collect();
+                        item.password = network.passphrase.map(|p| Password::new(&p));
+                        item.wifi = Some(network.wifi);
+                        book.add(item)?;
+                        count += 1;
+                    }
+                    outln!("{}", i18n::trf("Imported {} entries", &[&count]));
+                },
+            }
+            write_changes(&args, &p, &pw, book)?;
+        },
+        SubCommand::SshAdd(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            confirm_presence(&args, &p)?;
+
             let item = book.get_item_by_name(&c.name)?;
-            if let Some(ref item_pw) = item.password {
-                clipboard::write(&item_pw.plaintext());
+            let key = item.ssh_key.ok_or_else(|| Error::ItemNoSshKey(c.name.clone()))?;
+            ssh::add_to_agent(&key.plaintext(), c.lifetime.as_deref())?;
+            book.record_access(&c.name)?;
+            write_changes(&args, &p, &pw, book)?;
+        },
+        SubCommand::SshAgent(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let book = attempts::read(&args, &p, &pw)?;
+            for name in &c.names {
+                book.verify_exists(name)?;
+            }
+            confirm_presence(&args, &p)?;
+
+            let mut keys = Vec::new();
+            for item in book.items() {
+                if !c.names.is_empty() && !c.names.iter().any(|n| book.names_match(n, &item.name)) {
+                    continue;
+                }
+                if let Some(ref key) = item.ssh_key {
+                    let key = ssh::Ed25519Key::parse(&key.plaintext())
+                        .ok_or_else(|| Error::SshKeyUnsupported(item.name.clone()))?;
+                    keys.push(key);
+                } else if !c.names.is_empty() {
+                    return Err(Error::ItemNoSshKey(item.name.clone()));
+                }
+            }
+            let socket = match c.socket {
+                Some(ref socket) => socket.clone(),
+                None => ssh::default_socket(paths::profile(&args)?.as_deref())?,
+            };
+            println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display());
+            std::io::stdout().flush()?;
+            let config = cfg::read(&args)?;
+            let timer = idle::IdleTimer::new(config.agent_lock_seconds)
+                .lock_on_screen_lock(config.lock_on_screen_lock);
+            ssh::serve(&socket, keys, timer)?;
+            eprintln!("{}", i18n::tr("Agent locked"));
+        },
+        SubCommand::Render(ref c) => {
+            let template = fs::read_to_string(&c.template)?;
+            // Check the template before unlocking anything
+            template::placeholders(&template)?;
+            // Vaults are unlocked on first use, and the default vault is keyed by `None`
+            let mut books: HashMap<Option<String>, (PathBuf, String, vpass::Book)> = HashMap::new();
+            let output = template::render(&template, |ph| {
+                if !books.contains_key(&ph.vault) {
+                    let (p, pw) = match ph.vault {
+                        None => (get_vault_path(&args)?, prompt_vault_password!()),
+                        Some(ref name) => {
+                            Vaults::new(&args)?.verify_exists(name)?;
+                            // A password given as an argument or in the environment is used for all vaults
+                            let pw = match given_password(&args)? {
+                                Some(pw) => pw,
+                                None => prompt_password(&i18n::trf("Password [{}]:", &[&name]))?,
+                            };
+                            (vault_path(&args, name)?, pw)
+                        },
+                    };
+                    let book = read_book(&args, &p, &pw)?;
+                    books.insert(ph.vault.clone(), (p, pw, book));
+                }
+                let (p, vault_pw, book) = books.get_mut(&ph.vault).unwrap();
+                field_value(&args, p, book, &ph.item, ph.field, vault_pw)
+            })?;
+            if let Some(ref out) = c.out {
+                write_private(out, &output)?;
             } else {
-                return Err(Error::ItemNoPasswordSet);
+                print!("{}", output);
+            }
+            for (_, (p, pw, book)) in books {
+                write_changes(&args, &p, &pw, book)?;
             }
         },
         SubCommand::Sync(ref sc) => match sc.subcommand {
             None => {
                 let p = get_vault_path(&args)?;
                 let pw = prompt_vault_password!();
-                let mut book = vpass::read(&p, &pw)?;
+                let mut book = read_book(&args, &p, &pw)?;
                 // TODO: non-default locations?
                 let name = p.file_name().unwrap().to_str().unwrap();
-                vpass::sync::vault(name, &mut book, &pw)?;
-                vpass::write(&p, &pw, book)?;
+                if let Some(snapshot) = sync_state::snapshot(&p)? {
+                    sync::cache_snapshot(name, snapshot);
+                }
+                let result = if sc.force_adopt {
+                    vpass::sync::vault_adopt(name, &mut book, &pw)
+                } else {
+                    vpass::sync::vault(name, &mut book, &pw)
+                };
+                if let Err(e) = result {
+                    // Also shown outside of the terminal, e.g. when run from a launcher or a script
+                    let vault = p.file_stem().unwrap().to_string_lossy();
+                    notify::send(&args, "vpass", &i18n::trf("Synchronization of {} failed", &[&vault]));
+                    return Err(e);
+                }
+                sync_state::update(&p, sync::remote_state(name, &book)?, sync::cached_snapshot(name))?;
+                write_changes(&args, &p, &pw, book)?;
             },
             Some(SyncSubCommand::Setup(ref c)) => {
                 let p = get_vault_path(&args)?;
                 let pw = prompt_vault_password!();
-                let mut book = vpass::read(&p, &pw)?;
+                let mut book = attempts::read(&args, &p, &pw)?;
                 if let Some(ref import_data) = c.import {
-                    vpass::sync::config::book_setup(&mut book, sync::transfer_string::decode(&import_data)?)?;
-                    vpass::write(&p, &pw, book)?;
+                    vpass::sync::config::book_setup(&mut book, decode_transfer_string(import_data)?)?;
+                    write_changes(&args, &p, &pw, book)?;
                 } else if let Some(ref json_data) = c.json {
                     vpass::sync::config::book_setup(&mut book, serde_json::from_str(json_data)?)?;
-                    vpass::write(&p, &pw, book)?;
+                    write_changes(&args, &p, &pw, book)?;
                 } else {
                     // Interactive setup
                     if let Some(config) = vpass::cli::interactive::sync_setup(&book)? {
                         vpass::sync::config::book_setup(&mut book, config)?;
-                        vpass::write(&p, &pw, book)?;
+                        write_changes(&args, &p, &pw, book)?;
                     } else {
-                        println!("Cancelled");
+                        println!("{}", i18n::tr("Cancelled"));
+                    }
+                }
+            },
+            Some(SyncSubCommand::RemoteList) => {
+                let p = get_vault_path(&args)?;
+                let pw = prompt_vault_password!();
+                let book = attempts::read(&args, &p, &pw)?;
+                if sync::config::book_read(&book)?.is_none() {
+                    return Err(Error::SyncNotSetUp);
+                }
+                for name in sync::remote_vaults(&book)? {
+                    println!("{}", name);
+                }
+            },
+            Some(SyncSubCommand::CheckRemote(ref c)) => {
+                let p = get_vault_path(&args)?;
+                let pw = prompt_vault_password!();
+                let book = attempts::read(&args, &p, &pw)?;
+                if sync::config::book_read(&book)?.is_none() {
+                    return Err(Error::SyncNotSetUp);
+                }
+                let local = Vaults::new(&args)?;
+                let checks = sync::check_remote(&book)?;
+                // Readable vaults without a local copy are probably left over from renames
+                let orphan = |check: &sync::RemoteKeyCheck| {
+                    check.status != sync::KeyStatus::Unreadable
+                        && check.vault.as_ref().is_some_and(|name| !local.contains(name))
+                };
+                if c.json {
+                    let j: Vec<_> = checks
+                        .iter()
+                        .map(|check| {
+                            json!({
+                                "key": check.key,
+                                "vault": check.vault,
+                                "status": <&'static str>::from(check.status),
+                                "orphan": orphan(check),
+                            })
+                        })
+                        .collect();
+                    println!("{}", serde_json::to_string(&j)?);
+                } else {
+                    for check in &checks {
+                        let status: &'static str = check.status.into();
+                        if orphan(check) && check.status == sync::KeyStatus::Valid {
+                            println!("{}\torphan", check.key);
+                        } else {
+                            println!("{}\t{}", check.key, status);
+                        }
+                        if !c.clean {
+                            continue;
+                        }
+                        match check.status {
+                            sync::KeyStatus::Valid | sync::KeyStatus::Invalid if orphan(check) => {
+                                let prompt = i18n::trf("Move {} to trash?", &[&check.key]);
+                                if interactive::prompt_boolean(&prompt)? {
+                                    sync::vault_trash(&check.key, &book)?;
+                                }
+                            },
+                            sync::KeyStatus::Dangling
+                                if interactive::prompt_boolean(&i18n::trf("Delete {}?", &[&check.key]))? =>
+                            {
+                                sync::delete_key(&check.key, &book)?;
+                            },
+                            _ => {},
+                        }
                     }
                 }
             },
             Some(SyncSubCommand::Export) => {
                 let p = get_vault_path(&args)?;
                 let pw = prompt_vault_password!();
-                let book = vpass::read(&p, &pw)?;
+                let book = attempts::read(&args, &p, &pw)?;
                 if let Some(s) = sync::config::book_read(&book)? {
                     println!("{}", sync::transfer_string::encode(&s));
                 } else {
@@ -511,43 +2390,443 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
             Some(SyncSubCommand::Detach) => {
                 let p = get_vault_path(&args)?;
                 let pw = prompt_vault_password!();
-                let mut book = vpass::read(&p, &pw)?;
+                let mut book = attempts::read(&args, &p, &pw)?;
                 vpass::sync::config::book_remove(&mut book)?;
-                vpass::write(&p, &pw, book)?;
+                write_changes(&args, &p, &pw, book)?;
             },
-            Some(SyncSubCommand::Delete) => {
+            Some(SyncSubCommand::Delete(ref c)) => {
                 let p = get_vault_path(&args)?;
-                println!("Confirm remote vault deletion:");
                 let pw = prompt_vault_password!();
-                let book = vpass::read(&p, &pw)?;
+                let mut book = attempts::read(&args, &p, &pw)?;
+                if sync::config::book_read(&book)?.is_none() {
+                    return Err(Error::SyncNotSetUp);
+                }
+                let prompt = i18n::tr(
+                    "Move the remote copy of this vault to trash? The local vault is kept, and `sync purge-trash` \
+                     deletes the remote copy permanently.",
+                );
+                if !c.yes && !interactive::prompt_boolean(prompt)? {
+                    println!("{}", i18n::tr("Cancelled"));
+                    return Ok(());
+                }
                 // TODO: non-default locations?
                 let name = p.file_name().unwrap().to_str().unwrap();
-                vpass::sync::vault_delete(name, &book)?;
+                vpass::sync::vault_trash(name, &book)?;
+                sync_state::remove(&p)?;
+                if c.detach {
+                    vpass::sync::config::book_remove(&mut book)?;
+                    write_changes(&args, &p, &pw, book)?;
+                }
             },
             Some(SyncSubCommand::Overwrite) => {
                 let p = get_vault_path(&args)?;
                 let pw = prompt_vault_password!();
-                let book = vpass::read(&p, &pw)?;
+                let book = attempts::read(&args, &p, &pw)?;
                 // TODO: non-default locations?
                 let name = p.file_name().unwrap().to_str().unwrap();
                 vpass::sync::vault_overwrite(name, &book, &pw)?;
+                sync_state::update(&p, sync::remote_state(name, &book)?, None)?;
+            },
+            Some(SyncSubCommand::Status(ref c)) => {
+                let p = get_vault_path(&args)?;
+                let pw = prompt_vault_password!();
+                let book = attempts::read(&args, &p, &pw)?;
+                if sync::config::book_read(&book)?.is_none() {
+                    return Err(Error::SyncNotSetUp);
+                }
+                // TODO: non-default locations?
+                let name = p.file_name().unwrap().to_str().unwrap();
+                let last = sync_state::read(&p)?;
+                let (remote, offline) = match sync::remote_state(name, &book) {
+                    Ok(remote) => (remote, false),
+                    // Connection failed, show the state at the last sync instead
+                    Err(Error::Sync(ref e)) if e.is_offline() && last.is_some() => (last.clone(), true),
+                    Err(Error::Io(ref e)) if sync::Error::is_offline_io(e) && last.is_some() => {
+                        (last.clone(), true)
+                    },
+                    Err(e) => return Err(e),
+                };
+                // `None` if this device hasn't synchronized the vault yet, or the remote is unreachable
+                let changed = last.filter(|_| !offline).map(|l| Some(&l) != remote.as_ref());
+                // Only downloaded on request, and when there is something to download
+                let changes = if c.changes && remote.is_some() && !offline {
+                    let config = sync::config::book_read(&book)?.ok_or(vpass::sync::Error::NoRemoteSet)?;
+                    Some(book.diff(&vpass::sync::download_book(name, config, &pw)?))
+                } else {
+                    None
+                };
+                if c.json {
+                    let j = json!({
+                        "remote": remote,
+                        "changed": changed,
+                        "offline": offline,
+                        "changes": changes.as_ref().map(|cs| cs.iter().map(change_json).collect::<Vec<_>>()),
+                    });
+                    println!("{}", serde_json::to_string(&j)?);
+                } else {
+                    if offline {
+                        println!("{}", i18n::tr("Remote unreachable, showing the state at the last sync"));
+                    }
+                    match remote {
+                        Some(ref r) => {
+                            println!("{}", i18n::trf("Remote: {} bytes, {} deltas", &[&r.size, &r.deltas]))
+                        },
+                        None => println!("{}", i18n::tr("Not on the remote")),
+                    }
+                    match changed {
+                        Some(false) => println!("{}", i18n::tr("Up to date")),
+                        Some(true) => println!("{}", i18n::tr("Remote has changed since the last sync")),
+                        None if offline => {},
+                        None => println!("{}", i18n::tr("Not synchronized on this device yet")),
+                    }
+                    for change in changes.iter().flatten() {
+                        println!("{}", change_line(change));
+                    }
+                }
+            },
+            Some(SyncSubCommand::Listen(ref c)) => {
+                let data_dir = paths::data_dir(&args)?;
+                let dir = c.dir.clone().unwrap_or_else(|| data_dir.join("lan"));
+                let port = c.port.unwrap_or(sync::providers::lan::DEFAULT_PORT);
+                sync::providers::lan::listen(&dir, &data_dir.join("lan_secret"), port)?;
+            },
+            Some(SyncSubCommand::ResolveConflicts) => {
+                let p = get_vault_path(&args)?;
+                let pw = prompt_vault_password!();
+                let mut book = read_book(&args, &p, &pw)?;
+                // TODO: non-default locations?
+                let name = p.file_name().unwrap().to_str().unwrap();
+                let (count, changes) = vpass::sync::resolve_conflicts(name, &mut book, &pw)?;
+                write_changes(&args, &p, &pw, book)?;
+                if !args.quiet {
+                    println!("{}", i18n::trf("Merged {} conflict copies", &[&count]));
+                    for change in &changes {
+                        println!("{}", change_line(change));
+                    }
+                }
+            },
+            Some(SyncSubCommand::Test) => {
+                let p = get_vault_path(&args)?;
+                let pw = prompt_vault_password!();
+                let book = attempts::read(&args, &p, &pw)?;
+                let mut failure = None;
+                for step in vpass::sync::diagnose(&book)? {
+                    let ms = step.duration.as_millis();
+                    match step.result {
+                        Ok(()) => println!("{}: ok ({} ms)", step.name, ms),
+                        Err(e) => {
+                            println!("{}: FAILED ({} ms)", step.name, ms);
+                            failure = Some(e);
+                        },
+                    }
+                }
+                if let Some(e) = failure {
+                    return Err(e.into());
+                }
+            },
+            Some(SyncSubCommand::PurgeTrash(ref c)) => {
+                let p = get_vault_path(&args)?;
+                let pw = prompt_vault_password!();
+                let book = attempts::read(&args, &p, &pw)?;
+                let key = if let Some(ref name) = c.name {
+                    validate::vault_name(name)?;
+                    vault_filename(name)
+                } else {
+                    // TODO: non-default locations?
+                    p.file_name().unwrap().to_str().unwrap().to_owned()
+                };
+                vpass::sync::purge_trash(&key, &book)?;
+            },
+            Some(SyncSubCommand::RotateRemoteKey(ref c)) => {
+                let p = get_vault_path(&args)?;
+                let pw = prompt_vault_password!();
+                let mut book = attempts::read(&args, &p, &pw)?;
+                // TODO: non-default locations?
+                let name = p.file_name().unwrap().to_str().unwrap();
+                let new_key = if c.disable {
+                    None
+                } else {
+                    Some(sync::remote_key::RemoteKey::generate())
+                };
+                vpass::sync::rotate_remote_key(name, &mut book, &pw, new_key)?;
+                write_changes(&args, &p, &pw, book)?;
+                if !args.quiet {
+                    println!("{}", i18n::tr("Remote key changed, import the vault again on other devices"));
+                }
+            },
+            Some(SyncSubCommand::SetPassword(ref c)) => {
+                let p = get_vault_path(&args)?;
+                let pw = prompt_vault_password!();
+                let mut book = attempts::read(&args, &p, &pw)?;
+                // TODO: non-default locations?
+                let name = p.file_name().unwrap().to_str().unwrap();
+                let sync_password = if c.disable {
+                    None
+                } else if let Some(ref x) = c.password {
+                    Some(x.as_str().to_owned())
+                } else {
+                    Some(prompt_password(i18n::tr("New sync password [vault]:"))?)
+                };
+                vpass::sync::set_sync_password(name, &mut book, &pw, sync_password)?;
+                write_changes(&args, &p, &pw, book)?;
+                if !args.quiet {
+                    println!(
+                        "{}",
+                        i18n::tr("Sync password changed, import the vault again on other devices")
+                    );
+                }
             },
             Some(SyncSubCommand::Show(ref c)) => {
                 let p = get_vault_path(&args)?;
                 let pw = prompt_vault_password!();
-                let book = vpass::read(&p, &pw)?;
-                if let Some(config) = vpass::sync::config::book_read(&book)? {
-                    if c.json {
-                        println!("{}", serde_json::to_string(&config).unwrap());
+                let book = attempts::read(&args, &p, &pw)?;
+                let config = match vpass::sync::config::book_read(&book)? {
+                    Some(config) => config,
+                    None if c.json => {
+                        println!("{{}}");
+                        return Ok(());
+                    },
+                    None => {
+                        println!("{}", i18n::tr("Synchronization not set up"));
+                        return Ok(());
+                    },
+                };
+                let data = if c.secrets {
+                    config.data.clone()
+                } else {
+                    config.service.configuration_redact(&config.data)
+                };
+                let remote_key = config.remote_key.as_ref().map(|key| {
+                    if c.secrets {
+                        base64::encode(key.as_bytes())
                     } else {
-                        println!("{:?}", config.service);
+                        sync::providers::REDACTED.to_owned()
                     }
-                } else if c.json {
-                    println!("{{}}");
+                });
+                let last_sync = sync_state::last_sync(&p)?;
+                if c.json {
+                    let j = json!({
+                        "service": config.service,
+                        "data": data,
+                        "remote_key": remote_key,
+                        "sync_password": config.password.is_some(),
+                        "last_sync": last_sync,
+                    });
+                    println!("{}", serde_json::to_string(&j)?);
+                } else {
+                    println!("{}", i18n::trf("Provider: {}", &[&config.service]));
+                    for (field, value) in data.as_object().into_iter().flatten() {
+                        match value.as_str() {
+                            Some(s) => println!("  {}: {}", field, s),
+                            None => println!("  {}: {}", field, value),
+                        }
+                    }
+                    match remote_key {
+                        Some(key) => println!("{}", i18n::trf("Remote key: {}", &[&key])),
+                        None => println!("{}", i18n::tr("Remote key encryption disabled")),
+                    }
+                    if config.password.is_some() {
+                        println!("{}", i18n::tr("Remote copy encrypted with a separate sync password"));
+                    }
+                    match last_sync {
+                        Some(time) => {
+                            let time = time.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M");
+                            println!("{}", i18n::trf("Last synchronized: {}", &[&time]));
+                        },
+                        None => println!("{}", i18n::tr("Not synchronized on this device yet")),
+                    }
+                }
+            },
+        },
+        SubCommand::Publish(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            let mut item = book.get_item_by_name(&c.name)?;
+            // Derived and protected passwords can't be revealed without this vault
+            item.password = reveal_password(&args, &p, &item, &pw)?;
+            item.derived = None;
+            item.protected = None;
+            let transfer_options = decode_transfer_string(&c.provider)?;
+            let passphrase = publish_passphrase(&c.passphrase)?;
+            let key = sync::item_key(c.key.as_ref().unwrap_or(&c.name));
+            sync::publish_item(&key, transfer_options, &item, &passphrase)?;
+            book.record_access(&c.name)?;
+            write_changes(&args, &p, &pw, book)?;
+        },
+        SubCommand::FetchItem(ref c) => {
+            let p = get_vault_path(&args)?;
+            let pw = prompt_vault_password!();
+            let mut book = read_book(&args, &p, &pw)?;
+            let transfer_options = decode_transfer_string(&c.provider)?;
+            let passphrase = publish_passphrase(&c.passphrase)?;
+            let item = sync::fetch_item(&sync::item_key(&c.key), transfer_options, &passphrase)?;
+            validate::item_name(&item.name)?;
+            if c.replace && book.has_item(&item.name) {
+                let name = item.name.clone();
+                book.modify_by_name(&name, |old| *old = item)?;
+            } else {
+                book.add(item)?;
+            }
+            write_changes(&args, &p, &pw, book)?;
+        },
+        SubCommand::Daemon(ref c) => match c.subcommand {
+            Some(DaemonSubCommand::Install(ref ic)) => {
+                let kind = daemon::ServiceKind::current().ok_or(Error::ServiceUnsupported)?;
+                // The service runs with `--no-input` and is restarted on failure, so it can't ask
+                let has_source = match args.password_source() {
+                    Some(opt::PasswordSource::File(_)) | Some(opt::PasswordSource::Credential(_)) => true,
+                    _ => cfg::read(&args)?.password_command.is_some(),
+                };
+                if !has_source {
+                    return Err(Error::ServiceNoPassword);
+                }
+                let content = daemon::service_file(kind, &std::env::current_exe()?, &args)?;
+                if ic.print {
+                    print!("{}", content);
                 } else {
-                    println!("Synchronization not set up");
+                    let path = kind.path(&args)?;
+                    fs::create_dir_all(path.parent().unwrap())?;
+                    fs::write(&path, content)?;
+                    let enable = kind.enable_command(&args)?;
+                    println!(
+                        "{}",
+                        i18n::trf("Installed {}, start it with `{}`", &[&path.display(), &enable])
+                    );
                 }
             },
+            None => {
+                let config = cfg::read(&args)?;
+                let mut names = if config.daemon_vaults.is_empty() {
+                    Vaults::new(&args)?.to_vec()
+                } else {
+                    config.daemon_vaults.clone()
+                };
+                // Without another source, passwords are asked once on startup and kept in memory
+                let mut passwords: HashMap<String, daemon::CachedPassword> = HashMap::new();
+                if given_password(&args)?.is_none() && config.password_command.is_none() {
+                    for name in &names {
+                        let pw = prompt_password(&i18n::trf("Password [{}]:", &[name]))?;
+                        passwords.insert(name.clone(), daemon::CachedPassword::new(pw));
+                    }
+                }
+                // Never touched, so the passwords are forgotten a fixed time after startup
+                let mut timer = idle::IdleTimer::new(config.daemon_lock_seconds)
+                    .lock_on_screen_lock(config.lock_on_screen_lock);
+                let password_for =
+                    |cached: Option<&daemon::CachedPassword>| -> VResult<daemon::CachedPassword> {
+                        let pw = match cached {
+                            Some(pw) => pw.as_str().to_owned(),
+                            None => match given_password(&args)? {
+                                Some(pw) => pw,
+                                None => cfg::command_password(&args)?.unwrap_or_default(),
+                            },
+                        };
+                        Ok(daemon::CachedPassword::new(pw))
+                    };
+                loop {
+                    let mut failed = Vec::new();
+                    for name in &names {
+                        let result = password_for(passwords.get(name))
+                            .and_then(|pw| daemon_sync(&args, name, pw.as_str()));
+                        match result {
+                            Ok(Some(0)) => {
+                                if !args.quiet {
+                                    println!("{}", i18n::trf("Synchronized {}", &[name]));
+                                }
+                            },
+                            Ok(Some(count)) => {
+                                let message = i18n::trf(
+                                    "{} conflict copies of {}, use `sync resolve-conflicts` to merge them",
+                                    &[&count, name],
+                                );
+                                eprintln!("{}", message);
+                                notify::send(&args, "vpass", &message);
+                            },
+                            Ok(None) => {},
+                            Err(e) => {
+                                let message = i18n::trf("Synchronization of {} failed", &[name]);
+                                eprintln!("{}: {:?}", message, e);
+                                notify::send(&args, "vpass", &message);
+                                // Retrying with the same password would fail again
+                                if let Error::WrongPassword = e {
+                                    failed.push(name.clone());
+                                }
+                            },
+                        }
+                    }
+                    names.retain(|name| !failed.contains(name));
+                    if names.is_empty() && !failed.is_empty() {
+                        return Err(Error::DaemonStopped);
+                    }
+                    if c.once || names.is_empty() {
+                        break;
+                    }
+                    let interval = std::time::Duration::from_secs(config.sync_interval);
+                    let wake = std::time::Instant::now() + interval;
+                    while std::time::Instant::now() < wake {
+                        std::thread::sleep(idle::CHECK_INTERVAL);
+                        if passwords.is_empty() || !timer.expired() {
+                            continue;
+                        }
+                        // Dropping the passwords overwrites them
+                        for (name, _) in passwords.drain() {
+                            names.retain(|n| *n != name);
+                            let message =
+                                i18n::trf("{} locked, restart the daemon to synchronize it", &[&name]);
+                            eprintln!("{}", message);
+                            notify::send(&args, "vpass", &message);
+                        }
+                        if names.is_empty() {
+                            return Err(Error::DaemonStopped);
+                        }
+                    }
+                }
+            },
+        },
+        SubCommand::Log(OptLog::Show(ref c)) => {
+            for entry in audit_log::read(&args)? {
+                if c.json {
+                    println!("{}", serde_json::to_string(&entry).unwrap());
+                } else {
+                    println!(
+                        "{} {} {}{}{}{}",
+                        entry.time,
+                        if entry.success { "ok" } else { "FAILED" },
+                        entry.command,
+                        entry.vault.map(|v| format!(" [{}]", v)).unwrap_or_default(),
+                        if entry.items.is_empty() { "" } else { ": " },
+                        entry.items.join(", ")
+                    );
+                }
+            }
+        },
+        SubCommand::Log(OptLog::Clear) => {
+            audit_log::clear(&args)?;
+        },
+        SubCommand::Profile(OptProfile::List(ref c)) => {
+            let selected = paths::profile(&args)?;
+            let profiles = paths::profiles(&args)?;
+            if c.json {
+                let j: Vec<_> = profiles
+                    .iter()
+                    .map(|name| json!({ "name": name, "selected": selected.as_ref() == Some(name) }))
+                    .collect();
+                println!("{}", serde_json::to_string(&j).unwrap());
+            } else {
+                for name in profiles {
+                    let marker = if selected.as_ref() == Some(&name) { "*" } else { " " };
+                    println!("{} {}", marker, name);
+                }
+            }
+        },
+        SubCommand::Profile(OptProfile::Use(ref c)) => {
+            if let Some(ref name) = c.name {
+                if !paths::profiles(&args)?.contains(name) {
+                    return Err(Error::ProfileNotFound(name.clone()));
+                }
+            }
+            paths::set_default_profile(&args, c.name.as_deref())?;
         },
         SubCommand::Config(ref c) => {
             let config = cfg::read(&args)?;
@@ -555,7 +2834,20 @@ fn run_command(args: opt::OptRoot) -> VResult<()> {
             if c.json {
                 println!("{}", String::from_utf8(config.to_json_bytes()).unwrap());
             } else if c.clear {
-                cfg::write(&args, cfg::Config::default())?;
+                // Device id is kept, so that earlier changes are still attributed to this device
+                cfg::write(
+                    &args,
+                    cfg::Config {
+                        device_id: config.device_id,
+                        ..cfg::Config::default()
+                    },
+                )?;
+            } else if c.encrypt || c.decrypt {
+                cfg::set_encrypted(&args, c.encrypt)?;
+            } else if let Some(ref assignment) = c.set {
+                let mut config = config;
+                config.set(assignment)?;
+                cfg::write(&args, config)?;
             } else {
                 println!("{}", config.to_json_pretty());
             }
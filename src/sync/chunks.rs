@@ -0,0 +1,180 @@
+//! Splits values larger than the object size limit of a provider into multiple keys.
+//! The original key then stores a small manifest listing the chunks.
+//! Chunks of each write get a fresh id, so replacing the manifest
+//! switches to the new chunks atomically, and old chunks are removed afterwards.
+
+use super::progress::{self, Phase, Progress};
+use super::{Error, SyncProvider, SyncResult, UpdateKey};
+
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// Marks a value that has been split into chunks
+const MANIFEST_PREFIX: &[u8] = b"VPASS_CHUNKED:";
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+struct Manifest {
+    /// Unique for each write
+    id: Uuid,
+    /// Number of chunks
+    count: usize,
+}
+impl Manifest {
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut result = MANIFEST_PREFIX.to_vec();
+        result.extend(serde_json::to_vec(self).unwrap());
+        result
+    }
+
+    /// Returns `None` if the value is not a chunk manifest
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        if data.starts_with(MANIFEST_PREFIX) {
+            serde_json::from_slice(&data[MANIFEST_PREFIX.len()..]).ok()
+        } else {
+            None
+        }
+    }
+
+    fn chunk_keys(&self, key: &str) -> Vec<String> {
+        (0..self.count)
+            .map(|i| format!("{}.chunk.{}.{}", key, self.id, i))
+            .collect()
+    }
+}
+
+/// Writes chunks if required, and returns the value to be stored in the key itself
+fn split(sp: &mut dyn SyncProvider, key: &str, data: Vec<u8>) -> SyncResult<Vec<u8>> {
+    match sp.capabilities().max_object_size {
+        Some(max_size) if data.len() > max_size => {
+            let manifest = Manifest {
+                id: Uuid::new_v4(),
+                count: data.len().div_ceil(max_size),
+            };
+            debug!("Splitting {} into {} chunks", key, manifest.count);
+            let mut done = 0;
+            for (chunk_key, chunk) in manifest.chunk_keys(key).iter().zip(data.chunks(max_size)) {
+                sp.create(chunk_key, chunk.to_vec())?;
+                done += chunk.len();
+                progress::report(Progress::Transfer {
+                    done: done as u64,
+                    total: Some(data.len() as u64),
+                });
+            }
+            Ok(manifest.to_bytes())
+        },
+        _ => Ok(data),
+    }
+}
+
+/// Removes chunks referred by a stored value, if any
+pub fn remove_chunks(sp: &mut dyn SyncProvider, key: &str, stored: &[u8]) -> SyncResult<()> {
+    if let Some(manifest) = Manifest::from_bytes(stored) {
+        if !sp.capabilities().delete {
+            warn!("Provider doesn't support deletion, leaving old chunks of {}", key);
+            return Ok(());
+        }
+        for chunk_key in manifest.chunk_keys(key) {
+            if let Ok((_, update_key)) = sp.stat(&chunk_key) {
+                sp.delete(&chunk_key, update_key)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read a value, joining chunks if it has been split.
+/// The returned UpdateKey is the key of the manifest.
+pub fn read(sp: &mut dyn SyncProvider, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
+    progress::report(Progress::Phase(Phase::Read));
+    let (stored, update_key) = sp.read(key)?;
+    if let Some(manifest) = Manifest::from_bytes(&stored) {
+        let mut result = Vec::new();
+        for chunk_key in manifest.chunk_keys(key) {
+            let (chunk, _) = sp.read(&chunk_key).map_err(|e| match e {
+                Error::NoSuchKey(_) => Error::InvalidRemote,
+                other => other,
+            })?;
+            result.extend(chunk);
+            progress::report(Progress::Transfer {
+                done: result.len() as u64,
+                total: None,
+            });
+        }
+        Ok((result, update_key))
+    } else {
+        Ok((stored, update_key))
+    }
+}
+
+/// Create a new key, splitting the value if required
+pub fn create(sp: &mut dyn SyncProvider, key: &str, data: Vec<u8>) -> SyncResult<()> {
+    progress::report(Progress::Phase(Phase::Write));
+    let stored = split(sp, key, data)?;
+    match sp.create(key, stored.clone()) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            // Best-effort cleanup of the new chunks
+            let _ = remove_chunks(sp, key, &stored);
+            Err(e)
+        },
+    }
+}
+
+/// Update a key, splitting the value if required, and remove chunks of the previous value.
+/// A previous value encrypted with another remote key is replaced without removing its chunks.
+pub fn update(sp: &mut dyn SyncProvider, key: &str, data: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
+    let old_stored = match sp.read(key) {
+        Ok((stored, _)) => stored,
+        Err(Error::RemoteDecryption) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    progress::report(Progress::Phase(Phase::Write));
+    let stored = split(sp, key, data)?;
+    match sp.update(key, stored.clone(), update_key) {
+        Ok(()) => remove_chunks(sp, key, &old_stored),
+        Err(e) => {
+            // Best-effort cleanup of the new chunks
+            let _ = remove_chunks(sp, key, &stored);
+            Err(e)
+        },
+    }
+}
+
+/// Delete a key and its chunks
+pub fn delete(sp: &mut dyn SyncProvider, key: &str, update_key: UpdateKey) -> SyncResult<()> {
+    let (old_stored, _) = sp.read(key)?;
+    progress::report(Progress::Phase(Phase::Delete));
+    sp.delete(key, update_key)?;
+    remove_chunks(sp, key, &old_stored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::providers::mock::Mock;
+    use super::super::{Capabilities, SyncProvider};
+    use super::{create, delete, read, update, Manifest};
+
+    #[test]
+    fn chunked_roundtrip() {
+        let mut mock = Mock::with_capabilities(Capabilities {
+            max_object_size: Some(4),
+            ..Capabilities::default()
+        });
+
+        let data: Vec<u8> = (0..10).collect();
+        create(&mut mock, "key", data.clone()).unwrap();
+        let (stored, update_key) = mock.read("key").unwrap();
+        assert_eq!(Manifest::from_bytes(&stored).map(|m| m.count), Some(3));
+        assert_eq!(read(&mut mock, "key").unwrap(), (data, update_key.clone()));
+
+        let data2: Vec<u8> = (0..6).collect();
+        update(&mut mock, "key", data2.clone(), update_key).unwrap();
+        let (read2, update_key2) = read(&mut mock, "key").unwrap();
+        assert_eq!(read2, data2);
+        assert_eq!(mock.item_count(), 3);
+
+        delete(&mut mock, "key", update_key2).unwrap();
+        assert_eq!(mock.item_count(), 0);
+    }
+}
@@ -1,10 +1,12 @@
-use log::debug;
 use serde::{Deserialize, Serialize};
 use serde_json::Value as JsonValue;
+use tracing::debug;
 
+use super::instrumented::Instrumented;
 use super::providers::Provider;
-use super::Error;
-use crate::{backend::book::Item, backend::book::Password, Book, Error as VError, VResult};
+use super::remote_key::{self, Encrypted, RemoteKey};
+use super::{Error, SyncProvider, SyncResult};
+use crate::{Book, Error as VError, VResult};
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct SyncConfig {
@@ -12,15 +14,39 @@ pub struct SyncConfig {
     pub service: Provider,
     /// Per-service persistent configuration data
     pub data: serde_json::Value,
+    /// Additional encryption for remote data, if enabled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_key: Option<RemoteKey>,
+    /// Password of the remote copy, if different from the vault password.
+    /// Not included in transfer strings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
     // TODO: Multiple services? services: Vec<ServiceConfig>
     // TODO: Allow write-only providers for backups? push_only: bool
     // TODO: Change remote key name to be diffrent than the name of the book filename?
 }
+/// Set in the tag byte of compressed config if a remote key follows the tag
+const TAG_FLAG_REMOTE_KEY: u8 = 0x80;
+
 impl SyncConfig {
+    /// Load the provider, wrapped in the remote key encryption layer if enabled,
+    /// and in the logging and timing layer
+    pub fn load(&self) -> SyncResult<Box<dyn SyncProvider>> {
+        let mut service = self.service.load(&self.data)?;
+        if let Some(ref key) = self.remote_key {
+            service = Box::new(Encrypted::new(service, key.clone()));
+        }
+        Ok(Box::new(Instrumented::new(service)))
+    }
+
     pub fn compress(&self) -> Vec<u8> {
         use strum::EnumProperty;
         let tag: u8 = self.service.get_str("tag").unwrap().parse().unwrap();
         let mut result = vec![tag];
+        if let Some(ref key) = self.remote_key {
+            result[0] |= TAG_FLAG_REMOTE_KEY;
+            result.extend(key.as_bytes());
+        }
         let data = self.service.configuration_compress(&self.data);
         result.extend(data);
         result
@@ -29,11 +55,24 @@ impl SyncConfig {
     pub fn decompress(data: &[u8]) -> VResult<Self> {
         use strum::{EnumProperty, IntoEnumIterator};
 
+        if data.is_empty() {
+            return Err(VError::SynchronizationTransferString);
+        }
+        let (remote_key, rest) = if data[0] & TAG_FLAG_REMOTE_KEY != 0 {
+            let end = 1 + remote_key::KEY_BYTES;
+            let key_bytes = data.get(1..end).ok_or(VError::SynchronizationTransferString)?;
+            (Some(RemoteKey::from_bytes(key_bytes).unwrap()), &data[end..])
+        } else {
+            (None, &data[1..])
+        };
+
         for service in Provider::iter() {
-            if service.get_str("tag").unwrap().parse::<u8>().unwrap() == data[0] {
+            if service.get_str("tag").unwrap().parse::<u8>().unwrap() == data[0] & !TAG_FLAG_REMOTE_KEY {
                 return Ok(Self {
                     service,
-                    data: service.configuration_decompress(&data[1..])?,
+                    data: service.configuration_decompress(rest)?,
+                    remote_key,
+                    password: None,
                 });
             }
         }
@@ -41,26 +80,71 @@ impl SyncConfig {
     }
 }
 
-const ITEM_NAME_SYNC_CONFIG: &str = "vpass/sync_config.json";
+/// Key of the synchronization configuration in book metadata
+const METADATA_KEY: &str = "sync_config";
+
+/// Synchronization configuration used to be stored as the password of this item
+const LEGACY_ITEM_NAME: &str = "vpass/sync_config.json";
 
 /// Read synchronization configuration from a book
 pub fn book_read(book: &Book) -> VResult<Option<SyncConfig>> {
-    if !book.has_item(ITEM_NAME_SYNC_CONFIG) {
-        debug!("Syncronization configuration not set for the book");
-        Ok(None)
-    } else {
-        let item = book.get_item_by_name(ITEM_NAME_SYNC_CONFIG).unwrap();
+    if let Some(value) = book.metadata(METADATA_KEY) {
+        Ok(Some(
+            serde_json::from_value(value.clone()).map_err(|_| Error::ConfigurationItem)?,
+        ))
+    } else if book.has_item(LEGACY_ITEM_NAME) {
+        let item = book.get_item_by_name(LEGACY_ITEM_NAME).unwrap();
         let config_data = item.password.ok_or(Error::ConfigurationItem)?.plaintext();
         Ok(Some(
             serde_json::from_str(&config_data).map_err(|_| Error::ConfigurationItem)?,
         ))
+    } else {
+        debug!("Syncronization configuration not set for the book");
+        Ok(None)
+    }
+}
+
+/// Write configuration to book metadata, purging the legacy item,
+/// so that the credentials in its history don't stay in the book
+fn book_write(book: &mut Book, cfg: &SyncConfig) -> VResult<()> {
+    if book.has_item(LEGACY_ITEM_NAME) {
+        book.purge(LEGACY_ITEM_NAME)?;
     }
+    book.set_metadata(METADATA_KEY, serde_json::to_value(cfg).unwrap());
+    Ok(())
+}
+
+/// Move configuration from the legacy item to book metadata.
+/// Returns true if the book was changed.
+pub fn book_migrate(book: &mut Book) -> VResult<bool> {
+    if book.metadata(METADATA_KEY).is_some() || !book.has_item(LEGACY_ITEM_NAME) {
+        return Ok(false);
+    }
+    let cfg = book_read(book)?.unwrap();
+    book_write(book, &cfg)?;
+    Ok(true)
+}
+
+/// Replace the remote key in the synchronization configuration, without checking the remote
+pub fn book_set_remote_key(book: &mut Book, remote_key: Option<RemoteKey>) -> VResult<()> {
+    let mut cfg = book_read(book)?.ok_or(Error::NoRemoteSet)?;
+    cfg.remote_key = remote_key;
+    book_write(book, &cfg)
+}
+
+/// Replace the sync password in the synchronization configuration, without checking the remote
+pub fn book_set_password(book: &mut Book, password: Option<String>) -> VResult<()> {
+    let mut cfg = book_read(book)?.ok_or(Error::NoRemoteSet)?;
+    cfg.password = password;
+    book_write(book, &cfg)
 }
 
 /// Remove synchronization configuration from a book
 pub fn book_remove(book: &mut Book) -> VResult<()> {
-    // Only error Book::remove can give is missing file, which is ok here
-    let _ = book.remove(ITEM_NAME_SYNC_CONFIG);
+    book.remove_metadata(METADATA_KEY);
+    if book.has_item(LEGACY_ITEM_NAME) {
+        book.purge(LEGACY_ITEM_NAME)?;
+    }
     Ok(())
 }
 
@@ -68,15 +152,44 @@ pub fn book_remove(book: &mut Book) -> VResult<()> {
 /// overwriting any previous value
 pub fn book_setup(book: &mut Book, cfg: SyncConfig) -> VResult<()> {
     // Verify config validity
-    let mut service = cfg.service.load(&cfg.data);
+    let mut service = cfg.load()?;
     // Check credentials
     (*service).ping()?;
     // Actually write to the book
-    if book.has_item(ITEM_NAME_SYNC_CONFIG) {
-        book.remove(ITEM_NAME_SYNC_CONFIG)?;
+    book_write(book, &cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{book_migrate, book_read, book_remove, SyncConfig, LEGACY_ITEM_NAME};
+    use crate::sync::providers::Provider;
+    use crate::{Book, Event, Item, Password};
+
+    #[test]
+    fn migrate_legacy_item() {
+        let cfg = SyncConfig {
+            service: Provider::Mock,
+            data: serde_json::json!({}),
+            remote_key: None,
+            password: None,
+        };
+        let mut book = Book::new();
+        let mut item = Item::new(LEGACY_ITEM_NAME);
+        item.password = Some(Password::new(&serde_json::to_string(&cfg).unwrap()));
+        book.add(item).unwrap();
+        assert_eq!(book_read(&book).unwrap(), Some(cfg.clone()));
+
+        assert!(book_migrate(&mut book).unwrap());
+        assert!(!book.has_item(LEGACY_ITEM_NAME));
+        // The credentials don't remain in the history
+        assert!(book.events().all(|ef| match ef.event() {
+            Event::Update(_, item) => item.name != LEGACY_ITEM_NAME,
+            _ => true,
+        }));
+        assert_eq!(book_read(&book).unwrap(), Some(cfg));
+        assert!(!book_migrate(&mut book).unwrap());
+
+        book_remove(&mut book).unwrap();
+        assert_eq!(book_read(&book).unwrap(), None);
     }
-    let mut item = Item::new(ITEM_NAME_SYNC_CONFIG);
-    item.password = Some(Password::new(&serde_json::to_string(&cfg).unwrap()));
-    book.add(item)?;
-    Ok(())
 }
@@ -0,0 +1,346 @@
+//! Delta synchronization: changes are uploaded as encrypted event tails
+//! next to the full snapshot, instead of re-uploading the whole vault every time.
+//! Deltas are stored in keys `{key}.delta.{n}`, and each one records the history it applies on.
+//! After `SNAPSHOT_INTERVAL` deltas a new full snapshot is written, and the deltas are removed.
+//! A delta and a snapshot pushed concurrently are merged by whichever finishes last.
+
+use super::{
+    cache_snapshot, cached_snapshot, chunks, forget_snapshot, Error, Snapshot, SyncProvider, UpdateKey,
+};
+use crate::backend::book::BookDelta;
+use crate::backend::vault::{EncryptedVault, Vault};
+use crate::{Book, Error as VError, VResult};
+
+use tracing::debug;
+
+/// Number of deltas after which a full snapshot is written
+const SNAPSHOT_INTERVAL: usize = 16;
+
+fn delta_key(key: &str, index: usize) -> String {
+    format!("{}.delta.{}", key, index)
+}
+
+fn encrypt_delta(password: &str, delta: BookDelta) -> Vec<u8> {
+    Vault::new(delta).encrypt(password).to_bytes()
+}
+
+fn decrypt_delta(data: &[u8], password: &str) -> VResult<BookDelta> {
+    Ok(EncryptedVault::from_bytes(data)
+        .map_err(|_| VError::VaultCorrupted)?
+        .decrypt(password)
+        .ok_or(VError::WrongPassword)?
+        .content)
+}
+
+/// Remote version of a book, snapshot and deltas combined
+#[derive(Debug)]
+pub struct Remote {
+    pub book: Book,
+    /// UpdateKey of the snapshot
+    update_key: UpdateKey,
+    /// Number of deltas applied on the snapshot
+    delta_count: usize,
+    /// Leftover deltas from a previous snapshot were found
+    stale: bool,
+}
+
+/// Read the snapshot, or use the cached one if it's still current.
+/// Returns `Ok(None)` if the key doesn't exist.
+fn read_snapshot(
+    sp: &mut dyn SyncProvider, key: &str, password: &str,
+) -> VResult<Option<(Book, UpdateKey)>> {
+    if let Some(cached) = cached_snapshot(key) {
+        match sp.stat(key) {
+            Ok((_, update_key)) if update_key == cached.update_key => {
+                // Fails if the vault password has changed since, then the snapshot is downloaded
+                if let Ok(book) = crate::decrypt(&cached.data, password) {
+                    debug!("Using cached snapshot of {}", key);
+                    return Ok(Some((book, update_key)));
+                }
+            },
+            Ok(_) => {},
+            Err(Error::NoSuchKey(_)) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let (data, update_key) = match chunks::read(sp, key) {
+        Ok(r) => r,
+        Err(Error::NoSuchKey(_)) => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let book = crate::decrypt(&data, password)?;
+    cache_snapshot(key, Snapshot {
+        update_key: update_key.clone(),
+        data,
+    });
+    Ok(Some((book, update_key)))
+}
+
+/// Read the snapshot and apply all deltas.
+/// Returns `Ok(None)` if the key doesn't exist.
+pub fn read(sp: &mut dyn SyncProvider, key: &str, password: &str) -> VResult<Option<Remote>> {
+    let (book, update_key) = match read_snapshot(sp, key, password)? {
+        Some(r) => r,
+        None => return Ok(None),
+    };
+
+    let mut remote = Remote {
+        book,
+        update_key,
+        delta_count: 0,
+        stale: false,
+    };
+    apply_deltas(sp, key, password, &mut remote)?;
+    Ok(Some(remote))
+}
+
+/// Apply the deltas following the ones already applied on `remote`
+fn apply_deltas(sp: &mut dyn SyncProvider, key: &str, password: &str, remote: &mut Remote) -> VResult<()> {
+    loop {
+        let dkey = delta_key(key, remote.delta_count);
+        let delta_data = match chunks::read(sp, &dkey) {
+            Ok((delta_data, _)) => delta_data,
+            Err(Error::NoSuchKey(_)) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let delta = decrypt_delta(&delta_data, remote.book.content_password(password))?;
+        match remote.book.apply_delta(delta) {
+            Ok(()) => remote.delta_count += 1,
+            Err(_) => {
+                // Snapshot was rewritten, but removing old deltas didn't complete
+                debug!("Stale delta {}", dkey);
+                remote.stale = true;
+                break;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Write a new version of the book, as a delta if possible.
+/// `book` must be a merge result of the remote version.
+pub fn write(sp: &mut dyn SyncProvider, key: &str, remote: Remote, book: &Book, password: &str) -> VResult<()> {
+    if !remote.stale && sp.capabilities().delete && remote.delta_count < SNAPSHOT_INTERVAL {
+        if let Some(delta) = book.delta_since(&remote.book) {
+            let dkey = delta_key(key, remote.delta_count);
+            debug!("Writing delta {}", dkey);
+            // Fails with `KeyAlreadyExists` if someone else pushed a delta after the remote was read
+            chunks::create(sp, &dkey, encrypt_delta(book.content_password(password), delta))?;
+            // A snapshot written after the remote was read has removed the deltas this one applies on,
+            // so it would be ignored as stale. It is merged with that snapshot instead.
+            if sp.stat(key)?.1 == remote.update_key {
+                return Ok(());
+            }
+            debug!("Snapshot {} was replaced while writing {}", key, dkey);
+            if let Ok((_, update_key)) = sp.stat(&dkey) {
+                chunks::delete(sp, &dkey, update_key)?;
+            }
+            let current = read(sp, key, password)?.ok_or_else(|| Error::NoSuchKey(key.to_owned()))?;
+            let merged = current.book.clone().merge_versions(book)?;
+            return write_snapshot(sp, key, current, &merged, password);
+        }
+    }
+    write_snapshot(sp, key, remote, book, password)
+}
+
+/// Replace the snapshot and remove the deltas.
+/// Deltas pushed after the remote was read are merged first, as they are not in `book`.
+fn write_snapshot(
+    sp: &mut dyn SyncProvider, key: &str, mut remote: Remote, book: &Book, password: &str,
+) -> VResult<()> {
+    debug!("Writing full snapshot {}", key);
+    let data = crate::encrypt(password, book.clone())?;
+    forget_snapshot(key);
+    chunks::update(sp, key, data, remote.update_key.clone())?;
+    // Deltas pushed later see the new snapshot when writing, and merge with it themselves
+    if !remote.stale {
+        let read_count = remote.delta_count;
+        apply_deltas(sp, key, password, &mut remote)?;
+        if remote.delta_count > read_count {
+            debug!("Merging {} deltas pushed while writing {}", remote.delta_count - read_count, key);
+            let data = crate::encrypt(password, remote.book.merge_versions(book)?)?;
+            let (_, update_key) = sp.stat(key)?;
+            chunks::update(sp, key, data, update_key)?;
+        }
+    }
+    remove_all(sp, key)?;
+    Ok(())
+}
+
+/// Keys of all stored deltas, without decrypting them
+pub fn keys(sp: &mut dyn SyncProvider, key: &str) -> VResult<Vec<String>> {
+    let mut result = Vec::new();
+    loop {
+        let dkey = delta_key(key, result.len());
+        if !sp.exists(&dkey)? {
+            return Ok(result);
+        }
+        result.push(dkey);
+    }
+}
+
+/// Remove all deltas of a key
+pub fn remove_all(sp: &mut dyn SyncProvider, key: &str) -> VResult<()> {
+    if !sp.capabilities().delete {
+        return Ok(());
+    }
+    for dkey in keys(sp, key)? {
+        let (_, update_key) = sp.stat(&dkey)?;
+        chunks::delete(sp, &dkey, update_key)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::providers::mock::Mock;
+    use super::super::{
+        cache_snapshot, cached_snapshot, chunks, forget_snapshot, Snapshot, SyncProvider, UpdateKey,
+    };
+    use super::{read, write, write_snapshot};
+    use crate::{Book, Item};
+
+    #[test]
+    fn delta_roundtrip() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut mock = Mock::with_capabilities(Default::default());
+        let mut book = Book::new();
+        book.add(Item::new("Test 1")).unwrap();
+        chunks::create(&mut mock, "key", crate::encrypt("password", book.clone()).unwrap()).unwrap();
+
+        let remote = read(&mut mock, "key", "password").unwrap().unwrap();
+        book.add(Item::new("Test 2")).unwrap();
+        write(&mut mock, "key", remote, &book, "password").unwrap();
+        assert!(mock.exists("key.delta.0").unwrap());
+
+        let remote = read(&mut mock, "key", "password").unwrap().unwrap();
+        assert_eq!(remote.book, book);
+        assert_eq!(remote.delta_count, 1);
+    }
+
+    #[test]
+    fn concurrent_delta() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut mock = Mock::with_capabilities(Default::default());
+        let book = Book::new();
+        chunks::create(&mut mock, "concurrent", crate::encrypt("password", book.clone()).unwrap()).unwrap();
+
+        let remote1 = read(&mut mock, "concurrent", "password").unwrap().unwrap();
+        let remote2 = read(&mut mock, "concurrent", "password").unwrap().unwrap();
+        let mut book1 = book.clone();
+        book1.add(Item::new("Test 1")).unwrap();
+        let mut book2 = book;
+        book2.add(Item::new("Test 2")).unwrap();
+        write(&mut mock, "concurrent", remote1, &book1, "password").unwrap();
+        assert!(write(&mut mock, "concurrent", remote2, &book2, "password").is_err());
+        assert_eq!(read(&mut mock, "concurrent", "password").unwrap().unwrap().book, book1);
+    }
+
+    #[test]
+    fn snapshot_replaced_before_delta() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut mock = Mock::with_capabilities(Default::default());
+        let book = Book::new();
+        chunks::create(&mut mock, "replaced", crate::encrypt("password", book.clone()).unwrap()).unwrap();
+
+        // A snapshot is written and the deltas removed between reading the remote and pushing a delta
+        let remote1 = read(&mut mock, "replaced", "password").unwrap().unwrap();
+        let remote2 = read(&mut mock, "replaced", "password").unwrap().unwrap();
+        let mut book1 = book.clone();
+        book1.add(Item::new("Test 1")).unwrap();
+        let mut book2 = book;
+        book2.add(Item::new("Test 2")).unwrap();
+        write_snapshot(&mut mock, "replaced", remote2, &book2, "password").unwrap();
+        write(&mut mock, "replaced", remote1, &book1, "password").unwrap();
+
+        let remote = read(&mut mock, "replaced", "password").unwrap().unwrap();
+        assert!(remote.book.has_item("Test 1"));
+        assert!(remote.book.has_item("Test 2"));
+        assert!(!mock.exists("replaced.delta.0").unwrap());
+    }
+
+    #[test]
+    fn delta_pushed_before_snapshot() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut mock = Mock::with_capabilities(Default::default());
+        let book = Book::new();
+        chunks::create(&mut mock, "pushed", crate::encrypt("password", book.clone()).unwrap()).unwrap();
+
+        // A delta is pushed between reading the remote and writing a snapshot
+        let remote1 = read(&mut mock, "pushed", "password").unwrap().unwrap();
+        let remote2 = read(&mut mock, "pushed", "password").unwrap().unwrap();
+        let mut book1 = book.clone();
+        book1.add(Item::new("Test 1")).unwrap();
+        let mut book2 = book;
+        book2.add(Item::new("Test 2")).unwrap();
+        write(&mut mock, "pushed", remote1, &book1, "password").unwrap();
+        write_snapshot(&mut mock, "pushed", remote2, &book2, "password").unwrap();
+
+        let remote = read(&mut mock, "pushed", "password").unwrap().unwrap();
+        assert!(remote.book.has_item("Test 1"));
+        assert!(remote.book.has_item("Test 2"));
+        assert_eq!(remote.delta_count, 0);
+    }
+
+    #[test]
+    fn stale_delta_same_length() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut mock = Mock::with_capabilities(Default::default());
+        let base = Book::new();
+        let mut book = base.clone();
+        book.add(Item::new("Old")).unwrap();
+        chunks::create(&mut mock, "stale", crate::encrypt("password", book.clone()).unwrap()).unwrap();
+        let remote = read(&mut mock, "stale", "password").unwrap().unwrap();
+        let mut with_delta = book;
+        with_delta.add(Item::new("Delta")).unwrap();
+        write(&mut mock, "stale", remote, &with_delta, "password").unwrap();
+
+        // Snapshot rewritten with the same number of events, but the delta was left behind
+        let mut rewritten = base;
+        rewritten.add(Item::new("New")).unwrap();
+        let (_, update_key) = mock.stat("stale").unwrap();
+        forget_snapshot("stale");
+        chunks::update(&mut mock, "stale", crate::encrypt("password", rewritten.clone()).unwrap(), update_key)
+            .unwrap();
+
+        let remote = read(&mut mock, "stale", "password").unwrap().unwrap();
+        assert!(remote.stale);
+        assert_eq!(remote.book, rewritten);
+    }
+
+    #[test]
+    fn cached_snapshot_reuse() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut mock = Mock::with_capabilities(Default::default());
+        let mut book = Book::new();
+        book.add(Item::new("Remote")).unwrap();
+        chunks::create(&mut mock, "cached", crate::encrypt("password", book.clone()).unwrap()).unwrap();
+        read(&mut mock, "cached", "password").unwrap();
+        let snapshot = cached_snapshot("cached").unwrap();
+        assert_eq!(snapshot.update_key, mock.stat("cached").unwrap().1);
+
+        // A cached snapshot with the current UpdateKey is used instead of the remote one
+        let mut local = Book::new();
+        local.add(Item::new("Cached")).unwrap();
+        cache_snapshot("cached", Snapshot {
+            update_key: snapshot.update_key.clone(),
+            data: crate::encrypt("password", local.clone()).unwrap(),
+        });
+        assert_eq!(read(&mut mock, "cached", "password").unwrap().unwrap().book, local);
+
+        // Outdated ones are not
+        cache_snapshot("cached", Snapshot {
+            update_key: UpdateKey::from_byte(0),
+            data: crate::encrypt("password", local).unwrap(),
+        });
+        assert_eq!(read(&mut mock, "cached", "password").unwrap().unwrap().book, book);
+        assert_eq!(cached_snapshot("cached").unwrap(), snapshot);
+    }
+}
@@ -10,6 +10,8 @@ pub enum Error {
     NoSuchKey(String),
     /// HTTP connection error
     Http(reqwest::Error),
+    /// Redis connection or protocol error
+    Redis(redis::RedisError),
     /// HTTP status code implies error.
     /// These should usually be handled before user sees them.
     HttpStatus(u16, Option<Value>),
@@ -31,6 +33,44 @@ pub enum Error {
     InsufficientSecurity(String),
     /// Key already exists, not overwriting
     KeyAlreadyExists(String),
+    /// Value is larger than the provider accepts (limit in bytes)
+    ObjectTooLarge(usize),
+    /// Remote data couldn't be decrypted with the remote key
+    RemoteDecryption,
+    /// Provider returned a response in an unexpected format
+    ProviderProtocol(String),
+    /// Operation not supported by the provider
+    Unsupported(String),
+    /// Remote is locked by another host (hostname, since)
+    RemoteLocked(String, DateTime<Utc>),
+    /// Shared vaults are encrypted with a random content key, which a sync password can't replace
+    SharedSyncPassword,
+}
+impl Error {
+    /// The remote couldn't be reached at all, e.g. no network connection or an unmounted drive,
+    /// as opposed to the remote refusing or failing the request
+    pub fn is_offline(&self) -> bool {
+        match self {
+            // Status codes are reported separately, so these are connection errors
+            Error::Http(e) => e.is_http() || e.is_timeout(),
+            Error::Redis(e) => e.is_io_error() || e.is_connection_refusal() || e.is_timeout(),
+            Error::Io(e) => Self::is_offline_io(e),
+            _ => false,
+        }
+    }
+
+    /// Same as `is_offline`, for IO errors of a provider,
+    /// which are converted to plain IO errors when passed to the CLI
+    pub fn is_offline_io(error: &std::io::Error) -> bool {
+        use std::io::ErrorKind::*;
+        match error.kind() {
+            // Missing sync directory
+            NotFound => true,
+            ConnectionRefused | ConnectionReset | ConnectionAborted | NotConnected => true,
+            AddrNotAvailable | BrokenPipe | TimedOut | WouldBlock | UnexpectedEof => true,
+            _ => false,
+        }
+    }
 }
 impl From<std::io::Error> for Error {
     fn from(error: std::io::Error) -> Self {
@@ -42,3 +82,8 @@ impl From<reqwest::Error> for Error {
         Error::Http(error)
     }
 }
+impl From<redis::RedisError> for Error {
+    fn from(error: redis::RedisError) -> Self {
+        Error::Redis(error)
+    }
+}
@@ -0,0 +1,83 @@
+//! Logs each provider call in a span, and measures its time for `--timings`.
+
+use super::{Capabilities, Error, SyncProvider, SyncResult, UpdateKey};
+use crate::cli::timings::{self, Phase};
+use crate::VResult;
+
+use serde_json::Value;
+use tracing::debug;
+
+/// Run a provider call in a span, adding its duration to the sync phase
+fn call<T, F>(name: &'static str, key: Option<&str>, f: F) -> SyncResult<T>
+where F: FnOnce() -> SyncResult<T> {
+    let span = tracing::debug_span!("provider", call = name, key = key.unwrap_or(""));
+    let _entered = span.enter();
+    let result = timings::measure(Phase::Sync, f);
+    if let Err(ref e) = result {
+        debug!("Failed: {:?}", e);
+    }
+    result
+}
+
+/// Wraps a provider, see the module documentation
+pub struct Instrumented {
+    inner: Box<dyn SyncProvider>,
+}
+impl Instrumented {
+    pub fn new(inner: Box<dyn SyncProvider>) -> Self {
+        Self { inner }
+    }
+}
+impl SyncProvider for Instrumented {
+    fn interactive_setup() -> VResult<Value> {
+        Err(Error::Unsupported("interactive setup of the instrumentation layer".to_owned()).into())
+    }
+
+    fn load(_item: &Value) -> SyncResult<Self> {
+        Err(Error::Unsupported("loading the instrumentation layer from a configuration".to_owned()))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn ping(&mut self) -> SyncResult<()> {
+        call("ping", None, || self.inner.ping())
+    }
+
+    fn test(&mut self) -> SyncResult<()> {
+        call("test", None, || self.inner.test())
+    }
+
+    fn create(&mut self, key: &str, value: Vec<u8>) -> SyncResult<()> {
+        call("create", Some(key), || self.inner.create(key, value))
+    }
+
+    fn update(&mut self, key: &str, value: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
+        call("update", Some(key), || self.inner.update(key, value, update_key))
+    }
+
+    fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
+        call("read", Some(key), || self.inner.read(key))
+    }
+
+    fn stat(&mut self, key: &str) -> SyncResult<(u64, UpdateKey)> {
+        call("stat", Some(key), || self.inner.stat(key))
+    }
+
+    fn exists(&mut self, key: &str) -> SyncResult<bool> {
+        call("exists", Some(key), || self.inner.exists(key))
+    }
+
+    fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
+        call("delete", Some(key), || self.inner.delete(key, update_key))
+    }
+
+    fn conflicts(&mut self, key: &str) -> SyncResult<Vec<String>> {
+        call("conflicts", Some(key), || self.inner.conflicts(key))
+    }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        call("list", None, || self.inner.list())
+    }
+}
@@ -3,23 +3,57 @@
 //! Metavault `sync.meta.vpass_vault` is used for storing
 //! per-service configurations and access keys.
 
+mod chunks;
 pub mod config;
+mod delta;
 mod error;
+mod instrumented;
+pub mod progress;
 pub mod providers;
+pub mod remote_key;
 pub mod transfer_string;
 
-use crate::{backend::book::Item, backend::book::Password, Book, VResult};
+use crate::backend::vault::{EncryptedVault, Vault};
+use crate::{backend::book::Item, backend::book::ItemChange, backend::book::Password, Book, VResult};
 
-use log::debug;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use strum_macros::IntoStaticStr;
+use tracing::{debug, warn};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
 pub use self::error::Error;
+use self::progress::{Phase, Progress};
+
+/// Removed vaults are moved under this prefix on the remote
+const TRASH_PREFIX: &str = "trash/";
+
+/// Vault keys are file names of the vaults, other keys are their deltas and chunks
+const VAULT_SUFFIX: &str = ".vpass_vault";
+
+/// Default suffix for keys of items published with `publish_item`
+const ITEM_SUFFIX: &str = ".vpass_item";
+
+/// Accesses are pushed with other changes, or once the oldest one is this old,
+/// so that only reading entries doesn't upload a new version of the vault each time
+const ACCESS_PUSH_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Rate limits resetting sooner than this are waited out, instead of failing
+const RATE_LIMIT_MAX_WAIT: Duration = Duration::from_secs(60);
 
-#[must_use]
 pub type SyncResult<T> = Result<T, Error>;
 
+thread_local! {
+    /// Last downloaded snapshots by key
+    static SNAPSHOTS: RefCell<HashMap<String, Snapshot>> = RefCell::new(HashMap::new());
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct UpdateKey(Vec<u8>);
 impl UpdateKey {
@@ -35,8 +69,66 @@ impl UpdateKey {
         Self(s.bytes().collect())
     }
 
-    pub fn to_string(&self) -> String {
-        String::from_utf8(self.0.clone()).expect("UpdateKey is not a string")
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for UpdateKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            std::str::from_utf8(&self.0).expect("UpdateKey is not a string")
+        )
+    }
+}
+
+/// Snapshot of a remote vault as it was downloaded, still encrypted with the vault password.
+/// Reused instead of downloading the snapshot again, if the remote UpdateKey hasn't changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub update_key: UpdateKey,
+    pub data: Vec<u8>,
+}
+
+/// Provide a locally stored snapshot of `key`, e.g. from a previous run
+pub fn cache_snapshot(key: &str, snapshot: Snapshot) {
+    SNAPSHOTS.with(|s| s.borrow_mut().insert(key.to_owned(), snapshot));
+}
+
+/// Latest snapshot of `key` downloaded or provided with `cache_snapshot`.
+/// Snapshots replaced on the remote are dropped, as their new UpdateKey is not known.
+pub fn cached_snapshot(key: &str) -> Option<Snapshot> {
+    SNAPSHOTS.with(|s| s.borrow().get(key).cloned())
+}
+
+fn forget_snapshot(key: &str) {
+    SNAPSHOTS.with(|s| s.borrow_mut().remove(key));
+}
+
+/// Features supported by a provider.
+/// The sync layer adapts to missing features where possible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Updates and deletes fail if the UpdateKey doesn't match the current remote value
+    pub atomic_update: bool,
+    /// Keys can be deleted
+    pub delete: bool,
+    /// Keys can be listed
+    pub list: bool,
+    /// Maximum size of a single value in bytes, if limited.
+    /// Larger values are split into multiple keys.
+    pub max_object_size: Option<usize>,
+}
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self {
+            atomic_update: true,
+            delete: true,
+            list: true,
+            max_object_size: None,
+        }
     }
 }
 
@@ -56,14 +148,26 @@ pub trait SyncProvider {
     /// Provider should overwrite this to get smaller transfer strings.
     fn configuration_decompress(data: &[u8]) -> VResult<Value>
     where Self: Sized {
-        Ok(serde_json::from_slice(&data)?)
+        Ok(serde_json::from_slice(data)?)
+    }
+
+    /// Configuration with access tokens and other secrets replaced, for displaying.
+    /// Providers with secrets in their configuration must overwrite this.
+    fn configuration_redact(item: &Value) -> Value
+    where Self: Sized {
+        item.clone()
     }
 
     /// Load from config item from persistent configuration.
-    /// Panics if configuration has invalid format.
-    fn load(item: &Value) -> Self
+    /// Fails with `ConfigurationItem` if configuration has invalid format.
+    fn load(item: &Value) -> SyncResult<Self>
     where Self: Sized;
 
+    /// Features supported by this provider
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
     /// Check access and authentication.
     /// Can also act as a session refresh heartbeat.
     fn ping(&mut self) -> SyncResult<()>;
@@ -81,27 +185,51 @@ pub trait SyncProvider {
     /// Read value by key. Also returns UpdateKey to update.
     fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)>;
 
+    /// Size of the stored value in bytes, and its UpdateKey, without downloading the value
+    /// if the provider has a metadata request. Defaults to a full read.
+    fn stat(&mut self, key: &str) -> SyncResult<(u64, UpdateKey)> {
+        let (data, update_key) = self.read(key)?;
+        Ok((data.len() as u64, update_key))
+    }
+
     /// Check if key exists
     fn exists(&mut self, key: &str) -> SyncResult<bool> {
-        match self.read(key) {
+        match self.stat(key) {
             Ok(_) => Ok(true),
             Err(Error::NoSuchKey(_)) => Ok(false),
             e => e.map(|_| unreachable!()),
         }
     }
 
-    /// Read value by key
+    /// Delete key
     fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()>;
+
+    /// Conflict copies of a key created by external tools, e.g. file synchronization software.
+    /// Returns keys that can be read and deleted.
+    fn conflicts(&mut self, _key: &str) -> SyncResult<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// List all keys, excluding the marker file.
+    /// Only available if `capabilities().list` is set.
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        Err(Error::Unsupported("list".to_owned()))
+    }
 }
 
 fn load_service(book: &Book) -> VResult<Option<Box<dyn SyncProvider>>> {
-    if let Some(cfg) = config::book_read(&book)? {
+    if let Some(cfg) = config::book_read(book)? {
         // Load service information
-        let mut service = cfg.service.load(&cfg.data);
+        let mut service = cfg.load()?;
         // Check credentials and internet access
+        progress::report(Progress::Phase(Phase::Ping));
         (*service).ping()?;
         // Run sanity checks for the remote
+        progress::report(Progress::Phase(Phase::Test));
         service.test()?;
+        if !service.capabilities().atomic_update {
+            debug!("Provider doesn't support atomic updates, concurrent changes might be lost");
+        }
         // Everything ok, return the instance
         Ok(Some(service))
     } else {
@@ -109,6 +237,31 @@ fn load_service(book: &Book) -> VResult<Option<Box<dyn SyncProvider>>> {
     }
 }
 
+/// Password the remote copy is encrypted with: the sync password if set, or the vault password.
+/// Synchronization decrypts and encrypts again, so the two can differ.
+fn remote_password(book: &Book, password: &str) -> VResult<String> {
+    Ok(config::book_read(book)?
+        .and_then(|c| c.password)
+        .unwrap_or_else(|| password.to_owned()))
+}
+
+/// Run `f`, and if the provider is rate limited for only a short while, wait and retry once
+fn retry_rate_limited<T, F>(mut f: F) -> VResult<T>
+where F: FnMut() -> VResult<T> {
+    match f() {
+        Err(crate::cli::error::Error::Sync(Error::ApiRateLimit(when))) => {
+            let wait = (when - chrono::Utc::now()).to_std().unwrap_or(Duration::from_secs(0));
+            if wait > RATE_LIMIT_MAX_WAIT {
+                return Err(Error::ApiRateLimit(when).into());
+            }
+            warn!("Rate limited by the provider, retrying in {} seconds", wait.as_secs() + 1);
+            std::thread::sleep(wait + Duration::from_secs(1));
+            f()
+        },
+        other => other,
+    }
+}
+
 /// Check if some other key on the same sync repository is free.
 /// Checked before renaming vaults on remote.
 /// Unsynchronized books always return Ok.
@@ -121,6 +274,17 @@ pub fn check_rename(new_key: &str, book: &Book) -> VResult<()> {
     Ok(())
 }
 
+/// Check that the remote can delete keys, before anything is removed locally.
+/// Unsynchronized books always return Ok.
+pub fn check_delete(book: &Book) -> VResult<()> {
+    if let Some(cfg) = config::book_read(book)? {
+        if !cfg.load()?.capabilities().delete {
+            return Err(Error::Unsupported("delete".to_owned()).into());
+        }
+    }
+    Ok(())
+}
+
 /// Pushes new vault to remote, causes error if it already exists.
 /// Unsynchronized books are skipped with Ok.
 pub fn create(key: &str, book: &Book, password: &str) -> VResult<()> {
@@ -128,90 +292,566 @@ pub fn create(key: &str, book: &Book, password: &str) -> VResult<()> {
         if (*service).exists(key)? {
             return Err(Error::KeyAlreadyExists(key.to_owned()).into());
         }
-        let data = crate::encrypt(password, book.clone())?;
-        service.create(key, data)?;
+        let data = crate::encrypt(&remote_password(book, password)?, book.clone())?;
+        chunks::create(&mut *service, key, data)?;
     }
     Ok(())
 }
 
-/// Downloads a book from remote
+/// Downloads a book from remote, decrypting it with the sync password of `c` if set
 pub fn download_book(key: &str, c: config::SyncConfig, password: &str) -> VResult<Book> {
-    let mut service = c.service.load(&c.data);
-    (*service).ping()?;
-    (*service).test()?;
+    let password = c.password.as_ref().map_or(password, String::as_str);
+    retry_rate_limited(|| {
+        let mut service = c.load()?;
+        progress::report(Progress::Phase(Phase::Ping));
+        (*service).ping()?;
+        progress::report(Progress::Phase(Phase::Test));
+        (*service).test()?;
 
-    let (vault_data, _) = (*service).read(key)?;
-    crate::decrypt(&vault_data, password)
+        let remote =
+            delta::read(&mut *service, key, password)?.ok_or_else(|| Error::NoSuchKey(key.to_owned()))?;
+        Ok(remote.book)
+    })
+}
+
+/// Remote key of a published item, given the key or the item name
+pub fn item_key(name: &str) -> String {
+    if name.ends_with(ITEM_SUFFIX) {
+        name.to_owned()
+    } else {
+        format!("{}{}", name, ITEM_SUFFIX)
+    }
+}
+
+/// Encrypts a single item with a passphrase and uploads it,
+/// replacing the previous value of the key
+pub fn publish_item(key: &str, c: config::SyncConfig, item: &Item, passphrase: &str) -> VResult<()> {
+    let data = Vault::new(item.clone()).encrypt(passphrase).to_bytes();
+    retry_rate_limited(|| {
+        let mut service = c.load()?;
+        progress::report(Progress::Phase(Phase::Ping));
+        (*service).ping()?;
+        progress::report(Progress::Phase(Phase::Test));
+        (*service).test()?;
+
+        match (*service).stat(key) {
+            Ok((_, update_key)) => chunks::update(&mut *service, key, data.clone(), update_key)?,
+            Err(Error::NoSuchKey(_)) => chunks::create(&mut *service, key, data.clone())?,
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    })
+}
+
+/// Downloads and decrypts an item uploaded with `publish_item`
+pub fn fetch_item(key: &str, c: config::SyncConfig, passphrase: &str) -> VResult<Item> {
+    let data = retry_rate_limited(|| {
+        let mut service = c.load()?;
+        progress::report(Progress::Phase(Phase::Ping));
+        (*service).ping()?;
+        progress::report(Progress::Phase(Phase::Test));
+        (*service).test()?;
+
+        Ok(chunks::read(&mut *service, key)?.0)
+    })?;
+    let encrypted = EncryptedVault::from_bytes(&data)?;
+    Ok(encrypted
+        .decrypt::<Item>(passphrase)
+        .ok_or(crate::cli::error::Error::WrongPassphrase)?
+        .content)
 }
 
 /// Synchronizes local changes to remote.
 /// Unsynchronized books are skipped with Ok.
 pub fn vault(key: &str, book: &mut Book, password: &str) -> VResult<()> {
-    if let Some(service) = load_service(book)? {
-        synchronize(*service, key, book, password)
-    } else {
-        Ok(())
-    }
+    retry_rate_limited(|| {
+        if let Some(mut service) = load_service(book)? {
+            let password = remote_password(book, password)?;
+            synchronize(&mut *service, key, book, &password)
+        } else {
+            Ok(())
+        }
+    })
+}
+
+/// Synchronizes local changes to an unrelated remote vault with the same key,
+/// copying local items on top of it and adopting its origin.
+/// Unsynchronized books are skipped with Ok.
+pub fn vault_adopt(key: &str, book: &mut Book, password: &str) -> VResult<()> {
+    retry_rate_limited(|| {
+        if let Some(mut service) = load_service(book)? {
+            let password = remote_password(book, password)?;
+            if let Some(remote) = delta::read(&mut *service, key, &password)? {
+                if !remote.book.has_same_origin(book) {
+                    debug!("Adopting origin of remote vault {}", key);
+                    *book = book.adopt_into(&remote.book);
+                }
+            }
+            synchronize(&mut *service, key, book, &password)
+        } else {
+            Ok(())
+        }
+    })
 }
 
 /// Force pushes local changes to remote.
 /// Doesn't even check if they have same origin.
 /// Unsynchronized books are skipped with Ok.
 pub fn vault_overwrite(key: &str, book: &Book, password: &str) -> VResult<()> {
-    if let Some(service) = load_service(book)? {
-        synchronize_overwrite(*service, key, book, password)
-    } else {
-        Ok(())
-    }
+    retry_rate_limited(|| {
+        if let Some(mut service) = load_service(book)? {
+            synchronize_overwrite(&mut *service, key, book, &remote_password(book, password)?)
+        } else {
+            Ok(())
+        }
+    })
 }
 
 /// Delete a vault from the remote.
+/// Only warns if the provider doesn't support deletion.
 pub fn vault_delete(key: &str, book: &Book) -> VResult<()> {
     if let Some(mut service) = load_service(book)? {
-        if let Ok((_, update_key)) = (*service).read(key) {
-            (*service).delete(key, update_key)?;
+        if !service.capabilities().delete {
+            warn!("Provider doesn't support deletion, {} was not removed from the remote", key);
+            return Ok(());
+        }
+        if let Ok((_, update_key)) = (*service).stat(key) {
+            chunks::delete(&mut *service, key, update_key)?;
         }
+        delta::remove_all(&mut *service, key)?;
     }
     Ok(())
 }
 
-fn synchronize(mut sp: dyn SyncProvider, key: &str, book: &mut Book, password: &str) -> VResult<()> {
-    match sp.read(key) {
-        Ok((old_data, update_key)) => {
-            let b_old = crate::decrypt(&old_data, password)?;
-            if b_old != *book {
-                // Book is not updated until the new version is actually synchronized,
-                // so that this function is atomic regarding version merges.
-                // If pushing the new version fails, the local book is still in the original state.
-                let b_new = b_old.merge_versions(book)?;
-                let data = crate::encrypt(password, b_new.clone())?;
-                sp.update(key, data, update_key)?;
-                *book = b_new;
+/// Result of a single step of `diagnose`
+#[derive(Debug)]
+pub struct DiagnosticStep {
+    pub name: &'static str,
+    pub duration: Duration,
+    pub result: SyncResult<()>,
+}
+
+fn diagnostic_step<F>(steps: &mut Vec<DiagnosticStep>, name: &'static str, f: F) -> bool
+where F: FnOnce() -> SyncResult<()> {
+    let start = Instant::now();
+    let result = f();
+    let ok = result.is_ok();
+    steps.push(DiagnosticStep {
+        name,
+        duration: start.elapsed(),
+        result,
+    });
+    ok
+}
+
+/// Removes the throwaway key of `diagnose` when dropped, so that it's not left on the remote
+/// when a step after writing it fails
+struct DiagnosticKey {
+    service: Box<dyn SyncProvider>,
+    key: String,
+    written: bool,
+}
+impl Drop for DiagnosticKey {
+    fn drop(&mut self) {
+        if !self.written || !self.service.capabilities().delete {
+            return;
+        }
+        if let Ok((_, update_key)) = self.service.stat(&self.key) {
+            if let Err(e) = self.service.delete(&self.key, update_key) {
+                warn!("Unable to remove {}: {:?}", self.key, e);
             }
-            Ok(())
-        },
-        Err(Error::NoSuchKey(_)) => {
-            let data = crate::encrypt(password, book.clone())?;
-            sp.create(key, data)?;
-            Ok(())
-        },
-        e => {
-            e?;
-            unreachable!()
-        },
+        }
     }
 }
 
+/// Check connectivity and do a write/read/delete round-trip with a throwaway key.
+/// Stops at the first failed step.
+pub fn diagnose(book: &Book) -> VResult<Vec<DiagnosticStep>> {
+    let cfg = config::book_read(book)?.ok_or(Error::NoRemoteSet)?;
+    let key = format!("vpass-diagnostic-{}", Uuid::new_v4());
+    let data: Vec<u8> = key.bytes().collect();
+    let mut dk = DiagnosticKey {
+        service: cfg.load()?,
+        key: key.clone(),
+        written: false,
+    };
+    let mut update_key = None;
+    let mut steps = Vec::new();
+
+    let _ = diagnostic_step(&mut steps, "ping", || dk.service.ping())
+        && diagnostic_step(&mut steps, "test", || dk.service.test())
+        && diagnostic_step(&mut steps, "write", || {
+            // A failed write may still have stored the value
+            dk.written = true;
+            dk.service.create(&key, data.clone())
+        })
+        && diagnostic_step(&mut steps, "read", || {
+            let (read_data, uk) = dk.service.read(&key)?;
+            update_key = Some(uk);
+            if read_data == data {
+                Ok(())
+            } else {
+                Err(Error::InvalidRemote)
+            }
+        })
+        && (!dk.service.capabilities().delete
+            || diagnostic_step(&mut steps, "delete", || {
+                dk.service.delete(&key, update_key.take().unwrap())?;
+                dk.written = false;
+                Ok(())
+            }));
+
+    Ok(steps)
+}
+
+/// Merge conflict copies of a vault on the remote into the book,
+/// push the merged version and remove the conflict copies.
+/// Returns the number of merged conflict copies, and the changes they made to the book.
+/// Unsynchronized books are skipped with Ok.
+pub fn resolve_conflicts(key: &str, book: &mut Book, password: &str) -> VResult<(usize, Vec<ItemChange>)> {
+    if let Some(mut service) = load_service(book)? {
+        let conflicts = (*service).conflicts(key)?;
+        if conflicts.is_empty() {
+            return Ok((0, Vec::new()));
+        }
+        let password = &remote_password(book, password)?;
+
+        let mut merged = book.clone();
+        let mut update_keys = Vec::new();
+        for ckey in &conflicts {
+            debug!("Merging conflict copy {}", ckey);
+            let (data, update_key) = (*service).read(ckey)?;
+            merged = merged.merge_versions(&crate::decrypt(&data, password)?)?;
+            update_keys.push(update_key);
+        }
+
+        synchronize(&mut *service, key, &mut merged, password)?;
+        // Conflict copies are only removed after the merged version is pushed
+        let mut service = load_service(book)?.unwrap();
+        for (ckey, update_key) in conflicts.iter().zip(update_keys) {
+            (*service).delete(ckey, update_key)?;
+        }
+        let changes = book.diff(&merged);
+        *book = merged;
+        Ok((conflicts.len(), changes))
+    } else {
+        Ok((0, Vec::new()))
+    }
+}
+
+/// Conflict copies of a vault on the remote, to be merged with `resolve_conflicts`.
+/// Unsynchronized books return an empty list.
+pub fn conflict_copies(key: &str, book: &Book) -> VResult<Vec<String>> {
+    if let Some(mut service) = load_service(book)? {
+        Ok(service.conflicts(key)?)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Names of the vaults on the remote, without trashed copies, sorted.
+/// Unsynchronized books return an empty list.
+pub fn remote_vaults(book: &Book) -> VResult<Vec<String>> {
+    if let Some(mut service) = load_service(book)? {
+        if !service.capabilities().list {
+            return Err(Error::Unsupported("list".to_owned()).into());
+        }
+        let mut names: Vec<String> = service
+            .list()?
+            .into_iter()
+            .filter(|key| !key.starts_with(TRASH_PREFIX) && key.ends_with(VAULT_SUFFIX))
+            .map(|key| key[..key.len() - VAULT_SUFFIX.len()].to_owned())
+            .collect();
+        names.sort();
+        Ok(names)
+    } else {
+        Ok(Vec::new())
+    }
+}
+
+/// Version of a remote vault, read with `stat` without downloading or decrypting it.
+/// Any change pushed to the remote changes either the snapshot or the number of deltas.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteState {
+    /// Size of the stored snapshot in bytes, or of its chunk manifest
+    pub size: u64,
+    /// UpdateKey of the snapshot, base64 encoded
+    pub update_key: String,
+    /// Number of deltas stored after the snapshot
+    pub deltas: usize,
+}
+
+/// Current version of a vault on the remote.
+/// Returns `Ok(None)` if the vault is not on the remote, or the book is not synchronized.
+pub fn remote_state(key: &str, book: &Book) -> VResult<Option<RemoteState>> {
+    retry_rate_limited(|| {
+        if let Some(mut service) = load_service(book)? {
+            let (size, update_key) = match service.stat(key) {
+                Ok(r) => r,
+                Err(Error::NoSuchKey(_)) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            };
+            Ok(Some(RemoteState {
+                size,
+                update_key: base64::encode(update_key.as_bytes()),
+                deltas: delta::keys(&mut *service, key)?.len(),
+            }))
+        } else {
+            Ok(None)
+        }
+    })
+}
+
+/// Result of checking a key on the remote
+#[derive(Debug, Clone, Copy, PartialEq, Eq, IntoStaticStr)]
+#[strum(serialize_all = "kebab_case")]
+pub enum KeyStatus {
+    /// Vault that decodes as an encrypted vault. The contents are not decrypted.
+    Valid,
+    /// Vault that isn't an encrypted vault, or has missing chunks
+    Invalid,
+    /// Vault encrypted with another remote key, or without one, so it can't be checked
+    Unreadable,
+    /// Delta or chunk of a vault that doesn't exist on the remote
+    Dangling,
+    /// Key not created by vpass
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteKeyCheck {
+    pub key: String,
+    /// Name of the vault the key belongs to
+    pub vault: Option<String>,
+    pub status: KeyStatus,
+}
+
+/// Check the vaults on the remote, and find deltas and chunks left over from removed vaults.
+/// Deltas and chunks of existing vaults and trashed copies are not included.
+/// Unsynchronized books return an empty list.
+pub fn check_remote(book: &Book) -> VResult<Vec<RemoteKeyCheck>> {
+    let mut service = match load_service(book)? {
+        Some(service) => service,
+        None => return Ok(Vec::new()),
+    };
+    if !service.capabilities().list {
+        return Err(Error::Unsupported("list".to_owned()).into());
+    }
+    let keys: Vec<String> = service
+        .list()?
+        .into_iter()
+        .filter(|key| !key.starts_with(TRASH_PREFIX))
+        .collect();
+    let mut result = Vec::new();
+    for key in &keys {
+        let (vault, status) = if key.ends_with(VAULT_SUFFIX) {
+            let status = match chunks::read(&mut *service, key) {
+                Ok((ref data, _)) if EncryptedVault::from_bytes(data).is_ok() => KeyStatus::Valid,
+                Ok((ref data, _)) if remote_key::is_encrypted(data) => KeyStatus::Unreadable,
+                Ok(_) | Err(Error::InvalidRemote) => KeyStatus::Invalid,
+                Err(Error::RemoteDecryption) => KeyStatus::Unreadable,
+                // Removed while checking
+                Err(Error::NoSuchKey(_)) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            (Some(key[..key.len() - VAULT_SUFFIX.len()].to_owned()), status)
+        } else if let Some(i) = key.find(&format!("{}.", VAULT_SUFFIX)) {
+            let vault_key = &key[..i + VAULT_SUFFIX.len()];
+            if keys.iter().any(|k| k == vault_key) {
+                continue;
+            }
+            (Some(key[..i].to_owned()), KeyStatus::Dangling)
+        } else {
+            (None, KeyStatus::Unknown)
+        };
+        result.push(RemoteKeyCheck {
+            key: key.clone(),
+            vault,
+            status,
+        });
+    }
+    Ok(result)
+}
+
+/// Delete a single key from the remote of the book, bypassing the remote key encryption.
+/// Used to clean up keys found by `check_remote`, vaults should be moved to trash instead.
+pub fn delete_key(key: &str, book: &Book) -> VResult<()> {
+    let mut c = config::book_read(book)?.ok_or(Error::NoRemoteSet)?;
+    c.remote_key = None;
+    let mut service = c.load()?;
+    let (_, update_key) = service.stat(key)?;
+    service.delete(key, update_key)?;
+    Ok(())
+}
+
+/// Key of the trashed copy of a vault
+pub fn trash_key(key: &str) -> String {
+    format!("{}{}", TRASH_PREFIX, key)
+}
+
+/// Move a vault to trash on the remote, instead of deleting it.
+/// Overwrites any previously trashed copy with the same key.
+/// Unsynchronized books are skipped with Ok.
+pub fn vault_trash(key: &str, book: &Book) -> VResult<()> {
+    if let Some(mut service) = load_service(book)? {
+        if !service.capabilities().delete {
+            warn!("Provider doesn't support deletion, {} was not moved to trash", key);
+            return Ok(());
+        }
+        if (*service).exists(key)? {
+            // Deltas of a previously trashed copy would apply on the new one
+            delta::remove_all(&mut *service, &trash_key(key))?;
+            for from in delta::keys(&mut *service, key)? {
+                move_key(&mut *service, &from, &trash_key(&from))?;
+            }
+            move_key(&mut *service, key, &trash_key(key))?;
+        }
+    }
+    Ok(())
+}
+
+/// Move a value to another key, overwriting the target if it exists
+fn move_key(sp: &mut dyn SyncProvider, from: &str, to: &str) -> VResult<()> {
+    let (data, update_key) = chunks::read(sp, from)?;
+    match sp.stat(to) {
+        Ok((_, to_update_key)) => chunks::update(sp, to, data, to_update_key)?,
+        Err(Error::NoSuchKey(_)) => chunks::create(sp, to, data)?,
+        Err(e) => return Err(e.into()),
+    }
+    chunks::delete(sp, from, update_key)?;
+    Ok(())
+}
+
+/// Replace the remote key, or remove it if `new_key` is `None`.
+/// The vault is synchronized first with the old key, and then pushed again with the new one.
+/// Other devices must import the vault again, as they can no longer read the remote.
+pub fn rotate_remote_key(
+    key: &str, book: &mut Book, password: &str, new_key: Option<remote_key::RemoteKey>,
+) -> VResult<()> {
+    vault(key, book, password)?;
+    let mut new_book = book.clone();
+    config::book_set_remote_key(&mut new_book, new_key)?;
+    // Chunks of the old copy can only be found with the old key
+    let old = config::book_read(book)?;
+    let old_stored = match old {
+        Some(ref old) => Some(old.load()?.read(key)?.0),
+        None => None,
+    };
+    // Deltas encrypted with the old key are removed as well
+    vault_overwrite(key, &new_book, password)?;
+    if let (Some(old), Some(old_stored), Some(new)) = (old, old_stored, config::book_read(&new_book)?) {
+        chunks::remove_chunks(&mut *old.load()?, key, &old_stored)?;
+        reencrypt_trash(key, &old, &new)?;
+    }
+    *book = new_book;
+    Ok(())
+}
+
+/// Encrypt the trashed copy of a vault again after its remote key has changed,
+/// as it could no longer be read with the new key.
+/// The old copy is replaced in place, or removed only after the new one is written,
+/// so that a failure never loses both.
+pub fn reencrypt_trash(key: &str, old: &config::SyncConfig, new: &config::SyncConfig) -> VResult<()> {
+    let trashed = trash_key(key);
+    let mut old_service = old.load()?;
+    if !old_service.exists(&trashed)? {
+        return Ok(());
+    }
+    let same_remote = old.service == new.service && old.data == new.data;
+    if !same_remote && !old_service.capabilities().delete {
+        warn!("Provider doesn't support deletion, {} can't be encrypted with the new key", trashed);
+        return Ok(());
+    }
+    let mut new_service = new.load()?;
+    let mut keys = vec![trashed.clone()];
+    keys.extend(delta::keys(&mut *old_service, &trashed)?);
+    for k in keys {
+        let (data, update_key) = chunks::read(&mut *old_service, &k)?;
+        if same_remote {
+            // Chunks of the old copy can only be found with the old key
+            let (old_stored, _) = old_service.read(&k)?;
+            chunks::update(&mut *new_service, &k, data, update_key)?;
+            chunks::remove_chunks(&mut *old_service, &k, &old_stored)?;
+        } else {
+            chunks::create(&mut *new_service, &k, data)?;
+            chunks::delete(&mut *old_service, &k, update_key)?;
+        }
+    }
+    Ok(())
+}
+
+/// Encrypt the remote copy with a separate sync password, or with the vault password again
+/// if `sync_password` is `None`. The vault is synchronized first with the old password,
+/// and then pushed again with the new one.
+/// Other devices must import the vault again, as they can no longer read the remote.
+pub fn set_sync_password(
+    key: &str, book: &mut Book, password: &str, sync_password: Option<String>,
+) -> VResult<()> {
+    if book.shared().is_some() {
+        return Err(Error::SharedSyncPassword.into());
+    }
+    vault(key, book, password)?;
+    let mut new_book = book.clone();
+    config::book_set_password(&mut new_book, sync_password)?;
+    vault_overwrite(key, &new_book, password)?;
+    *book = new_book;
+    Ok(())
+}
+
+/// Permanently delete the trashed copy of a vault from the remote.
+/// Unsynchronized books are skipped with Ok.
+pub fn purge_trash(key: &str, book: &Book) -> VResult<()> {
+    vault_delete(&trash_key(key), book)
+}
+
+/// Pushing can be skipped when the only changes are recent accesses, which are kept locally until then
+fn accesses_can_wait(remote: &Book, new: &Book) -> bool {
+    new.only_new_accesses(remote).is_some_and(|oldest| {
+        (chrono::Utc::now() - oldest).to_std().map_or(true, |age| age < ACCESS_PUSH_DELAY)
+    })
+}
+
+fn synchronize(sp: &mut dyn SyncProvider, key: &str, book: &mut Book, password: &str) -> VResult<()> {
+    if let Some(remote) = delta::read(sp, key, password)? {
+        // Book is not updated until the new version is actually synchronized,
+        // so that this function is atomic regarding version merges.
+        // If pushing the new version fails, the local book is still in the original state.
+        let mut b_new = remote.book.clone().merge_versions(book)?;
+        if let Some(device) = book.device() {
+            b_new.mark_synced(device);
+            let pruned = b_new.prune();
+            if pruned > 0 {
+                debug!("Pruned history of {} removed items", pruned);
+            }
+            b_new.set_device(device);
+        }
+        if b_new != remote.book && !accesses_can_wait(&remote.book, &b_new) {
+            delta::write(sp, key, remote, &b_new, password)?;
+        }
+        *book = b_new;
+    } else {
+        if let Some(device) = book.device() {
+            book.mark_synced(device);
+        }
+        let data = crate::encrypt(password, book.clone())?;
+        chunks::create(sp, key, data)?;
+    }
+    Ok(())
+}
+
 /// Synchronize vault, overwriting the old value.
-fn synchronize_overwrite(mut sp: dyn SyncProvider, key: &str, book: &Book, password: &str) -> VResult<()> {
+fn synchronize_overwrite(sp: &mut dyn SyncProvider, key: &str, book: &Book, password: &str) -> VResult<()> {
     let data = crate::encrypt(password, book.clone())?;
-    match sp.read(key) {
+    forget_snapshot(key);
+    match sp.stat(key) {
         Ok((_, update_key)) => {
-            sp.update(key, data, update_key)?;
+            chunks::update(sp, key, data, update_key)?;
+            delta::remove_all(sp, key)?;
             Ok(())
         },
         Err(Error::NoSuchKey(_)) => {
-            sp.create(key, data)?;
+            delta::remove_all(sp, key)?;
+            chunks::create(sp, key, data)?;
             Ok(())
         },
         e => {
@@ -220,3 +860,38 @@ fn synchronize_overwrite(mut sp: dyn SyncProvider, key: &str, book: &Book, passw
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::config::SyncConfig;
+    use super::providers::Provider;
+    use super::remote_key::RemoteKey;
+    use super::{chunks, reencrypt_trash, trash_key};
+    use serde_json::json;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn trash_reencrypted_in_place() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        fs::write(td.path().join("VPassFile"), []).unwrap();
+        let old = SyncConfig {
+            service: Provider::FileSystem,
+            data: json!({ "path": td.path() }),
+            remote_key: Some(RemoteKey::generate()),
+            password: None,
+        };
+        let new = SyncConfig {
+            remote_key: Some(RemoteKey::generate()),
+            ..old.clone()
+        };
+        let trashed = trash_key("vault");
+        chunks::create(&mut *old.load().unwrap(), &trashed, b"trashed".to_vec()).unwrap();
+
+        reencrypt_trash("vault", &old, &new).unwrap();
+        assert_eq!(chunks::read(&mut *new.load().unwrap(), &trashed).unwrap().0, b"trashed".to_vec());
+        assert!(chunks::read(&mut *old.load().unwrap(), &trashed).is_err());
+    }
+}
@@ -0,0 +1,53 @@
+//! Progress reporting for slow synchronization operations.
+//! The sync layer and providers report progress through a callback,
+//! which is set by the user interface, e.g. to render a progress bar.
+
+use std::cell::RefCell;
+
+use strum_macros::Display;
+
+/// Step of a synchronization operation
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    #[strum(serialize = "Connecting")]
+    Ping,
+    #[strum(serialize = "Checking remote")]
+    Test,
+    #[strum(serialize = "Downloading")]
+    Read,
+    #[strum(serialize = "Uploading")]
+    Write,
+    #[strum(serialize = "Deleting")]
+    Delete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    /// A new phase started
+    Phase(Phase),
+    /// Bytes transferred in the current phase, and the total amount if known
+    Transfer { done: u64, total: Option<u64> },
+    /// All operations completed
+    Done,
+}
+
+type Handler = Box<dyn FnMut(Progress)>;
+
+thread_local! {
+    static HANDLER: RefCell<Option<Handler>> = const { RefCell::new(None) };
+}
+
+/// Set the progress callback, replacing any previous one
+pub fn set_handler<F>(f: F)
+where F: FnMut(Progress) + 'static {
+    HANDLER.with(|h| *h.borrow_mut() = Some(Box::new(f)));
+}
+
+/// Report progress to the callback, if set
+pub fn report(progress: Progress) {
+    HANDLER.with(|h| {
+        if let Some(f) = h.borrow_mut().as_mut() {
+            f(progress);
+        }
+    });
+}
@@ -0,0 +1,64 @@
+//! Behaviour every provider must have, so that the sync layer can detect concurrent changes
+//! without relying on provider-specific features like ETags.
+//! Public so that live providers can be checked from integration tests as well.
+
+use super::super::{Error, SyncProvider, SyncResult};
+
+use matches::matches;
+use uuid::Uuid;
+
+fn expect_err<T: std::fmt::Debug>(what: &str, result: SyncResult<T>, check: fn(&Error) -> bool) {
+    match result {
+        Err(ref e) if check(e) => {},
+        other => panic!("Contract: {}: unexpected result {:?}", what, other),
+    }
+}
+
+/// Run the contract checks against a provider, panicking on the first violation.
+/// Uses a random key, which is removed afterwards if the provider supports deletion.
+pub fn provider_contract_tests<P: SyncProvider + ?Sized>(sp: &mut P) {
+    let key = format!("vpass-contract-{}", Uuid::new_v4());
+    let capabilities = sp.capabilities();
+
+    // Missing keys
+    assert!(!sp.exists(&key).unwrap(), "Contract: exists() of a missing key");
+    expect_err("read of a missing key", sp.read(&key), |e| matches!(e, Error::NoSuchKey(_)));
+    expect_err("stat of a missing key", sp.stat(&key), |e| matches!(e, Error::NoSuchKey(_)));
+
+    // Create then read echoes the data
+    sp.create(&key, b"first".to_vec()).unwrap();
+    assert!(sp.exists(&key).unwrap(), "Contract: exists() after create");
+    let (data, uk_first) = sp.read(&key).unwrap();
+    assert_eq!(data, b"first".to_vec(), "Contract: read after create");
+    assert_eq!(sp.stat(&key).unwrap(), (5, uk_first.clone()), "Contract: stat after create");
+    expect_err("create of an existing key", sp.create(&key, b"other".to_vec()), |e| {
+        matches!(e, Error::KeyAlreadyExists(_))
+    });
+
+    // Update with the current key, and the old key becomes stale
+    sp.update(&key, b"second".to_vec(), uk_first.clone()).unwrap();
+    let (data, uk_second) = sp.read(&key).unwrap();
+    assert_eq!(data, b"second".to_vec(), "Contract: read after update");
+    assert_ne!(uk_first, uk_second, "Contract: update key didn't change");
+    assert_eq!(sp.stat(&key).unwrap(), (6, uk_second.clone()), "Contract: stat after update");
+    expect_err("update with a stale key", sp.update(&key, b"third".to_vec(), uk_first.clone()), |e| {
+        matches!(e, Error::InvalidUpdateKey)
+    });
+    assert_eq!(sp.read(&key).unwrap().0, b"second".to_vec(), "Contract: stale update modified data");
+
+    if capabilities.list {
+        assert!(sp.list().unwrap().contains(&key), "Contract: list() is missing a key");
+    }
+
+    if capabilities.delete {
+        expect_err("delete with a stale key", sp.delete(&key, uk_first), |e| {
+            matches!(e, Error::InvalidUpdateKey)
+        });
+        assert!(sp.exists(&key).unwrap(), "Contract: stale delete removed the key");
+        sp.delete(&key, uk_second.clone()).unwrap();
+        assert!(!sp.exists(&key).unwrap(), "Contract: exists() after delete");
+        expect_err("delete of a missing key", sp.delete(&key, uk_second), |e| {
+            matches!(e, Error::NoSuchKey(_))
+        });
+    }
+}
@@ -1,16 +1,21 @@
 //! Uses a filesystem folder as a repository.
 //! An empty file called VPassFile is used to mark this as a vpass repository.
+//! Modifications are done while holding a lockfile called VPassLock,
+//! as the folder might be shared between machines, e.g. using Dropbox or NFS.
 
-use super::super::{Error, SyncProvider, SyncResult, UpdateKey};
+use super::super::{Capabilities, Error, SyncProvider, SyncResult, UpdateKey};
 use crate::VResult;
 
 use base64;
+use chrono::prelude::*;
+use rust_sodium::crypto::hash::sha256;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
 
-use log::debug;
+use tracing::{debug, warn};
 
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Config {
@@ -22,9 +27,143 @@ impl std::fmt::Debug for Config {
     }
 }
 
+const MARKER_FILE: &str = "VPassFile";
+const LOCK_FILE: &str = "VPassLock";
+
+/// Files of the repository itself, which are not keys
+pub fn is_reserved_key(key: &str) -> bool {
+    key == MARKER_FILE || key == LOCK_FILE
+}
+
+/// Check that a key received from elsewhere is a relative path staying inside the repository
+pub fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && !key.starts_with('/') && !key.split('/').any(|p| p == ".." || p == "." || p.is_empty())
+}
+
+/// Locks older than this are considered to be left over from a crashed process
+const LOCK_STALE_SECONDS: i64 = 60;
+
+/// Contents of the lockfile
+#[derive(Debug, Serialize, Deserialize)]
+struct LockInfo {
+    host: String,
+    since: DateTime<Utc>,
+}
+
+/// Removes the lockfile when dropped
+struct Lock {
+    path: PathBuf,
+}
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            debug!("Unable to remove lockfile: {:?}", e);
+        }
+    }
+}
+
+fn hostname() -> String {
+    std::env::var("HOSTNAME")
+        .or_else(|_| std::env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| fs::read_to_string("/etc/hostname").ok())
+        .map(|h| h.trim().to_owned())
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Check if `candidate` is a conflict copy of `key` created by a file synchronization tool, e.g.
+/// * Dropbox: `vault (conflicted copy 2019-07-01).vpass_vault`
+/// * Syncthing: `vault.sync-conflict-20190701-120000-ABCDEFG.vpass_vault`
+fn is_conflict_copy(key: &str, candidate: &str) -> bool {
+    let (stem, ext) = match key.rfind('.') {
+        Some(i) => (&key[..i], &key[i..]),
+        None => (key, ""),
+    };
+    if candidate == key
+        || candidate.len() < stem.len() + ext.len()
+        || !candidate.starts_with(stem)
+        || !candidate.ends_with(ext)
+    {
+        return false;
+    }
+    let middle = &candidate[stem.len()..candidate.len() - ext.len()];
+    middle.contains("conflicted copy") || middle.starts_with(".sync-conflict-")
+}
+
+/// Relative paths of all files under `dir`, using `/` as separator and starting with `prefix`.
+/// Names which can't be keys are skipped, as other software may write to a shared folder.
+fn list_files(dir: &Path, prefix: &str) -> SyncResult<Vec<String>> {
+    let mut result = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => format!("{}{}", prefix, name),
+            Err(name) => {
+                warn!("Skipping non-unicode file name {:?} in {:?}", name, dir);
+                continue;
+            },
+        };
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            result.extend(list_files(&entry.path(), &format!("{}/", name))?);
+        } else if file_type.is_symlink() && entry.path().is_dir() {
+            // Not followed, as it could form a loop
+            warn!("Skipping symlinked directory {:?}", name);
+        } else {
+            result.push(name);
+        }
+    }
+    Ok(result)
+}
+
 pub struct FileSystem {
     config: Config,
 }
+impl FileSystem {
+    /// Acquire the lockfile, replacing it if it's stale
+    fn lock(&self) -> SyncResult<Lock> {
+        let path = self.config.path.join(LOCK_FILE);
+        let info = LockInfo {
+            host: hostname(),
+            since: Utc::now(),
+        };
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    serde_json::to_writer(file, &info).map_err(io::Error::from)?;
+                    return Ok(Lock { path });
+                },
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let holder = match fs::read(&path) {
+                        Ok(data) => serde_json::from_slice(&data).ok(),
+                        // Removed by the holder in the meantime
+                        Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                        Err(e) => return Err(e.into()),
+                    };
+                    // The holder might not have written the contents yet,
+                    // so an unreadable lock is held until the file itself is stale
+                    let holder = match holder {
+                        Some(h) => h,
+                        None => LockInfo {
+                            host: "unknown".to_owned(),
+                            since: match fs::metadata(&path).and_then(|m| m.modified()) {
+                                Ok(mtime) => mtime.into(),
+                                Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+                                Err(e) => return Err(e.into()),
+                            },
+                        },
+                    };
+                    if (Utc::now() - holder.since).num_seconds() < LOCK_STALE_SECONDS {
+                        return Err(Error::RemoteLocked(holder.host, holder.since));
+                    }
+                    debug!("Removing stale lockfile {:?}", holder);
+                    fs::remove_file(&path)?;
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
 impl SyncProvider for FileSystem {
     fn interactive_setup() -> VResult<Value> {
         use crate::cli::interactive::*;
@@ -33,14 +172,22 @@ impl SyncProvider for FileSystem {
             path: prompt_dir_path("Directory to use")?,
         };
 
-        fs::write(c.path.join("VPassFile"), &[])?;
+        fs::write(c.path.join(MARKER_FILE), [])?;
         Ok(serde_json::to_value(&c).unwrap())
     }
 
-    fn load(value: &Value) -> Self
+    fn load(value: &Value) -> SyncResult<Self>
     where Self: Sized {
-        FileSystem {
-            config: serde_json::from_value(value.clone()).expect("Invalid config for FileSystem integration"),
+        Ok(FileSystem {
+            config: serde_json::from_value(value.clone()).map_err(|_| Error::ConfigurationItem)?,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            // Update key check and write are separate operations
+            atomic_update: false,
+            ..Capabilities::default()
         }
     }
 
@@ -48,26 +195,43 @@ impl SyncProvider for FileSystem {
         if self.config.path.exists() {
             Ok(())
         } else {
-            Err(Error::InvalidRemote)
+            // E.g. a removable drive or a network share that isn't mounted
+            Err(io::Error::new(io::ErrorKind::NotFound, "Sync directory not found").into())
         }
     }
 
     fn test(&mut self) -> SyncResult<()> {
-        if !self.config.path.join("VPassFile").exists() {
+        if !self.config.path.join(MARKER_FILE).exists() {
             return Err(Error::InvalidRemote);
         }
+        for entry in fs::read_dir(&self.config.path)? {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name.contains("conflicted copy") || name.contains(".sync-conflict-") {
+                warn!("Conflict copy {:?} found, use `sync resolve-conflicts` to merge it", name);
+            }
+        }
         Ok(())
     }
 
     fn create(&mut self, key: &str, value: Vec<u8>) -> SyncResult<()> {
         debug!("Create: {}", key);
+        let _lock = self.lock()?;
 
-        fs::write(self.config.path.join(key), &value)?;
+        let path = self.config.path.join(key);
+        if path.exists() {
+            return Err(Error::KeyAlreadyExists(key.to_owned()));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, &value)?;
         Ok(())
     }
 
     fn update(&mut self, key: &str, value: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
         debug!("Update: {}", key);
+        let _lock = self.lock()?;
         let (_, uk) = self.read(key)?;
         if uk != update_key {
             Err(Error::InvalidUpdateKey)
@@ -82,13 +246,22 @@ impl SyncProvider for FileSystem {
         if !self.config.path.join(key).exists() {
             return Err(Error::NoSuchKey(key.to_owned()));
         }
+        if !self.conflicts(key)?.is_empty() {
+            warn!("Conflict copies of {} found, use `sync resolve-conflicts` to merge them", key);
+        }
         let data = fs::read(self.config.path.join(key))?;
-        let uk = UpdateKey::from_byte(data.get(0).copied().unwrap_or(0));
+        // Any change to the contents changes the key
+        let uk = UpdateKey::from_bytes(&sha256::hash(&data).0);
         Ok((data, uk))
     }
 
+    fn exists(&mut self, key: &str) -> SyncResult<bool> {
+        Ok(self.config.path.join(key).is_file())
+    }
+
     fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
         debug!("Delete: {}", key);
+        let _lock = self.lock()?;
         let (_, uk) = self.read(key)?;
         if uk != update_key {
             Err(Error::InvalidUpdateKey)
@@ -97,4 +270,128 @@ impl SyncProvider for FileSystem {
             Ok(())
         }
     }
+
+    fn conflicts(&mut self, key: &str) -> SyncResult<Vec<String>> {
+        let mut result = Vec::new();
+        for entry in fs::read_dir(&self.config.path)? {
+            if let Some(name) = entry?.file_name().to_str() {
+                if is_conflict_copy(key, name) {
+                    result.push(name.to_owned());
+                }
+            }
+        }
+        result.sort();
+        Ok(result)
+    }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        debug!("List");
+        let mut keys: Vec<String> = list_files(&self.config.path, "")?
+            .into_iter()
+            .filter(|k| !is_reserved_key(k))
+            .collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::{Error, SyncProvider};
+    use super::super::contract::provider_contract_tests;
+    use super::{is_conflict_copy, FileSystem};
+    use serde_json::json;
+    use tempfile::tempdir;
+
+    #[test]
+    fn stale_update_key_rejected() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let mut fs = FileSystem::load(&json!({ "path": td.path() })).unwrap();
+        fs.create("key", vec![1, 2, 3]).unwrap();
+        let (_, uk_old) = fs.read("key").unwrap();
+
+        // Same first byte, which was previously used as the update key
+        fs.update("key", vec![1, 2, 4], uk_old.clone()).unwrap();
+        let (_, uk_new) = fs.read("key").unwrap();
+        assert_ne!(uk_old, uk_new);
+
+        match fs.update("key", vec![1, 2, 5], uk_old.clone()) {
+            Err(Error::InvalidUpdateKey) => {},
+            other => panic!("Expected InvalidUpdateKey, got {:?}", other),
+        }
+        match fs.delete("key", uk_old) {
+            Err(Error::InvalidUpdateKey) => {},
+            other => panic!("Expected InvalidUpdateKey, got {:?}", other),
+        }
+        assert_eq!(fs.read("key").unwrap().0, vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn contract() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        provider_contract_tests(&mut FileSystem::load(&json!({ "path": td.path() })).unwrap());
+    }
+
+    #[test]
+    fn conflict_copy_names() {
+        let key = "vault.vpass_vault";
+        assert!(is_conflict_copy(key, "vault (conflicted copy).vpass_vault"));
+        assert!(is_conflict_copy(key, "vault (Laptop's conflicted copy 2019-07-01).vpass_vault"));
+        assert!(is_conflict_copy(key, "vault.sync-conflict-20190701-120000-ABCDEFG.vpass_vault"));
+        assert!(!is_conflict_copy(key, key));
+        assert!(!is_conflict_copy(key, "vault2.vpass_vault"));
+        assert!(!is_conflict_copy(key, "other (conflicted copy).vpass_vault"));
+        assert!(!is_conflict_copy(key, "vault (conflicted copy).txt"));
+    }
+
+    #[test]
+    fn locked_remote() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let mut fs = FileSystem::load(&json!({ "path": td.path() })).unwrap();
+        let lock = fs.lock().unwrap();
+        match fs.create("key", vec![1]) {
+            Err(Error::RemoteLocked(_, _)) => {},
+            other => panic!("Expected RemoteLocked, got {:?}", other),
+        }
+        drop(lock);
+        fs.create("key", vec![1]).unwrap();
+        assert!(!td.path().join("VPassLock").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn unusual_file_names_listed() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let mut fs = FileSystem::load(&json!({ "path": td.path() })).unwrap();
+        fs.create("dir/key", vec![1]).unwrap();
+        std::fs::write(td.path().join(OsStr::from_bytes(b"caf\xe9")), b"").unwrap();
+        std::os::unix::fs::symlink(td.path(), td.path().join("dir/loop")).unwrap();
+        assert_eq!(fs.list().unwrap(), vec!["dir/key".to_owned()]);
+    }
+
+    #[test]
+    fn unwritten_lockfile_held() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let fs = FileSystem::load(&json!({ "path": td.path() })).unwrap();
+        // Created by another process, which hasn't written the contents yet
+        std::fs::write(td.path().join("VPassLock"), b"").unwrap();
+        match fs.lock() {
+            Err(Error::RemoteLocked(_, _)) => {},
+            other => panic!("Expected RemoteLocked, got {:?}", other.map(|_| ())),
+        }
+        assert!(td.path().join("VPassLock").exists());
+    }
 }
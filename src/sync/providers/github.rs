@@ -1,20 +1,28 @@
 //! Uses a private GitHub repository to syncronize passwords.
 //! An empty file called VPassFile is used to mark this as a vpass repository
 
-use super::super::{Error, SyncProvider, SyncResult, UpdateKey};
-use crate::VResult;
+use super::super::progress::{self, Progress};
+use super::super::{Capabilities, Error, SyncProvider, SyncResult, UpdateKey};
+use super::{redact_fields, response_field};
+use crate::{Error as VError, VResult};
 
 use base64;
-use reqwest::header::{HeaderMap, AUTHORIZATION};
+use chrono::prelude::*;
+use reqwest::header::{HeaderMap, AUTHORIZATION, RETRY_AFTER};
 use reqwest::Response;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::convert::AsMut;
+use std::io::Read;
 
-use log::debug;
+use tracing::debug;
 
 const API_URL: &str = "https://api.github.com";
 
+/// GitHub rejects files over 100 MB, and the contents API sends them base64 encoded.
+/// Larger values are split into chunks by the sync layer.
+const MAX_FILE_SIZE: usize = 50 * 1024 * 1024;
+
 type ConfigIntermediate = (Vec<u8>, Vec<u8>, [u8; 20], bool);
 
 fn clone_into_array<A, T>(slice: &[T]) -> A
@@ -27,36 +35,35 @@ where
     a
 }
 
+/// Length as a single byte, or 0xff followed by a 32-bit length
 fn sizeopt_string(s: &str) -> Vec<u8> {
     let len = s.len();
     let mut buf: Vec<u8> = Vec::new();
-    if len < std::u8::MAX as usize {
+    if len < u8::MAX as usize {
         buf.push(len as u8)
     } else {
-        assert!(len <= std::u32::MAX as usize);
+        assert!(len <= u32::MAX as usize);
+        buf.push(u8::MAX);
         buf.extend(&(len as u32).to_le_bytes());
     }
     buf.extend(s.bytes());
     buf
 }
 
-/// Returns: (String, used_bytes)
-fn read_sizeopt_string(s: &[u8]) -> (String, usize) {
-    let (len, index) = if s[0] == std::u8::MAX {
-        (s[0] as usize, 1)
+/// Returns: (String, used_bytes), or None if the data is truncated or invalid
+fn read_sizeopt_string(s: &[u8]) -> Option<(String, usize)> {
+    let (len, index): (usize, usize) = if *s.first()? == u8::MAX {
+        (u32::from_le_bytes(clone_into_array(s.get(1..5)?)) as usize, 5)
     } else {
-        (u32::from_le_bytes([s[1], s[2], s[3], s[4]]) as usize, 5)
+        (s[0] as usize, 1)
     };
-
-    (
-        String::from_utf8(s[index..index + len].to_vec()).unwrap(),
-        index + len,
-    )
+    let bytes = s.get(index..index.checked_add(len)?)?;
+    Some((String::from_utf8(bytes.to_vec()).ok()?, index + len))
 }
 
 fn hex_string(data: &[u8]) -> String {
     data.iter()
-        .map(|b| format!("{:x}", b))
+        .map(|b| format!("{:02x}", b))
         .collect::<Vec<_>>()
         .join("")
 }
@@ -87,16 +94,20 @@ impl Config {
     }
 
     fn decompress(data: &[u8]) -> VResult<Self> {
+        Self::try_decompress(data).ok_or(VError::SynchronizationTransferString)
+    }
+
+    fn try_decompress(data: &[u8]) -> Option<Self> {
         let mut index: usize = 0;
-        let (username, l) = read_sizeopt_string(&data[index..]);
+        let (username, l) = read_sizeopt_string(&data[index..])?;
         index += l;
-        let (repo_name, l) = read_sizeopt_string(&data[index..]);
+        let (repo_name, l) = read_sizeopt_string(&data[index..])?;
         index += l;
-        let access_token = hex_string(&data[index..index + 20]);
+        let access_token = hex_string(data.get(index..index + 20)?);
         index += 20;
-        let access_token_id = u64::from_le_bytes(clone_into_array(&data[index..index + 8]));
+        let access_token_id = u64::from_le_bytes(clone_into_array(data.get(index..index + 8)?));
 
-        Ok(Config {
+        Some(Config {
             username,
             repo_name,
             access_token,
@@ -139,28 +150,26 @@ fn create_oauth_token(
     let h = res.headers();
     if res.status() == 401 {
         if let Some(otp_header) = h.get("x-github-otp") {
-            Ok(Err(otp_header.to_str().unwrap().to_owned()))
+            Ok(Err(otp_header
+                .to_str()
+                .map_err(|_| Error::ProviderProtocol("Invalid OTP header".to_owned()))?
+                .to_owned()))
         } else {
             Err(Error::InvalidCredentials(
                 res.json::<Value>()
                     .map_err(Error::Http)?
                     .get("message")
-                    .map(|s| {
-                        s.as_str()
-                            .expect("GitHub API: Message is not a string")
-                            .to_owned()
-                    })
-                    .unwrap_or_else(|| "No message provided".to_owned()),
+                    .and_then(Value::as_str)
+                    .unwrap_or("No message provided")
+                    .to_owned(),
             )
             .into())
         }
     } else {
         let j = res.json::<Value>().map_err(Error::Http)?;
-        let id = j.get("id").expect("Missing key: id");
-        let token = j.get("token").expect("Missing key: token");
         Ok(Ok((
-            id.as_u64().expect("token_id must be an u64"),
-            token.as_str().expect("token must be a string").to_owned(),
+            response_field(&j, "id", Value::as_u64)?,
+            response_field(&j, "token", Value::as_str)?.to_owned(),
         )))
     }
 }
@@ -176,30 +185,65 @@ fn create_oauth_token_interactive(username: &str) -> VResult<(u64, String)> {
             let otp_code = match otp_header.as_str() {
                 "required; app" => prompt_string("2FA code from app")?,
                 "required; SMS" => prompt_string("2FA code from SMS")?,
-                other => panic!("Unknown GitHub OTP header: {:?}", other),
+                other => {
+                    return Err(Error::ProviderProtocol(format!("Unknown GitHub OTP header: {:?}", other)).into())
+                },
             };
-            Ok(create_oauth_token(username, &password, Some(otp_code))?.unwrap())
+            match create_oauth_token(username, &password, Some(otp_code))? {
+                Ok(token) => Ok(token),
+                Err(_) => Err(Error::InvalidCredentials("Invalid 2FA code".to_owned()).into()),
+            }
         },
     }
 }
 
+fn header_i64(headers: &HeaderMap, name: &str) -> Option<i64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Returns the time when the rate limit resets, if the response was rate limited.
+/// Secondary rate limits use Retry-After, primary ones X-RateLimit-Reset.
+fn rate_limit_reset(status: u16, headers: &HeaderMap) -> Option<DateTime<Utc>> {
+    let exhausted = header_i64(headers, "x-ratelimit-remaining") == Some(0);
+    let retry_after = header_i64(headers, RETRY_AFTER.as_str());
+    if status != 429 && !(status == 403 && (exhausted || retry_after.is_some())) {
+        return None;
+    }
+    if let Some(seconds) = retry_after {
+        Some(Utc::now() + chrono::Duration::seconds(seconds))
+    } else if let Some(reset) = header_i64(headers, "x-ratelimit-reset") {
+        Utc.timestamp_opt(reset, 0).single()
+    } else {
+        // No information available, GitHub suggests waiting at least a minute
+        Some(Utc::now() + chrono::Duration::minutes(1))
+    }
+}
+
 fn wrap_response<F, R>(mut res: Response, mut f: F) -> SyncResult<R>
 where F: FnMut(Response) -> SyncResult<R> {
     if res.status().is_success() {
         f(res)
+    } else if let Some(when) = rate_limit_reset(res.status().as_u16(), res.headers()) {
+        debug!("Rate limited until {}", when);
+        Err(Error::ApiRateLimit(when))
+    } else if res.status() == 413 {
+        Err(Error::ObjectTooLarge(MAX_FILE_SIZE))
     } else if res.status() == 403 {
         Err(Error::InvalidCredentials(
             res.json::<Value>()?
                 .get("message")
-                .map(|s| {
-                    s.as_str()
-                        .expect("GitHub API: Message is not a string")
-                        .to_owned()
-                })
-                .unwrap_or_else(|| "No message provided".to_owned()),
+                .and_then(Value::as_str)
+                .unwrap_or("No message provided")
+                .to_owned(),
         ))
     } else {
-        Err(Error::HttpStatus(res.status().as_u16(), Some(res.json()?)))
+        let j: Value = res.json()?;
+        let message = j.get("message").and_then(Value::as_str).unwrap_or("");
+        if res.status() == 422 && message.contains("too large") {
+            Err(Error::ObjectTooLarge(MAX_FILE_SIZE))
+        } else {
+            Err(Error::HttpStatus(res.status().as_u16(), Some(j)))
+        }
     }
 }
 
@@ -214,8 +258,20 @@ fn wrap_response_json(res: Response) -> SyncResult<(Value, HeaderMap)> {
 fn wrap_response_raw(res: Response) -> SyncResult<(Vec<u8>, HeaderMap)> {
     wrap_response(res, |mut r| {
         let h = r.headers().clone();
+        let total = r.content_length();
         let mut buf: Vec<u8> = Vec::new();
-        r.copy_to(&mut buf)?;
+        let mut block = [0u8; 64 * 1024];
+        loop {
+            let count = r.read(&mut block)?;
+            if count == 0 {
+                break;
+            }
+            buf.extend(&block[..count]);
+            progress::report(Progress::Transfer {
+                done: buf.len() as u64,
+                total,
+            });
+        }
         Ok((buf, h))
     })
 }
@@ -312,12 +368,7 @@ impl SyncProvider for GitHub {
                 self_.config.username, self_.config.repo_name
             )) {
                 Ok((j, _)) => {
-                    if !j
-                        .get("private")
-                        .expect("GitHub API: Key missing")
-                        .as_bool()
-                        .expect("GitHub API: Bool required")
-                    {
+                    if !response_field(&j, "private", Value::as_bool)? {
                         println!("{:?} is not private.", self_.config.repo_name);
                         continue;
                     }
@@ -332,7 +383,7 @@ impl SyncProvider for GitHub {
                                 "repos/{}/{}/contents/",
                                 self_.config.username, self_.config.repo_name,
                             ))?;
-                            if j.as_array().expect("GitHub API: Array required").is_empty() {
+                            if j.as_array().map(Vec::is_empty).unwrap_or(false) {
                                 self_.create("VPassFile", vec![])?;
                             } else {
                                 println!("Non-empty repository cannot be used automatically.");
@@ -375,15 +426,27 @@ impl SyncProvider for GitHub {
     /// Provider should overwrite this to get smaller transfer strings.
     fn configuration_decompress(data: &[u8]) -> VResult<Value>
     where Self: Sized {
-        let c: Config = Config::decompress(&data)?;
+        let c: Config = Config::decompress(data)?;
         Ok(serde_json::to_value(&c)?)
     }
 
-    fn load(value: &Value) -> Self
+    fn configuration_redact(item: &Value) -> Value
     where Self: Sized {
-        GitHub {
-            config: serde_json::from_value(value.clone()).expect("Invalid config for GitHub integration"),
+        redact_fields(item, &["access_token"])
+    }
+
+    fn load(value: &Value) -> SyncResult<Self>
+    where Self: Sized {
+        Ok(GitHub {
+            config: serde_json::from_value(value.clone()).map_err(|_| Error::ConfigurationItem)?,
             client: reqwest::Client::new(),
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            max_object_size: Some(MAX_FILE_SIZE),
+            ..Capabilities::default()
         }
     }
 
@@ -403,12 +466,7 @@ impl SyncProvider for GitHub {
             self.config.username, self.config.repo_name
         ))?;
 
-        if !j
-            .get("private")
-            .expect("GitHub API: Key missing")
-            .as_bool()
-            .expect("GitHub API: Bool required")
-        {
+        if !response_field(&j, "private", Value::as_bool)? {
             return Err(Error::InsufficientSecurity(
                 "Repository must be private".to_owned(),
             ));
@@ -453,26 +511,63 @@ impl SyncProvider for GitHub {
     }
 
     fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
-        match self.api_get_raw(&format!(
+        let (j, _) = match self.api_get(&format!(
             "repos/{}/{}/contents/{}",
             self.config.username, self.config.repo_name, key,
         )) {
-            Ok((raw, headers)) => {
-                // Etag header contains quoted sha1
-                let quoted_sha = headers
-                    .get("etag")
-                    .expect("GitHub API: etag sha missing")
-                    .to_str()
-                    .unwrap();
-
-                Ok((
-                    raw,
-                    UpdateKey::from_string(quoted_sha[1..quoted_sha.len() - 1].to_owned()),
-                ))
+            Ok(r) => r,
+            Err(Error::HttpStatus(404, _)) => return Err(Error::NoSuchKey(key.to_owned())),
+            Err(other) => return Err(other),
+        };
+
+        let sha = response_field(&j, "sha", Value::as_str)?.to_owned();
+
+        // Contents API only includes files up to 1 MB, larger ones are read using the blob API
+        let raw = match j.get("encoding").and_then(Value::as_str) {
+            Some("base64") => {
+                let content: String = response_field(&j, "content", Value::as_str)?
+                    .split_whitespace()
+                    .collect();
+                base64::decode(&content).map_err(|_| Error::InvalidRemote)?
             },
-            Err(Error::HttpStatus(404, _)) => Err(Error::NoSuchKey(key.to_owned())),
-            Err(other) => Err(other),
-        }
+            _ => {
+                let (raw, _) = self.api_get_raw(&format!(
+                    "repos/{}/{}/git/blobs/{}",
+                    self.config.username, self.config.repo_name, sha,
+                ))?;
+                raw
+            },
+        };
+
+        Ok((raw, UpdateKey::from_string(sha)))
+    }
+
+    /// Uses the listing of the parent directory, which has sizes and hashes but no content
+    fn stat(&mut self, key: &str) -> SyncResult<(u64, UpdateKey)> {
+        let (dir, name) = match key.rfind('/') {
+            Some(i) => (&key[..i], &key[i + 1..]),
+            None => ("", key),
+        };
+        let (j, _) = match self.api_get(&format!(
+            "repos/{}/{}/contents/{}",
+            self.config.username, self.config.repo_name, dir,
+        )) {
+            Ok(r) => r,
+            Err(Error::HttpStatus(404, _)) => return Err(Error::NoSuchKey(key.to_owned())),
+            Err(other) => return Err(other),
+        };
+        let entry = j
+            .as_array()
+            .ok_or(Error::InvalidRemote)?
+            .iter()
+            .find(|e| {
+                e.get("name").and_then(Value::as_str) == Some(name)
+                    && e.get("type").and_then(Value::as_str) == Some("file")
+            })
+            .ok_or_else(|| Error::NoSuchKey(key.to_owned()))?;
+        let size = response_field(entry, "size", Value::as_u64)?;
+        let sha = response_field(entry, "sha", Value::as_str)?.to_owned();
+        Ok((size, UpdateKey::from_string(sha)))
     }
 
     fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
@@ -488,4 +583,58 @@ impl SyncProvider for GitHub {
         )?;
         Ok(())
     }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        let (j, _) = self.api_get(&format!(
+            "repos/{}/{}/git/trees/HEAD?recursive=1",
+            self.config.username, self.config.repo_name
+        ))?;
+        let mut keys: Vec<String> = response_field(&j, "tree", Value::as_array)?
+            .iter()
+            .filter(|entry| entry.get("type").and_then(Value::as_str) == Some("blob"))
+            .map(|entry| response_field(entry, "path", Value::as_str).map(str::to_owned))
+            .collect::<SyncResult<Vec<_>>>()?;
+        keys.retain(|k| k != "VPassFile");
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rate_limit_reset, Config};
+    use chrono::prelude::*;
+    use reqwest::header::{HeaderMap, HeaderValue};
+
+    #[test]
+    fn rate_limit_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("10"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("1600000000"));
+        assert_eq!(rate_limit_reset(200, &headers), None);
+        assert_eq!(rate_limit_reset(403, &headers), None);
+
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        assert_eq!(rate_limit_reset(403, &headers), Utc.timestamp_opt(1_600_000_000, 0).single());
+
+        headers.insert("retry-after", HeaderValue::from_static("30"));
+        let when = rate_limit_reset(429, &headers).unwrap();
+        assert!(when > Utc::now() + chrono::Duration::seconds(20));
+        assert!(when <= Utc::now() + chrono::Duration::seconds(30));
+    }
+
+    #[test]
+    fn config_compress() {
+        let c = Config {
+            username: "user".to_owned(),
+            access_token: "0a1b2c3d4e5f00112233445566778899aabbccdd".to_owned(),
+            access_token_id: 1234,
+            repo_name: "r".repeat(300),
+        };
+        let data = c.compress();
+        assert!(Config::decompress(&data).unwrap() == c);
+        for len in 0..data.len() {
+            assert!(Config::decompress(&data[..len]).is_err());
+        }
+    }
 }
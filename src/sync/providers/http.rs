@@ -0,0 +1,236 @@
+//! Uses a `vpass-server` instance, or any server implementing the same REST API.
+//! See `src/bin/vpass-server.rs` for the API description.
+
+use super::super::{Error, SyncProvider, SyncResult, UpdateKey};
+use super::filesystem::is_valid_key;
+use super::redact_fields;
+use crate::VResult;
+
+use reqwest::header::{AUTHORIZATION, CONTENT_LENGTH, ETAG, IF_MATCH, IF_NONE_MATCH};
+use reqwest::{RequestBuilder, Response};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Read;
+
+use tracing::debug;
+
+/// Percent-encode a key for use in an url path.
+/// Slashes are kept, as keys can contain them.
+pub fn encode_key(key: &str) -> String {
+    key.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+/// Decode a percent-encoded key.
+/// Returns `None` for invalid encoding and for keys that could escape the storage directory.
+pub fn decode_key(encoded: &str) -> Option<String> {
+    let bytes = encoded.as_bytes();
+    let mut result = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            result.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            result.push(bytes[i]);
+            i += 1;
+        }
+    }
+    let key = String::from_utf8(result).ok()?;
+    if is_valid_key(&key) {
+        Some(key)
+    } else {
+        None
+    }
+}
+
+/// Revision as a quoted hex string, for ETag and If-Match headers
+pub fn encode_revision(uk: &UpdateKey) -> String {
+    format!(
+        "\"{}\"",
+        uk.as_bytes().iter().map(|b| format!("{:02x}", b)).collect::<String>()
+    )
+}
+
+pub fn decode_revision(s: &str) -> Option<UpdateKey> {
+    let s = s.trim();
+    if s.len() < 2 || !s.starts_with('"') || !s.ends_with('"') || !s.len().is_multiple_of(2) {
+        return None;
+    }
+    let hex = &s[1..s.len() - 1];
+    let bytes: Option<Vec<u8>> = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect();
+    Some(UpdateKey::from_bytes(&bytes?))
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Config {
+    url: String,
+    token: String,
+}
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Http_Config({})", self.url)
+    }
+}
+
+fn wrap_response(res: Response, key: &str) -> SyncResult<Response> {
+    match res.status().as_u16() {
+        200..=299 => Ok(res),
+        401 | 403 => Err(Error::InvalidCredentials("Invalid access token".to_owned())),
+        404 => Err(Error::NoSuchKey(key.to_owned())),
+        412 => Err(Error::InvalidUpdateKey),
+        other => Err(Error::HttpStatus(other, None)),
+    }
+}
+
+/// Revision of a key from the ETag header
+fn response_revision(res: &Response) -> SyncResult<UpdateKey> {
+    res.headers()
+        .get(ETAG)
+        .and_then(|h| h.to_str().ok())
+        .and_then(decode_revision)
+        .ok_or(Error::InvalidRemote)
+}
+
+pub struct Http {
+    client: reqwest::Client,
+    config: Config,
+}
+impl Http {
+    fn request(&self, method: reqwest::Method, path: &str) -> RequestBuilder {
+        debug!("{} {}", method, path);
+        self.client
+            .request(method, &format!("{}/{}", self.config.url.trim_end_matches('/'), path))
+            .header(AUTHORIZATION, format!("Bearer {}", self.config.token))
+    }
+
+    fn key_path(key: &str) -> String {
+        format!("keys/{}", encode_key(key))
+    }
+}
+impl SyncProvider for Http {
+    fn interactive_setup() -> VResult<Value> {
+        use crate::cli::interactive::*;
+
+        let mut self_ = Http {
+            client: reqwest::Client::new(),
+            config: Config {
+                url: prompt_string("Server url")?,
+                token: prompt_password("Access token")?,
+            },
+        };
+        self_.ping()?;
+        Ok(serde_json::to_value(&self_.config).unwrap())
+    }
+
+    fn configuration_redact(item: &Value) -> Value
+    where Self: Sized {
+        redact_fields(item, &["token"])
+    }
+
+    fn load(value: &Value) -> SyncResult<Self>
+    where Self: Sized {
+        Ok(Http {
+            config: serde_json::from_value(value.clone()).map_err(|_| Error::ConfigurationItem)?,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn ping(&mut self) -> SyncResult<()> {
+        wrap_response(self.request(reqwest::Method::GET, "ping").send()?, "ping")?;
+        Ok(())
+    }
+
+    fn test(&mut self) -> SyncResult<()> {
+        Ok(())
+    }
+
+    fn create(&mut self, key: &str, value: Vec<u8>) -> SyncResult<()> {
+        let res = self
+            .request(reqwest::Method::PUT, &Self::key_path(key))
+            .header(IF_NONE_MATCH, "*")
+            .body(value)
+            .send()?;
+        match wrap_response(res, key) {
+            Err(Error::InvalidUpdateKey) => Err(Error::KeyAlreadyExists(key.to_owned())),
+            other => other.map(|_| ()),
+        }
+    }
+
+    fn update(&mut self, key: &str, value: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
+        let res = self
+            .request(reqwest::Method::PUT, &Self::key_path(key))
+            .header(IF_MATCH, encode_revision(&update_key))
+            .body(value)
+            .send()?;
+        wrap_response(res, key)?;
+        Ok(())
+    }
+
+    fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
+        let mut res = wrap_response(self.request(reqwest::Method::GET, &Self::key_path(key)).send()?, key)?;
+        let update_key = response_revision(&res)?;
+        let mut data = Vec::new();
+        res.read_to_end(&mut data)?;
+        Ok((data, update_key))
+    }
+
+    fn stat(&mut self, key: &str) -> SyncResult<(u64, UpdateKey)> {
+        let res = wrap_response(self.request(reqwest::Method::HEAD, &Self::key_path(key)).send()?, key)?;
+        let size = res
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|s| s.parse().ok())
+            .ok_or(Error::InvalidRemote)?;
+        Ok((size, response_revision(&res)?))
+    }
+
+    fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
+        let res = self
+            .request(reqwest::Method::DELETE, &Self::key_path(key))
+            .header(IF_MATCH, encode_revision(&update_key))
+            .send()?;
+        wrap_response(res, key)?;
+        Ok(())
+    }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        let mut res = wrap_response(self.request(reqwest::Method::GET, "keys").send()?, "keys")?;
+        Ok(res.json()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::super::UpdateKey;
+    use super::{decode_key, decode_revision, encode_key, encode_revision};
+
+    #[test]
+    fn key_encoding() {
+        let key = "trash/vault (conflicted copy).vpass_vault";
+        assert_eq!(decode_key(&encode_key(key)).as_deref(), Some(key));
+        assert_eq!(decode_key("../secret"), None);
+        assert_eq!(decode_key("/etc/passwd"), None);
+        assert_eq!(decode_key("a/%2E%2E/b"), None);
+    }
+
+    #[test]
+    fn revision_encoding() {
+        let uk = UpdateKey::from_bytes(&[0, 1, 254, 255]);
+        assert_eq!(encode_revision(&uk), "\"0001feff\"");
+        assert_eq!(decode_revision(&encode_revision(&uk)), Some(uk));
+        assert_eq!(decode_revision("0001"), None);
+    }
+}
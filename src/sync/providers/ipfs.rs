@@ -0,0 +1,212 @@
+//! Stores vaults on an IPFS node, using the node's RPC API.
+//! Keys are mapped to files in the Mutable File System (MFS) of the node,
+//! which acts as the mutable pointer to the latest content id (CID) of each key.
+//! MFS content is never garbage collected by the node, so it stays pinned.
+//! The CID of the content is used as the UpdateKey.
+//! An empty file called VPassFile is used to mark the root folder as a vpass repository.
+
+use super::super::{Capabilities, Error, SyncProvider, SyncResult, UpdateKey};
+use super::response_field;
+use crate::VResult;
+
+use reqwest::multipart::{Form, Part};
+use reqwest::Response;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::Read;
+
+use tracing::debug;
+
+const DEFAULT_API_URL: &str = "http://127.0.0.1:5001";
+const DEFAULT_ROOT: &str = "/vpass";
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Config {
+    /// RPC API address of the node
+    api_url: String,
+    /// MFS folder for the repository
+    root: String,
+}
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Ipfs_Config({}{})", self.api_url, self.root)
+    }
+}
+
+/// Errors from the API are returned with status 500 and a JSON message
+fn wrap_response(mut res: Response) -> SyncResult<Response> {
+    if res.status().is_success() {
+        Ok(res)
+    } else {
+        let j: Value = res.json().unwrap_or(Value::Null);
+        let message = j.get("Message").and_then(Value::as_str).unwrap_or("");
+        if message.contains("does not exist") {
+            Err(Error::NoSuchKey(message.to_owned()))
+        } else {
+            Err(Error::HttpStatus(res.status().as_u16(), Some(j)))
+        }
+    }
+}
+
+pub struct Ipfs {
+    client: reqwest::Client,
+    config: Config,
+}
+impl Ipfs {
+    fn path(&self, key: &str) -> String {
+        format!("{}/{}", self.config.root, key)
+    }
+
+    fn api(&self, command: &str, args: &[(&str, &str)]) -> SyncResult<Response> {
+        debug!("API: {} {:?}", command, args);
+        wrap_response(
+            self.client
+                .post(&format!("{}/api/v0/{}", self.config.api_url, command))
+                .query(args)
+                .send()?,
+        )
+    }
+
+    fn api_json(&self, command: &str, args: &[(&str, &str)]) -> SyncResult<Value> {
+        Ok(self.api(command, args)?.json()?)
+    }
+
+    /// CID of a file
+    fn stat(&self, key: &str) -> SyncResult<UpdateKey> {
+        let j = self
+            .api_json("files/stat", &[("arg", &self.path(key))])
+            .map_err(|e| match e {
+                Error::NoSuchKey(_) => Error::NoSuchKey(key.to_owned()),
+                other => other,
+            })?;
+        Ok(UpdateKey::from_string(response_field(&j, "Hash", Value::as_str)?.to_owned()))
+    }
+
+    fn write(&self, key: &str, value: Vec<u8>) -> SyncResult<()> {
+        let path = self.path(key);
+        debug!("API: files/write {}", path);
+        wrap_response(
+            self.client
+                .post(&format!("{}/api/v0/files/write", self.config.api_url))
+                .query(&[
+                    ("arg", path.as_str()),
+                    ("create", "true"),
+                    ("truncate", "true"),
+                    ("parents", "true"),
+                ])
+                .multipart(Form::new().part("file", Part::bytes(value)))
+                .send()?,
+        )?;
+        Ok(())
+    }
+
+    fn check_update_key(&self, key: &str, update_key: &UpdateKey) -> SyncResult<()> {
+        if self.stat(key)? == *update_key {
+            Ok(())
+        } else {
+            Err(Error::InvalidUpdateKey)
+        }
+    }
+
+    fn list_dir(&self, dir: &str, prefix: &str, result: &mut Vec<String>) -> SyncResult<()> {
+        let j = self.api_json("files/ls", &[("arg", dir), ("long", "true")])?;
+        for entry in j.get("Entries").and_then(Value::as_array).cloned().unwrap_or_default() {
+            let name = response_field(&entry, "Name", Value::as_str)?;
+            // Type 1 is a directory
+            if entry.get("Type").and_then(Value::as_u64) == Some(1) {
+                self.list_dir(&format!("{}/{}", dir, name), &format!("{}{}/", prefix, name), result)?;
+            } else {
+                result.push(format!("{}{}", prefix, name));
+            }
+        }
+        Ok(())
+    }
+}
+impl SyncProvider for Ipfs {
+    fn interactive_setup() -> VResult<Value> {
+        use crate::cli::interactive::*;
+
+        let api_url = if prompt_boolean(&format!("Use local node at {}?", DEFAULT_API_URL))? {
+            DEFAULT_API_URL.to_owned()
+        } else {
+            prompt_string("Node API address")?
+        };
+        let mut self_ = Ipfs {
+            client: reqwest::Client::new(),
+            config: Config {
+                api_url,
+                root: DEFAULT_ROOT.to_owned(),
+            },
+        };
+        self_.ping()?;
+
+        if !self_.exists("VPassFile")? {
+            self_.write("VPassFile", vec![])?;
+        }
+        self_.test()?;
+        Ok(serde_json::to_value(&self_.config).unwrap())
+    }
+
+    fn load(value: &Value) -> SyncResult<Self>
+    where Self: Sized {
+        Ok(Ipfs {
+            config: serde_json::from_value(value.clone()).map_err(|_| Error::ConfigurationItem)?,
+            client: reqwest::Client::new(),
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            // CID check and write are separate API calls
+            atomic_update: false,
+            ..Capabilities::default()
+        }
+    }
+
+    fn ping(&mut self) -> SyncResult<()> {
+        self.api("id", &[])?;
+        Ok(())
+    }
+
+    fn test(&mut self) -> SyncResult<()> {
+        match self.stat("VPassFile") {
+            Ok(_) => Ok(()),
+            Err(Error::NoSuchKey(_)) => Err(Error::InvalidRemote),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn create(&mut self, key: &str, value: Vec<u8>) -> SyncResult<()> {
+        if self.exists(key)? {
+            return Err(Error::KeyAlreadyExists(key.to_owned()));
+        }
+        self.write(key, value)
+    }
+
+    fn update(&mut self, key: &str, value: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
+        self.check_update_key(key, &update_key)?;
+        self.write(key, value)
+    }
+
+    fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
+        let (_, update_key) = self.stat(key)?;
+        let mut res = self.api("files/read", &[("arg", &self.path(key))])?;
+        let mut data = Vec::new();
+        res.read_to_end(&mut data)?;
+        Ok((data, update_key))
+    }
+
+    fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
+        self.check_update_key(key, &update_key)?;
+        self.api("files/rm", &[("arg", &self.path(key))])?;
+        Ok(())
+    }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        let mut keys = Vec::new();
+        self.list_dir(&self.config.root, "", &mut keys)?;
+        keys.retain(|k| k != "VPassFile");
+        keys.sort();
+        Ok(keys)
+    }
+}
@@ -0,0 +1,1002 @@
+//! Synchronizes directly with another machine in the local network.
+//! The other machine runs `vpass sync listen`, serving a local folder as a FileSystem repository.
+//! Listeners are discovered with mDNS, as the `_vpass._tcp.local` service.
+//!
+//! Connections use a handshake like the Noise NNpsk0 pattern: both sides send an ephemeral
+//! X25519 key, and the session key is derived from their shared key and a pre-shared secret,
+//! so that only machines knowing the secret can connect, and recorded traffic can't be decrypted later.
+//! The secret is obtained by pairing: the listener displays a pairing code, accepted once
+//! and only for a few minutes, from which the pre-shared key of the pairing connection is derived.
+//! Messages carry the random session id of the listener and sequence numbers to prevent replays.
+
+use super::super::{Capabilities, Error, SyncProvider, SyncResult, UpdateKey};
+use super::filesystem::{is_reserved_key, is_valid_key, FileSystem};
+use super::redact_fields;
+use crate::VResult;
+
+use rust_sodium::crypto::{auth::hmacsha256, pwhash, scalarmult, secretbox};
+use rust_sodium::randombytes::randombytes;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{fs, thread};
+use tracing::{debug, warn};
+
+pub const DEFAULT_PORT: u16 = 47801;
+
+const MDNS_ADDRESS: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+const MDNS_SERVICE: &str = "_vpass._tcp.local";
+const MDNS_INSTANCE: &str = "vpass._vpass._tcp.local";
+const DNS_TYPE_PTR: u16 = 12;
+const DNS_TYPE_SRV: u16 = 33;
+const DNS_TYPE_ANY: u16 = 255;
+const DNS_CLASS_IN: u16 = 1;
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Upper limit for a single message, to avoid allocating arbitrary amounts of memory
+const MAX_FRAME_SIZE: usize = 64 * 1024 * 1024;
+/// Room left in a frame for the envelope around a value
+const FRAME_OVERHEAD: usize = 64 * 1024;
+
+/// A silent client would otherwise block the listener, which serves one connection at a time
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Pairing code characters, excluding ambiguous ones
+const PAIRING_ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+const PAIRING_CODE_LEN: usize = 12;
+const PAIRING_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+/// Separates session keys from other uses of the same secrets, changed with the handshake
+const SESSION_KEY_INFO: &[u8] = b"vpass lan session key v1";
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Config {
+    address: SocketAddr,
+    /// Shared secret from pairing
+    secret: secretbox::Key,
+}
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Lan_Config({})", self.address)
+    }
+}
+
+/// Sent unencrypted by the listener when a connection is opened
+#[derive(Debug, Serialize, Deserialize)]
+struct Hello {
+    session: Vec<u8>,
+    pairing_salt: pwhash::Salt,
+    /// Ephemeral public key of the listener
+    ephemeral: Vec<u8>,
+}
+
+/// First message of the client, with its ephemeral public key and the sealed request
+#[derive(Debug, Serialize, Deserialize)]
+struct ClientHello {
+    ephemeral: Vec<u8>,
+    request: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Request {
+    /// Only accepted with the pairing key
+    Pair,
+    Ping,
+    Test,
+    Create(String, Vec<u8>),
+    Update(String, Vec<u8>, Vec<u8>),
+    Read(String),
+    Delete(String, Vec<u8>),
+    List,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum Response {
+    Ok,
+    Secret(secretbox::Key),
+    Data(Vec<u8>, Vec<u8>),
+    Keys(Vec<String>),
+    NoSuchKey(String),
+    InvalidUpdateKey,
+    Failed(String),
+}
+
+/// Encrypted content of a frame
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope<T> {
+    session: Vec<u8>,
+    seq: u64,
+    body: T,
+}
+
+fn write_frame(stream: &mut TcpStream, data: &[u8]) -> io::Result<()> {
+    stream.write_all(&(data.len() as u32).to_le_bytes())?;
+    stream.write_all(data)?;
+    stream.flush()
+}
+
+fn read_frame(stream: &mut TcpStream) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > MAX_FRAME_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "Frame too large"));
+    }
+    let mut buf = vec![0u8; len];
+    stream.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn seal<T: Serialize>(key: &secretbox::Key, envelope: &Envelope<T>) -> Vec<u8> {
+    let nonce = secretbox::gen_nonce();
+    let mut result = nonce.0.to_vec();
+    result.extend(secretbox::seal(&bincode::serialize(envelope).unwrap(), &nonce, key));
+    result
+}
+
+fn open<T: DeserializeOwned>(key: &secretbox::Key, data: &[u8]) -> Option<Envelope<T>> {
+    let nonce = secretbox::Nonce::from_slice(data.get(..secretbox::NONCEBYTES)?)?;
+    let plaintext = secretbox::open(&data[secretbox::NONCEBYTES..], &nonce, key).ok()?;
+    bincode::deserialize(&plaintext).ok()
+}
+
+/// New ephemeral key pair for a handshake
+fn ephemeral_keys() -> (scalarmult::Scalar, scalarmult::GroupElement) {
+    let secret = scalarmult::Scalar::from_slice(&randombytes(scalarmult::SCALARBYTES)).unwrap();
+    let public = scalarmult::scalarmult_base(&secret);
+    (secret, public)
+}
+
+/// HKDF-SHA256 (RFC 5869) with a single block of output
+fn hkdf_sha256(salt: &[u8], ikm: &[u8], info: &[u8]) -> [u8; 32] {
+    let mut extract = hmacsha256::State::init(salt);
+    extract.update(ikm);
+    let prk = extract.finalize();
+    let mut expand = hmacsha256::State::init(&prk.0);
+    expand.update(info);
+    expand.update(&[1]);
+    expand.finalize().0
+}
+
+/// Session key from the ephemeral keys of both sides and the pre-shared key,
+/// bound to the session id and both public keys
+fn session_key(
+    secret: &scalarmult::Scalar, peer_public: &[u8], psk: &secretbox::Key, hello: &Hello,
+    client_public: &[u8],
+) -> Option<secretbox::Key> {
+    let peer_public = scalarmult::GroupElement::from_slice(peer_public)?;
+    let shared = scalarmult::scalarmult(secret, &peer_public).ok()?;
+    let mut ikm = shared.0.to_vec();
+    ikm.extend(&psk.0);
+    let mut info = SESSION_KEY_INFO.to_vec();
+    info.extend(&hello.ephemeral);
+    info.extend(client_public);
+    Some(secretbox::Key(hkdf_sha256(&hello.session, &ikm, &info)))
+}
+
+fn pairing_key(code: &str, salt: &pwhash::Salt) -> secretbox::Key {
+    let mut key = secretbox::Key([0; secretbox::KEYBYTES]);
+    {
+        let secretbox::Key(ref mut kb) = key;
+        pwhash::derive_key(
+            kb,
+            code.trim().to_uppercase().replace("-", "").as_bytes(),
+            salt,
+            pwhash::OPSLIMIT_INTERACTIVE,
+            pwhash::MEMLIMIT_INTERACTIVE,
+        )
+        .unwrap();
+    }
+    key
+}
+
+fn generate_pairing_code() -> String {
+    let chars: Vec<char> = randombytes(PAIRING_CODE_LEN)
+        .iter()
+        .map(|b| PAIRING_ALPHABET[*b as usize % PAIRING_ALPHABET.len()] as char)
+        .collect();
+    chars
+        .chunks(4)
+        .map(|c| c.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// A single request-response exchange with a listener
+fn exchange(address: SocketAddr, key: &secretbox::Key, request: Request) -> SyncResult<Response> {
+    exchange_with(address, |_| key.clone(), request)
+}
+
+fn exchange_with<F>(address: SocketAddr, psk_for: F, request: Request) -> SyncResult<Response>
+where F: FnOnce(&Hello) -> secretbox::Key {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+    let hello: Hello = bincode::deserialize(&read_frame(&mut stream)?).map_err(|_| Error::InvalidRemote)?;
+    let (secret, public) = ephemeral_keys();
+    let key = session_key(&secret, &hello.ephemeral, &psk_for(&hello), &hello, &public.0)
+        .ok_or(Error::InvalidRemote)?;
+    let envelope = Envelope {
+        session: hello.session.clone(),
+        seq: 0,
+        body: request,
+    };
+    let client_hello = ClientHello {
+        ephemeral: public.0.to_vec(),
+        request: seal(&key, &envelope),
+    };
+    write_frame(&mut stream, &bincode::serialize(&client_hello).unwrap())?;
+    let response: Envelope<Response> = open(&key, &read_frame(&mut stream)?)
+        .ok_or_else(|| Error::InvalidCredentials("Lan: Pairing rejected".to_owned()))?;
+    if response.session != hello.session || response.seq != 1 {
+        return Err(Error::InvalidRemote);
+    }
+    Ok(response.body)
+}
+
+fn unexpected(response: Response) -> Error {
+    match response {
+        Response::NoSuchKey(key) => Error::NoSuchKey(key),
+        Response::InvalidUpdateKey => Error::InvalidUpdateKey,
+        Response::Failed(msg) => Error::Misc(json!(msg)),
+        other => Error::Misc(json!(format!("Lan: Unexpected response {:?}", other))),
+    }
+}
+
+fn put_dns_name(packet: &mut Vec<u8>, name: &str) {
+    for label in name.split('.') {
+        packet.push(label.len() as u8);
+        packet.extend(label.as_bytes());
+    }
+    packet.push(0);
+}
+
+/// Skip a possibly compressed name at `pos`, returning the position after it
+fn skip_dns_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(pos)? as usize;
+        if len == 0 {
+            return Some(pos + 1);
+        } else if len & 0xc0 == 0xc0 {
+            return Some(pos + 2);
+        }
+        pos += 1 + len;
+    }
+}
+
+fn dns_u16(packet: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*packet.get(pos)?, *packet.get(pos + 1)?]))
+}
+
+/// Question for the listener service, asking for a unicast response
+fn mdns_query() -> Vec<u8> {
+    // Id, flags, one question, no records
+    let mut packet = vec![0, 0, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    put_dns_name(&mut packet, MDNS_SERVICE);
+    packet.extend(&DNS_TYPE_PTR.to_be_bytes());
+    packet.extend(&(DNS_CLASS_IN | 0x8000).to_be_bytes());
+    packet
+}
+
+/// Response to a query asking for the listener service, pointing to the instance and its port
+fn mdns_answer(query: &[u8], port: u16) -> Option<Vec<u8>> {
+    if query.len() < 12 || query[2] & 0x80 != 0 || dns_u16(query, 4)? == 0 {
+        return None;
+    }
+    // Only the first question is looked at, queries for this service have just one
+    let mut service = Vec::new();
+    put_dns_name(&mut service, MDNS_SERVICE);
+    let name = query.get(12..12 + service.len())?;
+    let qtype = dns_u16(query, 12 + service.len())?;
+    if !name.eq_ignore_ascii_case(&service) || (qtype != DNS_TYPE_PTR && qtype != DNS_TYPE_ANY) {
+        return None;
+    }
+    // Same id, authoritative response, no questions, an answer and an additional record
+    let mut packet = vec![query[0], query[1], 0x84, 0, 0, 0, 0, 1, 0, 0, 0, 1];
+    let mut instance = Vec::new();
+    put_dns_name(&mut instance, MDNS_INSTANCE);
+    let ttl = 120u32.to_be_bytes();
+
+    packet.extend(&service);
+    packet.extend(&DNS_TYPE_PTR.to_be_bytes());
+    packet.extend(&DNS_CLASS_IN.to_be_bytes());
+    packet.extend(&ttl);
+    packet.extend(&(instance.len() as u16).to_be_bytes());
+    packet.extend(&instance);
+
+    // Clients connect to the address the response came from, so the target is not used
+    let mut target = Vec::new();
+    put_dns_name(&mut target, "vpass.local");
+    packet.extend(&instance);
+    packet.extend(&DNS_TYPE_SRV.to_be_bytes());
+    packet.extend(&DNS_CLASS_IN.to_be_bytes());
+    packet.extend(&ttl);
+    packet.extend(&((6 + target.len()) as u16).to_be_bytes());
+    packet.extend(&[0, 0, 0, 0]);
+    packet.extend(&port.to_be_bytes());
+    packet.extend(&target);
+    Some(packet)
+}
+
+/// Port of the listener service in an mDNS response
+fn mdns_port(response: &[u8]) -> Option<u16> {
+    if response.len() < 12 || response[2] & 0x80 == 0 {
+        return None;
+    }
+    let mut pos = 12;
+    for _ in 0..dns_u16(response, 4)? {
+        pos = skip_dns_name(response, pos)? + 4;
+    }
+    let records = dns_u16(response, 6)? as usize
+        + dns_u16(response, 8)? as usize
+        + dns_u16(response, 10)? as usize;
+    let mut instance = Vec::new();
+    put_dns_name(&mut instance, MDNS_INSTANCE);
+    for _ in 0..records {
+        let start = pos;
+        pos = skip_dns_name(response, pos)?;
+        let rtype = dns_u16(response, pos)?;
+        let len = dns_u16(response, pos + 8)? as usize;
+        let data = pos + 10;
+        if rtype == DNS_TYPE_SRV && response[start..pos].eq_ignore_ascii_case(&instance) {
+            return dns_u16(response, data + 4);
+        }
+        pos = data + len;
+    }
+    None
+}
+
+/// Socket receiving mDNS queries, shared with other responders on this machine
+#[cfg(unix)]
+fn mdns_socket() -> io::Result<UdpSocket> {
+    use std::os::unix::io::FromRawFd;
+
+    let socket = unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM, 0);
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Closed when dropped, also if setting it up fails
+        let socket = UdpSocket::from_raw_fd(fd);
+        let one: libc::c_int = 1;
+        for option in &[libc::SO_REUSEADDR, libc::SO_REUSEPORT] {
+            let value = &one as *const libc::c_int as *const libc::c_void;
+            let len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+            if libc::setsockopt(fd, libc::SOL_SOCKET, *option, value, len) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        let mut address: libc::sockaddr_in = std::mem::zeroed();
+        address.sin_family = libc::AF_INET as libc::sa_family_t;
+        address.sin_port = MDNS_PORT.to_be();
+        let len = std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t;
+        if libc::bind(fd, &address as *const libc::sockaddr_in as *const libc::sockaddr, len) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        socket
+    };
+    socket.join_multicast_v4(&MDNS_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+    Ok(socket)
+}
+
+#[cfg(not(unix))]
+fn mdns_socket() -> io::Result<UdpSocket> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, MDNS_PORT))?;
+    socket.join_multicast_v4(&MDNS_ADDRESS, &Ipv4Addr::UNSPECIFIED)?;
+    Ok(socket)
+}
+
+/// Find listeners in the local network
+fn discover() -> SyncResult<Vec<SocketAddr>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_read_timeout(Some(DISCOVERY_TIMEOUT))?;
+    socket.send_to(&mdns_query(), (MDNS_ADDRESS, MDNS_PORT))?;
+
+    let mut result = Vec::new();
+    let mut buf = [0u8; 1500];
+    let start = Instant::now();
+    while start.elapsed() < DISCOVERY_TIMEOUT {
+        let (len, from) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(_) => break,
+        };
+        if let Some(port) = mdns_port(&buf[..len]) {
+            let addr = SocketAddr::new(from.ip(), port);
+            if !result.contains(&addr) {
+                result.push(addr);
+            }
+        }
+    }
+    Ok(result)
+}
+
+pub struct Lan {
+    config: Config,
+}
+impl Lan {
+    fn request(&self, request: Request) -> SyncResult<Response> {
+        exchange(self.config.address, &self.config.secret, request)
+    }
+
+    fn request_ok(&self, request: Request) -> SyncResult<()> {
+        match self.request(request)? {
+            Response::Ok => Ok(()),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+impl SyncProvider for Lan {
+    fn interactive_setup() -> VResult<Value> {
+        use crate::cli::interactive::*;
+
+        println!("Searching for listeners, run `vpass sync listen` on the other machine");
+        let found = discover()?;
+        for addr in &found {
+            println!("* {}", addr);
+        }
+        let address: SocketAddr = loop {
+            let input = if found.len() == 1 && prompt_boolean(&format!("Use {}?", found[0]))? {
+                found[0].to_string()
+            } else {
+                prompt_string("Listener address (ip:port)")?
+            };
+            match input.parse() {
+                Ok(addr) => break addr,
+                Err(_) => println!("Invalid address {:?}", input),
+            }
+        };
+
+        let code = prompt_string("Pairing code shown by the listener")?;
+        let psk_for = |hello: &Hello| pairing_key(&code, &hello.pairing_salt);
+        let secret = match exchange_with(address, psk_for, Request::Pair)? {
+            Response::Secret(secret) => secret,
+            other => return Err(unexpected(other).into()),
+        };
+        println!("Pairing successful");
+
+        let mut self_ = Lan {
+            config: Config { address, secret },
+        };
+        self_.test()?;
+        Ok(serde_json::to_value(&self_.config).unwrap())
+    }
+
+    fn configuration_redact(item: &Value) -> Value
+    where Self: Sized {
+        redact_fields(item, &["secret"])
+    }
+
+    fn load(value: &Value) -> SyncResult<Self>
+    where Self: Sized {
+        Ok(Lan {
+            config: serde_json::from_value(value.clone()).map_err(|_| Error::ConfigurationItem)?,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            // The served folder may also be used locally with the FileSystem provider
+            atomic_update: false,
+            max_object_size: Some(MAX_FRAME_SIZE - FRAME_OVERHEAD),
+            ..Capabilities::default()
+        }
+    }
+
+    fn ping(&mut self) -> SyncResult<()> {
+        self.request_ok(Request::Ping)
+    }
+
+    fn test(&mut self) -> SyncResult<()> {
+        self.request_ok(Request::Test)
+    }
+
+    fn create(&mut self, key: &str, value: Vec<u8>) -> SyncResult<()> {
+        self.request_ok(Request::Create(key.to_owned(), value))
+    }
+
+    fn update(&mut self, key: &str, value: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
+        self.request_ok(Request::Update(key.to_owned(), value, update_key.as_bytes().to_vec()))
+    }
+
+    fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
+        match self.request(Request::Read(key.to_owned()))? {
+            Response::Data(data, update_key) => Ok((data, UpdateKey::from_bytes(&update_key))),
+            other => Err(unexpected(other)),
+        }
+    }
+
+    fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
+        self.request_ok(Request::Delete(key.to_owned(), update_key.as_bytes().to_vec()))
+    }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        match self.request(Request::List)? {
+            Response::Keys(keys) => Ok(keys),
+            other => Err(unexpected(other)),
+        }
+    }
+}
+
+fn to_response(result: SyncResult<()>) -> Response {
+    match result {
+        Ok(()) => Response::Ok,
+        Err(e) => error_response(e),
+    }
+}
+
+fn error_response(error: Error) -> Response {
+    match error {
+        Error::NoSuchKey(key) => Response::NoSuchKey(key),
+        Error::InvalidUpdateKey => Response::InvalidUpdateKey,
+        other => Response::Failed(format!("{:?}", other)),
+    }
+}
+
+fn serve_request(repo: &mut FileSystem, request: Request) -> Response {
+    // Keys are paths in the served folder, so a peer must not be able to escape it
+    let key = match request {
+        Request::Create(ref key, _)
+        | Request::Update(ref key, _, _)
+        | Request::Read(ref key)
+        | Request::Delete(ref key, _) => Some(key),
+        Request::Pair | Request::Ping | Request::Test | Request::List => None,
+    };
+    if let Some(key) = key {
+        if !is_valid_key(key) || is_reserved_key(key) {
+            return Response::Failed(format!("Invalid key {:?}", key));
+        }
+    }
+
+    match request {
+        Request::Pair => Response::Failed("Already paired".to_owned()),
+        Request::Ping => to_response(repo.ping()),
+        Request::Test => to_response(repo.test()),
+        Request::Create(key, value) => to_response(repo.create(&key, value)),
+        Request::Update(key, value, uk) => to_response(repo.update(&key, value, UpdateKey::from_bytes(&uk))),
+        Request::Read(key) => match repo.read(&key) {
+            Ok((data, uk)) => Response::Data(data, uk.as_bytes().to_vec()),
+            Err(e) => error_response(e),
+        },
+        Request::Delete(key, uk) => to_response(repo.delete(&key, UpdateKey::from_bytes(&uk))),
+        Request::List => match repo.list() {
+            Ok(keys) => Response::Keys(keys),
+            Err(e) => error_response(e),
+        },
+    }
+}
+
+/// Pairing code, accepted for one pairing until it expires
+struct Pairing {
+    salt: pwhash::Salt,
+    key: Option<secretbox::Key>,
+    expires: Instant,
+}
+impl Pairing {
+    fn new(code: &str) -> Self {
+        let salt = pwhash::gen_salt();
+        Self {
+            key: Some(pairing_key(code, &salt)),
+            salt,
+            expires: Instant::now() + PAIRING_TIMEOUT,
+        }
+    }
+
+    fn key(&self) -> Option<&secretbox::Key> {
+        self.key.as_ref().filter(|_| Instant::now() < self.expires)
+    }
+}
+
+struct Listener {
+    repo: FileSystem,
+    secret: secretbox::Key,
+    pairing: Pairing,
+}
+impl Listener {
+    fn handle(&mut self, mut stream: TcpStream) -> io::Result<()> {
+        stream.set_read_timeout(Some(CONNECTION_TIMEOUT))?;
+        let session = randombytes(32);
+        let (secret, public) = ephemeral_keys();
+        let hello = Hello {
+            session: session.clone(),
+            pairing_salt: self.pairing.salt,
+            ephemeral: public.0.to_vec(),
+        };
+        write_frame(&mut stream, &bincode::serialize(&hello).unwrap())?;
+
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let client: ClientHello = bincode::deserialize(&read_frame(&mut stream)?)
+            .map_err(|_| invalid("Invalid handshake"))?;
+        let key_with = |psk: &secretbox::Key| {
+            session_key(&secret, &client.ephemeral, psk, &hello, &client.ephemeral)
+                .ok_or_else(|| invalid("Invalid ephemeral key"))
+        };
+        let paired_key = key_with(&self.secret)?;
+        let (key, response) = if let Some(req) = open::<Request>(&paired_key, &client.request) {
+            if req.session != session || req.seq != 0 {
+                return Err(invalid("Replayed request"));
+            }
+            debug!("Request: {:?}", req.body);
+            (paired_key, serve_request(&mut self.repo, req.body))
+        } else {
+            let pairing_key = match self.pairing.key() {
+                Some(psk) => key_with(psk)?,
+                None => return Err(invalid("Unauthenticated request")),
+            };
+            match open::<Request>(&pairing_key, &client.request) {
+                Some(Envelope {
+                    body: Request::Pair,
+                    session: ref s,
+                    seq: 0,
+                }) if *s == session => {
+                    // The code can't be used again
+                    self.pairing.key = None;
+                    println!("Paired with {}", stream.peer_addr()?);
+                    (pairing_key, Response::Secret(self.secret.clone()))
+                },
+                _ => return Err(invalid("Unauthenticated request")),
+            }
+        };
+
+        let envelope = Envelope {
+            session,
+            seq: 1,
+            body: response,
+        };
+        write_frame(&mut stream, &seal(&key, &envelope))
+    }
+}
+
+/// Answer mDNS queries for the listener service with the listening port
+fn mdns_responder(port: u16) -> io::Result<()> {
+    let socket = mdns_socket()?;
+    let mut buf = [0u8; 1500];
+    loop {
+        let (len, from) = socket.recv_from(&mut buf)?;
+        if let Some(answer) = mdns_answer(&buf[..len], port) {
+            debug!("Discovery request from {}", from);
+            socket.send_to(&answer, from)?;
+        }
+    }
+}
+
+/// Load the shared secret, or create it if it doesn't exist
+fn load_or_create_secret(path: &Path) -> io::Result<secretbox::Key> {
+    if path.exists() {
+        secretbox::Key::from_slice(&fs::read(path)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Invalid secret file"))
+    } else {
+        let key = secretbox::gen_key();
+        // Created owner-only, so that the secret is never readable by others
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(path)?.write_all(&key.0)?;
+        Ok(key)
+    }
+}
+
+/// Serve `dir` as a repository for `Lan` providers, until interrupted.
+/// The shared secret is stored in `secret_path`.
+pub fn listen(dir: &Path, secret_path: &Path, port: u16) -> VResult<()> {
+    fs::create_dir_all(dir)?;
+    if !dir.join("VPassFile").exists() {
+        fs::write(dir.join("VPassFile"), [])?;
+    }
+
+    let code = generate_pairing_code();
+    let mut listener = Listener {
+        repo: FileSystem::load(&json!({ "path": dir }))?,
+        secret: load_or_create_secret(secret_path)?,
+        pairing: Pairing::new(&code),
+    };
+
+    let socket = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?;
+    thread::spawn(move || {
+        if let Err(e) = mdns_responder(port) {
+            warn!("Discovery disabled: {:?}", e);
+        }
+    });
+
+    println!("Serving {:?} on port {}", dir, port);
+    println!(
+        "Pairing code: {}, valid for one machine for {} minutes",
+        code,
+        PAIRING_TIMEOUT.as_secs() / 60
+    );
+
+    for stream in socket.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = listener.handle(stream) {
+                    warn!("Connection failed: {:?}", e);
+                }
+            },
+            Err(e) => warn!("Connection failed: {:?}", e),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ephemeral_keys, exchange, exchange_with, generate_pairing_code, hkdf_sha256, load_or_create_secret,
+        mdns_answer, mdns_port, mdns_query, open, pairing_key, read_frame, seal, serve_request, session_key,
+        write_frame, ClientHello, Envelope, Hello, Listener, Pairing, Request, Response,
+    };
+    use crate::sync::providers::filesystem::FileSystem;
+    use crate::sync::SyncProvider;
+    use serde_json::json;
+    use rust_sodium::crypto::{pwhash, secretbox};
+    use std::io;
+    use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+    use std::path::Path;
+    use std::thread;
+    use std::time::Instant;
+    use tempfile::tempdir;
+
+    const CODE: &str = "ABCD-EFGH-JKLM";
+
+    fn listener(dir: &Path) -> Listener {
+        std::fs::write(dir.join("VPassFile"), b"").unwrap();
+        Listener {
+            repo: FileSystem::load(&json!({ "path": dir })).unwrap(),
+            secret: secretbox::gen_key(),
+            pairing: Pairing::new(CODE),
+        }
+    }
+
+    /// Handle the given number of connections in another thread
+    fn serve(
+        mut listener: Listener, connections: usize,
+    ) -> (SocketAddr, thread::JoinHandle<Vec<io::Result<()>>>) {
+        let socket = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let address = socket.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            (0..connections)
+                .map(|_| listener.handle(socket.accept()?.0))
+                .collect()
+        });
+        (address, handle)
+    }
+
+    /// Send a `Ping` with the given sequence number, returning the connection and the sent frame
+    fn send_ping(address: SocketAddr, psk: &secretbox::Key, seq: u64) -> (TcpStream, Vec<u8>) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let hello: Hello = bincode::deserialize(&read_frame(&mut stream).unwrap()).unwrap();
+        let (secret, public) = ephemeral_keys();
+        let key = session_key(&secret, &hello.ephemeral, psk, &hello, &public.0).unwrap();
+        let envelope = Envelope {
+            session: hello.session,
+            seq,
+            body: Request::Ping,
+        };
+        let client_hello = ClientHello {
+            ephemeral: public.0.to_vec(),
+            request: seal(&key, &envelope),
+        };
+        let frame = bincode::serialize(&client_hello).unwrap();
+        write_frame(&mut stream, &frame).unwrap();
+        (stream, frame)
+    }
+
+    #[test]
+    fn pairing_code_normalized() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let code = generate_pairing_code();
+        assert_eq!(code.len(), 14);
+        let salt = pwhash::gen_salt();
+        let k1 = pairing_key(&code, &salt);
+        let k2 = pairing_key(&code.to_lowercase().replace("-", ""), &salt);
+        assert!(k1 == k2);
+    }
+
+    #[test]
+    fn sealed_envelope() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let key = secretbox::gen_key();
+        let sealed = seal(
+            &key,
+            &Envelope {
+                session: vec![1, 2, 3],
+                seq: 0,
+                body: Request::Ping,
+            },
+        );
+        let opened = open::<Request>(&key, &sealed).unwrap();
+        assert_eq!(opened.session, vec![1, 2, 3]);
+        assert!(open::<Request>(&secretbox::gen_key(), &sealed).is_none());
+    }
+
+    #[test]
+    fn handshake_keys() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let psk = secretbox::gen_key();
+        let (listener_secret, listener_public) = ephemeral_keys();
+        let (client_secret, client_public) = ephemeral_keys();
+        let hello = Hello {
+            session: vec![1, 2, 3],
+            pairing_salt: pwhash::gen_salt(),
+            ephemeral: listener_public.0.to_vec(),
+        };
+        let client = session_key(&client_secret, &hello.ephemeral, &psk, &hello, &client_public.0).unwrap();
+        let listener =
+            session_key(&listener_secret, &client_public.0, &psk, &hello, &client_public.0).unwrap();
+        assert!(client == listener);
+
+        // Without the pre-shared key, the same ephemeral keys give another session key
+        let other_psk = secretbox::gen_key();
+        let other = session_key(&client_secret, &hello.ephemeral, &other_psk, &hello, &client_public.0);
+        assert!(other.unwrap() != listener);
+    }
+
+    #[test]
+    fn hkdf_test_vector() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        // RFC 5869, test case 1, first 32 bytes of output
+        let salt: Vec<u8> = (0x00..=0x0c).collect();
+        let info: Vec<u8> = (0xf0..=0xf9).collect();
+        let okm = hkdf_sha256(&salt, &[0x0b; 22], &info);
+        assert_eq!(okm, [
+            0x3c, 0xb2, 0x5f, 0x25, 0xfa, 0xac, 0xd5, 0x7a, 0x90, 0x43, 0x4f, 0x64, 0xd0, 0x36, 0x2f, 0x2a,
+            0x2d, 0x2d, 0x0a, 0x90, 0xcf, 0x1a, 0x5a, 0x4c, 0x5d, 0xb0, 0x2d, 0x56, 0xec, 0xc4, 0xc5, 0xbf,
+        ]);
+    }
+
+    #[test]
+    fn wrong_psk_rejected() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let listener = listener(td.path());
+        let secret = listener.secret.clone();
+        let (address, served) = serve(listener, 2);
+
+        assert!(exchange(address, &secretbox::gen_key(), Request::Ping).is_err());
+        match exchange(address, &secret, Request::Ping) {
+            Ok(Response::Ok) => {},
+            other => panic!("Expected Ok, got {:?}", other),
+        }
+
+        let results = served.join().unwrap();
+        assert_eq!(results[0].as_ref().unwrap_err().to_string(), "Unauthenticated request");
+        assert!(results[1].is_ok());
+    }
+
+    #[test]
+    fn replayed_request_rejected() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let listener = listener(td.path());
+        let secret = listener.secret.clone();
+        let (address, served) = serve(listener, 3);
+
+        let (mut stream, frame) = send_ping(address, &secret, 0);
+        assert!(read_frame(&mut stream).is_ok());
+
+        // The same first message again, in a new session
+        let mut stream = TcpStream::connect(address).unwrap();
+        read_frame(&mut stream).unwrap();
+        write_frame(&mut stream, &frame).unwrap();
+        assert!(read_frame(&mut stream).is_err());
+
+        // A sequence number other than the first one
+        let (mut stream, _) = send_ping(address, &secret, 1);
+        assert!(read_frame(&mut stream).is_err());
+
+        let results = served.join().unwrap();
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().to_string(), "Unauthenticated request");
+        assert_eq!(results[2].as_ref().unwrap_err().to_string(), "Replayed request");
+    }
+
+    #[test]
+    fn expired_pairing_code_rejected() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let mut listener = listener(td.path());
+        listener.pairing.expires = Instant::now();
+        let (address, served) = serve(listener, 1);
+
+        let pair = exchange_with(address, |hello| pairing_key(CODE, &hello.pairing_salt), Request::Pair);
+        assert!(pair.is_err());
+        let results = served.join().unwrap();
+        assert_eq!(results[0].as_ref().unwrap_err().to_string(), "Unauthenticated request");
+    }
+
+    #[test]
+    fn pairing_single_use() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let mut pairing = Pairing::new("ABCD-EFGH-JKLM");
+        assert!(pairing.key().is_some());
+        pairing.key = None;
+        assert!(pairing.key().is_none());
+
+        let mut pairing = Pairing::new("ABCD-EFGH-JKLM");
+        pairing.expires = Instant::now();
+        assert!(pairing.key().is_none());
+    }
+
+    #[test]
+    fn mdns_discovery() {
+        let answer = mdns_answer(&mdns_query(), 47802).unwrap();
+        assert_eq!(mdns_port(&answer), Some(47802));
+        // Responses are not answered, and queries are not taken as responses
+        assert!(mdns_answer(&answer, 47802).is_none());
+        assert_eq!(mdns_port(&mdns_query()), None);
+
+        let mut other = mdns_query();
+        other[13] = b'X';
+        assert!(mdns_answer(&other, 47802).is_none());
+    }
+
+    #[test]
+    fn secret_file() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let path = td.path().join("lan_secret");
+        let key = load_or_create_secret(&path).unwrap();
+        assert_eq!(load_or_create_secret(&path).unwrap(), key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[test]
+    fn served_keys_stay_inside_folder() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let td = tempdir().unwrap();
+        let served = td.path().join("served");
+        std::fs::create_dir(&served).unwrap();
+        std::fs::write(served.join("VPassFile"), b"").unwrap();
+        let mut repo = FileSystem::load(&json!({ "path": served })).unwrap();
+
+        let outside = td.path().join("outside");
+        for key in &[
+            "../outside".to_owned(),
+            outside.to_str().unwrap().to_owned(),
+            "a/../../outside".to_owned(),
+            "./x".to_owned(),
+            "a//b".to_owned(),
+            String::new(),
+            "VPassFile".to_owned(),
+            "VPassLock".to_owned(),
+        ] {
+            for request in [
+                Request::Create(key.clone(), vec![1]),
+                Request::Update(key.clone(), vec![1], vec![]),
+                Request::Read(key.clone()),
+                Request::Delete(key.clone(), vec![]),
+            ] {
+                match serve_request(&mut repo, request) {
+                    Response::Failed(_) => {},
+                    other => panic!("Expected Failed for {:?}, got {:?}", key, other),
+                }
+            }
+        }
+        assert!(!outside.exists());
+        assert!(served.join("VPassFile").exists());
+
+        match serve_request(&mut repo, Request::Create("dir/key".to_owned(), vec![1])) {
+            Response::Ok => {},
+            other => panic!("Expected Ok, got {:?}", other),
+        }
+    }
+}
@@ -1,27 +1,114 @@
 //! Stores files in memory instead of actually saving them somewhere.
-//! All data is discarded when the service is unloaded.
+//! All data is discarded when the service is unloaded, unless a backing file is configured.
 //! Used for testing, and maybe for dry-runs in the future.
+//! Faults can be injected to test conflict handling and retries deterministically.
 
-use super::super::{Error, SyncProvider, SyncResult, UpdateKey};
+use super::super::{Capabilities, Error, SyncProvider, SyncResult, UpdateKey};
 use crate::VResult;
 
+use rust_sodium::crypto::hash::sha256;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
 
-pub struct Mock {
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Config {
+    /// Persist state to this file, so that it's shared between invocations
+    #[serde(default)]
+    path: Option<PathBuf>,
+}
+
+/// Everything stored in the backing file
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+struct State {
     items: HashMap<String, Vec<u8>>,
+    /// Number of next updates that fail
+    fail_updates: u32,
+    /// Number of next updates and deletes that reject the update key as stale
+    stale_update_keys: u32,
+}
+
+pub struct Mock {
+    config: Config,
+    state: State,
+    capabilities: Capabilities,
+}
+impl Mock {
+    /// Emulate a provider with limited capabilities
+    pub fn with_capabilities(capabilities: Capabilities) -> Self {
+        Mock {
+            config: Config::default(),
+            state: State::default(),
+            capabilities,
+        }
+    }
+
+    /// Number of stored keys
+    pub fn item_count(&self) -> usize {
+        self.state.items.len()
+    }
+
+    /// Make the next `count` updates fail
+    pub fn inject_update_failures(&mut self, count: u32) {
+        self.state.fail_updates = count;
+        self.save();
+    }
+
+    /// Make the next `count` updates and deletes reject the update key as stale
+    pub fn inject_stale_update_keys(&mut self, count: u32) {
+        self.state.stale_update_keys = count;
+        self.save();
+    }
+
+    fn save(&self) {
+        if let Some(ref path) = self.config.path {
+            fs::write(path, serde_json::to_vec(&self.state).unwrap()).expect("Mock: Unable to write backing file");
+        }
+    }
+
+    /// Consumes one injected stale update key, if any
+    fn check_update_key(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
+        let (_, uk) = self.read(key)?;
+        if self.state.stale_update_keys > 0 {
+            self.state.stale_update_keys -= 1;
+            self.save();
+            Err(Error::InvalidUpdateKey)
+        } else if uk != update_key {
+            Err(Error::InvalidUpdateKey)
+        } else {
+            Ok(())
+        }
+    }
 }
 impl SyncProvider for Mock {
     fn interactive_setup() -> VResult<Value> {
         Ok(Value::Null)
     }
 
-    fn load(_: &Value) -> Self
+    fn load(value: &Value) -> SyncResult<Self>
     where Self: Sized {
-        Mock {
-            items: HashMap::new(),
-        }
+        let config: Config = if value.is_null() {
+            Config::default()
+        } else {
+            serde_json::from_value(value.clone()).map_err(|_| Error::ConfigurationItem)?
+        };
+        let state = match config.path {
+            Some(ref path) if path.exists() => {
+                serde_json::from_slice(&fs::read(path)?).map_err(|_| Error::ConfigurationItem)?
+            },
+            _ => State::default(),
+        };
+        Ok(Mock {
+            config,
+            state,
+            capabilities: Capabilities::default(),
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
     }
 
     fn ping(&mut self) -> SyncResult<()> {
@@ -33,32 +120,60 @@ impl SyncProvider for Mock {
     }
 
     fn create(&mut self, key: &str, value: Vec<u8>) -> SyncResult<()> {
-        self.items.insert(key.to_owned(), value);
+        if self.state.items.contains_key(key) {
+            return Err(Error::KeyAlreadyExists(key.to_owned()));
+        }
+        self.state.items.insert(key.to_owned(), value);
+        self.save();
         Ok(())
     }
 
     fn update(&mut self, key: &str, value: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
-        let (_, uk) = self.read(key)?;
-        assert_eq!(uk, update_key, "Incorrect update key");
-        self.items.insert(key.to_owned(), value);
+        self.check_update_key(key, update_key)?;
+        if self.state.fail_updates > 0 {
+            self.state.fail_updates -= 1;
+            self.save();
+            return Err(Error::Misc(json!("Mock: Injected update failure")));
+        }
+        self.state.items.insert(key.to_owned(), value);
+        self.save();
         Ok(())
     }
 
     fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
         let data = self
+            .state
             .items
             .get(key)
             .ok_or_else(|| Error::NoSuchKey(key.to_owned()))?;
-        Ok((
-            data.clone(),
-            UpdateKey::from_byte(data.get(data.len() - 1).copied().unwrap_or(0)),
-        ))
+        Ok((data.clone(), UpdateKey::from_bytes(&sha256::hash(data).0)))
     }
 
     fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
-        let (_, uk) = self.read(key)?;
-        assert_eq!(uk, update_key, "Incorrect update key");
-        self.items.remove(key);
+        self.check_update_key(key, update_key)?;
+        self.state.items.remove(key);
+        self.save();
         Ok(())
     }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        let mut keys: Vec<String> = self.state.items.keys().cloned().collect();
+        keys.sort();
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::contract::provider_contract_tests;
+    use super::Mock;
+    use super::super::super::SyncProvider;
+    use serde_json::Value;
+
+    #[test]
+    fn contract() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        provider_contract_tests(&mut Mock::load(&Value::Null).unwrap());
+    }
 }
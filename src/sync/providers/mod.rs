@@ -1,14 +1,43 @@
+pub mod contract;
 pub mod filesystem;
 pub mod github;
+pub mod http;
+pub mod ipfs;
+pub mod lan;
 pub mod mock;
+pub mod redis;
 
-use super::SyncProvider;
+use super::{Error, SyncProvider, SyncResult};
 use crate::VResult;
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use strum_macros::{Display, EnumIter, EnumProperty, EnumString};
 
+/// Shown in place of secrets in configuration
+pub const REDACTED: &str = "****";
+
+/// Replace the given fields of a configuration object, if present
+fn redact_fields(item: &Value, fields: &[&str]) -> Value {
+    let mut result = item.clone();
+    if let Some(object) = result.as_object_mut() {
+        for field in fields {
+            if let Some(value) = object.get_mut(*field) {
+                *value = Value::String(REDACTED.to_owned());
+            }
+        }
+    }
+    result
+}
+
+/// Read a field from a JSON API response, failing with a protocol error if it's missing or invalid
+fn response_field<'a, T, F>(j: &'a Value, key: &str, f: F) -> SyncResult<T>
+where F: FnOnce(&'a Value) -> Option<T> {
+    j.get(key)
+        .and_then(f)
+        .ok_or_else(|| Error::ProviderProtocol(format!("Missing or invalid field {:?}", key)))
+}
+
 #[derive(
     Debug,
     Clone,
@@ -33,14 +62,26 @@ pub enum Provider {
     FileSystem,
     #[strum(props(tag = "2"))]
     Mock,
+    #[strum(props(tag = "3"))]
+    Lan,
+    #[strum(props(tag = "4"))]
+    Ipfs,
+    #[strum(props(tag = "5"))]
+    Redis,
+    #[strum(props(tag = "6"))]
+    Http,
 }
 impl Provider {
-    pub fn load(self, value: &Value) -> Box<dyn SyncProvider> {
-        match self {
-            Self::GitHub => Box::new(github::GitHub::load(value)) as Box<dyn SyncProvider>,
-            Self::FileSystem => Box::new(filesystem::FileSystem::load(value)) as Box<dyn SyncProvider>,
-            Self::Mock => Box::new(mock::Mock::load(value)) as Box<dyn SyncProvider>,
-        }
+    pub fn load(self, value: &Value) -> SyncResult<Box<dyn SyncProvider>> {
+        Ok(match self {
+            Self::GitHub => Box::new(github::GitHub::load(value)?) as Box<dyn SyncProvider>,
+            Self::FileSystem => Box::new(filesystem::FileSystem::load(value)?) as Box<dyn SyncProvider>,
+            Self::Mock => Box::new(mock::Mock::load(value)?) as Box<dyn SyncProvider>,
+            Self::Lan => Box::new(lan::Lan::load(value)?) as Box<dyn SyncProvider>,
+            Self::Ipfs => Box::new(ipfs::Ipfs::load(value)?) as Box<dyn SyncProvider>,
+            Self::Redis => Box::new(redis::Redis::load(value)?) as Box<dyn SyncProvider>,
+            Self::Http => Box::new(http::Http::load(value)?) as Box<dyn SyncProvider>,
+        })
     }
 
     pub fn interactive_setup(self) -> VResult<Value> {
@@ -48,6 +89,10 @@ impl Provider {
             Self::GitHub => github::GitHub::interactive_setup(),
             Self::FileSystem => filesystem::FileSystem::interactive_setup(),
             Self::Mock => mock::Mock::interactive_setup(),
+            Self::Lan => lan::Lan::interactive_setup(),
+            Self::Ipfs => ipfs::Ipfs::interactive_setup(),
+            Self::Redis => redis::Redis::interactive_setup(),
+            Self::Http => http::Http::interactive_setup(),
         }
     }
 
@@ -56,6 +101,10 @@ impl Provider {
             Self::GitHub => github::GitHub::configuration_compress(v),
             Self::FileSystem => filesystem::FileSystem::configuration_compress(v),
             Self::Mock => mock::Mock::configuration_compress(v),
+            Self::Lan => lan::Lan::configuration_compress(v),
+            Self::Ipfs => ipfs::Ipfs::configuration_compress(v),
+            Self::Redis => redis::Redis::configuration_compress(v),
+            Self::Http => http::Http::configuration_compress(v),
         }
     }
 
@@ -64,6 +113,43 @@ impl Provider {
             Self::GitHub => github::GitHub::configuration_decompress(v),
             Self::FileSystem => filesystem::FileSystem::configuration_decompress(v),
             Self::Mock => mock::Mock::configuration_decompress(v),
+            Self::Lan => lan::Lan::configuration_decompress(v),
+            Self::Ipfs => ipfs::Ipfs::configuration_decompress(v),
+            Self::Redis => redis::Redis::configuration_decompress(v),
+            Self::Http => http::Http::configuration_decompress(v),
         }
     }
+
+    pub fn configuration_redact(self, v: &Value) -> Value {
+        match self {
+            Self::GitHub => github::GitHub::configuration_redact(v),
+            Self::FileSystem => filesystem::FileSystem::configuration_redact(v),
+            Self::Mock => mock::Mock::configuration_redact(v),
+            Self::Lan => lan::Lan::configuration_redact(v),
+            Self::Ipfs => ipfs::Ipfs::configuration_redact(v),
+            Self::Redis => redis::Redis::configuration_redact(v),
+            Self::Http => http::Http::configuration_redact(v),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Provider;
+    use serde_json::json;
+
+    #[test]
+    fn redact_configuration() {
+        let github = json!({"username": "u", "access_token": "abc", "access_token_id": 1, "repo_name": "r"});
+        let redacted = Provider::GitHub.configuration_redact(&github);
+        assert_eq!(redacted["access_token"], "****");
+        assert_eq!(redacted["username"], "u");
+
+        let redis = json!({"url": "redis://:hunter2@localhost:6379/0", "prefix": "vpass:"});
+        let redacted = Provider::Redis.configuration_redact(&redis);
+        assert_eq!(redacted["url"], "redis://:****@localhost:6379/0");
+
+        let fs = json!({"path": "/tmp"});
+        assert_eq!(Provider::FileSystem.configuration_redact(&fs), fs);
+    }
 }
@@ -0,0 +1,247 @@
+//! Stores vaults on a Redis server, e.g. in a homelab.
+//! Each key is a hash with fields `data` and `rev`, and the revision is used as the UpdateKey.
+//! Revisions are random UUIDs, so a key that is deleted and created again never gets
+//! a revision an older UpdateKey would match.
+//! Updates and deletes use WATCH/MULTI, so concurrent modifications are always detected,
+//! and creates use a script, so that a key is never seen without its data.
+//! Authentication is done with the password in the url, `redis://:password@host:port/db`.
+//! TLS is used with `rediss://` urls, verifying the server certificate against the system roots.
+//! The data itself is always encrypted with the remote key before it is sent.
+//! A key called VPassFile is used to mark this as a vpass repository.
+
+use super::super::{Error, SyncProvider, SyncResult, UpdateKey};
+use super::REDACTED;
+use crate::VResult;
+
+use redis::{Commands, Connection, IntoConnectionInfo};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use tracing::debug;
+use uuid::Uuid;
+
+const DEFAULT_PREFIX: &str = "vpass:";
+
+/// Sets the data and the revision if the key doesn't exist, returning 0 if it does
+const CREATE_SCRIPT: &str = "
+if redis.call('EXISTS', KEYS[1]) == 1 then
+    return 0
+end
+redis.call('HMSET', KEYS[1], 'rev', ARGV[2], 'data', ARGV[1])
+return 1
+";
+
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Config {
+    /// Connection url, including the password
+    url: String,
+    /// Prefix for all keys
+    prefix: String,
+}
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Redis_Config({})", self.prefix)
+    }
+}
+
+pub struct Redis {
+    config: Config,
+    connection: Option<Connection>,
+}
+/// A new globally unique revision
+fn new_rev() -> String {
+    Uuid::new_v4().to_string()
+}
+
+impl Redis {
+    /// Checks that the url can be used, without connecting yet
+    fn new(config: Config) -> SyncResult<Self> {
+        config.url.as_str().into_connection_info()?;
+        Ok(Redis {
+            config,
+            connection: None,
+        })
+    }
+
+    fn rkey(&self, key: &str) -> String {
+        format!("{}{}", self.config.prefix, key)
+    }
+
+    /// Connects on first use
+    fn con(&mut self) -> SyncResult<&mut Connection> {
+        if self.connection.is_none() {
+            debug!("Connecting");
+            let client = redis::Client::open(self.config.url.as_str())?;
+            self.connection = Some(client.get_connection()?);
+        }
+        Ok(self.connection.as_mut().unwrap())
+    }
+
+    /// Run `f` in a transaction if the revision matches `update_key`
+    fn modify_checked<F>(&mut self, key: &str, update_key: UpdateKey, f: F) -> SyncResult<()>
+    where F: Fn(&mut redis::Pipeline, &str) {
+        let rkey = self.rkey(key);
+        let con = self.con()?;
+        // `None` if the key doesn't exist
+        let matched: Option<bool> = redis::transaction(con, &[&rkey], |con, pipe| {
+            let rev: Option<String> = con.hget(&rkey, "rev")?;
+            match rev {
+                Some(ref r) if r.as_bytes() == update_key.as_bytes() => {
+                    f(pipe, &rkey);
+                    // Returns None if a watched key was modified, and the transaction is retried
+                    pipe.cmd("EXISTS")
+                        .arg(&rkey)
+                        .query(con)
+                        .map(|r: Option<(i64,)>| r.map(|_| Some(true)))
+                },
+                Some(_) => Ok(Some(Some(false))),
+                None => Ok(Some(None)),
+            }
+        })?;
+        match matched {
+            Some(true) => Ok(()),
+            Some(false) => Err(Error::InvalidUpdateKey),
+            None => Err(Error::NoSuchKey(key.to_owned())),
+        }
+    }
+}
+impl SyncProvider for Redis {
+    fn interactive_setup() -> VResult<Value> {
+        use crate::cli::interactive::*;
+
+        let mut self_ = Redis::new(Config {
+            url: prompt_string("Server url, e.g. redis://:password@host:6379/0, or rediss:// for TLS")?,
+            prefix: DEFAULT_PREFIX.to_owned(),
+        })?;
+        self_.ping()?;
+        if !self_.exists("VPassFile")? {
+            self_.create("VPassFile", vec![])?;
+        }
+        self_.test()?;
+        Ok(serde_json::to_value(&self_.config).unwrap())
+    }
+
+    /// The password is part of the url
+    fn configuration_redact(item: &Value) -> Value
+    where Self: Sized {
+        let mut result = item.clone();
+        if let Some(url) = item.get("url").and_then(Value::as_str) {
+            if let Ok(mut url) = reqwest::Url::parse(url) {
+                if url.password().is_some() {
+                    url.set_password(Some(REDACTED)).unwrap();
+                }
+                result["url"] = Value::String(url.into_string());
+            }
+        }
+        result
+    }
+
+    fn load(value: &Value) -> SyncResult<Self>
+    where Self: Sized {
+        Redis::new(serde_json::from_value(value.clone()).map_err(|_| Error::ConfigurationItem)?)
+    }
+
+    fn ping(&mut self) -> SyncResult<()> {
+        let _: String = redis::cmd("PING").query(self.con()?)?;
+        Ok(())
+    }
+
+    fn test(&mut self) -> SyncResult<()> {
+        let rkey = self.rkey("VPassFile");
+        if self.con()?.exists(rkey)? {
+            Ok(())
+        } else {
+            Err(Error::InvalidRemote)
+        }
+    }
+
+    fn create(&mut self, key: &str, value: Vec<u8>) -> SyncResult<()> {
+        debug!("Create: {}", key);
+        let rkey = self.rkey(key);
+        // Scripts run atomically, so the key can't be created in between
+        let created: bool = redis::Script::new(CREATE_SCRIPT)
+            .key(&rkey)
+            .arg(value)
+            .arg(new_rev())
+            .invoke(self.con()?)?;
+        if created {
+            Ok(())
+        } else {
+            Err(Error::KeyAlreadyExists(key.to_owned()))
+        }
+    }
+
+    fn update(&mut self, key: &str, value: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
+        debug!("Update: {}", key);
+        self.modify_checked(key, update_key, |pipe, rkey| {
+            pipe.hset(rkey, "data", value.clone())
+                .ignore()
+                .hset(rkey, "rev", new_rev())
+                .ignore();
+        })
+    }
+
+    fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
+        debug!("Read: {}", key);
+        let rkey = self.rkey(key);
+        let (data, rev): (Option<Vec<u8>>, Option<String>) = self.con()?.hget(&rkey, &["data", "rev"])?;
+        match (data, rev) {
+            (Some(data), Some(rev)) => Ok((data, UpdateKey::from_string(rev))),
+            _ => Err(Error::NoSuchKey(key.to_owned())),
+        }
+    }
+
+    fn stat(&mut self, key: &str) -> SyncResult<(u64, UpdateKey)> {
+        debug!("Stat: {}", key);
+        let rkey = self.rkey(key);
+        let (size, rev): (u64, Option<String>) = redis::pipe()
+            .cmd("HSTRLEN")
+            .arg(&rkey)
+            .arg("data")
+            .cmd("HGET")
+            .arg(&rkey)
+            .arg("rev")
+            .query(self.con()?)?;
+        match rev {
+            Some(rev) => Ok((size, UpdateKey::from_string(rev))),
+            None => Err(Error::NoSuchKey(key.to_owned())),
+        }
+    }
+
+    fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
+        debug!("Delete: {}", key);
+        self.modify_checked(key, update_key, |pipe, rkey| {
+            pipe.del(rkey).ignore();
+        })
+    }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        let prefix = self.config.prefix.clone();
+        // SCAN doesn't block the server like KEYS, but may return a key more than once
+        let rkeys: Vec<String> = self.con()?.scan_match(format!("{}*", prefix))?.collect();
+        let mut keys: Vec<String> = rkeys
+            .into_iter()
+            .map(|k| k[prefix.len()..].to_owned())
+            .filter(|k| k != "VPassFile")
+            .collect();
+        keys.sort();
+        keys.dedup();
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url_checked_on_load() {
+        let config = json!({"url": "rediss://:hunter2@localhost:6379/0", "prefix": DEFAULT_PREFIX});
+        assert!(Redis::load(&config).is_ok());
+        let config = json!({"url": "https://localhost:6379/0", "prefix": DEFAULT_PREFIX});
+        match Redis::load(&config) {
+            Err(Error::Redis(_)) => {},
+            _ => panic!("https:// url accepted"),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Optional second encryption layer for everything stored on the remote.
+//! The remote key is random, so remote data stays protected even with a weak vault password.
+//! It's stored in the sync configuration inside the vault and included in transfer strings.
+
+use super::{Capabilities, Error, SyncProvider, SyncResult, UpdateKey};
+use crate::VResult;
+
+use rust_sodium::crypto::secretbox;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// Marks a value encrypted with a remote key
+const MAGIC: &[u8] = b"VPASS_RK1:";
+
+/// Size added to each value
+const OVERHEAD: usize = MAGIC.len() + secretbox::NONCEBYTES + secretbox::MACBYTES;
+
+pub const KEY_BYTES: usize = secretbox::KEYBYTES;
+
+/// Check whether a stored value is encrypted with some remote key
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct RemoteKey(secretbox::Key);
+impl RemoteKey {
+    pub fn generate() -> Self {
+        Self(secretbox::gen_key())
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        secretbox::Key::from_slice(bytes).map(Self)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &(self.0).0
+    }
+
+    pub fn encrypt(&self, data: &[u8]) -> Vec<u8> {
+        let nonce = secretbox::gen_nonce();
+        let mut result = MAGIC.to_vec();
+        result.extend(&nonce.0);
+        result.extend(secretbox::seal(data, &nonce, &self.0));
+        result
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> SyncResult<Vec<u8>> {
+        if !data.starts_with(MAGIC) || data.len() < OVERHEAD {
+            return Err(Error::RemoteDecryption);
+        }
+        let nonce_end = MAGIC.len() + secretbox::NONCEBYTES;
+        let nonce = secretbox::Nonce::from_slice(&data[MAGIC.len()..nonce_end]).unwrap();
+        secretbox::open(&data[nonce_end..], &nonce, &self.0).map_err(|_| Error::RemoteDecryption)
+    }
+}
+impl std::fmt::Debug for RemoteKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "RemoteKey(***)")
+    }
+}
+impl Serialize for RemoteKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&base64::encode(self.as_bytes()))
+    }
+}
+impl<'de> Deserialize<'de> for RemoteKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        let bytes = base64::decode(&s).map_err(de::Error::custom)?;
+        Self::from_bytes(&bytes).ok_or_else(|| de::Error::custom("Invalid remote key length"))
+    }
+}
+
+/// Wraps a provider, encrypting all values with the remote key.
+/// UpdateKeys and key names are passed through unchanged.
+pub struct Encrypted {
+    inner: Box<dyn SyncProvider>,
+    key: RemoteKey,
+}
+impl Encrypted {
+    pub fn new(inner: Box<dyn SyncProvider>, key: RemoteKey) -> Self {
+        Self { inner, key }
+    }
+}
+impl SyncProvider for Encrypted {
+    fn interactive_setup() -> VResult<Value> {
+        Err(Error::Unsupported("interactive setup of the encryption layer".to_owned()).into())
+    }
+
+    fn load(_item: &Value) -> SyncResult<Self> {
+        Err(Error::Unsupported("loading the encryption layer from a configuration".to_owned()))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let inner = self.inner.capabilities();
+        Capabilities {
+            max_object_size: inner.max_object_size.map(|s| s.saturating_sub(OVERHEAD)),
+            ..inner
+        }
+    }
+
+    fn ping(&mut self) -> SyncResult<()> {
+        self.inner.ping()
+    }
+
+    fn test(&mut self) -> SyncResult<()> {
+        self.inner.test()
+    }
+
+    fn create(&mut self, key: &str, value: Vec<u8>) -> SyncResult<()> {
+        let encrypted = self.key.encrypt(&value);
+        self.inner.create(key, encrypted)
+    }
+
+    fn update(&mut self, key: &str, value: Vec<u8>, update_key: UpdateKey) -> SyncResult<()> {
+        let encrypted = self.key.encrypt(&value);
+        self.inner.update(key, encrypted, update_key)
+    }
+
+    fn read(&mut self, key: &str) -> SyncResult<(Vec<u8>, UpdateKey)> {
+        let (data, update_key) = self.inner.read(key)?;
+        Ok((self.key.decrypt(&data)?, update_key))
+    }
+
+    fn stat(&mut self, key: &str) -> SyncResult<(u64, UpdateKey)> {
+        let (size, update_key) = self.inner.stat(key)?;
+        Ok((size.saturating_sub(OVERHEAD as u64), update_key))
+    }
+
+    fn exists(&mut self, key: &str) -> SyncResult<bool> {
+        self.inner.exists(key)
+    }
+
+    fn delete(&mut self, key: &str, update_key: UpdateKey) -> SyncResult<()> {
+        self.inner.delete(key, update_key)
+    }
+
+    fn conflicts(&mut self, key: &str) -> SyncResult<Vec<String>> {
+        self.inner.conflicts(key)
+    }
+
+    fn list(&mut self) -> SyncResult<Vec<String>> {
+        self.inner.list()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::providers::contract::provider_contract_tests;
+    use super::super::providers::mock::Mock;
+    use super::super::SyncProvider;
+    use super::{Encrypted, RemoteKey};
+    use serde_json::Value;
+
+    #[test]
+    fn encrypted_layer() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let key = RemoteKey::generate();
+        let mut sp = Encrypted::new(Box::new(Mock::load(&Value::Null).unwrap()), key.clone());
+        provider_contract_tests(&mut sp);
+
+        sp.create("key", b"secret".to_vec()).unwrap();
+        let (stored, _) = sp.inner.read("key").unwrap();
+        assert!(!stored.windows(6).any(|w| w == b"secret"));
+        assert_eq!(key.decrypt(&stored).unwrap(), b"secret".to_vec());
+        assert!(RemoteKey::generate().decrypt(&stored).is_err());
+
+        let json = serde_json::to_string(&key).unwrap();
+        assert_eq!(serde_json::from_str::<RemoteKey>(&json).unwrap(), key);
+    }
+}
@@ -1,6 +1,7 @@
 use super::config::SyncConfig;
 use super::providers::Provider;
 
+use crate::backend::vault::{EncryptedVault, Vault};
 use crate::cli::{Error, VResult};
 
 use bitvec::vec::BitVec;
@@ -9,6 +10,8 @@ use serde::{Deserialize, Serialize};
 use std::mem::size_of;
 
 const PREFIX: &str = "VPASS_";
+/// Transfer strings encrypted with a passphrase
+const PROTECTED_PREFIX: &str = "VPASSP_";
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 struct Metadata {
@@ -45,6 +48,24 @@ pub fn encode(c: &SyncConfig) -> String {
     format!("{}{}", PREFIX, base64::encode(&compressed))
 }
 
+/// Transfer string encrypted with a passphrase, e.g. for printing on paper
+pub fn encode_protected(c: &SyncConfig, passphrase: &str) -> String {
+    let encrypted = Vault::new(encode(c)).encrypt(passphrase);
+    format!("{}{}", PROTECTED_PREFIX, base64::encode(&encrypted.to_bytes()))
+}
+
+pub fn is_protected(s: &str) -> bool {
+    s.starts_with(PROTECTED_PREFIX)
+}
+
+pub fn decode_protected(s: &str, passphrase: &str) -> VResult<SyncConfig> {
+    if !is_protected(s) {
+        return Err(Error::SynchronizationTransferString);
+    }
+    let encrypted = EncryptedVault::from_bytes(&base64::decode(&s[PROTECTED_PREFIX.len()..])?)?;
+    decode(&encrypted.decrypt::<String>(passphrase).ok_or(Error::WrongPassphrase)?.content)
+}
+
 pub fn decode(s: &str) -> VResult<SyncConfig> {
     if !s.starts_with(PREFIX) {
         return Err(Error::SynchronizationTransferString);
@@ -53,16 +74,20 @@ pub fn decode(s: &str) -> VResult<SyncConfig> {
     let decoded = base64::decode(&s[PREFIX.len()..])?;
 
     let meta_size = size_of::<Metadata>();
+    if decoded.len() <= meta_size {
+        return Err(Error::SynchronizationTransferString);
+    }
     let meta: Metadata = bincode::deserialize(&decoded[..meta_size])?;
     meta.check(&decoded[meta_size..])?;
-    Ok(SyncConfig::decompress(&decoded[meta_size..])?)
+    SyncConfig::decompress(&decoded[meta_size..])
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::config::SyncConfig;
     use super::super::providers::Provider;
-    use super::{decode, encode, Metadata};
+    use super::super::remote_key::RemoteKey;
+    use super::{decode, decode_protected, encode, encode_protected, is_protected, Metadata};
     use crate::cli::VResult;
 
     #[test]
@@ -79,11 +104,60 @@ mod tests {
         let c = SyncConfig {
             service: Provider::Mock,
             data: serde_json::Value::Null,
+            remote_key: None,
+            password: None,
         };
 
         assert_eq!(decode(&encode(&c)).expect("Decode failed"), c);
     }
 
+    #[test]
+    fn encode_decode_remote_key() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let c = SyncConfig {
+            service: Provider::Mock,
+            data: serde_json::Value::Null,
+            remote_key: Some(RemoteKey::generate()),
+            password: None,
+        };
+
+        assert_eq!(decode(&encode(&c)).expect("Decode failed"), c);
+    }
+
+    #[test]
+    fn encode_without_sync_password() {
+        let c = SyncConfig {
+            service: Provider::Mock,
+            data: serde_json::Value::Null,
+            remote_key: None,
+            password: Some("sync password".to_owned()),
+        };
+
+        // The sync password is asked on import instead
+        let decoded = decode(&encode(&c)).expect("Decode failed");
+        assert_eq!(decoded.password, None);
+        assert_eq!(decoded.service, c.service);
+    }
+
+    #[test]
+    fn encode_decode_protected() {
+        rust_sodium::init().expect("Sodium init failed");
+
+        let c = SyncConfig {
+            service: Provider::Mock,
+            data: serde_json::Value::Null,
+            remote_key: Some(RemoteKey::generate()),
+            password: None,
+        };
+
+        let s = encode_protected(&c, "passphrase");
+        assert!(is_protected(&s));
+        assert!(decode(&s).is_err());
+        assert!(decode_protected(&s, "wrong").is_err());
+        assert_eq!(decode_protected(&s, "passphrase").expect("Decode failed"), c);
+    }
+
     #[test]
     #[should_panic]
     fn decode_invalid_prefix() {
@@ -1,6 +1,7 @@
 #![allow(dead_code, unused_imports)]
 
 use assert_cmd::prelude::*;
+use serde_json::json;
 use std::collections::HashSet;
 use std::fs;
 use std::io;
@@ -10,10 +11,8 @@ use tempfile::{tempdir, TempDir};
 #[macro_export]
 macro_rules! cmd {
     ($td:expr; $($a:expr)*) => {{
-        let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
-            .unwrap()
+        let output = vpass(&$td)
             .args(&[$($a,)*])
-            .env("VPASS_VAULT_DIR", $td.path())
             .env(concat!("RUST_LOG=", env!("CARGO_PKG_NAME")), "trace")
             .unwrap();
         eprint!("{}", String::from_utf8_lossy(&output.stderr));
@@ -24,10 +23,8 @@ macro_rules! cmd {
 #[macro_export]
 macro_rules! cmd_stdout {
     ($td:expr; $($a:expr)*) => {{
-        let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
-            .unwrap()
+        let output = vpass(&$td)
             .args(&[$($a,)*])
-            .env("VPASS_VAULT_DIR", $td.path())
             .env(concat!("RUST_LOG=", env!("CARGO_PKG_NAME")), "trace")
             .unwrap();
         eprint!("{}", String::from_utf8_lossy(&output.stderr));
@@ -36,7 +33,32 @@ macro_rules! cmd_stdout {
     }};
 }
 
-#[must_use]
+/// Run a command that must fail, returning its stderr
+#[macro_export]
+macro_rules! cmd_err {
+    ($td:expr; $($a:expr)*) => {
+        cmd_err(&$td, &[$($a,)*])
+    };
+}
+
+/// Command running vpass with the vault directory `td`.
+/// The locale is cleared, as the tests check messages in English.
+pub fn vpass(td: &TempDir) -> Command {
+    let mut command = Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap();
+    command.env("VPASS_VAULT_DIR", td.path());
+    for var in &["LC_ALL", "LC_MESSAGES", "LANG"] {
+        command.env_remove(var);
+    }
+    command
+}
+
+/// Run a command that must fail, returning its stderr
+pub fn cmd_err(td: &TempDir, args: &[&str]) -> String {
+    let output = vpass(td).args(args).output().unwrap();
+    assert!(!output.status.success());
+    String::from_utf8(output.stderr).unwrap()
+}
+
 pub fn init() -> io::Result<TempDir> {
     let td = tempdir().unwrap();
     cmd!(td; "init");
@@ -71,6 +93,10 @@ pub fn remove_item(td: &TempDir, name: &str, password: &str, item_name: &str) {
     cmd!(td; "-p" password "-n" name "remove" item_name)
 }
 
+pub fn rename_item(td: &TempDir, name: &str, password: &str, old_name: &str, new_name: &str) {
+    cmd!(td; "-p" password "-n" name "rename" old_name new_name)
+}
+
 pub fn edit_item_change_password(
     td: &TempDir, name: &str, password: &str, item_name: &str, new_password: &str,
 ) {
@@ -82,11 +108,58 @@ pub fn edit_item_add_tag(td: &TempDir, name: &str, password: &str, item_name: &s
 }
 
 pub fn get_item_json(td: &TempDir, name: &str, password: &str, item_name: &str) -> serde_json::Value {
-    let output = Command::cargo_bin(env!("CARGO_PKG_NAME"))
-        .unwrap()
-        .args(&["-p", password, "-n", name, "show", item_name, "-jp"])
-        .env("VPASS_VAULT_DIR", td.path())
-        .unwrap();
+    let output = vpass(td).args(["-p", password, "-n", name, "show", item_name, "-jp"]).unwrap();
     assert!(output.status.success());
     serde_json::from_slice(&output.stdout).unwrap()
 }
+
+pub fn list_items(td: &TempDir, name: &str, password: &str) -> Vec<String> {
+    let output = cmd_stdout!(td; "-p" password "-n" name "list");
+    String::from_utf8(output).unwrap().lines().map(str::to_owned).collect()
+}
+
+/// Empty FileSystem remote
+pub fn create_sync_fs() -> io::Result<TempDir> {
+    let td = tempdir().unwrap();
+    fs::write(td.path().join("VPassFile"), [])?;
+    Ok(td)
+}
+
+/// Synchronize the vault to a FileSystem remote created with `create_sync_fs`
+pub fn setup_sync(td: &TempDir, remote: &TempDir, name: &str, password: &str) {
+    let setup = json!({
+        "service": "FileSystem",
+        "data": {
+            "path": remote.path()
+        }
+    })
+    .to_string();
+    cmd!(td; "-n" name "-p" password "sync" "setup" "--json" setup.as_str())
+}
+
+pub fn sync(td: &TempDir, name: &str, password: &str) {
+    cmd!(td; "-n" name "-p" password "sync")
+}
+
+/// Two devices, each with its own vault directory, sharing a vault through one FileSystem remote
+pub struct Devices {
+    pub a: TempDir,
+    pub b: TempDir,
+    pub remote: TempDir,
+}
+
+/// Create a vault on device `a`, push it to the remote, and import it on device `b`
+pub fn two_devices(name: &str, password: &str) -> io::Result<Devices> {
+    let devices = Devices {
+        a: init()?,
+        b: init()?,
+        remote: create_sync_fs()?,
+    };
+    vault_create(&devices.a, name, password);
+    setup_sync(&devices.a, &devices.remote, name, password);
+    sync(&devices.a, name, password);
+    let transfer = cmd_stdout!(devices.a; "-n" name "-p" password "sync" "export");
+    let transfer = String::from_utf8(transfer).unwrap();
+    cmd!(devices.b; "-p" password "vault" "import" name transfer.trim());
+    Ok(devices)
+}
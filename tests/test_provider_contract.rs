@@ -0,0 +1,19 @@
+//! Provider contract tests against a live remote.
+//! Run with `VPASS_LIVE_REMOTE='{"service":"...","data":{...}}' cargo test --features live-tests`,
+//! using the same JSON as `sync setup --json`.
+#![cfg(feature = "live-tests")]
+
+use vpass::sync::config::SyncConfig;
+use vpass::sync::providers::contract::provider_contract_tests;
+
+#[test]
+fn test_live_provider_contract() {
+    rust_sodium::init().expect("Sodium init failed");
+
+    let remote = std::env::var("VPASS_LIVE_REMOTE").expect("VPASS_LIVE_REMOTE not set");
+    let config: SyncConfig = serde_json::from_str(&remote).expect("Invalid VPASS_LIVE_REMOTE");
+    let mut service = config.load().expect("Invalid VPASS_LIVE_REMOTE");
+    service.ping().unwrap();
+    service.test().unwrap();
+    provider_contract_tests(&mut *service);
+}
@@ -2,28 +2,18 @@ use assert_cmd::prelude::*;
 use serde_json::json;
 use std::fs;
 use std::io;
-use std::process::Command;
 use tempfile::{tempdir, TempDir};
 
 mod common;
 use common::*;
 
-#[must_use]
-fn create_sync_fs() -> io::Result<TempDir> {
-    let td = tempdir().unwrap();
-    fs::write(td.path().join("VPassFile"), &[])?;
-    Ok(td)
-}
-
 #[test]
 fn test_sync_push() -> io::Result<()> {
     let td = init()?;
     let td_sync = create_sync_fs()?;
 
     vault_create(&td, "testvault", "password");
-    cmd!(td; "-n" "testvault" "-p" "password" "sync" "setup"
-        "--json" format!("{{\"service\":\"FileSystem\",\"data\":{{\"path\":{:?}}}}}", td_sync.path()).as_str()
-    );
+    setup_sync(&td, &td_sync, "testvault", "password");
 
     cmd!(td; "-n" "testvault" "-p" "password" "sync");
 
@@ -34,34 +24,592 @@ fn test_sync_push() -> io::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_sync_plain_progress() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+
+    let output = vpass(&td).args(["--plain", "-n", "testvault", "-p", "password", "sync"]).output()?;
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.lines().any(|line| line == "Uploading..."));
+    assert!(!stderr.contains('\r'));
+    Ok(())
+}
+
 #[test]
 fn test_sync_import_data() -> io::Result<()> {
     let td = init()?;
     let td_sync = create_sync_fs()?;
 
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+
+    cmd!(td; "-n" "testvault" "-p" "password" "add" "testitem" "-p" "testpassword");
+    cmd!(td; "-n" "testvault" "-p" "password" "sync");
+    let import_string = cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "export");
+    assert!(import_string.starts_with(b"VPASS_"));
+    vault_delete(&td, "testvault");
+
+    cmd!(td; "-p" "password" "vault" "import" "testvault" String::from_utf8(import_string).unwrap().as_str().trim());
+
+    let data = get_item_json(&td, "testvault", "password", "testitem");
+    assert_eq!(
+        data.get("password").and_then(serde_json::Value::as_str),
+        Some("testpassword")
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_clone_from_remote() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    for &name in &["testvault", "other"] {
+        vault_create(&td, name, "password");
+        setup_sync(&td, &td_sync, name, "password");
+        cmd!(td; "-n" name "-p" "password" "sync");
+    }
+    cmd!(td; "-n" "other" "-p" "password" "add" "testitem" "-p" "testpassword");
+    cmd!(td; "-n" "other" "-p" "password" "sync");
+    vault_delete(&td, "other");
+
+    let names = cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "remote-list");
+    assert_eq!(names, b"other\ntestvault\n");
+
+    cmd!(td; "-n" "testvault" "-p" "password" "vault" "clone" "other");
+    let data = get_item_json(&td, "other", "password", "testitem");
+    assert_eq!(
+        data.get("password").and_then(serde_json::Value::as_str),
+        Some("testpassword")
+    );
+    Ok(())
+}
+
+#[test]
+fn test_sync_check_remote() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    for &name in &["testvault", "other"] {
+        vault_create(&td, name, "password");
+        setup_sync(&td, &td_sync, name, "password");
+        cmd!(td; "-n" name "-p" "password" "sync");
+    }
+    vault_delete(&td, "other");
+    fs::write(td_sync.path().join("junk.vpass_vault"), b"not a vault")?;
+    fs::write(td_sync.path().join("gone.vpass_vault.delta.0"), b"leftover")?;
+
+    let output = String::from_utf8(cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "check-remote"))
+        .unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert_eq!(
+        lines,
+        vec![
+            "gone.vpass_vault.delta.0\tdangling",
+            "junk.vpass_vault\tinvalid",
+            "other.vpass_vault\torphan",
+            "testvault.vpass_vault\tvalid",
+        ]
+    );
+
+    let stdout = cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "check-remote" "--json");
+    let j: serde_json::Value = serde_json::from_slice(&stdout).unwrap();
+    assert_eq!(j[2]["vault"], "other");
+    assert_eq!(j[2]["orphan"], true);
+    assert_eq!(j[3]["orphan"], false);
+    Ok(())
+}
+
+#[test]
+fn test_sync_status() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+    let status = || -> serde_json::Value {
+        serde_json::from_slice(&cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "status" "--json"))
+            .unwrap()
+    };
+
+    let j = status();
+    assert_eq!(j["remote"], serde_json::Value::Null);
+    assert_eq!(j["changed"], serde_json::Value::Null);
+
+    cmd!(td; "-n" "testvault" "-p" "password" "sync");
+    let j = status();
+    assert_eq!(j["remote"]["deltas"], 0);
+    assert_eq!(j["changed"], false);
+
+    // Another device pushed a change
+    fs::write(td_sync.path().join("testvault.vpass_vault.delta.0"), b"change")?;
+    let j = status();
+    assert_eq!(j["remote"]["deltas"], 1);
+    assert_eq!(j["changed"], true);
+
+    // Remote unreachable, e.g. an unmounted drive
+    let moved = td.path().join("unmounted");
+    fs::rename(td_sync.path(), &moved)?;
+    let j = status();
+    fs::rename(&moved, td_sync.path())?;
+    assert_eq!(j["offline"], true);
+    assert_eq!(j["remote"]["deltas"], 0);
+    assert_eq!(j["changed"], serde_json::Value::Null);
+    Ok(())
+}
+
+#[test]
+fn test_sync_status_changes() -> io::Result<()> {
+    let d = two_devices("testvault", "password")?;
+    add_item(&d.a, "testvault", "password", "testitem", "testpassword");
+    sync(&d.a, "testvault", "password");
+
+    let j: serde_json::Value = serde_json::from_slice(
+        &cmd_stdout!(d.b; "-n" "testvault" "-p" "password" "sync" "status" "--json" "--changes"),
+    )
+    .unwrap();
+    assert_eq!(j["changes"], json!([{"added": "testitem"}]));
+    // Nothing was synchronized
+    assert!(!list_items(&d.b, "testvault", "password").contains(&"testitem".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn test_sync_delete() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+    add_item(&td, "testvault", "password", "testitem", "testpassword");
+    sync(&td, "testvault", "password");
+    assert!(td_sync.path().join("testvault.vpass_vault").exists());
+
+    // Settings are kept, so the next sync uploads the vault again
+    cmd!(td; "-n" "testvault" "-p" "password" "sync" "delete" "--yes");
+    assert!(!td_sync.path().join("testvault.vpass_vault").exists());
+    assert!(td_sync.path().join("trash/testvault.vpass_vault").exists());
+    assert!(td.path().join("testvault.vpass_vault").exists());
+    sync(&td, "testvault", "password");
+    assert!(td_sync.path().join("testvault.vpass_vault").exists());
+
+    cmd!(td; "-n" "testvault" "-p" "password" "sync" "delete" "--yes" "--detach");
+    assert!(!td_sync.path().join("testvault.vpass_vault").exists());
+    let config = cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "show" "--json");
+    assert_eq!(String::from_utf8(config).unwrap().trim(), "{}");
+    assert_eq!(list_items(&td, "testvault", "password"), vec!["testitem"]);
+    Ok(())
+}
+
+#[test]
+fn test_sync_show() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+    let show = |secrets: bool| -> serde_json::Value {
+        let output = if secrets {
+            cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "show" "--json" "--secrets")
+        } else {
+            cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "show" "--json")
+        };
+        serde_json::from_slice(&output).unwrap()
+    };
+
+    let j = show(false);
+    assert_eq!(j["service"], "FileSystem");
+    assert_eq!(j["data"]["path"], td_sync.path().to_str().unwrap());
+    assert_eq!(j["remote_key"], serde_json::Value::Null);
+    assert_eq!(j["last_sync"], serde_json::Value::Null);
+
+    cmd!(td; "-n" "testvault" "-p" "password" "sync" "rotate-remote-key");
+    sync(&td, "testvault", "password");
+    let j = show(false);
+    assert_eq!(j["remote_key"], "****");
+    assert!(j["last_sync"].is_string());
+    let key = show(true)["remote_key"].as_str().unwrap().to_owned();
+    assert_ne!(key, "****");
+
+    let text = String::from_utf8(cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "show")).unwrap();
+    assert!(text.starts_with("Provider: FileSystem\n"));
+    assert!(text.contains("Remote key: ****\n"));
+    assert!(!text.contains(&key));
+    Ok(())
+}
+
+#[test]
+fn test_publish_fetch_item() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+    vault_create(&td, "sender", "password");
+    vault_create(&td, "receiver", "other");
+    setup_sync(&td, &td_sync, "sender", "password");
+    let transfer = String::from_utf8(cmd_stdout!(td; "-n" "sender" "-p" "password" "sync" "export")).unwrap();
+    let transfer = transfer.trim();
+
+    // Derived passwords are published as stored ones
+    cmd!(td; "-n" "sender" "-p" "password" "add" "shared" "--derive");
+    cmd!(td; "-n" "sender" "-p" "password" "publish" "shared" "--provider" transfer "--passphrase" "phrase");
+    assert!(td_sync.path().join("shared.vpass_item").exists());
+    let sent = get_item_json(&td, "sender", "password", "shared");
+
+    let fetch = |extra: &[&str]| {
+        vpass(&td)
+            .args(["-n", "receiver", "-p", "other", "fetch-item", "shared", "--provider", transfer])
+            .args(extra)
+            .output()
+            .unwrap()
+            .status
+            .success()
+    };
+    assert!(!fetch(&["--passphrase", "wrong"]));
+    assert!(fetch(&["--passphrase", "phrase"]));
+    assert_eq!(list_items(&td, "receiver", "other"), vec!["shared"]);
+    assert_eq!(get_item_json(&td, "receiver", "other", "shared")["password"], sent["password"]);
+
+    // Republishing replaces the remote copy, fetching again requires --replace
+    cmd!(td; "-n" "sender" "-p" "password" "edit" "shared" "-p" "changed");
+    cmd!(td; "-n" "sender" "-p" "password" "publish" "shared" "--provider" transfer "--passphrase" "phrase");
+    assert!(!fetch(&["--passphrase", "phrase"]));
+    assert!(fetch(&["--passphrase", "phrase", "--replace"]));
+    assert_eq!(get_item_json(&td, "receiver", "other", "shared")["password"], "changed");
+    Ok(())
+}
+
+#[test]
+fn test_sync_daemon_once() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    vault_create(&td, "synced", "password");
+    vault_create(&td, "local", "password");
+    setup_sync(&td, &td_sync, "synced", "password");
+    cmd!(td; "-n" "synced" "-p" "password" "add" "entry" "-p" "secret");
+
+    let stdout = String::from_utf8(cmd_stdout!(td; "-p" "password" "daemon" "--once")).unwrap();
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), vec!["Synchronized synced"]);
+    assert!(td_sync.path().join("synced.vpass_vault").exists());
+    assert!(!td_sync.path().join("local.vpass_vault").exists());
+    // No vault left to synchronize
+    cmd_err!(td; "-p" "wrong" "daemon" "--once");
+
+    if cfg!(target_os = "linux") {
+        // The service can't prompt for the password
+        cmd_err!(td; "daemon" "install" "--print");
+        let password_file = td.path().join("password");
+        fs::write(&password_file, "password")?;
+        let unit = String::from_utf8(
+            cmd_stdout!(td; "--password-file" password_file.to_str().unwrap() "daemon" "install" "--print"),
+        )
+        .unwrap();
+        assert!(unit.contains("\"--no-input\""));
+        assert!(unit.contains("\"--password-file\""));
+        assert!(unit.contains("\"daemon\"\n"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sync_rename_trash() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+    cmd!(td; "-n" "testvault" "-p" "password" "sync");
+
+    vault_rename(&td, "testvault", "renamed", "password");
+    assert!(td_sync.path().join("renamed.vpass_vault").exists());
+    assert!(!td_sync.path().join("testvault.vpass_vault").exists());
+    assert!(td_sync.path().join("trash/testvault.vpass_vault").exists());
+
+    cmd!(td; "-n" "renamed" "-p" "password" "sync" "purge-trash" "testvault");
+    assert!(!td_sync.path().join("trash/testvault.vpass_vault").exists());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_test_command() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+
+    let output = String::from_utf8(cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "test")).unwrap();
+    for step in &["ping", "test", "write", "read", "delete"] {
+        assert!(output.contains(&format!("{}: ok", step)));
+    }
+    assert_eq!(fs::read_dir(td_sync.path())?.count(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_mock_stale_update_key() -> io::Result<()> {
+    use vpass::sync::providers::mock::Mock;
+    use vpass::sync::SyncProvider;
+
+    let td = init()?;
+    let td_mock = tempdir()?;
+    let mock_config = json!({ "path": td_mock.path().join("mock.json") });
+
     vault_create(&td, "testvault", "password");
     cmd!(td; "-n" "testvault" "-p" "password" "sync" "setup"
         "--json" json!({
-            "service": "FileSystem",
-            "data": {
-                "path": td_sync.path()
-            }
+            "service": "Mock",
+            "data": mock_config,
         }).to_string().as_str()
     );
+    cmd!(td; "-n" "testvault" "-p" "password" "sync");
+    cmd!(td; "-n" "testvault" "-p" "password" "add" "testitem" "-p" "testpassword");
+
+    Mock::load(&mock_config).unwrap().inject_stale_update_keys(1);
+    cmd_err!(td; "-n" "testvault" "-p" "password" "sync" "overwrite");
+
+    cmd!(td; "-n" "testvault" "-p" "password" "sync" "overwrite");
+    let (data, _) = Mock::load(&mock_config).unwrap().read("testvault.vpass_vault").unwrap();
+    rust_sodium::init().expect("Sodium init failed");
+    let book = vpass::decrypt(&data, "password").unwrap();
+    assert!(book.has_item("testitem"));
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_rotate_remote_key() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
 
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
     cmd!(td; "-n" "testvault" "-p" "password" "add" "testitem" "-p" "testpassword");
     cmd!(td; "-n" "testvault" "-p" "password" "sync");
+
+    rust_sodium::init().expect("Sodium init failed");
+    let remote_path = td_sync.path().join("testvault.vpass_vault");
+    assert!(vpass::decrypt(&fs::read(&remote_path)?, "password").is_ok());
+
+    cmd!(td; "-n" "testvault" "-p" "password" "sync" "rotate-remote-key");
+    assert!(fs::read(&remote_path)?.starts_with(b"VPASS_RK1:"));
+
+    // Import with the new transfer string works
     let import_string = cmd_stdout!(td; "-n" "testvault" "-p" "password" "sync" "export");
-    assert!(import_string.starts_with(b"VPASS_"));
     vault_delete(&td, "testvault");
-
     cmd!(td; "-p" "password" "vault" "import" "testvault" String::from_utf8(import_string).unwrap().as_str().trim());
-
     let data = get_item_json(&td, "testvault", "password", "testitem");
     assert_eq!(
         data.get("password").and_then(serde_json::Value::as_str),
         Some("testpassword")
     );
 
+    cmd!(td; "-n" "testvault" "-p" "password" "sync" "rotate-remote-key" "--disable");
+    assert!(vpass::decrypt(&fs::read(&remote_path)?, "password").is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_password() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+    cmd!(td; "-n" "testvault" "-p" "password" "add" "testitem" "-p" "testpassword");
+    cmd!(td; "-n" "testvault" "-p" "password" "sync" "set-password" "-p" "strong sync password");
+
+    rust_sodium::init().expect("Sodium init failed");
+    let remote_path = td_sync.path().join("testvault.vpass_vault");
+    assert!(vpass::decrypt(&fs::read(&remote_path)?, "password").is_err());
+    assert!(vpass::decrypt(&fs::read(&remote_path)?, "strong sync password").is_ok());
+
+    // Later changes are pushed with the sync password, and the local password stays the same
+    cmd!(td; "-n" "testvault" "-p" "password" "add" "other" "-p" "otherpassword");
+    cmd!(td; "-n" "testvault" "-p" "password" "sync");
+    vault_change_password(&td, "testvault", "password", "new password");
+    assert!(vpass::decrypt(&fs::read(&remote_path)?, "new password").is_err());
+    assert!(vpass::decrypt(&fs::read(&remote_path)?, "strong sync password").is_ok());
+
+    // Other devices import with the sync password
+    let import_string = cmd_stdout!(td; "-n" "testvault" "-p" "new password" "sync" "export");
+    vault_delete(&td, "testvault");
+    cmd!(td; "-p" "strong sync password" "vault" "import" "testvault"
+        String::from_utf8(import_string).unwrap().as_str().trim());
+    let data = get_item_json(&td, "testvault", "strong sync password", "other");
+    assert_eq!(data.get("password").and_then(serde_json::Value::as_str), Some("otherpassword"));
+
+    cmd!(td; "-n" "testvault" "-p" "strong sync password" "sync" "set-password" "--disable");
+    assert!(vpass::decrypt(&fs::read(&remote_path)?, "strong sync password").is_ok());
+    Ok(())
+}
+
+/// Two separately created vaults synchronizing to the same remote key
+fn create_unrelated_vaults(td_sync: &TempDir) -> io::Result<(TempDir, TempDir)> {
+    let td1 = init()?;
+    vault_create(&td1, "testvault", "password");
+    setup_sync(&td1, td_sync, "testvault", "password");
+    add_item(&td1, "testvault", "password", "item1", "pass1");
+    cmd!(td1; "-n" "testvault" "-p" "password" "sync");
+
+    let td2 = init()?;
+    vault_create(&td2, "testvault", "password");
+    setup_sync(&td2, td_sync, "testvault", "password");
+    add_item(&td2, "testvault", "password", "item2", "pass2");
+    Ok((td1, td2))
+}
+
+#[test]
+#[should_panic]
+fn test_sync_different_origin() {
+    let td_sync = create_sync_fs().unwrap();
+    let (_td1, td2) = create_unrelated_vaults(&td_sync).unwrap();
+    cmd!(td2; "-n" "testvault" "-p" "password" "sync");
+}
+
+#[test]
+fn test_sync_force_adopt() -> io::Result<()> {
+    let td_sync = create_sync_fs()?;
+    let (td1, td2) = create_unrelated_vaults(&td_sync)?;
+    cmd!(td2; "-n" "testvault" "-p" "password" "sync" "--force-adopt");
+    cmd!(td1; "-n" "testvault" "-p" "password" "sync");
+
+    for td in &[td1, td2] {
+        for (item, password) in &[("item1", "pass1"), ("item2", "pass2")] {
+            let data = get_item_json(td, "testvault", "password", item);
+            assert_eq!(data.get("password").and_then(serde_json::Value::as_str), Some(*password));
+        }
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_config_not_an_item() -> io::Result<()> {
+    let td = init()?;
+    let td_sync = create_sync_fs()?;
+
+    vault_create(&td, "testvault", "password");
+    setup_sync(&td, &td_sync, "testvault", "password");
+    add_item(&td, "testvault", "password", "testitem", "testpassword");
+
+    let names = cmd_stdout!(td; "-n" "testvault" "-p" "password" "list");
+    assert_eq!(names, b"testitem\n");
+    cmd!(td; "-n" "testvault" "-p" "password" "sync");
+    assert!(td_sync.path().join("testvault.vpass_vault").exists());
+    Ok(())
+}
+
+#[test]
+fn test_sync_conflict_rename_race() -> io::Result<()> {
+    let d = two_devices("testvault", "password")?;
+    add_item(&d.a, "testvault", "password", "item", "secret");
+    sync(&d.a, "testvault", "password");
+    sync(&d.b, "testvault", "password");
+
+    rename_item(&d.a, "testvault", "password", "item", "renamed.a");
+    sync(&d.a, "testvault", "password");
+    rename_item(&d.b, "testvault", "password", "item", "renamed.b");
+    sync(&d.b, "testvault", "password");
+    sync(&d.a, "testvault", "password");
+
+    // The later rename wins, and the item is not duplicated
+    for td in &[&d.a, &d.b] {
+        assert_eq!(list_items(td, "testvault", "password"), vec!["renamed.b"]);
+        let data = get_item_json(td, "testvault", "password", "renamed.b");
+        assert_eq!(data.get("password").and_then(serde_json::Value::as_str), Some("secret"));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sync_conflict_same_name() -> io::Result<()> {
+    let d = two_devices("testvault", "password")?;
+    add_item(&d.a, "testvault", "password", "item", "secret-a");
+    add_item(&d.b, "testvault", "password", "item", "secret-b");
+    sync(&d.a, "testvault", "password");
+    sync(&d.b, "testvault", "password");
+    sync(&d.a, "testvault", "password");
+
+    // Separately created items are different items, so both are kept,
+    // and the one created later is renamed so that both can be used by name
+    for td in &[&d.a, &d.b] {
+        assert_eq!(list_items(td, "testvault", "password"), vec!["item", "item.conflict"]);
+        assert_eq!(get_item_json(td, "testvault", "password", "item")["password"], "secret-a");
+        assert_eq!(get_item_json(td, "testvault", "password", "item.conflict")["password"], "secret-b");
+    }
+    Ok(())
+}
+
+#[test]
+fn test_sync_conflict_delete_and_edit() -> io::Result<()> {
+    let d = two_devices("testvault", "password")?;
+    add_item(&d.a, "testvault", "password", "deleted.first", "secret");
+    add_item(&d.a, "testvault", "password", "edited.first", "secret");
+    add_item(&d.a, "testvault", "password", "kept", "secret");
+    sync(&d.a, "testvault", "password");
+    sync(&d.b, "testvault", "password");
+
+    remove_item(&d.a, "testvault", "password", "deleted.first");
+    edit_item_change_password(&d.b, "testvault", "password", "deleted.first", "changed");
+    edit_item_change_password(&d.b, "testvault", "password", "edited.first", "changed");
+    remove_item(&d.a, "testvault", "password", "edited.first");
+    sync(&d.a, "testvault", "password");
+    sync(&d.b, "testvault", "password");
+    sync(&d.a, "testvault", "password");
+
+    // Removal wins over concurrent edits, whichever happened first
+    for td in &[&d.a, &d.b] {
+        assert_eq!(list_items(td, "testvault", "password"), vec!["kept"]);
+    }
+    Ok(())
+}
+
+/// Stops the server when the test ends, also on failure
+struct ServerProcess(std::process::Child);
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+#[test]
+fn test_http_server_contract() -> io::Result<()> {
+    use std::process::{Command, Stdio};
+    use vpass::sync::providers::contract::provider_contract_tests;
+    use vpass::sync::providers::http::Http;
+    use vpass::sync::SyncProvider;
+
+    rust_sodium::init().expect("Sodium init failed");
+    let td = tempdir()?;
+    let port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+    let bind = format!("127.0.0.1:{}", port);
+    let _server = ServerProcess(
+        Command::cargo_bin("vpass-server")
+            .unwrap()
+            .args(["--dir", td.path().to_str().unwrap(), "--bind", &bind, "--token", "secret"])
+            .stderr(Stdio::null())
+            .spawn()?,
+    );
+
+    let mut http = Http::load(&json!({"url": format!("http://{}", bind), "token": "secret"})).unwrap();
+    let mut attempts = 0;
+    while http.ping().is_err() {
+        attempts += 1;
+        assert!(attempts < 50, "Server didn't start");
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+    provider_contract_tests(&mut http);
+
+    let mut wrong_token = Http::load(&json!({"url": format!("http://{}", bind), "token": "wrong"})).unwrap();
+    assert!(wrong_token.ping().is_err());
     Ok(())
 }